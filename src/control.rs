@@ -0,0 +1,726 @@
+//! The control socket, which lets other programs talk to a running admiral.
+//!
+//! Each connection carries a single command terminated by a newline. The reply
+//! is written back before the connection is closed, and starts with `error` if
+//! the command could not be carried out. The exception is `attach <format>`,
+//! which keeps the connection open and sends every new bar line down it.
+
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::fs::{self, File};
+use std::io::{stderr, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc;
+use rustc_serialize::json::Json;
+use toml::{self, Value};
+
+use {instantiate_template, read_config, Event, Format, Message, SharedConfig, Source, PAUSED, SCRIPT_KEYS};
+use output::{Attached, Item, OutputFormat};
+use schedule;
+use widgets;
+
+/// Tables that decide which bars there are and what's on them, which are only read at startup.
+const RESTART_TABLES: &[&str] = &["admiral", "bars", "profiles"];
+
+/// Entries of a section that are only read at startup: its scrolling, and the signal it's refreshed by.
+const RESTART_KEYS: &[&str] = &["scroll", "signal"];
+
+/// What a section last did, as reported by `status`.
+#[derive(Debug, Clone, Default)]
+pub struct Status {
+    /// The output it last printed, before it was formatted.
+    pub output: Option<String>,
+    /// When it last printed output, or failed to.
+    pub updated: Option<SystemTime>,
+    /// The code its script last exited with.
+    pub exit_code: Option<i32>,
+}
+
+/// The status of each section, by section name, kept up to date by the script threads.
+pub type Statuses = Arc<Mutex<HashMap<String, Status>>>;
+
+pub struct Controller {
+    pub config: SharedConfig,
+    /// The script threads serving each section, by section name.
+    pub runners: HashMap<String, Vec<Sender<Event>>>,
+    /// One for each thread running sections, whichever sections it runs.
+    pub threads: Vec<Sender<Event>>,
+    /// The threads of rotating sections, which aren't among `threads`.
+    pub rotations: Vec<Sender<Event>>,
+    /// The sections that each section shares its process with, when `deduplicate` is on.
+    pub shared: HashMap<String, Vec<String>>,
+    pub statuses: Statuses,
+    /// Where each section on the bar is shown, by section name.
+    pub positions: HashMap<String, usize>,
+    /// The main thread, which composes the bar.
+    pub messages: Sender<Message>,
+    /// The configuration file, read again by `reload`.
+    pub config_file: PathBuf,
+    /// Where `set-config --persist` stores its overrides.
+    pub overrides_file: PathBuf,
+    pub attached: Arc<Mutex<Attached>>,
+}
+
+impl Controller {
+    fn handle(&self, command: &str) -> String {
+        let mut words = command.splitn(2, ' ');
+        let result = match (words.next(), words.next()) {
+            (Some("set-config"), Some(arguments)) => self.set_config(arguments),
+            (Some("set-config"), None) => Err(String::from("usage: set-config [--persist] <section>.<key> <value>")),
+            (Some("reload"), None) => self.reload(),
+            (Some("rotate"), Some(section)) => self.rotate(section.trim()),
+            (Some("rotate"), None) => Err(String::from("usage: rotate <section>")),
+            (Some("refresh"), Some(section)) => self.refresh(section.trim()),
+            (Some("refresh"), None) => Err(String::from("usage: refresh <section>")),
+            (Some("toggle"), Some(section)) => self.toggle(section.trim()),
+            (Some("toggle"), None) => Err(String::from("usage: toggle <section>")),
+            (Some("pause"), None) => self.pause(false),
+            (Some("pause"), Some("--stop")) => self.pause(true),
+            (Some("pause"), Some(_)) => Err(String::from("usage: pause [--stop]")),
+            (Some("resume"), None) => self.resume(),
+            (Some("status"), None) => Ok(format!("{}\n", self.status())),
+            (Some("set"), Some(arguments)) => self.set(arguments.trim_start()),
+            (Some("set"), None) => Err(String::from("usage: set <section> [text]")),
+            (Some("action"), Some(arguments)) => self.action(arguments.trim()),
+            (Some("action"), None) => Err(String::from("usage: action <section> <action>")),
+            _ => Err(format!("unknown command {}", command)),
+        };
+
+        match result {
+            Ok(reply) => reply,
+            Err(e) => format!("error: {}\n", e),
+        }
+    }
+
+    fn set_config(&self, arguments: &str) -> Result<String, String> {
+        let (persist, arguments) = match arguments.strip_prefix("--persist ") {
+            Some(arguments) => (true, arguments.trim_start()),
+            None => (false, arguments),
+        };
+
+        let (path, value) = arguments.split_once(' ')
+            .ok_or_else(|| String::from("usage: set-config [--persist] <section>.<key> <value>"))?;
+        let (section, key) = path.split_once('.')
+            .ok_or_else(|| format!("Invalid key {}: expected <section>.<key>", path))?;
+        let value = parse_value(value.trim());
+
+        if !self.runners.contains_key(section) {
+            return Err(format!("{} is not a running item", section));
+        }
+
+        let (current, patched, item) = {
+            let mut config = self.config.write().unwrap();
+            let current = config.get(section).and_then(Value::as_table).cloned().unwrap_or_default();
+            let mut patched = current.clone();
+            patched.insert(key.to_owned(), value.clone());
+            let theme = config.get("theme").and_then(Value::as_table).cloned().unwrap_or_default();
+            let item = self.check_change(section, &current, &patched, &theme)?;
+            config.insert(section.to_owned(), Value::Table(patched.clone()));
+            (current, patched, item)
+        };
+        self.reconfigure(section, &[&current, &patched]);
+        if let Some(item) = item {
+            let _ = self.messages.send(Message::Restyle(self.positions[section], Box::new(item)));
+        }
+
+        if persist {
+            let mut overrides = read_overrides(&self.overrides_file)?;
+            if let Value::Table(ref mut table) = *overrides.entry(section.to_owned()).or_insert_with(|| Value::Table(toml::Table::new())) {
+                table.insert(key.to_owned(), value);
+            }
+            fs::write(&self.overrides_file, Value::Table(overrides).to_string())
+                .map_err(|e| format!("Could not write {}: {}", self.overrides_file.display(), e))?;
+        }
+
+        Ok(String::from("ok\n"))
+    }
+
+    /// Reads the configuration file again, restarting the sections whose
+    /// tables have changed and redrawing them on the bar. Nothing changes
+    /// unless every one of them is valid and can change while admiral runs.
+    fn reload(&self) -> Result<String, String> {
+        let mut fresh = read_config(&self.config_file, &self.overrides_file)?;
+        for section in self.runners.keys() {
+            instantiate_template(&mut fresh, section)?;
+        }
+
+        let mut config = self.config.write().unwrap();
+        // Which bars there are, and what's on them, is only read at startup
+        if let Some(table) = RESTART_TABLES.iter().find(|&&table| config.get(table) != fresh.get(table)) {
+            return Err(format!("[{}] can't change until admiral is restarted", table));
+        }
+        // A new theme can change the colours of any section
+        let theme_changed = config.get("theme") != fresh.get("theme");
+        let theme = fresh.get("theme").and_then(Value::as_table).cloned().unwrap_or_default();
+
+        let mut changed = Vec::new();
+        let mut restyled = Vec::new();
+        for section in self.runners.keys() {
+            let table = fresh.get(section).and_then(Value::as_table)
+                .ok_or_else(|| format!("{} is no longer in the configuration file; restart admiral to remove it", section))?;
+            let current = config.get(section).and_then(Value::as_table).cloned().unwrap_or_default();
+            if current == *table {
+                if theme_changed && self.positions.contains_key(section) {
+                    restyled.push((self.positions[section], Item::from_config(section, table, &theme)?));
+                }
+                continue;
+            }
+            // A rotation's members are fixed once it's running
+            if table.get("type").and_then(Value::as_str) == Some("rotate") {
+                return Err(format!("{} is a rotate section, which can't change until admiral is restarted", section));
+            }
+            if let Some(item) = self.check_change(section, &current, table, &theme)? {
+                restyled.push((self.positions[section], item));
+            }
+            changed.push((section, current));
+        }
+
+        if theme_changed {
+            config.insert(String::from("theme"), Value::Table(theme));
+        }
+        for (section, _) in &changed {
+            config.insert(section.to_string(), fresh[section.as_str()].clone());
+        }
+        drop(config);
+        for (section, current) in changed {
+            self.reconfigure(section, &[&current, fresh[section.as_str()].as_table().unwrap()]);
+        }
+        for (position, item) in restyled {
+            let _ = self.messages.send(Message::Restyle(position, Box::new(item)));
+        }
+        Ok(String::from("ok\n"))
+    }
+
+    /// Checks that a section can be given its changed table while admiral
+    /// runs, returning how it's drawn from now on if it's on the bar.
+    fn check_change(&self, section: &str, current: &toml::Table, changed: &toml::Table, theme: &toml::Table) -> Result<Option<Item>, String> {
+        self.check_shared(section, current, changed)?;
+        if let Some(key) = RESTART_KEYS.iter().find(|&&key| current.get(key) != changed.get(key)) {
+            return Err(format!("{}'s {} can't change until admiral is restarted", section, key));
+        }
+        Source::from_config(section, changed)?;
+        Format::from_config(section, changed)?;
+        schedule::separators(section, changed)?;
+        match self.positions.contains_key(section) {
+            true => Item::from_config(section, changed, theme).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    /// Refuses a change to what process a section runs if it shares the process
+    /// with other sections, as the change could only apply to all of them.
+    fn check_shared(&self, section: &str, current: &toml::Table, changed: &toml::Table) -> Result<(), String> {
+        let others = match self.shared.get(section) {
+            Some(others) => others,
+            None => return Ok(()),
+        };
+        match ["type"].iter().chain(SCRIPT_KEYS).find(|&&key| current.get(key) != changed.get(key)) {
+            Some(key) => Err(format!("{} shares its script with {}, as deduplicate is on, so its {} can't change until admiral is restarted", section, others.join(", "), key)),
+            None => Ok(()),
+        }
+    }
+
+    /// Restarts a section with its new configuration, and the sections named
+    /// in its `separators` before or after, which follow its active hours.
+    fn reconfigure(&self, section: &str, tables: &[&toml::Table]) {
+        let mut restarted = vec![section.to_owned()];
+        for table in tables {
+            restarted.extend(schedule::separators(section, table).unwrap_or_default());
+        }
+        restarted.sort();
+        restarted.dedup();
+        for name in restarted {
+            for runner in self.runners.get(&name).into_iter().flatten() {
+                let _ = runner.send(Event::Reconfigure);
+            }
+        }
+    }
+
+    /// Moves a rotating section on to its next member.
+    fn rotate(&self, section: &str) -> Result<String, String> {
+        let is_rotation = self.config.read().unwrap().get(section)
+            .and_then(|section| section.lookup("type"))
+            .and_then(Value::as_str) == Some("rotate");
+        let runners = match self.runners.get(section) {
+            Some(runners) if is_rotation => runners,
+            _ => return Err(format!("{} is not a running rotate section", section)),
+        };
+
+        for runner in runners {
+            let _ = runner.send(Event::Rotate);
+        }
+        Ok(String::from("ok\n"))
+    }
+
+    /// Has a section update straight away, out of its usual schedule. A
+    /// script that keeps running is started again, and a rotating section
+    /// has each of its members update.
+    fn refresh(&self, section: &str) -> Result<String, String> {
+        let runners = self.runners.get(section)
+            .ok_or_else(|| format!("{} is not a running item", section))?;
+        for runner in runners {
+            let _ = runner.send(Event::Refresh);
+        }
+        Ok(String::from("ok\n"))
+    }
+
+    /// Hides a section on the bar, or shows it again, while it keeps running.
+    fn toggle(&self, section: &str) -> Result<String, String> {
+        let position = self.positions.get(section)
+            .ok_or_else(|| format!("{} is not on the bar", section))?;
+        let _ = self.messages.send(Message::Toggle(*position));
+        Ok(String::from("ok\n"))
+    }
+
+    /// Stops scripts from being run, and widgets from being polled, until
+    /// `resume`, leaving the bar as it is. Scripts that keep running have
+    /// their output held back, or with `--stop`, their process stopped, and
+    /// rotating sections stay on the member they're showing.
+    fn pause(&self, stop: bool) -> Result<String, String> {
+        PAUSED.store(true, Ordering::Relaxed);
+        for thread in self.threads.iter().chain(&self.rotations) {
+            let _ = thread.send(Event::Pause(stop));
+        }
+        Ok(String::from("ok\n"))
+    }
+
+    fn resume(&self) -> Result<String, String> {
+        if !PAUSED.swap(false, Ordering::Relaxed) {
+            return Err(String::from("updates aren't paused"));
+        }
+        for thread in self.threads.iter().chain(&self.rotations) {
+            let _ = thread.send(Event::Resume);
+        }
+        Ok(String::from("ok\n"))
+    }
+
+    /// Describes every running section, so that one that has stopped updating can be found.
+    fn status(&self) -> Json {
+        let statuses = self.statuses.lock().unwrap();
+        let mut names = self.runners.keys().collect::<Vec<_>>();
+        names.sort();
+
+        let sections = names.into_iter().map(|name| {
+            let status = statuses.get(name).cloned().unwrap_or_default();
+            let updated = status.updated.and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+            let mut object = BTreeMap::new();
+            object.insert(String::from("name"), Json::String(name.clone()));
+            object.insert(String::from("output"), status.output.map_or(Json::Null, Json::String));
+            object.insert(String::from("updated"), updated.map_or(Json::Null, |updated| Json::U64(updated.as_secs())));
+            object.insert(String::from("exit_code"), status.exit_code.map_or(Json::Null, |code| Json::I64(code as i64)));
+            Json::Object(object)
+        }).collect();
+
+        let mut object = BTreeMap::new();
+        object.insert(String::from("paused"), Json::Boolean(PAUSED.load(Ordering::Relaxed)));
+        object.insert(String::from("items"), Json::Array(sections));
+        Json::Object(object)
+    }
+
+    /// Shows text in a remote section, or with no text, hides it.
+    fn set(&self, arguments: &str) -> Result<String, String> {
+        let (section, text) = arguments.split_once(' ').unwrap_or((arguments, ""));
+        let is_remote = self.config.read().unwrap().get(section)
+            .and_then(|section| section.lookup("type"))
+            .and_then(Value::as_str) == Some("remote");
+        let runners = match self.runners.get(section) {
+            Some(runners) if is_remote => runners,
+            _ => return Err(format!("{} is not a running remote section", section)),
+        };
+
+        for runner in runners {
+            let _ = runner.send(Event::Action(format!("set {}", text)));
+        }
+        Ok(String::from("ok\n"))
+    }
+
+    /// Passes an action on to a widget section, such as starting its timer.
+    fn action(&self, arguments: &str) -> Result<String, String> {
+        let (section, action) = arguments.split_once(' ')
+            .ok_or_else(|| String::from("usage: action <section> <action>"))?;
+        let is_widget = self.config.read().unwrap().get(section)
+            .and_then(|section| section.lookup("type"))
+            .and_then(Value::as_str)
+            .is_some_and(|type_name| widgets::REGISTRY.iter().any(|&(name, _)| name == type_name));
+        let runners = match self.runners.get(section) {
+            Some(runners) if is_widget => runners,
+            _ => return Err(format!("{} is not a running widget", section)),
+        };
+
+        for runner in runners {
+            let _ = runner.send(Event::Action(action.trim().to_owned()));
+        }
+        Ok(String::from("ok\n"))
+    }
+}
+
+/// Reads a value the way it would be written in the configuration file,
+/// treating anything that isn't valid TOML as a bare string.
+fn parse_value(value: &str) -> Value {
+    toml::Parser::new(&format!("value = {}", value)).parse()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(value.to_owned()))
+}
+
+/// Where the control socket is: in `$XDG_RUNTIME_DIR`, or failing that, in a
+/// directory of the user's own in the temporary directory, so that nobody else
+/// can take the path first.
+pub fn socket_path() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("admiral.sock"),
+        None => env::temp_dir().join(format!("admiral-{}", unsafe { libc::getuid() })).join("admiral.sock"),
+    }
+}
+
+/// Makes sure the directory the socket goes in when there's no
+/// `$XDG_RUNTIME_DIR` exists, belongs to this user, and that nobody else can
+/// get into it.
+fn prepare_socket_dir(dir: &Path) -> Result<(), String> {
+    let _ = fs::DirBuilder::new().mode(0o700).create(dir);
+    // Looked at without following links, so that nobody can point it somewhere else
+    let metadata = fs::symlink_metadata(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    if !metadata.is_dir() || metadata.uid() != unsafe { libc::getuid() } || metadata.mode() & 0o077 != 0 {
+        return Err(format!("Refusing to use {}: it isn't a directory that only you can use", dir.display()));
+    }
+    Ok(())
+}
+
+/// The drop-in file that persisted overrides are kept in, next to the configuration file.
+pub fn overrides_file(config_file: &Path) -> PathBuf {
+    config_file.with_extension("overrides.toml")
+}
+
+pub fn read_overrides(path: &Path) -> Result<toml::Table, String> {
+    let mut buffer = String::new();
+    match File::open(path) {
+        Ok(mut file) => {
+            file.read_to_string(&mut buffer).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        },
+        Err(_) => return Ok(toml::Table::new()),
+    }
+
+    toml::Parser::new(&buffer).parse().ok_or_else(|| format!("Syntax error in {}", path.display()))
+}
+
+/// Starts serving the control socket on a background thread. Each connection
+/// is read on a thread of its own, so that a client slow to send its command
+/// holds up nobody else, and the commands are then carried out one at a time.
+pub fn listen(controller: Controller) {
+    let path = socket_path();
+    if let (None, Some(dir)) = (env::var_os("XDG_RUNTIME_DIR"), path.parent()) {
+        if let Err(e) = prepare_socket_dir(dir) {
+            let _ = stderr().write(format!("Could not create control socket {}: {}\n", path.display(), e).as_bytes());
+            return;
+        }
+    }
+    if UnixStream::connect(&path).is_ok() {
+        let _ = stderr().write(format!("Another admiral is already listening on {}\n", path.display()).as_bytes());
+        return;
+    }
+
+    // Anything left at the path is a stale socket from an instance that has exited
+    let _ = fs::remove_file(&path);
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            let _ = stderr().write(format!("Could not create control socket {}: {}\n", path.display(), e).as_bytes());
+            return;
+        },
+    };
+
+    let controller = Arc::new(Mutex::new(controller));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(_) => continue,
+            };
+            let controller = controller.clone();
+            thread::spawn(move || serve(&controller, stream));
+        }
+    });
+}
+
+/// Reads a connection's command and carries it out.
+fn serve(controller: &Mutex<Controller>, mut stream: UnixStream) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+    let mut reader = match stream.try_clone() {
+        Ok(clone) => BufReader::new(clone),
+        Err(_) => return,
+    };
+    let mut command = String::new();
+    if reader.read_line(&mut command).is_err() {
+        return;
+    }
+
+    if let Some(name) = command.trim().strip_prefix("attach ") {
+        match OutputFormat::from_name(name.trim()) {
+            Some(format) => {
+                // A client that stops reading is dropped rather than stalling the bar
+                let _ = stream.set_write_timeout(Some(Duration::from_secs(1)));
+                let attached = controller.lock().unwrap().attached.clone();
+                if format == OutputFormat::Framed {
+                    if let Ok(writer) = stream.try_clone() {
+                        serve_resyncs(attached.clone(), reader, writer);
+                    }
+                }
+                attached.lock().unwrap().attach(format, stream);
+            },
+            None => { let _ = stream.write_all(format!("error: unknown format {}\n", name).as_bytes()); },
+        }
+        return;
+    }
+
+    let reply = controller.lock().unwrap().handle(command.trim());
+    let _ = stream.write_all(reply.as_bytes());
+}
+
+/// Answers each `sync` line a framed client sends with the full state, until it goes away.
+fn serve_resyncs(attached: Arc<Mutex<Attached>>, reader: BufReader<UnixStream>, mut writer: UnixStream) {
+    let _ = reader.get_ref().set_read_timeout(None);
+    thread::spawn(move || {
+        for line in reader.lines().map_while(Result::ok) {
+            if line.trim() == "sync" && attached.lock().unwrap().resync(&mut writer).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Sends a command to the running instance and returns its reply.
+pub fn send(command: &str) -> std::io::Result<String> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
+
+/// Prints every line the running instance sends in the given format, until it goes away.
+pub fn attach(format: &str) -> std::io::Result<()> {
+    let mut stream = UnixStream::connect(socket_path())?;
+    stream.write_all(format!("attach {}\n", format).as_bytes())?;
+
+    // Framed consumers ask to resynchronise by writing sync to our input
+    if format == "framed" {
+        let mut writer = stream.try_clone()?;
+        thread::spawn(move || {
+            let stdin = std::io::stdin();
+            for line in stdin.lock().lines().map_while(Result::ok) {
+                if writeln!(writer, "{}", line.trim()).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let stdout = std::io::stdout();
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.starts_with("error") {
+            let _ = stderr().write(format!("{}\n", line).as_bytes());
+            break;
+        }
+
+        let mut stdout = stdout.lock();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs::{self, DirBuilder};
+    use std::io::{Read, Write};
+    use std::net::Shutdown;
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    use std::os::unix::net::UnixStream;
+    use std::process;
+    use std::sync::{Arc, Mutex, RwLock};
+    use std::sync::atomic::Ordering;
+    use std::sync::mpsc::{channel, Receiver};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use rustc_serialize::json::Json;
+    use toml::{self, Value};
+
+    use {Event, Message, PAUSED};
+    use super::{parse_value, prepare_socket_dir, serve, Controller, Status, Statuses};
+
+    #[test]
+    fn values_are_read_as_toml() {
+        assert_eq!(parse_value("5"), Value::Integer(5));
+        assert_eq!(parse_value("2.5"), Value::Float(2.5));
+        assert_eq!(parse_value("true"), Value::Boolean(true));
+        assert_eq!(parse_value("\"quoted\""), Value::String(String::from("quoted")));
+        assert_eq!(parse_value("[1, 2]"), Value::Array(vec![Value::Integer(1), Value::Integer(2)]));
+    }
+
+    #[test]
+    fn anything_else_is_a_string() {
+        assert_eq!(parse_value("#ff0000"), Value::String(String::from("#ff0000")));
+        assert_eq!(parse_value("two words"), Value::String(String::from("two words")));
+        assert_eq!(parse_value("\"unclosed"), Value::String(String::from("\"unclosed")));
+    }
+
+    /// A controller for the sections of `config`, which is written where
+    /// `reload` reads it, with a runner for each section and the clock on the bar.
+    fn controller(name: &str, config: &str) -> (Controller, Receiver<Event>, Receiver<Message>) {
+        let dir = env::temp_dir().join(format!("admiral-test-control-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config_file = dir.join("admiral.toml");
+        fs::write(&config_file, config).unwrap();
+        let (runner, events) = channel();
+        let (messages, shown) = channel();
+        let table = toml::Parser::new(config).parse().unwrap();
+        let runners = table.keys()
+            .filter(|&section| section != "admiral")
+            .map(|section| (section.clone(), vec![runner.clone()]))
+            .collect();
+        let controller = Controller {
+            config: Arc::new(RwLock::new(table)),
+            runners,
+            threads: vec![runner],
+            rotations: Vec::new(),
+            shared: HashMap::new(),
+            statuses: Statuses::default(),
+            positions: vec![(String::from("clock"), 0)].into_iter().collect(),
+            messages,
+            overrides_file: dir.join("overrides.toml"),
+            config_file,
+            attached: Arc::default(),
+        };
+        (controller, events, shown)
+    }
+
+    #[test]
+    fn reloading_restarts_the_sections_that_changed() {
+        let config = "[admiral]\nitems = [\"clock\"]\n[clock]\npath = \"date\"\n[uptime]\npath = \"uptime\"\n";
+        let (controller, events, shown) = controller("reload", config);
+        assert_eq!(controller.handle("reload"), "ok\n");
+        assert!(events.try_recv().is_err());
+
+        fs::write(&controller.config_file, config.replace("\"date\"", "\"date +%T\"\nfg = \"#ff0000\"")).unwrap();
+        assert_eq!(controller.handle("reload"), "ok\n");
+        assert!(matches!(events.try_recv(), Ok(Event::Reconfigure)));
+        assert!(events.try_recv().is_err());
+        assert!(matches!(shown.try_recv(), Ok(Message::Restyle(0, _))));
+        assert_eq!(controller.config.read().unwrap()["clock"].lookup("path").and_then(Value::as_str), Some("date +%T"));
+
+        fs::write(&controller.config_file, config.replace("[\"clock\"]", "[\"clock\", \"uptime\"]")).unwrap();
+        assert!(controller.handle("reload").starts_with("error: [admiral] can't change"));
+        fs::write(&controller.config_file, config.replace("\"date\"", "\"date\"\nfg = 1")).unwrap();
+        assert!(controller.handle("reload").starts_with("error: "));
+        assert!(events.try_recv().is_err());
+        let _ = fs::remove_dir_all(controller.config_file.parent().unwrap());
+    }
+
+    #[test]
+    fn each_connection_gets_a_reply_to_its_command() {
+        let (controller, _events, _shown) = controller("serve", "[clock]\npath = \"date\"\n");
+        let controller = Mutex::new(controller);
+        let reply = |command: &str| {
+            let (mut client, server) = UnixStream::pair().unwrap();
+            client.write_all(command.as_bytes()).unwrap();
+            client.shutdown(Shutdown::Write).unwrap();
+            serve(&controller, server);
+            let mut reply = String::new();
+            client.read_to_string(&mut reply).unwrap();
+            reply
+        };
+        assert_eq!(reply("rotate clock\n"), "error: clock is not a running rotate section\n");
+        assert_eq!(reply("frobnicate\n"), "error: unknown command frobnicate\n");
+        assert_eq!(reply("attach sideways\n"), "error: unknown format sideways\n");
+        let _ = fs::remove_dir_all(controller.into_inner().unwrap().config_file.parent().unwrap());
+    }
+
+    #[test]
+    fn refresh_wakes_the_section() {
+        let (controller, events, _shown) = controller("refresh", "[clock]\npath = \"date\"\n");
+        assert_eq!(controller.handle("refresh clock"), "ok\n");
+        assert!(matches!(events.try_recv(), Ok(Event::Refresh)));
+        assert_eq!(controller.handle("refresh weather"), "error: weather is not a running item\n");
+        assert_eq!(controller.handle("refresh"), "error: usage: refresh <section>\n");
+        let _ = fs::remove_dir_all(controller.config_file.parent().unwrap());
+    }
+
+    #[test]
+    fn toggle_hides_sections_on_the_bar() {
+        let (controller, _events, shown) = controller("toggle", "[clock]\npath = \"date\"\n[uptime]\npath = \"uptime\"\n");
+        assert_eq!(controller.handle("toggle clock"), "ok\n");
+        assert!(matches!(shown.try_recv(), Ok(Message::Toggle(0))));
+        assert_eq!(controller.handle("toggle uptime"), "error: uptime is not on the bar\n");
+        assert!(shown.try_recv().is_err());
+        let _ = fs::remove_dir_all(controller.config_file.parent().unwrap());
+    }
+
+    #[test]
+    fn pausing_reaches_every_thread_until_resumed() {
+        let (controller, events, _shown) = controller("pause", "[clock]\npath = \"date\"\n");
+        assert_eq!(controller.handle("pause --stop"), "ok\n");
+        assert!(PAUSED.load(Ordering::Relaxed));
+        assert_eq!(controller.handle("resume"), "ok\n");
+        assert_eq!(controller.handle("resume"), "error: updates aren't paused\n");
+        assert!(matches!(events.try_recv(), Ok(Event::Pause(true))));
+        assert!(matches!(events.try_recv(), Ok(Event::Resume)));
+        assert!(events.try_recv().is_err());
+        assert_eq!(controller.handle("pause now"), "error: usage: pause [--stop]\n");
+        let _ = fs::remove_dir_all(controller.config_file.parent().unwrap());
+    }
+
+    #[test]
+    fn status_describes_each_section() {
+        let (controller, _events, _shown) = controller("status", "[clock]\npath = \"date\"\n[uptime]\npath = \"uptime\"\n");
+        controller.statuses.lock().unwrap().insert(String::from("clock"), Status {
+            output: Some(String::from("12:00")),
+            updated: Some(UNIX_EPOCH + Duration::from_secs(1700000000)),
+            exit_code: Some(0),
+        });
+        let status = Json::from_str(&controller.handle("status")).unwrap();
+        let items = status.find("items").and_then(Json::as_array).unwrap();
+        assert_eq!(items[0].to_string(), r#"{"exit_code":0,"name":"clock","output":"12:00","updated":1700000000}"#);
+        assert_eq!(items[1].to_string(), r#"{"exit_code":null,"name":"uptime","output":null,"updated":null}"#);
+        assert!(status.find("paused").is_some_and(Json::is_boolean));
+        let _ = fs::remove_dir_all(controller.config_file.parent().unwrap());
+    }
+
+    #[test]
+    fn set_pushes_text_to_remote_sections() {
+        let (controller, events, _shown) = controller("set", "[clock]\npath = \"date\"\n[note]\ntype = \"remote\"\n");
+        assert_eq!(controller.handle("set note recording now"), "ok\n");
+        assert!(matches!(events.try_recv(), Ok(Event::Action(ref action)) if action == "set recording now"));
+        assert_eq!(controller.handle("set note"), "ok\n");
+        assert!(matches!(events.try_recv(), Ok(Event::Action(ref action)) if action == "set "));
+        assert_eq!(controller.handle("set clock 12:00"), "error: clock is not a running remote section\n");
+        let _ = fs::remove_dir_all(controller.config_file.parent().unwrap());
+    }
+
+    #[test]
+    fn socket_dirs_are_private() {
+        let dir = env::temp_dir().join(format!("admiral-test-{}", process::id()));
+        let private = dir.join("private");
+        let shared = dir.join("shared");
+        DirBuilder::new().mode(0o755).recursive(true).create(&shared).unwrap();
+
+        assert_eq!(prepare_socket_dir(&private), Ok(()));
+        assert_eq!(fs::metadata(&private).unwrap().permissions().mode() & 0o777, 0o700);
+        assert_eq!(prepare_socket_dir(&private), Ok(()));
+        assert!(prepare_socket_dir(&shared).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
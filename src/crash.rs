@@ -0,0 +1,161 @@
+//! Reporting panics somewhere they will be noticed.
+//!
+//! Every panic is written to a log file along with a backtrace. A panic on
+//! the main thread also replaces the bar's contents with a short message, as
+//! the bar would otherwise freeze with no explanation. It's sent to every
+//! output the bars are sent to, in each one's output mode. Panics in a
+//! section's thread only affect that section, so the message takes the place
+//! of its output instead, leaving the rest of the bar running.
+
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{stdout, Write};
+use std::panic::{self, AssertUnwindSafe, PanicHookInfo};
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use toml;
+
+use {Escape, Update};
+use output::{Attached, Item, Mode};
+use sink::Sink;
+
+/// Where the bars' lines are sent, once the configuration has been read.
+static SINKS: Mutex<Vec<(Mode, Sink)>> = Mutex::new(Vec::new());
+
+thread_local! {
+    /// Why this thread last panicked, for `contain` to show once it has unwound.
+    static REASON: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// How long pipes, tmux, and bars that Admiral runs are given to write the last line before it exits.
+const LAST_WRITE: Duration = Duration::from_millis(200);
+
+/// Has a panic on the main thread sent to `sinks`, as well as to attached clients.
+pub fn report_to(sinks: Vec<(Mode, Sink)>) {
+    if let Ok(mut reported) = SINKS.lock() {
+        *reported = sinks;
+    }
+}
+
+pub fn log_file() -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(state_home.join("admiral").join("admiral.log"))
+}
+
+fn reason(info: &PanicHookInfo) -> String {
+    let payload = info.payload();
+    let message = payload.downcast_ref::<&str>().map(|s| s.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .filter(|s| !s.is_empty())
+        .unwrap_or_else(|| String::from("explicit panic"));
+
+    match info.location() {
+        Some(location) => format!("{} at {}:{}", message, location.file(), location.line()),
+        None => message,
+    }
+}
+
+fn write_log(thread_name: &str, reason: &str) {
+    let path = match log_file() {
+        Some(path) => path,
+        None => return,
+    };
+    if let Some(parent) = path.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+
+    if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&path) {
+        let _ = writeln!(file, "thread '{}' panicked: {}\n{}\n", thread_name, reason, Backtrace::force_capture());
+    }
+}
+
+/// Installs the panic hook. `print` says whether bar lines go to stdout.
+pub fn install(attached: Arc<Mutex<Attached>>, print: bool) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+
+        let thread = thread::current();
+        let thread_name = thread.name().unwrap_or("<unnamed>");
+        let reason = reason(info);
+        write_log(thread_name, &reason);
+
+        if thread_name != "main" {
+            REASON.with(|last| *last.borrow_mut() = Some(reason));
+            return;
+        }
+
+        let line = format!("admiral crashed: {}", reason);
+        // Drawn as an urgent item, so that it stands out
+        let mut item = Item::from_config("admiral", &toml::Table::new(), &toml::Table::new()).unwrap();
+        item.set_urgent(true);
+        let items = [item];
+        match SINKS.try_lock() {
+            Ok(mut sinks) if !sinks.is_empty() => {
+                for &mut (mode, ref mut sink) in sinks.iter_mut() {
+                    let _ = sink.send(&mode.render(&items, &[mode.escape(&line)]));
+                }
+                if sinks.iter().any(|(_, sink)| sink.is_threaded()) {
+                    sleep(LAST_WRITE);
+                }
+            },
+            // Before the configuration is read, the line can only be printed
+            _ if print => {
+                let mut stdout = stdout();
+                let _ = writeln!(stdout, "{}", line);
+                let _ = stdout.flush();
+            },
+            _ => {},
+        }
+        // The panic may have happened while the lock was held
+        if let Ok(mut attached) = attached.try_lock() {
+            let plain = Mode::Plain.render(&items, &[Mode::Plain.escape(&line)]);
+            attached.broadcast(&plain, &items, &[Mode::Tmux.escape(&line)]);
+        }
+    }));
+}
+
+/// Runs the body of a section's thread, and if it panics, shows
+/// `admiral crashed: <reason>` in each of its `slots` as an urgent item,
+/// escaped for the markup the section is escaped for.
+pub fn contain<F: FnOnce()>(slots: Vec<(usize, Escape)>, sender: Sender<Update>, run: F) {
+    if panic::catch_unwind(AssertUnwindSafe(run)).is_ok() {
+        return;
+    }
+    let reason = REASON.with(|last| last.borrow_mut().take()).unwrap_or_else(|| String::from("explicit panic"));
+    let line = format!("admiral crashed: {}", reason);
+    for (position, escape) in slots {
+        let _ = sender.send(Update { position, message: escape.apply(&line), urgent: true, ..Update::default() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    use Escape;
+    use super::{contain, REASON};
+
+    #[test]
+    fn crashed_sections_say_why_in_their_slots() {
+        let (sender, updates) = channel();
+        thread::spawn(move || contain(vec![(3, Escape::Lemonbar), (5, Escape::None)], sender, || {
+            // As the panic hook records it
+            REASON.with(|last| *last.borrow_mut() = Some(String::from("100% broken at src/main.rs:1")));
+            panic!("100% broken");
+        })).join().unwrap();
+        let updates = updates.iter().map(|update| (update.position, update.message, update.urgent)).collect::<Vec<_>>();
+        assert_eq!(updates, [
+            (3, String::from("admiral crashed: 100%% broken at src/main.rs:1"), true),
+            (5, String::from("admiral crashed: 100% broken at src/main.rs:1"), true),
+        ]);
+    }
+}
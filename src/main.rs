@@ -1,143 +1,1604 @@
 extern crate toml;
 extern crate clap;
+extern crate rustc_serialize;
+extern crate libc;
+#[cfg(feature = "wayland")]
+extern crate fontdue;
+#[cfg(feature = "wayland")]
+extern crate smithay_client_toolkit;
+#[cfg(feature = "nvml")]
+extern crate nvml_wrapper;
+#[cfg(feature = "imap")]
+extern crate native_tls;
+
+mod control;
+mod crash;
+mod output;
+mod rotate;
+mod schedule;
+mod scroll;
+mod signals;
+mod sink;
+#[cfg(feature = "wayland")]
+mod wayland;
+mod widgets;
+mod x11;
 
 use std::process::{Command, exit, Stdio};
+use std::os::unix::process::CommandExt;
 use std::io::{stderr, Write, Read, BufRead, BufReader};
-use std::sync::mpsc::{channel, Sender};
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::fs::File;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::thread::{self, sleep};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+use std::cell::RefCell;
+use std::rc::Rc;
 use std::env;
 use std::ffi::OsStr;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use toml::Value;
+use clap::{App, AppSettings, Arg, SubCommand};
+use rustc_serialize::json::Json;
+
+use control::{Status, Statuses};
+use rotate::Rotation;
+use schedule::ActiveHours;
+use scroll::Scroll;
+use sink::Sink;
+
+/// Cargo features this binary was built with.
+const FEATURES: &[&str] = &[
+    #[cfg(feature = "wayland")]
+    "wayland",
+    #[cfg(feature = "nvml")]
+    "nvml",
+    #[cfg(feature = "imap")]
+    "imap",
+];
+
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Update {
+    position: usize,
+    message: String,
+    /// Whether the section should stand out, as a battery running low should.
+    urgent: bool,
+    /// A shorter message, for bars without room for the whole one.
+    short_message: Option<String>,
+    /// The icon shown before the message.
+    icon: Option<String>,
+    /// How things stand, for a widget that says.
+    state: Option<State>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone)]
+struct Padding {
+    min_width: usize,
+    align: Align,
+    fill: char,
+}
+
+impl Padding {
+    fn apply(&self, message: &str) -> String {
+        let width = message.chars().count();
+        if width >= self.min_width {
+            return message.to_owned();
+        }
+
+        let missing = self.min_width - width;
+        let (left, right) = match self.align {
+            Align::Left => (0, missing),
+            Align::Right => (missing, 0),
+            Align::Center => (missing / 2, missing - missing / 2),
+        };
+
+        let mut padded = String::with_capacity(message.len() + missing);
+        padded.extend(std::iter::repeat_n(self.fill, left));
+        padded.push_str(message);
+        padded.extend(std::iter::repeat_n(self.fill, right));
+        padded
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Multiline {
+    First,
+    Last,
+    Join,
+}
+
+impl Multiline {
+    fn collapse(&self, output: &str, delimiter: &str, trim: Trim) -> String {
+        let mut lines = output.split('\n')
+            .map(|line| trim.apply(line))
+            .filter(|line| !line.is_empty());
+
+        match *self {
+            Multiline::First => lines.next().unwrap_or("").to_owned(),
+            Multiline::Last => lines.next_back().unwrap_or("").to_owned(),
+            Multiline::Join => lines.collect::<Vec<_>>().join(delimiter),
+        }
+    }
+}
+
+/// What is removed from the start and end of each line of output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Trim {
+    /// Only `\r` and `\n` characters.
+    Newlines,
+    /// All whitespace.
+    Both,
+    /// Whitespace at the end, keeping any indentation at the start.
+    End,
+    /// Nothing but the `\n` or `\r\n` line terminator.
+    None,
+}
+
+impl Trim {
+    fn apply<'a>(&self, line: &'a str) -> &'a str {
+        match *self {
+            Trim::Newlines => line.trim_matches(&['\r', '\n'] as &[_]),
+            Trim::Both => line.trim(),
+            Trim::End => line.trim_end(),
+            Trim::None => line.strip_suffix('\r').unwrap_or(line),
+        }
+    }
+}
+
+/// Markup that script output is escaped for, so it is displayed literally.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Pango,
+    Lemonbar,
+    Dzen2,
+}
+
+impl Escape {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Escape, String> {
+        match configuration.get("escape").map(|v| v.as_str()) {
+            Some(Some("none")) | None => Ok(Escape::None),
+            Some(Some("pango")) => Ok(Escape::Pango),
+            Some(Some("lemonbar")) => Ok(Escape::Lemonbar),
+            Some(Some("dzen2")) => Ok(Escape::Dzen2),
+            Some(_) => Err(format!("Invalid escape found for {}: expected \"pango\", \"lemonbar\", \"dzen2\" or \"none\"", section_name)),
+        }
+    }
+
+    fn apply(&self, message: &str) -> String {
+        match *self {
+            Escape::None => message.to_owned(),
+            Escape::Pango => {
+                let mut escaped = String::with_capacity(message.len());
+                for c in message.chars() {
+                    match c {
+                        '&' => escaped.push_str("&amp;"),
+                        '<' => escaped.push_str("&lt;"),
+                        '>' => escaped.push_str("&gt;"),
+                        '\'' => escaped.push_str("&#39;"),
+                        '"' => escaped.push_str("&quot;"),
+                        c => escaped.push(c),
+                    }
+                }
+                escaped
+            },
+            // lemonbar prints the character following a % literally
+            Escape::Lemonbar => message.replace('%', "%%"),
+            // As does dzen2 with a ^
+            Escape::Dzen2 => message.replace('^', "^^"),
+        }
+    }
+}
+
+/// What happens to terminal escape sequences and other control characters in
+/// script output, which could otherwise break up the line a bar reads.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Sanitize {
+    /// Escape sequences are removed along with other control characters.
+    Strip,
+    /// Control characters are shown as `\x1b` and so on.
+    Escape,
+    None,
+}
+
+impl Sanitize {
+    fn apply(&self, message: &str) -> String {
+        let mut sanitized = String::with_capacity(message.len());
+        let mut chars = message.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (*self, c) {
+                (Sanitize::None, c) => sanitized.push(c),
+                // Tabs are harmless, but bars don't agree on how wide they are
+                (_, '\t') => sanitized.push(' '),
+                (Sanitize::Escape, c) if c.is_control() => sanitized.push_str(&format!("\\x{:02x}", c as u32)),
+                (Sanitize::Strip, '\x1b') => match chars.next() {
+                    // A control sequence, such as a colour, ends with a character from @ to ~
+                    Some('[') => {
+                        let _ = chars.by_ref().find(|c| ('\x40'..='\x7e').contains(c));
+                    },
+                    // An operating system command, such as a window title, ends with BEL or ESC \
+                    Some(']') => {
+                        while let Some(c) = chars.next() {
+                            if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                                break;
+                            }
+                        }
+                    },
+                    _ => {},
+                },
+                (Sanitize::Strip, '\u{9b}') => {
+                    let _ = chars.by_ref().find(|c| ('\x40'..='\x7e').contains(c));
+                },
+                (Sanitize::Strip, c) if c.is_control() => {},
+                (_, c) => sanitized.push(c),
+            }
+        }
+        sanitized
+    }
+}
+
+/// The number that `output` starts with, ignoring anything after it, such as a unit.
+fn leading_number(output: &str) -> Option<f64> {
+    let output = output.trim();
+    let end = output.char_indices()
+        .take_while(|&(index, c)| c.is_ascii_digit() || c == '.' || (index == 0 && (c == '-' || c == '+')))
+        .last()
+        .map_or(0, |(index, c)| index + c.len_utf8());
+    output[..end].parse().ok()
+}
+
+/// How a section's output is drawn.
+#[derive(Debug, Clone)]
+enum Render {
+    /// As the output itself.
+    Text,
+    Sparkline(Sparkline),
+    Gauge(Gauge),
+}
+
+impl Render {
+    /// Returns `None` for output that a sparkline or gauge can't draw, as it isn't a number.
+    fn apply(&self, message: String) -> Option<String> {
+        match *self {
+            Render::Text => Some(message),
+            Render::Sparkline(ref sparkline) => Some(sparkline.push(leading_number(&message)?)),
+            Render::Gauge(ref gauge) => Some(gauge.draw(leading_number(&message)?)),
+        }
+    }
+}
+
+fn range(section_name: &str, configuration: &toml::Table) -> Result<Option<(f64, f64)>, String> {
+    let number = |value: &Value| value.as_float().or(value.as_integer().map(|number| number as f64));
+    match configuration.get("range").map(|v| v.as_slice()) {
+        Some(Some([low, high])) => match (number(low), number(high)) {
+            (Some(low), Some(high)) if low < high => Ok(Some((low, high))),
+            _ => Err(format!("Invalid range found for {}: expected the lowest and highest values, such as [0, 100]", section_name)),
+        },
+        Some(_) => Err(format!("Invalid range found for {}: expected the lowest and highest values, such as [0, 100]", section_name)),
+        None => Ok(None),
+    }
+}
+
+/// When a section's output is urgent, such as a battery's that is nearly empty.
+#[derive(Debug, Clone, Default)]
+struct Urgency {
+    /// Output starting with a number at or above this is urgent.
+    above: Option<f64>,
+    /// Output starting with a number at or below this is urgent.
+    below: Option<f64>,
+    /// Whether the output of a failed script, or its fallback, is urgent.
+    on_failure: bool,
+    /// Output starting with this is urgent, and shown without it.
+    prefix: Option<String>,
+}
+
+impl Urgency {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Urgency, String> {
+        let threshold = |key: &str| match configuration.get(key) {
+            Some(&Value::Integer(threshold)) => Ok(Some(threshold as f64)),
+            Some(&Value::Float(threshold)) => Ok(Some(threshold)),
+            Some(_) => Err(format!("Invalid {} found for {}: expected a number", key, section_name)),
+            None => Ok(None),
+        };
+        let on_failure = match configuration.get("urgent_on_failure") {
+            Some(&Value::Boolean(on_failure)) => on_failure,
+            Some(_) => return Err(format!("Invalid urgent_on_failure found for {}: expected true or false", section_name)),
+            None => false,
+        };
+        let prefix = match configuration.get("urgent_prefix").map(Value::as_str) {
+            Some(Some(prefix)) if !prefix.is_empty() => Some(prefix.to_owned()),
+            Some(_) => return Err(format!("Invalid urgent_prefix found for {}: expected the text that urgent output starts with", section_name)),
+            None => None,
+        };
+
+        Ok(Urgency { above: threshold("urgent_above")?, below: threshold("urgent_below")?, on_failure, prefix })
+    }
+
+    /// Whether `message` is urgent, removing the prefix that says so if it has one.
+    fn check(&self, message: &mut String, failed: bool) -> bool {
+        let prefixed = match self.prefix {
+            Some(ref prefix) if message.starts_with(prefix.as_str()) => {
+                message.drain(..prefix.len());
+                true
+            },
+            _ => false,
+        };
+        let value = leading_number(message);
+        prefixed
+            || (failed && self.on_failure)
+            || value.is_some_and(|value| self.above.is_some_and(|above| value >= above) || self.below.is_some_and(|below| value <= below))
+    }
+}
+
+/// The icons a section is shown with, picked by its output's number if there
+/// are several, as for a battery's charge.
+#[derive(Debug, Clone)]
+struct Icons {
+    icons: Vec<String>,
+    /// The values spread across the icons, the first icon showing the lowest.
+    range: (f64, f64),
+}
+
+impl Icons {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Option<Icons>, String> {
+        let icons = match configuration.get("icon") {
+            Some(Value::String(icon)) => vec![icon.clone()],
+            Some(Value::Array(icons)) if !icons.is_empty() => icons.iter()
+                .map(|icon| icon.as_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("Invalid icon found for {}: expected an icon, or an array of them", section_name))?,
+            Some(_) => return Err(format!("Invalid icon found for {}: expected an icon, or an array of them", section_name)),
+            None => return Ok(None),
+        };
+        Ok(Some(Icons { icons, range: range(section_name, configuration)?.unwrap_or((0f64, 100f64)) }))
+    }
+
+    /// The icon for `output`, or `None` if there are several and it isn't a number.
+    fn pick(&self, output: &str) -> Option<&str> {
+        if let [ref icon] = self.icons[..] {
+            return Some(icon);
+        }
+        let (low, high) = self.range;
+        let fraction = ((leading_number(output)? - low) / (high - low)).clamp(0f64, 1f64);
+        let index = ((fraction * self.icons.len() as f64) as usize).min(self.icons.len() - 1);
+        Some(&self.icons[index])
+    }
+}
+
+/// A line of block characters, drawn from a section's recent numeric output.
+#[derive(Debug, Clone)]
+struct Sparkline {
+    /// How many outputs are kept and drawn.
+    length: usize,
+    /// The values drawn as the lowest and highest blocks, or else the lowest
+    /// and highest of those kept.
+    range: Option<(f64, f64)>,
+    /// Shared with the section's next format when it's reloaded, so the history is kept.
+    values: Rc<RefCell<VecDeque<f64>>>,
+}
+
+impl Sparkline {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+    /// Adds a value and draws the sparkline.
+    fn push(&self, value: f64) -> String {
+        let mut values = self.values.borrow_mut();
+        while values.len() >= self.length {
+            values.pop_front();
+        }
+        values.push_back(value);
+
+        let (low, high) = self.range.unwrap_or_else(|| values.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), &value| (low.min(value), high.max(value))));
+        let top = (Sparkline::BLOCKS.len() - 1) as f64;
+        values.iter().map(|&value| {
+            let level = if high > low { ((value - low) / (high - low) * top).round().clamp(0f64, top) } else { 0f64 };
+            Sparkline::BLOCKS[level as usize]
+        }).collect()
+    }
+}
+
+/// A gauge of a fixed width, filled in as far as a section's numeric output
+/// is along its range.
+#[derive(Debug, Clone)]
+struct Gauge {
+    width: usize,
+    range: (f64, f64),
+    fill: String,
+    empty: String,
+}
+
+impl Gauge {
+    fn draw(&self, value: f64) -> String {
+        let (low, high) = self.range;
+        let filled = (((value - low) / (high - low)).clamp(0f64, 1f64) * self.width as f64).round() as usize;
+        let mut gauge = self.fill.repeat(filled);
+        gauge.push_str(&self.empty.repeat(self.width - filled));
+        gauge
+    }
+}
+
+/// Returns a random number in `[0, 1)`. Each `RandomState` is seeded
+/// differently, which is plenty for spreading out reloads.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+fn if_readable(path: PathBuf) -> Option<PathBuf> { if path.exists() { Some(path) } else { None } }
+
+fn get_config_file() -> Option<PathBuf> {
+    let xdg_path = env::var("XDG_CONFIG_HOME").ok()
+        .map(|v| PathBuf::from(v).join("admiral.d").join("admiral.toml"))
+        .and_then(if_readable);
+
+    let dot_home = env::var("HOME").ok()
+        .map(|v| PathBuf::from(v).join(".config").join("admiral.d").join("admiral.toml"))
+        .and_then(if_readable);
+
+    xdg_path.or(dot_home)
+}
+
+/// The result of running a script once.
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    Success(String),
+    /// The script failed to spawn, exited non-zero, or timed out. Holds
+    /// whatever output it managed to produce, if any.
+    Failure(Option<String>),
+}
+
+/// How things stand for a widget that says, such as whether a CI run passed,
+/// which its item is coloured by with `success_color`, `failure_color` and
+/// `pending_color`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Success,
+    Failure,
+    Pending,
+}
+
+#[derive(Debug, Clone)]
+struct Script {
+    command: String,
+    shell: String,
+    duration: Option<u64>,
+    /// How far each wait may stray from `duration`, as a fraction of it.
+    jitter: f64,
+    timeout: Option<u64>,
+    is_static: bool,
+}
+
+impl Script {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Script, String> {
+        let command = match configuration.get("path") {
+            Some(value) => {
+                let value = value.to_owned();
+                match value {
+                    toml::Value::Array(_) => {
+                        return Err(format!("Invalid path found for {}: arrays are deprecated - use a string instead", section_name));
+                    },
+
+                    toml::Value::String(string) => {
+                        string
+                    },
+
+                    _ => {
+                        return Err(format!("Invalid path found for {}", section_name));
+                    },
+                }
+            },
+            None => {
+                return Err(format!("No path found for {}", section_name));
+            },
+        };
+
+        let is_static: bool = configuration.get("static").and_then(Value::as_bool).unwrap_or(false);
+
+        let duration: Option<u64> = match configuration.get("reload") {
+            Some(value) => {
+                let value = value.to_owned();
+                match value {
+                    toml::Value::Float(float) => {
+                        Some((float * 1000f64) as u64)
+                    }
+                    toml::Value::Integer(int) => {
+                        Some((int as f64 * 1000f64) as u64)
+                    },
+                    _ => None,
+                }
+            },
+            None => None
+        };
+
+        let shell = match configuration.get("shell") {
+            Some(value) => {
+                let value = value.to_owned();
+                match value {
+                    toml::Value::String(string) => {
+                        string
+                    },
+                    _ => {
+                        return Err(format!("Invalid shell found for {}", section_name));
+                    }
+                }
+            },
+            None => {
+                match env::var("SHELL").ok() {
+                    Some(sh) => {
+                        sh
+                    },
+                    None => {
+                        return Err(String::from("Could not find your system's shell. Make sure the $SHELL variable is set."));
+                    }
+                }
+            }
+        };
+
+        let timeout: Option<u64> = match configuration.get("timeout") {
+            Some(&toml::Value::Float(float)) if float > 0f64 => Some((float * 1000f64) as u64),
+            Some(&toml::Value::Integer(int)) if int > 0 => Some((int as f64 * 1000f64) as u64),
+            Some(_) => {
+                return Err(format!("Invalid timeout found for {}", section_name));
+            },
+            None => None,
+        };
+
+        let jitter: f64 = match configuration.get("jitter") {
+            Some(&toml::Value::Float(float)) if (0f64..=100f64).contains(&float) => float / 100f64,
+            Some(&toml::Value::Integer(int)) if (0..=100).contains(&int) => int as f64 / 100f64,
+            Some(_) => {
+                return Err(format!("Invalid jitter found for {}: expected a percentage between 0 and 100", section_name));
+            },
+            None => 0f64,
+        };
+
+        Ok(Script { command, shell, duration, jitter, timeout, is_static })
+    }
+
+    /// The time to wait before the next run, offset randomly by up to `jitter`.
+    fn next_wait(&self) -> Option<Duration> {
+        let duration = self.duration.filter(|_| !self.is_static)? as f64;
+        let offset = (random_fraction() * 2f64 - 1f64) * self.jitter;
+        Some(Duration::from_millis((duration * (1f64 + offset)) as u64))
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::new(OsStr::new(&self.shell));
+        command.args(["-c", &self.command]);
+        command
+    }
+
+    /// Runs the script to completion, killing it if it outlives its timeout.
+    /// Returns its exit code too, if it exited.
+    ///
+    /// The script runs in a process group of its own, which is killed once
+    /// the shell is done with, so that nothing it left running in the
+    /// background can hold its output open.
+    fn run(&self) -> (Outcome, Option<i32>) {
+        let mut child = match self.command().stdout(Stdio::piped()).process_group(0).spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                let _ = stderr().write(format!("Failed to run {}: {}\n", self.command, e).as_bytes());
+                return (Outcome::Failure(None), None);
+            },
+        };
+        let group = child.id() as libc::pid_t;
+        let kill_group = || unsafe { libc::kill(-group, libc::SIGKILL); };
+
+        let mut stdout = child.stdout.take().unwrap();
+        let reader = thread::spawn(move || {
+            let mut buffer = Vec::new();
+            let _ = stdout.read_to_end(&mut buffer);
+            buffer
+        });
+
+        let status = match self.timeout {
+            Some(timeout) => {
+                let start = Instant::now();
+                loop {
+                    match child.try_wait() {
+                        Ok(Some(status)) => break Some(status),
+                        Ok(None) if start.elapsed() < Duration::from_millis(timeout) => sleep(Duration::from_millis(10)),
+                        _ => {
+                            kill_group();
+                            let _ = child.wait();
+                            let _ = reader.join();
+                            let _ = stderr().write(format!("Timed out running {}\n", self.command).as_bytes());
+                            return (Outcome::Failure(None), None);
+                        },
+                    }
+                }
+            },
+            None => child.wait().ok(),
+        };
+        kill_group();
+
+        let output = String::from_utf8_lossy(&reader.join().unwrap_or_default()).into_owned();
+        let code = status.and_then(|status| status.code());
+        match status {
+            Some(status) if status.success() => (Outcome::Success(output), code),
+            _ => (Outcome::Failure(Some(output)), code),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Format {
+    padding: Padding,
+    multiline: Multiline,
+    delimiter: String,
+    fallback: Option<String>,
+    escape: Escape,
+    /// Whether the output is scrolled, and so escaped a frame at a time by its scroller instead.
+    scrolled: bool,
+    trim: Trim,
+    sanitize: Sanitize,
+    render: Render,
+    urgency: Urgency,
+    /// What's shown when the bar is short of room, with `{output}` replaced by the output.
+    short_format: Option<String>,
+    /// How many characters of the output are kept in `short_format`.
+    short_width: Option<usize>,
+    icons: Option<Icons>,
+}
+
+impl Format {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Format, String> {
+        let min_width = match configuration.get("min_width") {
+            Some(&toml::Value::Integer(int)) if int >= 0 => int as usize,
+            Some(_) => {
+                return Err(format!("Invalid min_width found for {}", section_name));
+            },
+            None => 0,
+        };
+
+        let align = match configuration.get("align").map(|v| v.as_str()) {
+            Some(Some("left")) | None => Align::Left,
+            Some(Some("right")) => Align::Right,
+            Some(Some("center")) => Align::Center,
+            Some(_) => {
+                return Err(format!("Invalid align found for {}: expected \"left\", \"right\" or \"center\"", section_name));
+            },
+        };
+
+        let fill = match configuration.get("pad").map(|v| v.as_str()) {
+            Some(Some(string)) if string.chars().count() == 1 => string.chars().next().unwrap(),
+            Some(_) => {
+                return Err(format!("Invalid pad found for {}: expected a single character", section_name));
+            },
+            None => ' ',
+        };
+
+        let padding = Padding { min_width, align, fill };
+
+        let multiline = match configuration.get("multiline").map(|v| v.as_str()) {
+            Some(Some("last")) | None => Multiline::Last,
+            Some(Some("first")) => Multiline::First,
+            Some(Some("join")) => Multiline::Join,
+            Some(_) => {
+                return Err(format!("Invalid multiline found for {}: expected \"first\", \"last\" or \"join\"", section_name));
+            },
+        };
+
+        let delimiter = match configuration.get("delimiter").map(|v| v.as_str()) {
+            Some(Some(string)) => string.to_owned(),
+            Some(None) => {
+                return Err(format!("Invalid delimiter found for {}", section_name));
+            },
+            None => String::from(" "),
+        };
+
+        let fallback = match configuration.get("fallback").map(|v| v.as_str()) {
+            Some(Some(string)) => Some(string.to_owned()),
+            Some(None) => {
+                return Err(format!("Invalid fallback found for {}", section_name));
+            },
+            None => None,
+        };
+
+        let escape = Escape::from_config(section_name, configuration)?;
+        let scrolled = configuration.contains_key("scroll");
+
+        let trim = match configuration.get("trim").map(|v| v.as_str()) {
+            Some(Some("newlines")) | None => Trim::Newlines,
+            Some(Some("both")) => Trim::Both,
+            Some(Some("end")) => Trim::End,
+            Some(Some("none")) => Trim::None,
+            Some(_) => {
+                return Err(format!("Invalid trim found for {}: expected \"both\", \"end\", \"newlines\" or \"none\"", section_name));
+            },
+        };
+
+        let sanitize = match configuration.get("sanitize").map(|v| v.as_str()) {
+            Some(Some("strip")) | None => Sanitize::Strip,
+            Some(Some("escape")) => Sanitize::Escape,
+            Some(Some("none")) => Sanitize::None,
+            Some(_) => {
+                return Err(format!("Invalid sanitize found for {}: expected \"strip\", \"escape\" or \"none\"", section_name));
+            },
+        };
+
+        let render = match configuration.get("render").map(|v| v.as_str()) {
+            Some(Some("text")) | None => Render::Text,
+            Some(Some("sparkline")) => {
+                let length = match configuration.get("length") {
+                    Some(&toml::Value::Integer(length)) if length > 0 => length as usize,
+                    Some(_) => {
+                        return Err(format!("Invalid length found for {}: expected a number of outputs above 0", section_name));
+                    },
+                    None => 10,
+                };
+                Render::Sparkline(Sparkline { length, range: range(section_name, configuration)?, values: Rc::default() })
+            },
+            Some(Some("bar")) => {
+                let width = match configuration.get("width") {
+                    Some(&toml::Value::Integer(width)) if width > 0 => width as usize,
+                    Some(_) => {
+                        return Err(format!("Invalid width found for {}: expected a number of characters above 0", section_name));
+                    },
+                    None => 10,
+                };
+                let character = |key: &str, default: &str| match configuration.get(key).map(|v| v.as_str()) {
+                    Some(Some(string)) => Ok(string.to_owned()),
+                    Some(None) => Err(format!("Invalid {} found for {}", key, section_name)),
+                    None => Ok(default.to_owned()),
+                };
+                Render::Gauge(Gauge {
+                    width,
+                    range: range(section_name, configuration)?.unwrap_or((0f64, 100f64)),
+                    fill: character("bar_fill", "█")?,
+                    empty: character("bar_empty", "░")?,
+                })
+            },
+            Some(_) => {
+                return Err(format!("Invalid render found for {}: expected \"text\", \"sparkline\" or \"bar\"", section_name));
+            },
+        };
+
+        let short_format = match configuration.get("short_format").map(|v| v.as_str()) {
+            Some(Some(string)) => Some(string.to_owned()),
+            Some(None) => {
+                return Err(format!("Invalid short_format found for {}", section_name));
+            },
+            None => None,
+        };
+
+        let short_width = match configuration.get("short_width") {
+            Some(&toml::Value::Integer(int)) if int > 0 => Some(int as usize),
+            Some(_) => {
+                return Err(format!("Invalid short_width found for {}: expected a number of characters above 0", section_name));
+            },
+            None => None,
+        };
+
+        let urgency = Urgency::from_config(section_name, configuration)?;
+        let icons = Icons::from_config(section_name, configuration)?;
+        Ok(Format { padding, multiline, delimiter, fallback, escape, scrolled, trim, sanitize, render, urgency, short_format, short_width, icons })
+    }
+
+    /// Returns the update to show at `position` for an outcome, or `None` if the
+    /// currently displayed message should be kept.
+    fn render(&self, position: usize, outcome: &Outcome) -> Option<Update> {
+        match (outcome, &self.fallback) {
+            (Outcome::Success(output), _) => self.apply(position, output, false),
+            (Outcome::Failure(_), Some(fallback)) => Some(Update {
+                position,
+                message: self.padding.apply(fallback),
+                urgent: self.urgency.on_failure,
+                short_message: None,
+                icon: self.icon(fallback),
+                state: None,
+            }),
+            (Outcome::Failure(Some(output)), None) => self.apply(position, output, true),
+            (Outcome::Failure(None), None) => None,
+        }
+    }
+
+    /// Returns `None` if the output can't be drawn, as when a sparkline's isn't a number.
+    fn apply(&self, position: usize, output: &str, failed: bool) -> Option<Update> {
+        let mut message = self.sanitize.apply(&self.multiline.collapse(output, &self.delimiter, self.trim));
+        let urgent = self.urgency.check(&mut message, failed);
+        let icon = self.icon(&message);
+        let message = self.render.apply(message)?;
+
+        let short_message = match (&self.short_format, self.short_width) {
+            (None, None) => None,
+            (format, width) => {
+                let output = match width {
+                    Some(width) if message.chars().count() > width => message.chars().take(width.saturating_sub(1)).chain(Some('…')).collect(),
+                    _ => message.clone(),
+                };
+                let format = format.as_ref().map_or("{output}", String::as_str);
+                Some(self.escape.apply(&widgets::fill(format, &[("output", output)])))
+            },
+        };
+        // Padding comes first, so that escape sequences don't count towards the width
+        let message = self.padding.apply(&message);
+        let message = match self.scrolled {
+            true => message,
+            false => self.escape.apply(&message),
+        };
+        Some(Update { position, message, urgent, short_message, icon, state: None })
+    }
+
+    fn icon(&self, output: &str) -> Option<String> {
+        self.icons.as_ref().and_then(|icons| icons.pick(output)).map(|icon| self.escape.apply(icon))
+    }
+}
+
+/// A section that receives the output of a running script.
+#[derive(Debug)]
+struct Target {
+    section_name: String,
+    position: usize,
+}
+
+/// Messages that steer a running script from outside its thread.
+#[derive(Debug)]
+enum Event {
+    /// The script's configuration changed, so it should be restarted.
+    Reconfigure,
+    /// A line printed by the streaming process with the given generation.
+    Line(usize, String),
+    /// The streaming process with the given generation closed its output.
+    Closed(usize),
+    /// New output from the member of a rotating section, reported at its index.
+    Member(Update),
+    /// A rotating section should move on to its next member.
+    Rotate,
+    /// A widget has something new to show, so it should be polled straight away.
+    Wake,
+    /// The section should update now rather than on its usual schedule.
+    Refresh,
+    /// A widget should carry out the named action, such as starting a timer.
+    Action(String),
+    /// Updates are paused, stopping a running script's process too if this is `true`.
+    Pause(bool),
+    /// Updates carry on, catching up straight away.
+    Resume,
+}
+
+/// What the main thread is sent: the sections' output, and requests from the
+/// control socket about how the bar shows it.
+#[derive(Debug)]
+enum Message {
+    Update(Update),
+    /// The section at this position should be hidden if it's shown, or shown if it's hidden.
+    Toggle(usize),
+    /// The section at this position is drawn this way from now on, as its configuration changed.
+    Restyle(usize, Box<output::Item>),
+}
+
+/// Why `wait_for_events` stopped waiting.
+#[derive(Debug, Clone, PartialEq)]
+enum Woken {
+    /// The wait ran out, or a widget asked to be polled.
+    Elapsed,
+    Refresh,
+    Reconfigure,
+    Action(String),
+}
+
+/// Whether updates are paused with `admiral ctl pause`, so that no scripts are run.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+
+fn paused() -> bool {
+    PAUSED.load(Ordering::Relaxed)
+}
+
+/// The configuration file, shared between the script threads and the control socket.
+type SharedConfig = Arc<RwLock<toml::Table>>;
+
+/// Where a section's output comes from.
+enum Source {
+    Script(Script),
+    Widget(Box<dyn widgets::Widget>),
+}
+
+impl Source {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Source, String> {
+        match configuration.get("type").map(Value::as_str) {
+            None | Some(Some("command")) => Script::from_config(section_name, configuration).map(Source::Script),
+            Some(Some(type_name)) => match widgets::from_config(type_name, section_name, configuration) {
+                Some(widget) => widget.map(Source::Widget),
+                None => Err(format!("Unknown type {} found for {}", type_name, section_name)),
+            },
+            Some(None) => Err(format!("Invalid type found for {}", section_name)),
+        }
+    }
+}
+
+/// Waits for the given time, or forever if there is none, until something
+/// wakes the script. Returns `None` if nothing can reach it anymore.
+fn wait_for_events(events: &Receiver<Event>, wait: Option<Duration>) -> Option<Woken> {
+    let start = Instant::now();
+    loop {
+        let event = match wait {
+            Some(wait) => match events.recv_timeout(wait.saturating_sub(start.elapsed())) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => return Some(Woken::Elapsed),
+                Err(RecvTimeoutError::Disconnected) => return None,
+            },
+            None => events.recv().ok()?,
+        };
+
+        match event {
+            Event::Reconfigure => return Some(Woken::Reconfigure),
+            Event::Wake => return Some(Woken::Elapsed),
+            Event::Refresh | Event::Resume => return Some(Woken::Refresh),
+            Event::Action(action) => return Some(Woken::Action(action)),
+            _ => {},
+        }
+    }
+}
+
+/// Time left before `deadline`, if there is one.
+fn remaining(deadline: Option<Instant>) -> Option<Duration> {
+    deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()))
+}
+
+/// Polls a widget until it is reconfigured or `deadline` passes, returning
+/// `false` if nothing can reach it anymore.
+fn poll_widget(widget: &mut dyn widgets::Widget, events: (&Sender<Event>, &Receiver<Event>), send: &dyn Fn(Outcome, Option<State>), deadline: Option<Instant>) -> bool {
+    let (event_sender, events) = events;
+    widget.subscribe(event_sender.clone());
+    loop {
+        if paused() {
+            // Waits as usual, but isn't polled until updates are resumed
+        } else if let Some(outcome) = widget.poll() {
+            send(outcome, widget.state());
+        }
+        let wait = match remaining(deadline) {
+            Some(left) if left == Duration::from_secs(0) => return true,
+            Some(left) => left.min(widget.wait()),
+            None => widget.wait(),
+        };
+        match wait_for_events(events, Some(wait)) {
+            Some(Woken::Reconfigure) => return true,
+            Some(Woken::Refresh) => widget.refresh(),
+            Some(Woken::Action(action)) => widget.action(&action),
+            Some(Woken::Elapsed) => {},
+            None => return false,
+        }
+    }
+}
+
+/// A group's shared source, the format of each of its sections, and when it may run.
+type Loaded = (Source, Vec<(usize, Format)>, Option<ActiveHours>);
+
+fn load_targets(config: &toml::Table, targets: &[Target]) -> Result<Loaded, String> {
+    let mut sections = Vec::new();
+    for target in targets {
+        match config.get(&target.section_name).and_then(Value::as_table) {
+            Some(section) => sections.push((target, section)),
+            None => return Err(format!("Failed to find valid section for {}", target.section_name)),
+        }
+    }
+
+    let source = Source::from_config(&sections[0].0.section_name, sections[0].1)?;
+    let active_hours = schedule::for_section(config, &sections[0].0.section_name, sections[0].1)?;
+    let mut formats = Vec::new();
+    for (target, section) in sections {
+        schedule::separators(&target.section_name, section)?;
+        formats.push((target.position, Format::from_config(&target.section_name, section)?));
+    }
+
+    Ok((source, formats, active_hours))
+}
+
+fn execute_script(config_root: PathBuf, config: SharedConfig, targets: Vec<Target>, sender: Sender<Update>, events: (Sender<Event>, Receiver<Event>), statuses: Statuses) {
+    let _ = env::set_current_dir(&config_root);
+    let (event_sender, events) = events;
+    let mut generation: usize = 0;
+    let mut histories: HashMap<usize, Rc<RefCell<VecDeque<f64>>>> = HashMap::new();
+    // The configuration last loaded without errors, kept while a reloaded one has a mistake in it
+    let mut last_good: Option<toml::Table> = None;
+    let mut last_error = None;
+
+    loop {
+        let current = config.read().unwrap().clone();
+        let loaded = match load_targets(&current, &targets) {
+            Ok(loaded) => {
+                last_good = Some(current);
+                last_error = None;
+                Ok(loaded)
+            },
+            Err(e) => {
+                // Each run loads the configuration again, so the same mistake is only reported once
+                if last_error.as_ref() != Some(&e) {
+                    let _ = stderr().write(format!("{}\n", e).as_bytes());
+                    last_error = Some(e.clone());
+                }
+                match last_good {
+                    Some(ref previous) => load_targets(previous, &targets),
+                    None => Err(e),
+                }
+            },
+        };
+        let (source, mut formats, active_hours) = match loaded {
+            Ok(loaded) => loaded,
+            // Nothing to fall back on, so wait for the configuration to be fixed
+            Err(_) => {
+                if wait_for_events(&events, None).is_none() {
+                    return;
+                }
+                continue;
+            },
+        };
+        for &mut (position, ref mut format) in &mut formats {
+            if let Render::Sparkline(ref mut sparkline) = format.render {
+                sparkline.values = histories.entry(position).or_default().clone();
+            }
+        }
+
+        let send_with_state = |outcome: Outcome, state: Option<State>| {
+            record(&statuses, &targets, |status| {
+                status.updated = Some(SystemTime::now());
+                if let Outcome::Success(ref output) | Outcome::Failure(Some(ref output)) = outcome {
+                    status.output = Some(output.clone());
+                }
+            });
+            for &(position, ref format) in &formats {
+                if let Some(update) = format.render(position, &outcome) {
+                    let _ = sender.send(Update { state, ..update });
+                }
+            }
+        };
+        let send = |outcome: Outcome| send_with_state(outcome, None);
+
+        // Outside its active hours a section is hidden until the window opens again
+        if let Some(hours) = active_hours {
+            if !hours.is_active() {
+                for &(position, _) in &formats {
+                    let _ = sender.send(Update { position, ..Update::default() });
+                }
+                if wait_for_events(&events, Some(hours.until_change())).is_none() {
+                    return;
+                }
+                continue;
+            }
+        }
+        let deadline = active_hours.map(|hours| Instant::now() + hours.until_change());
+
+        let script = match source {
+            Source::Script(script) => script,
+            Source::Widget(mut widget) => {
+                if poll_widget(&mut *widget, (&event_sender, &events), &send_with_state, deadline) {
+                    continue;
+                }
+                return;
+            },
+        };
+
+        if script.is_static || script.duration.is_some() {
+            // Nothing runs until updates are resumed
+            if paused() {
+                if wait_for_events(&events, remaining(deadline)).is_none() {
+                    return;
+                }
+                continue;
+            }
+            let (outcome, code) = script.run();
+            record(&statuses, &targets, |status| status.exit_code = code);
+            send(outcome);
+
+            let wait = match (script.next_wait(), remaining(deadline)) {
+                (Some(wait), Some(left)) => Some(wait.min(left)),
+                (wait, left) => wait.or(left),
+            };
+            if wait_for_events(&events, wait).is_none() {
+                return;
+            }
+        } else {
+            let mut child = match script.command().stdout(Stdio::piped()).spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    let _ = stderr().write(format!("Failed to run {}: {}\n", script.command, e).as_bytes());
+                    send(Outcome::Failure(None));
+                    sleep(Duration::from_secs(1));
+                    continue;
+                },
+            };
+
+            generation += 1;
+            let reader = BufReader::new(child.stdout.take().unwrap());
+            let line_sender = event_sender.clone();
+            let line_generation = generation;
+            thread::spawn(move || {
+                for line in reader.lines().map_while(Result::ok) {
+                    let _ = line_sender.send(Event::Line(line_generation, line));
+                }
+                let _ = line_sender.send(Event::Closed(line_generation));
+            });
+
+            // The latest line printed while updates are paused, shown once they're resumed
+            let mut held = None;
+            loop {
+                let event = match remaining(deadline) {
+                    Some(left) => match events.recv_timeout(left) {
+                        Ok(event) => event,
+                        Err(RecvTimeoutError::Timeout) => Event::Reconfigure,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    },
+                    None => match events.recv() {
+                        Ok(event) => event,
+                        Err(_) => return,
+                    },
+                };
+                match event {
+                    Event::Line(line_generation, line) if line_generation == generation => match paused() {
+                        true => held = Some(line),
+                        false => send(Outcome::Success(line)),
+                    },
+                    Event::Pause(true) => unsafe {
+                        libc::kill(child.id() as libc::pid_t, libc::SIGSTOP);
+                    },
+                    Event::Resume => {
+                        unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGCONT); }
+                        if let Some(line) = held.take() {
+                            send(Outcome::Success(line));
+                        }
+                    },
+                    Event::Closed(line_generation) if line_generation == generation => {
+                        let status = child.wait();
+                        record(&statuses, &targets, |recorded| recorded.exit_code = status.as_ref().ok().and_then(|status| status.code()));
+                        match status {
+                            Ok(ref status) if status.success() => {},
+                            _ => send(Outcome::Failure(None)),
+                        }
+                        sleep(Duration::from_millis(10));
+                        break;
+                    },
+                    Event::Refresh if paused() => {},
+                    // Refreshing a script that only updates when it prints starts it again
+                    Event::Reconfigure | Event::Refresh => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        break;
+                    },
+                    // Leftovers from a process that has already been replaced
+                    _ => {},
+                }
+            }
+        }
+    }
+}
+
+/// Updates what `admiral ctl status` reports for each of the sections run by a thread.
+fn record(statuses: &Statuses, targets: &[Target], update: impl Fn(&mut Status)) {
+    let mut statuses = statuses.lock().unwrap();
+    for target in targets {
+        update(statuses.entry(target.section_name.clone()).or_default());
+    }
+}
+
+/// How long `--once` waits for a script that keeps running, or a widget, to have something to show.
+const ONCE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Runs a section's source a single time, returning what it first has to
+/// show: a script's output, the first line of one that keeps running, or a
+/// widget's first poll with something in it, along with the widget's state
+/// after it. Returns `None` if nothing arrives in time.
+fn run_once(source: Source) -> Option<(Outcome, Option<State>)> {
+    match source {
+        Source::Script(script) if script.is_static || script.duration.is_some() => Some((script.run().0, None)),
+        Source::Script(script) => {
+            let mut child = match script.command().stdout(Stdio::piped()).spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    let _ = stderr().write(format!("Failed to run {}: {}\n", script.command, e).as_bytes());
+                    return Some((Outcome::Failure(None), None));
+                },
+            };
+            let reader = BufReader::new(child.stdout.take().unwrap());
+            let (first_line, receiver) = channel();
+            thread::spawn(move || {
+                let _ = first_line.send(reader.lines().next());
+            });
+            let first_line = receiver.recv_timeout(ONCE_TIMEOUT);
+            let _ = child.kill();
+            let _ = child.wait();
+            match first_line {
+                Ok(Some(Ok(line))) => Some((Outcome::Success(line), None)),
+                Ok(_) => Some((Outcome::Failure(None), None)),
+                Err(_) => None,
+            }
+        },
+        Source::Widget(mut widget) => {
+            let (wake, events) = channel::<Event>();
+            widget.subscribe(wake);
+            let deadline = Instant::now() + ONCE_TIMEOUT;
+            loop {
+                if let Some(outcome) = widget.poll() {
+                    return Some((outcome, widget.state()));
+                }
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left == Duration::from_secs(0) {
+                    return None;
+                }
+                // A widget that doesn't keep the sender can't be woken early
+                if wait_for_events(&events, Some(widget.wait().min(left))).is_none() {
+                    sleep(widget.wait().min(left));
+                }
+            }
+        },
+    }
+}
+
+/// Runs a single section once for `admiral run`, printing what it printed,
+/// what the bar would show, and how long it took. Returns the exit code.
+fn run_section(config_toml: &mut toml::Table, section_name: &str) -> i32 {
+    if let Err(e) = instantiate_template(config_toml, section_name) {
+        let _ = stderr().write(format!("{}\n", e).as_bytes());
+        return 1;
+    }
+    let section = match config_toml.get(section_name).and_then(Value::as_table) {
+        Some(section) => section,
+        None => {
+            let _ = stderr().write(format!("No {} found\n", section_name).as_bytes());
+            return 1;
+        },
+    };
+    if section.get("type").and_then(Value::as_str) == Some("rotate") {
+        let _ = stderr().write(format!("{} is a rotate section, so run one of its members instead\n", section_name).as_bytes());
+        return 1;
+    }
+    let loaded = Source::from_config(section_name, section)
+        .and_then(|source| Format::from_config(section_name, section).map(|format| (source, format)));
+    let (source, format) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            let _ = stderr().write(format!("{}\n", e).as_bytes());
+            return 1;
+        },
+    };
+
+    let start = Instant::now();
+    let (outcome, state) = match run_once(source) {
+        Some((outcome, state)) => (Some(outcome), state),
+        None => (None, None),
+    };
+    let elapsed = start.elapsed();
+    let succeeded = matches!(outcome, Some(Outcome::Success(_)));
+
+    let (result, output) = match outcome {
+        Some(Outcome::Success(ref output)) => ("succeeded", Some(output)),
+        Some(Outcome::Failure(ref output)) => ("failed", output.as_ref()),
+        None => ("showed nothing in time", None),
+    };
+    println!("{}", section_name);
+    println!("  result:  {}", result);
+    println!("  output:  {}", output.map_or(String::from("(none)"), |output| format!("{:?}", output)));
+    match outcome.and_then(|outcome| format.render(0, &outcome)) {
+        Some(update) => {
+            println!("  shown:   {:?}", update.message);
+            if let Some(short_message) = update.short_message {
+                println!("  short:   {:?}", short_message);
+            }
+            if let Some(icon) = update.icon {
+                println!("  icon:    {:?}", icon);
+            }
+            if update.urgent {
+                println!("  urgent:  yes");
+            }
+            if let Some(state) = state {
+                println!("  state:   {}", format!("{:?}", state).to_lowercase());
+            }
+        },
+        None => println!("  shown:   (nothing, keeping what was shown before)"),
+    }
+    println!("  took:    {:.3}s", elapsed.as_secs_f64());
+    if succeeded { 0 } else { 1 }
+}
 
-use toml::Value;
-use clap::{App, Arg};
+/// The updates a group of sections shows once its source has run once, for `--once`.
+fn updates_once(config: &SharedConfig, targets: &[Target]) -> Vec<Update> {
+    let (source, formats, active_hours) = match load_targets(&config.read().unwrap(), targets) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            let _ = stderr().write(format!("{}\n", e).as_bytes());
+            return Vec::new();
+        },
+    };
+    if active_hours.is_some_and(|hours| !hours.is_active()) {
+        return Vec::new();
+    }
+    match run_once(source) {
+        Some((outcome, state)) => formats.iter()
+            .filter_map(|&(position, ref format)| format.render(position, &outcome))
+            .map(|update| Update { state, ..update })
+            .collect(),
+        None => Vec::new(),
+    }
+}
 
-#[derive(Debug)]
-struct Update {
-    position: usize,
-    message: String,
+/// The entries that decide what process a section runs.
+const SCRIPT_KEYS: &[&str] = &["path", "shell", "reload", "jitter", "timeout", "static", "active_hours"];
+
+/// Identifies sections that would run exactly the same process, so that
+/// deduplicated sections can share one.
+fn script_key(script: &Value) -> Option<String> {
+    let table = script.as_table()?;
+    // Native sections keep their own state, so they aren't shared
+    if table.get("type").is_some_and(|t| t.as_str() != Some("command")) {
+        return None;
+    }
+
+    let key = SCRIPT_KEYS.iter()
+        .map(|key| format!("{:?}", table.get(*key)))
+        .collect::<Vec<_>>()
+        .join("\0");
+    Some(key)
 }
 
-fn if_readable(path: PathBuf) -> Option<PathBuf> { if path.exists() { Some(path) } else { None } }
+/// Reads the configuration file, with any persisted overrides laid over it
+/// and its `[vars]` filled in.
+fn read_config(config_file: &Path, overrides_file: &Path) -> Result<toml::Table, String> {
+    let mut buffer = String::new();
+    File::open(config_file)
+        .and_then(|mut file| file.read_to_string(&mut buffer))
+        .map_err(|e| format!("Could not read configuration file: {}", e))?;
 
-fn get_config_file() -> Option<PathBuf> {
-    let xdg_path = env::var("XDG_CONFIG_HOME").ok()
-        .map(|v| PathBuf::from(v).join("admiral.d").join("admiral.toml"))
-        .and_then(if_readable);
+    let mut config_toml = toml::Parser::new(&buffer).parse()
+        .ok_or_else(|| String::from("Syntax error in configuration file"))?;
+    merge_tables(&mut config_toml, &control::read_overrides(overrides_file)?);
+    expand_vars(&mut config_toml)?;
+    Ok(config_toml)
+}
 
-    let dot_home = env::var("HOME").ok()
-        .map(|v| PathBuf::from(v).join(".config").join("admiral.d").join("admiral.toml"))
-        .and_then(if_readable);
+/// Lays `overlay` over `base`, merging tables that appear in both.
+fn merge_tables(base: &mut toml::Table, overlay: &toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(key), value) {
+            (Some(&mut Value::Table(ref mut base)), Value::Table(overlay)) => merge_tables(base, overlay),
+            _ => { base.insert(key.to_owned(), value.to_owned()); },
+        }
+    }
+}
 
-    xdg_path.or(dot_home)
+fn substitute(value: &Value, placeholder: &str, parameter: &str) -> Value {
+    match *value {
+        Value::String(ref string) => Value::String(string.replace(placeholder, parameter)),
+        Value::Array(ref array) => Value::Array(array.iter().map(|v| substitute(v, placeholder, parameter)).collect()),
+        Value::Table(ref table) => Value::Table(table.iter().map(|(k, v)| (k.to_owned(), substitute(v, placeholder, parameter))).collect()),
+        ref other => other.to_owned(),
+    }
 }
 
-fn execute_script(section_name: &str, config_root: PathBuf, configuration: Option<&toml::Table>, position: usize, sender: Sender<Update>,) {
-    let _ = env::set_current_dir(&config_root);
-    let configuration = configuration.expect(&format!("Failed to find valid section for {}", section_name));
-    let command = match configuration.get("path") {
-        Some(value) => {
-            let value = value.to_owned();
-            match value {
-                toml::Value::Array(_) => {
-                    let _ = stderr().write(format!("Invalid path found for {}: arrays are deprecated - use a string instead\n", section_name).as_bytes());
-
-                    panic!();
-                },
+/// Replaces every `{{name}}` in the configuration with the matching entry of `[vars]`.
+fn expand_vars(config_toml: &mut toml::Table) -> Result<(), String> {
+    let vars = match config_toml.get("vars") {
+        Some(Value::Table(vars)) => vars.to_owned(),
+        Some(_) => return Err(String::from("Invalid vars section: expected a table")),
+        None => return Ok(()),
+    };
 
-                toml::Value::String(string) => {
-                    string
-                },
+    for (name, value) in &vars {
+        let text = match *value {
+            Value::String(ref string) => string.to_owned(),
+            Value::Integer(_) | Value::Float(_) | Value::Boolean(_) => value.to_string(),
+            _ => return Err(format!("Invalid value found for vars.{}: expected a string or number", name)),
+        };
 
-                _ => {
-                    let _ = stderr().write(format!("Invalid path found for {}\n", section_name).as_bytes());
-                    panic!();
-                },
+        let placeholder = format!("{{{{{}}}}}", name);
+        for (section_name, section) in config_toml.iter_mut() {
+            if section_name != "vars" {
+                *section = substitute(section, &placeholder, &text);
             }
-        },
-        None => {
-            let _ = stderr().write(format!("No path found for {}\n", section_name).as_bytes());
-            panic!();
-        },
+        }
+    }
+
+    Ok(())
+}
+
+/// Creates a section for an item of the form `template:parameter`, by copying
+/// the template section and replacing its placeholder with the parameter.
+/// The placeholder is named by the template's `parameter` entry, so
+/// `parameter = "mount"` makes `disk:/home` replace `{mount}` with `/home`.
+fn instantiate_template(config_toml: &mut toml::Table, item: &str) -> Result<(), String> {
+    if config_toml.contains_key(item) {
+        return Ok(());
+    }
+
+    let (template_name, parameter) = match item.split_once(':') {
+        Some(split) => split,
+        None => return Ok(()),
     };
 
-    let is_static: bool = match configuration.get("static").and_then(Value::as_bool) {
-        Some(value) => value,
-        None => false,
+    // A missing template is reported like any other missing section
+    let template = match config_toml.get(template_name).and_then(Value::as_table) {
+        Some(template) => template,
+        None => return Ok(()),
     };
 
-    let duration: Option<u64> = match configuration.get("reload") {
-        Some(value) => {
-            let value = value.to_owned();
-            match value {
-                toml::Value::Float(float) => {
-                    Some((float * 1000f64) as u64)
-                }
-                toml::Value::Integer(int) => {
-                    Some((int as f64 * 1000f64) as u64)
-                },
-                _ => None,
-            }
-        },
-        None => None
+    let placeholder = match template.get("parameter").map(Value::as_str) {
+        Some(Some(name)) => format!("{{{}}}", name),
+        Some(None) => return Err(format!("Invalid parameter found for {}", template_name)),
+        None => return Err(format!("No parameter found for {}, which is used as a template by {}", template_name, item)),
     };
 
-    let shell = match configuration.get("shell") {
-        Some(value) => {
-            let value = value.to_owned();
-            match value {
-                toml::Value::String(string) => {
-                    string
-                },
-                _ => {
-                    let _ = stderr().write(format!("Invalid shell found for {}\n", section_name).as_bytes());
-                    panic!()
-                }
-            }
+    let instance = substitute(&Value::Table(template.to_owned()), &placeholder, parameter);
+    config_toml.insert(item.to_owned(), instance);
+    Ok(())
+}
+
+fn get_hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"].iter()
+        .filter_map(|path| {
+            let mut buffer = String::new();
+            File::open(path).and_then(|mut file| file.read_to_string(&mut buffer)).ok()?;
+            Some(buffer.trim().to_owned())
+        })
+        .find(|hostname| !hostname.is_empty())
+}
+
+/// Returns the `[admiral]` table with the selected profile's entries laid over it.
+/// Without an explicit profile, one named after the hostname is used if present.
+fn get_admiral_config(config_toml: &toml::Table, profile: Option<&str>) -> toml::Table {
+    let mut admiral_config = config_toml.get("admiral").and_then(Value::as_table).cloned().unwrap_or_default();
+    let profiles = config_toml.get("profiles").and_then(Value::as_table);
+
+    let selected = match profile {
+        Some(name) => match profiles.and_then(|p| p.get(name)).and_then(Value::as_table) {
+            Some(table) => Some(table),
+            None => {
+                let _ = stderr().write(format!("No profile named {} found\n", name).as_bytes());
+                exit(1);
+            },
         },
-        None => {
-            match env::var("SHELL").ok() {
-                Some(sh) => {
-                    sh
-                },
-                None => {
-                    let _ = stderr().write("Could not find your system's shell. Make sure the $SHELL variable is set.\n".as_bytes());
-                    panic!()
-                }
-            }
+        None => get_hostname().and_then(|hostname| profiles.and_then(|p| p.get(&hostname)).and_then(Value::as_table)),
+    };
+
+    if let Some(table) = selected {
+        for (key, value) in table {
+            admiral_config.insert(key.to_owned(), value.to_owned());
+        }
+    }
+
+    admiral_config
+}
+
+/// One of the bars being shown: its items, picked out of every bar's by
+/// their positions, and where its lines go.
+struct Bar {
+    positions: Vec<usize>,
+    items: Vec<output::Item>,
+    messages: Vec<String>,
+    line: String,
+    /// Whether a message has changed since the line was last composed.
+    changed: bool,
+    sinks: Vec<(output::Mode, Sink)>,
+}
+
+impl Bar {
+    /// Picks up a section's new message, if the section is on this bar.
+    fn update(&mut self, update: &Update) {
+        for ((shown, item), _) in self.messages.iter_mut().zip(&mut self.items).zip(&self.positions).filter(|&(_, &at)| at == update.position) {
+            update.message.clone_into(shown);
+            item.set_urgent(update.urgent);
+            item.set_short_message(update.short_message.clone());
+            item.set_icon(update.icon.clone());
+            item.set_state(update.state);
+            self.changed = true;
         }
+    }
+
+    /// Draws a section as `item` from now on, if the section is on this bar.
+    fn restyle(&mut self, position: usize, item: &output::Item) {
+        for (shown, _) in self.items.iter_mut().zip(&self.positions).filter(|&(_, &at)| at == position) {
+            *shown = item.clone();
+            self.changed = true;
+        }
+    }
+
+    /// Composes the bar's next line into `compose_buffer`, swapping it in if
+    /// the messages have changed since. Returns whether the line changed.
+    fn redraw(&mut self, compose_buffer: &mut String) -> bool {
+        if !std::mem::take(&mut self.changed) {
+            return false;
+        }
+        output::compose(&self.items, &self.messages, compose_buffer);
+        if self.line == *compose_buffer {
+            return false;
+        }
+        std::mem::swap(&mut self.line, compose_buffer);
+        true
+    }
+}
+
+/// Reads the items of each bar, `[admiral]`'s own unless there are `[bars]`,
+/// naming each bar's table for messages about it.
+fn bar_tables(config_toml: &toml::Table, admiral_config: &toml::Table) -> Result<Vec<(String, toml::Table, Vec<String>)>, String> {
+    let tables = match config_toml.get("bars") {
+        Some(Value::Table(bars)) if !bars.is_empty() => bars.iter().map(|(name, table)| match table.as_table() {
+            Some(table) => Ok((format!("bars.{}", name), table.clone())),
+            None => Err(format!("Invalid bar found in [bars]: expected a table, as in [bars.{}]", name)),
+        }).collect::<Result<Vec<_>, _>>()?,
+        Some(_) => return Err(String::from("Invalid bars found: expected a table for each bar, as in [bars.top]")),
+        None => vec![(String::from("admiral"), admiral_config.clone())],
     };
+    tables.into_iter().map(|(name, table)| {
+        let items = match table.get("items").and_then(Value::as_slice) {
+            Some(items) => items.iter().map(|item| item.as_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(|| format!("Invalid items found in [{}]: expected the names of sections", name))?,
+            None => return Err(format!("Failed to find items in [{}]", name)),
+        };
+        Ok((name, table, items))
+    }).collect()
+}
 
-    let shell = OsStr::new(&shell);
+/// Reads the least time between printed lines from `max_fps`, or none without it.
+fn min_interval(admiral_config: &toml::Table) -> Result<Duration, String> {
+    match admiral_config.get("max_fps") {
+        Some(&Value::Integer(fps)) if fps > 0 => Ok(Duration::from_secs_f64(1f64 / fps as f64)),
+        Some(&Value::Float(fps)) if fps > 0f64 => Ok(Duration::from_secs_f64(1f64 / fps)),
+        Some(_) => Err(String::from("Invalid max_fps found in [admiral]: expected a number of lines a second, above 0")),
+        None => Ok(Duration::from_secs(0)),
+    }
+}
 
-    let arguments = &["-c", &command];
+/// Reads what each of these sections' output is escaped for, by the positions
+/// they report to, so that a crash can be shown in their place.
+fn escapes<'a>(config_toml: &toml::Table, sections: impl IntoIterator<Item = (usize, &'a str)>) -> Vec<(usize, Escape)> {
+    sections.into_iter()
+        .map(|(position, section_name)| {
+            let section = config_toml.get(section_name).and_then(Value::as_table).cloned().unwrap_or_default();
+            (position, Escape::from_config(section_name, &section).unwrap_or(Escape::None))
+        })
+        .collect()
+}
 
-    if is_static {
-        let output = Command::new(&shell).args(arguments).output().expect(&format!("Failed to run {}", &command));
-        let _ = sender.send(Update { position: position, message: String::from_utf8_lossy(&output.stdout).trim_matches(&['\r', '\n'] as &[_]).to_owned(), });
-    } else {
-        match duration {
-            Some(time) => {
-                loop {
-                    let output = Command::new(&shell).args(arguments).output().expect(&format!("Failed to run {}", &command));
-                    let _ = sender.send(Update { position: position, message: String::from_utf8_lossy(&output.stdout).trim_matches(&['\r', '\n'] as &[_]).to_owned(), });
-                    sleep(Duration::from_millis(time));
-                }
-            },
-            None => {
-                loop {
-                    let output = Command::new(&shell).args(arguments).stdout(Stdio::piped()).spawn().expect(&format!("Failed to run {}", &command));
-                    let reader = BufReader::new(output.stdout.unwrap());
-                    for line in reader.lines().flat_map(Result::ok) {
-                        let _ = sender.send(Update { position: position, message: line.trim_matches(&['\r', '\n'] as &[_]).to_owned(), });
-                    }
-                    sleep(Duration::from_millis(10));
-                }
+/// Reads which of these sections scroll, by the positions they report to,
+/// exiting if one says so wrongly.
+fn scrolls<'a>(config_toml: &toml::Table, sections: impl IntoIterator<Item = (usize, &'a str)>) -> Vec<(usize, Scroll)> {
+    let mut scrolls = Vec::new();
+    for (position, section_name) in sections {
+        let section = config_toml.get(section_name).and_then(Value::as_table).cloned().unwrap_or_default();
+        match Scroll::from_config(section_name, &section) {
+            Ok(Some(scroll)) => scrolls.push((position, scroll)),
+            Ok(None) => {},
+            Err(e) => {
+                let _ = stderr().write(format!("{}\n", e).as_bytes());
+                exit(1);
             },
         }
     }
+    scrolls
+}
+
+/// Reads how an item is drawn, with colours named in `[theme]`, exiting if its section says so wrongly.
+fn bar_item(section_name: &str, section: &Value, theme: &toml::Table) -> output::Item {
+    let section = section.as_table().cloned().unwrap_or_default();
+    output::Item::from_config(section_name, &section, theme).unwrap_or_else(|e| {
+        let _ = stderr().write(format!("{}\n", e).as_bytes());
+        exit(1)
+    })
+}
+
+fn version_json() -> Json {
+    let list = |values: &[&str]| Json::Array(values.iter().map(|v| Json::String(v.to_string())).collect());
+
+    let mut object = BTreeMap::new();
+    object.insert(String::from("name"), Json::String(String::from(env!("CARGO_PKG_NAME"))));
+    object.insert(String::from("version"), Json::String(String::from(env!("CARGO_PKG_VERSION"))));
+    object.insert(String::from("features"), list(FEATURES));
+    object.insert(String::from("output_formats"), list(output::NAMES));
+    object.insert(String::from("output_modes"), list(output::MODES));
+    object.insert(String::from("modules"), list(&widgets::REGISTRY.iter().map(|&(name, _)| name).collect::<Vec<_>>()));
+    Json::Object(object)
 }
 
 fn main() {
@@ -147,8 +1608,107 @@ fn main() {
              .short("c")
              .long("config-file")
              .takes_value(true))
+        .arg(Arg::with_name("profile")
+             .help("Select a profile from the configuration file (defaults to the hostname)")
+             .short("p")
+             .long("profile")
+             .takes_value(true))
+        .arg(Arg::with_name("version")
+             .help("Prints version information")
+             .short("V")
+             .long("version"))
+        .arg(Arg::with_name("json")
+             .help("Prints version information as JSON")
+             .long("json")
+             .requires("version"))
+        .arg(Arg::with_name("daemon")
+             .help("Runs without printing, for clients started with the attach subcommand")
+             .short("d")
+             .long("daemon"))
+        .arg(Arg::with_name("preview")
+             .help("Shows the bar in the terminal, in colour, instead of printing each line")
+             .long("preview")
+             .conflicts_with_all(&["daemon", "wayland"]))
+        .arg(Arg::with_name("bar")
+             .help("Selects which of the bars in [bars] to preview, draw, or send to attached clients (defaults to the first)")
+             .short("b")
+             .long("bar")
+             .takes_value(true))
+        .arg(Arg::with_name("no-buffer")
+             .help("Prints every line as soon as it changes, even faster than max_fps")
+             .short("u")
+             .long("no-buffer"))
+        .arg(Arg::with_name("once")
+             .help("Runs every section once, prints the bar, and exits")
+             .long("once")
+             .conflicts_with_all(&["preview", "wayland", "daemon"]))
+        .arg(Arg::with_name("wayland")
+             .help("Draws the bar itself as a Wayland layer-shell surface instead of printing it")
+             .short("w")
+             .long("wayland"))
+        .subcommand(SubCommand::with_name("attach")
+             .about("Prints the output of the running instance")
+             .arg(Arg::with_name("format")
+                  .help("The format to print lines in")
+                  .short("f")
+                  .long("format")
+                  .takes_value(true)
+                  .possible_values(output::NAMES)
+                  .default_value("plain")))
+        .subcommand(SubCommand::with_name("run")
+             .about("Runs a single section once, showing its output and how long it took")
+             .arg(Arg::with_name("section")
+                  .help("The section to run")
+                  .required(true)))
+        .subcommand(SubCommand::with_name("ctl")
+             .about("Sends a command to the running instance")
+             .visible_alias("msg")
+             .setting(AppSettings::TrailingVarArg)
+             .arg(Arg::with_name("command")
+                  .allow_hyphen_values(true)
+                  .help("The command to send, e.g. set-config clock.reload 30")
+                  .required(true)
+                  .multiple(true)))
         .get_matches();
 
+    if let Some(attach) = matches.subcommand_matches("attach") {
+        if let Err(e) = control::attach(attach.value_of("format").unwrap()) {
+            let _ = stderr().write(format!("Lost connection to admiral: {}\n", e).as_bytes());
+            exit(1);
+        }
+        exit(0);
+    }
+
+    if let Some(ctl) = matches.subcommand_matches("ctl") {
+        let command = ctl.values_of("command").unwrap().collect::<Vec<_>>().join(" ");
+        match control::send(&command) {
+            Ok(ref reply) if reply.starts_with("error") => {
+                let _ = stderr().write(reply.as_bytes());
+                exit(1);
+            },
+            Ok(reply) => print!("{}", reply),
+            Err(e) => {
+                let _ = stderr().write(format!("Could not connect to admiral: {}\n", e).as_bytes());
+                exit(1);
+            },
+        }
+        exit(0);
+    }
+
+    if matches.is_present("version") {
+        if matches.is_present("json") {
+            println!("{}", version_json());
+        } else {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+        }
+        exit(0);
+    }
+
+    let attached = Arc::new(Mutex::new(output::Attached::default()));
+    // Drawing the bar natively replaces printing it
+    let daemon = matches.is_present("daemon") || matches.is_present("wayland");
+    crash::install(attached.clone(), !daemon);
+
     let config_file = match matches.value_of("config") {
         Some(file) => PathBuf::from(file),
         None => {
@@ -169,43 +1729,129 @@ fn main() {
 
     let config_root = PathBuf::from(&config_file.parent().unwrap());
 
-    let mut buffer = String::new();
-    if let Ok(mut file) = File::open(&config_file) {
-        file.read_to_string(&mut buffer).expect("Could not read configuration file");
+    let overrides_file = control::overrides_file(&config_file);
+    let mut config_toml = read_config(&config_file, &overrides_file).unwrap_or_else(|e| {
+        let _ = stderr().write(format!("{}\n", e).as_bytes());
+        exit(1)
+    });
+
+    if let Some(run) = matches.subcommand_matches("run") {
+        // Scripts run from the configuration's directory, as they do on the bar
+        let _ = env::set_current_dir(&config_root);
+        exit(run_section(&mut config_toml, run.value_of("section").unwrap()));
     }
 
-    let config_toml = match toml::Parser::new(&buffer).parse() {
-        Some(val) => val,
-        None => {
-            let _ = stderr().write("Syntax error in configuration file.\n".as_bytes());
-            panic!();
+    let admiral_config = get_admiral_config(&config_toml, matches.value_of("profile"));
+    let bar_tables = bar_tables(&config_toml, &admiral_config).unwrap_or_else(|e| {
+        let _ = stderr().write(format!("{}\n", e).as_bytes());
+        exit(1)
+    });
+    // Sections shown on more than one bar are only run once
+    let mut items: Vec<&str> = Vec::new();
+    for item in bar_tables.iter().flat_map(|(_, _, items)| items) {
+        if !items.contains(&item.as_str()) {
+            items.push(item);
+        }
+    }
+    // The bar that's previewed, drawn on Wayland, and sent to attached clients
+    let shown = match matches.value_of("bar") {
+        Some(name) => bar_tables.iter().position(|(table, _, _)| *table == format!("bars.{}", name)).unwrap_or_else(|| {
+            let _ = stderr().write(format!("No bar named {} found in [bars]\n", name).as_bytes());
+            exit(1)
+        }),
+        None => 0,
+    };
+
+    for item in &items {
+        if let Err(e) = instantiate_template(&mut config_toml, item) {
+            let _ = stderr().write(format!("{}\n", e).as_bytes());
+        }
+    }
+
+    let (sender, updates) = channel::<Update>();
+    let (messages, receiver) = channel::<Message>();
+    // The sections' output is passed on to the main thread with everything else it's sent
+    let forward = messages.clone();
+    let _ = thread::Builder::new().name(String::from("updates")).spawn(move || {
+        for update in updates.iter() {
+            let _ = forward.send(Message::Update(update));
         }
+    });
+    let mut runners: HashMap<String, Vec<Sender<Event>>> = HashMap::new();
+    // One for each thread running sections, whichever sections it runs
+    let mut threads: Vec<Sender<Event>> = Vec::new();
+
+    let mut message_vec: Vec<Update> = Vec::new();
+
+    #[cfg(feature = "wayland")]
+    let bar = match matches.is_present("wayland") {
+        true => {
+            let settings = admiral_config.get("wayland").and_then(Value::as_table).cloned().unwrap_or_default();
+            Some(wayland::open(&settings).unwrap_or_else(|e| {
+                let _ = stderr().write(format!("{}\n", e).as_bytes());
+                exit(1)
+            }))
+        },
+        false => None,
     };
+    #[cfg(not(feature = "wayland"))]
+    if matches.is_present("wayland") {
+        let _ = stderr().write("admiral was built without Wayland support; rebuild it with --features wayland\n".as_bytes());
+        exit(1);
+    }
 
-    let admiral_config = config_toml.get("admiral").unwrap();
-    let items = admiral_config.as_table().unwrap().get("items").unwrap().as_slice().unwrap().iter().map(|x| x.as_str().unwrap()).collect::<Vec<_>>();
+    let deduplicate = admiral_config.get("deduplicate").and_then(Value::as_bool).unwrap_or(false);
+    let min_interval = match min_interval(&admiral_config) {
+        _ if matches.is_present("no-buffer") => Duration::from_secs(0),
+        Ok(min_interval) => min_interval,
+        Err(e) => {
+            let _ = stderr().write(format!("{}\n", e).as_bytes());
+            exit(1);
+        },
+    };
 
-    let (sender, receiver) = channel::<Update>();
+    let mut bar_items: Vec<output::Item> = Vec::new();
+    let mut item_positions: HashMap<&str, usize> = HashMap::new();
+    let theme = config_toml.get("theme").and_then(Value::as_table).cloned().unwrap_or_default();
 
-    let mut message_vec: Vec<String> = Vec::new();
-    let mut print_message = String::new();
+    let mut groups: Vec<(Option<String>, Vec<Target>)> = Vec::new();
+    let mut rotations: Vec<(Target, Rotation)> = Vec::new();
 
     let mut position: usize = 0;
     for value in items {
         match config_toml.get(value) {
+            Some(section) if section.lookup("type").and_then(Value::as_str) == Some("rotate") => {
+                let rotation = Rotation::from_config(value, section.as_table().unwrap()).unwrap_or_else(|e| {
+                    let _ = stderr().write(format!("{}\n", e).as_bytes());
+                    exit(1)
+                });
+                if let Some(missing) = rotation.members.iter().find(|member| config_toml.get(member.as_str()).and_then(Value::as_table).is_none()) {
+                    let _ = stderr().write(format!("No {} found\n", missing).as_bytes());
+                    exit(1);
+                }
+                rotations.push((Target { section_name: value.to_owned(), position, }, rotation));
+
+                item_positions.insert(value, position);
+                message_vec.push(Update { position, ..Update::default() });
+                position += 1;
+                bar_items.push(bar_item(value, section, &theme));
+            },
             Some(script) => {
-                // Annoying stuff because of how ownership works with closures
-                let script = script.to_owned();
-                let value = value.to_owned();
-                let config_root = config_root.clone();
-                let clone = sender.clone();
+                let key = if deduplicate { script_key(script) } else { None };
+                let target = Target {
+                    section_name: value.to_owned(),
+                    position,
+                };
 
-                let _ = thread::spawn(move || {
-                    execute_script(&value, config_root, script.as_table(), position, clone);
-                });
+                match groups.iter_mut().find(|group| key.is_some() && group.0 == key) {
+                    Some(group) => group.1.push(target),
+                    None => groups.push((key, vec![target])),
+                }
 
+                item_positions.insert(value, position);
+                message_vec.push(Update { position, ..Update::default() });
                 position += 1;
-                message_vec.push(String::new());
+                bar_items.push(bar_item(value, script, &theme));
             },
             None => {
                 let _ = stderr().write(format!("No {} found\n", value).as_bytes());
@@ -214,13 +1860,566 @@ fn main() {
         }
     }
 
-    for line in receiver.iter() {
-        let position = line.position;
-        message_vec[position] = line.message;
-        if print_message != message_vec.iter().cloned().collect::<String>() {
-            print_message = message_vec.iter().cloned().collect::<String>();
+    let mut bars = Vec::new();
+    for (index, (name, table, items)) in bar_tables.iter().enumerate() {
+        let sinks = match (matches.is_present("preview"), matches.is_present("once")) {
+            (true, _) if index == shown => vec![(output::Mode::Ansi, Sink::Preview)],
+            (false, true) if index == shown => {
+                let mode = sink::printed_mode(table, name).unwrap_or_else(|e| {
+                    let _ = stderr().write(format!("{}\n", e).as_bytes());
+                    exit(1)
+                });
+                vec![(mode, Sink::Stdout("\n"))]
+            },
+            (true, _) | (false, true) => Vec::new(),
+            (false, false) => sink::from_config(table, name, daemon).unwrap_or_else(|e| {
+                let _ = stderr().write(format!("{}\n", e).as_bytes());
+                exit(1)
+            }),
+        };
+        let positions = items.iter().filter_map(|item| item_positions.get(item.as_str()).cloned()).collect::<Vec<_>>();
+        bars.push(Bar {
+            items: positions.iter().map(|&position| bar_items[position].clone()).collect(),
+            messages: vec![String::new(); positions.len()],
+            positions,
+            line: String::new(),
+            changed: false,
+            sinks,
+        });
+    }
+    // Two bars printing at once would garble each other's lines
+    if bars.iter().flat_map(|bar| &bar.sinks).filter(|(_, sink)| matches!(*sink, Sink::Stdout(_))).count() > 1 {
+        let _ = stderr().write("Failed to read [bars]: only one bar can be printed, the rest need a fifo, socket, file, or bar to run\n".as_bytes());
+        exit(1);
+    }
+
+    let config_toml: SharedConfig = Arc::new(RwLock::new(config_toml));
+
+    if matches.is_present("once") {
+        // Every section runs at the same time, rather than one after another
+        let updates = thread::scope(|scope| {
+            let config = &config_toml;
+            let groups = groups.iter().map(|(_, targets)| scope.spawn(move || updates_once(config, targets))).collect::<Vec<_>>();
+            // A rotating section shows the first of its members with something to show
+            let rotations = rotations.iter().map(|(slot, rotation)| scope.spawn(move || {
+                let members = rotation.members.iter().map(|member| vec![Target { section_name: member.clone(), position: 0 }]);
+                members.flat_map(|targets| updates_once(config, &targets))
+                    .find(|update| !update.message.is_empty())
+                    .map(|update| Update { position: slot.position, ..update })
+            })).collect::<Vec<_>>();
+
+            let mut updates = groups.into_iter().flat_map(|group| group.join().unwrap_or_default()).collect::<Vec<_>>();
+            updates.extend(rotations.into_iter().filter_map(|rotation| rotation.join().ok().flatten()));
+            updates
+        });
+
+        let bar = &mut bars[shown];
+        for update in &updates {
+            bar.update(update);
+        }
+        let mut compose_buffer = String::new();
+        bar.redraw(&mut compose_buffer);
+        for &mut (mode, ref mut sink) in &mut bar.sinks {
+            let _ = match mode {
+                output::Mode::Plain => sink.send(&bar.line),
+                // Without the comma that continues i3bar's endless array
+                output::Mode::I3bar => sink.send(mode.render(&bar.items, &bar.messages).trim_end_matches(',')),
+                mode => sink.send(&mode.render(&bar.items, &bar.messages)),
+            };
+        }
+        exit(0);
+    }
+
+    // Who each deduplicated section shares its process with
+    let mut shared: HashMap<String, Vec<String>> = HashMap::new();
+    for (_, targets) in groups.iter().filter(|(_, targets)| targets.len() > 1) {
+        for target in targets {
+            let others = targets.iter().filter(|other| other.position != target.position).map(|other| other.section_name.clone());
+            shared.insert(target.section_name.clone(), others.collect());
+        }
+    }
+
+    let statuses = Statuses::default();
+    for (_, targets) in groups {
+        let config_root = config_root.clone();
+        let config = config_toml.clone();
+        let statuses = statuses.clone();
+        let clone = scroll::scrolled(&sender, scrolls(&config.read().unwrap(), targets.iter().map(|target| (target.position, target.section_name.as_str()))));
+        let events = channel::<Event>();
+
+        for target in &targets {
+            runners.entry(target.section_name.clone()).or_default().push(events.0.clone());
+        }
+        threads.push(events.0.clone());
+
+        let name = targets.iter().map(|target| target.section_name.as_str()).collect::<Vec<_>>().join(",");
+        let slots = escapes(&config.read().unwrap(), targets.iter().map(|target| (target.position, target.section_name.as_str())));
+        let _ = thread::Builder::new().name(name).spawn(move || {
+            crash::contain(slots, clone.clone(), || execute_script(config_root, config, targets, clone, events, statuses));
+        });
+    }
+
+    // The threads of rotating sections, which stop rotating while updates are paused
+    let mut rotating: Vec<Sender<Event>> = Vec::new();
+    for (slot, rotation) in rotations {
+        let (rotation_events, events) = channel::<Event>();
+        runners.entry(slot.section_name.clone()).or_default().push(rotation_events.clone());
+        rotating.push(rotation_events.clone());
+
+        // Members report to the rotation rather than to the bar, and are refreshed through it
+        let (member_sender, member_receiver) = channel::<Update>();
+        let mut member_events = Vec::new();
+        let forward = rotation_events.clone();
+        let _ = thread::Builder::new().name(format!("{} rotation", slot.section_name)).spawn(move || {
+            for update in member_receiver.iter() {
+                let _ = forward.send(Event::Member(update));
+            }
+        });
+
+        for (index, member) in rotation.members.iter().enumerate() {
+            let config_root = config_root.clone();
+            let config = config_toml.clone();
+            let statuses = statuses.clone();
+            let clone = scroll::scrolled(&member_sender, scrolls(&config.read().unwrap(), vec![(index, member.as_str())]));
+            let events = channel::<Event>();
+            runners.entry(member.clone()).or_default().push(events.0.clone());
+            threads.push(events.0.clone());
+            member_events.push(events.0.clone());
+
+            let targets = vec![Target { section_name: member.clone(), position: index, }];
+            let slots = escapes(&config.read().unwrap(), vec![(index, member.as_str())]);
+            let _ = thread::Builder::new().name(member.clone()).spawn(move || {
+                crash::contain(slots, clone.clone(), || execute_script(config_root, config, targets, clone, events, statuses));
+            });
+        }
+
+        let clone = scroll::scrolled(&sender, scrolls(&config_toml.read().unwrap(), vec![(slot.position, slot.section_name.as_str())]));
+        let slots = escapes(&config_toml.read().unwrap(), vec![(slot.position, slot.section_name.as_str())]);
+        let _ = thread::Builder::new().name(slot.section_name.clone()).spawn(move || {
+            crash::contain(slots, clone.clone(), || rotation.run(slot.position, clone, events, member_events));
+        });
+    }
+
+    let mut signalled = Vec::new();
+    for (section_name, section_runners) in &runners {
+        let signal = match config_toml.read().unwrap().get(section_name).and_then(Value::as_table) {
+            Some(section) => signals::from_config(section_name, section),
+            None => Ok(None),
+        };
+        match signal {
+            Ok(Some(signal)) => signalled.push((signal, section_runners.clone())),
+            Ok(None) => {},
+            Err(e) => {
+                let _ = stderr().write(format!("{}\n", e).as_bytes());
+                exit(1);
+            },
+        }
+    }
+    signals::listen(threads.clone(), signalled);
+    control::listen(control::Controller {
+        config: config_toml,
+        runners,
+        threads,
+        rotations: rotating,
+        shared,
+        statuses,
+        positions: item_positions.iter().map(|(&name, &position)| (name.to_owned(), position)).collect(),
+        messages,
+        config_file,
+        overrides_file,
+        attached: attached.clone(),
+    });
+
+    // The next line is composed here and swapped with a bar's line once it is
+    // printed, so that neither buffer has to be reallocated for each update
+    let mut compose_buffer = String::new();
+
+    // Pipes and sockets send the header to each reader themselves
+    for &mut (mode, ref mut sink) in bars.iter_mut().flat_map(|bar| &mut bar.sinks) {
+        if let (Sink::Stdout(_), Some(header)) = (&*sink, mode.header()) {
+            let _ = sink.send(header);
+        }
+    }
+
+    // A crash is reported on every bar
+    crash::report_to(bars.iter().flat_map(|bar| &bar.sinks).filter_map(|&(mode, ref sink)| Some((mode, sink.try_clone()?))).collect());
+
+    // Updates arriving before the next line is due are shown together once it is
+    let mut next_print = Instant::now();
+    let mut pending = false;
+    // Sections hidden with `admiral ctl toggle`, which keep running
+    let mut hidden: HashSet<usize> = HashSet::new();
+    loop {
+        let message = match pending {
+            true => match receiver.recv_timeout(next_print.saturating_duration_since(Instant::now())) {
+                Ok(message) => Some(message),
+                Err(RecvTimeoutError::Timeout) => None,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            false => match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => break,
+            },
+        };
+        let position = match message {
+            Some(Message::Update(line)) => {
+                let position = line.position;
+                if message_vec[position] == line {
+                    continue;
+                }
+                message_vec[position] = line;
+                Some(position)
+            },
+            Some(Message::Toggle(position)) => {
+                if !hidden.remove(&position) {
+                    hidden.insert(position);
+                }
+                Some(position)
+            },
+            // Its urgency, short text, and icon are picked up again below
+            Some(Message::Restyle(position, item)) => {
+                for bar in &mut bars {
+                    bar.restyle(position, &item);
+                }
+                Some(position)
+            },
+            None => None,
+        };
+        if let Some(position) = position {
+            let line = match hidden.contains(&position) {
+                true => Update { position, ..Update::default() },
+                false => message_vec[position].clone(),
+            };
+            for bar in &mut bars {
+                bar.update(&line);
+            }
+            pending = true;
+            if Instant::now() < next_print {
+                continue;
+            }
+        }
+        pending = false;
+        next_print = Instant::now() + min_interval;
+
+        for (index, current) in bars.iter_mut().enumerate() {
+            if !current.redraw(&mut compose_buffer) {
+                continue;
+            }
             sleep(Duration::from_millis(5));
-            println!("{}", print_message);
+            for &mut (mode, ref mut sink) in &mut current.sinks {
+                let sent = match mode {
+                    output::Mode::Plain => sink.send(&current.line),
+                    mode => sink.send(&mode.render(&current.items, &current.messages)),
+                };
+                if let Err(e) = sent {
+                    let message = match *sink {
+                        // The name was refused, but the display is still there for the next one
+                        Sink::RootWindow(_) if e.kind() == std::io::ErrorKind::InvalidInput => {
+                            let _ = stderr().write(format!("Failed to set the root window's name: {}\n", e).as_bytes());
+                            continue;
+                        },
+                        Sink::RootWindow(_) => format!("Lost connection to the X display: {}\n", e),
+                        // The file is written whole each time, so the next line puts it right
+                        Sink::OutputFile(_) => {
+                            let _ = stderr().write(format!("Failed to write the output file {}\n", e).as_bytes());
+                            continue;
+                        },
+                        _ => format!("Failed to write the bar: {}\n", e),
+                    };
+                    let _ = stderr().write(message.as_bytes());
+                    exit(1);
+                }
+            }
+            if index != shown {
+                continue;
+            }
+            #[cfg(feature = "wayland")]
+            if let Some(ref bar) = bar {
+                bar.show(&current.line);
+            }
+            attached.lock().unwrap().broadcast(&current.line, &current.items, &current.messages);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+    use std::time::{Duration, Instant};
+
+    use toml::{self, Value};
+
+    use output;
+    use super::{bar_tables, expand_vars, get_admiral_config, instantiate_template, min_interval, run_section, script_key, updates_once, version_json, Align, Bar, Escape, Format, Multiline, Outcome, Padding, Sanitize, Script, Target, Trim, Update};
+
+    #[test]
+    fn padding_fills_to_the_minimum_width() {
+        let padding = |align| Padding { min_width: 5, align, fill: '.' };
+        assert_eq!(padding(Align::Left).apply("ab"), "ab...");
+        assert_eq!(padding(Align::Right).apply("ab"), "...ab");
+        assert_eq!(padding(Align::Center).apply("ab"), ".ab..");
+        assert_eq!(padding(Align::Right).apply("longer"), "longer");
+        // Width is counted in characters, not bytes
+        assert_eq!(padding(Align::Left).apply("héé"), "héé..");
+    }
+
+    #[test]
+    fn multiline_picks_non_empty_lines() {
+        let output = "\n first \n\n second\r\n last \n";
+        assert_eq!(Multiline::First.collapse(output, " | ", Trim::Both), "first");
+        assert_eq!(Multiline::Last.collapse(output, " | ", Trim::Both), "last");
+        assert_eq!(Multiline::Join.collapse(output, " | ", Trim::Both), "first | second | last");
+        assert_eq!(Multiline::First.collapse("", " | ", Trim::Both), "");
+    }
+
+    fn table(source: &str) -> toml::Table {
+        toml::Parser::new(source).parse().unwrap()
+    }
+
+    #[test]
+    fn identical_commands_share_a_key() {
+        let config = table("[a]\npath = \"date\"\nreload = 1\n[b]\npath = \"date\"\nreload = 1\nfg = \"#fff\"\n[c]\npath = \"date\"\nreload = 2\n[d]\ntype = \"clock\"\n");
+        let key = |name: &str| script_key(&config[name]);
+        assert!(key("a").is_some());
+        // Styles don't change what runs, but any of the script's own settings do
+        assert_eq!(key("a"), key("b"));
+        assert_ne!(key("a"), key("c"));
+        assert_eq!(key("d"), None);
+    }
+
+    fn format(source: &str) -> Format {
+        Format::from_config("section", &table(source)).unwrap()
+    }
+
+    #[test]
+    fn failures_show_the_fallback() {
+        let message = |format: &Format, outcome| format.render(0, &outcome).map(|update| update.message);
+        let with_fallback = format("fallback = \"n/a\"");
+        assert_eq!(message(&with_fallback, Outcome::Failure(None)), Some(String::from("n/a")));
+        assert_eq!(message(&with_fallback, Outcome::Failure(Some(String::from("error")))), Some(String::from("n/a")));
+        assert_eq!(message(&with_fallback, Outcome::Success(String::from("fine"))), Some(String::from("fine")));
+        // Without one, whatever the script printed is shown, if anything
+        let without = format("");
+        assert_eq!(message(&without, Outcome::Failure(Some(String::from("error")))), Some(String::from("error")));
+        assert_eq!(message(&without, Outcome::Failure(None)), None);
+    }
+
+    #[test]
+    fn version_json_lists_what_is_built_in() {
+        let version = version_json();
+        assert_eq!(version.find("name").and_then(|name| name.as_string()), Some("admiral"));
+        let modules = version.find("modules").and_then(|modules| modules.as_array()).unwrap();
+        assert!(modules.iter().any(|module| module.as_string() == Some("clock")));
+        assert!(version.find("output_modes").and_then(|modes| modes.as_array()).is_some());
+    }
+
+    #[test]
+    fn profiles_are_laid_over_the_admiral_table() {
+        let config = table("[admiral]\nitems = [\"clock\"]\noutput = \"plain\"\n[profiles.laptop]\nitems = [\"battery\", \"clock\"]\n");
+        let admiral = get_admiral_config(&config, Some("laptop"));
+        assert_eq!(admiral.get("items"), config["profiles"].lookup("laptop.items"));
+        assert_eq!(admiral.get("output").and_then(Value::as_str), Some("plain"));
+    }
+
+    #[test]
+    fn templates_are_filled_in_with_the_parameter() {
+        let mut config = table("[disk]\nparameter = \"mount\"\npath = \"df {mount}\"\nargs = [\"{mount}\"]\n[plain]\npath = \"date\"\n");
+        assert_eq!(instantiate_template(&mut config, "disk:/home"), Ok(()));
+        assert_eq!(config["disk:/home"].lookup("path").and_then(Value::as_str), Some("df /home"));
+        assert_eq!(config["disk:/home"].lookup("args.0").and_then(Value::as_str), Some("/home"));
+        // The template is left as it was
+        assert_eq!(config["disk"].lookup("path").and_then(Value::as_str), Some("df {mount}"));
+        assert!(instantiate_template(&mut config, "plain:x").is_err());
+        assert_eq!(instantiate_template(&mut config, "missing:x"), Ok(()));
+        assert!(!config.contains_key("missing:x"));
+    }
+
+    #[test]
+    fn vars_are_interpolated_everywhere_but_vars() {
+        let mut config = table("[vars]\naccent = \"#ff8800\"\ninterval = 5\n[clock]\nfg = \"{{accent}}\"\npath = \"sleep {{interval}}; date\"\nkeep = \"{{unknown}}\"\n");
+        assert_eq!(expand_vars(&mut config), Ok(()));
+        assert_eq!(config["clock"].lookup("fg").and_then(Value::as_str), Some("#ff8800"));
+        assert_eq!(config["clock"].lookup("path").and_then(Value::as_str), Some("sleep 5; date"));
+        assert_eq!(config["clock"].lookup("keep").and_then(Value::as_str), Some("{{unknown}}"));
+        assert!(expand_vars(&mut table("[vars]\nlist = [1]\n")).is_err());
+        assert!(expand_vars(&mut table("vars = 1\n")).is_err());
+    }
+
+    #[test]
+    fn escape_quotes_markup() {
+        assert_eq!(Escape::Pango.apply("<b>Tom & \"Jerry's\"</b>"), "&lt;b&gt;Tom &amp; &quot;Jerry&#39;s&quot;&lt;/b&gt;");
+        assert_eq!(Escape::Lemonbar.apply("100% %{F-}"), "100%% %%{F-}");
+        assert_eq!(Escape::Dzen2.apply("^fg(red)"), "^^fg(red)");
+        assert_eq!(Escape::None.apply("<%^>"), "<%^>");
+    }
+
+    #[test]
+    fn trim_removes_what_it_says() {
+        assert_eq!(Trim::Newlines.apply("\r  line  \n"), "  line  ");
+        assert_eq!(Trim::Both.apply("\t line \r"), "line");
+        assert_eq!(Trim::End.apply("  line \r"), "  line");
+        assert_eq!(Trim::None.apply("  line \r"), "  line ");
+    }
+
+    #[test]
+    fn jitter_stays_within_its_percentage() {
+        let jittery = Script::from_config("section", &table("path = \"date\"\nshell = \"sh\"\nreload = 10\njitter = 20\n")).unwrap();
+        for _ in 0..100 {
+            let wait = jittery.next_wait().unwrap();
+            assert!(wait >= Duration::from_secs(8) && wait <= Duration::from_secs(12), "{:?}", wait);
         }
+        assert!(Script::from_config("section", &table("path = \"date\"\nshell = \"sh\"\njitter = 150\n")).is_err());
+    }
+
+    #[test]
+    fn each_bar_shows_its_own_items() {
+        let config: toml::Table = toml::Parser::new("[bars.top]\nitems = [\"clock\", \"cpu\"]\n[bars.bottom]\nitems = [\"cpu\"]\n").parse().unwrap();
+        let tables = bar_tables(&config, &toml::Table::new()).unwrap();
+        let names = tables.iter().map(|(name, _, items)| (name.as_str(), items.clone())).collect::<Vec<_>>();
+        assert_eq!(names, [("bars.bottom", vec![String::from("cpu")]), ("bars.top", vec![String::from("clock"), String::from("cpu")])]);
+        let admiral: toml::Table = toml::Parser::new("items = [\"clock\"]").parse().unwrap();
+        assert_eq!(bar_tables(&toml::Table::new(), &admiral).unwrap()[0].0, "admiral");
+        assert!(bar_tables(&toml::Parser::new("[bars.top]\n").parse().unwrap(), &admiral).is_err());
+
+        let item = output::Item::from_config("clock", &toml::Table::new(), &toml::Table::new()).unwrap();
+        let mut bar = Bar { positions: vec![1], items: vec![item], messages: vec![String::new()], line: String::new(), changed: false, sinks: Vec::new() };
+        let update = |position: usize, message: &str| Update { position, message: message.to_owned(), urgent: false, short_message: None, icon: None, state: None };
+        let mut buffer = String::new();
+        bar.update(&update(0, "elsewhere"));
+        assert!(!bar.redraw(&mut buffer));
+        bar.update(&update(1, "12:00"));
+        assert!(bar.redraw(&mut buffer));
+        assert_eq!(bar.line, "12:00");
+        bar.update(&update(1, "12:00"));
+        assert!(!bar.redraw(&mut buffer));
+    }
+
+    #[test]
+    fn max_fps_spaces_out_lines() {
+        let parse = |source: &str| toml::Parser::new(source).parse().unwrap();
+        assert_eq!(min_interval(&parse("max_fps = 4")), Ok(Duration::from_millis(250)));
+        assert_eq!(min_interval(&parse("max_fps = 0.5")), Ok(Duration::from_secs(2)));
+        assert_eq!(min_interval(&toml::Table::new()), Ok(Duration::from_secs(0)));
+        assert!(min_interval(&parse("max_fps = 0")).is_err());
+        assert!(min_interval(&parse("max_fps = \"fast\"")).is_err());
+    }
+
+    #[test]
+    fn sparklines_draw_recent_numbers() {
+        let message = |format: &Format, output: &str| format.render(0, &Outcome::Success(output.to_owned())).map(|update| update.message);
+        let sparkline = format("render = \"sparkline\"\nlength = 3\nrange = [0, 7]");
+        assert_eq!(message(&sparkline, "0%"), Some(String::from("▁")));
+        assert_eq!(message(&sparkline, "7"), Some(String::from("▁█")));
+        assert_eq!(message(&sparkline, "n/a"), None);
+        assert_eq!(message(&sparkline, "3.5 GHz"), Some(String::from("▁█▅")));
+        assert_eq!(message(&sparkline, "12"), Some(String::from("█▅█")));
+
+        // Without a range, the lowest and highest values kept are the bottom and the top
+        let scaled = format("render = \"sparkline\"");
+        assert_eq!(message(&scaled, "10"), Some(String::from("▁")));
+        assert_eq!(message(&scaled, "20"), Some(String::from("▁█")));
+        assert!(Format::from_config("section", &table("render = \"sparkline\"\nrange = [5, 1]")).is_err());
+    }
+
+    #[test]
+    fn gauges_fill_in_along_their_range() {
+        let message = |format: &Format, output: &str| format.render(0, &Outcome::Success(output.to_owned())).map(|update| update.message);
+        let gauge = format("render = \"bar\"\nwidth = 4\nbar_fill = \"#\"\nbar_empty = \"-\"");
+        assert_eq!(message(&gauge, "0%"), Some(String::from("----")));
+        assert_eq!(message(&gauge, "50%"), Some(String::from("##--")));
+        assert_eq!(message(&gauge, "150%"), Some(String::from("####")));
+        assert_eq!(message(&gauge, "muted"), None);
+        let ranged = format("render = \"bar\"\nwidth = 4\nrange = [-10, 30]");
+        assert_eq!(message(&ranged, "20"), Some(String::from("███░")));
+        assert!(Format::from_config("section", &table("render = \"bar\"\nwidth = 0")).is_err());
+        assert!(Format::from_config("section", &table("render = \"pie\"")).is_err());
+    }
+
+    #[test]
+    fn urgency_comes_from_thresholds_prefixes_and_failures() {
+        let urgent = |format: &Format, outcome| format.render(0, &outcome).map(|update| (update.message, update.urgent));
+        let battery = format("urgent_below = 10\nurgent_above = 99.5\nurgent_prefix = \"!\"");
+        assert_eq!(urgent(&battery, Outcome::Success(String::from("50%"))), Some((String::from("50%"), false)));
+        assert_eq!(urgent(&battery, Outcome::Success(String::from("10%"))), Some((String::from("10%"), true)));
+        assert_eq!(urgent(&battery, Outcome::Success(String::from("100%"))), Some((String::from("100%"), true)));
+        assert_eq!(urgent(&battery, Outcome::Success(String::from("!unplugged"))), Some((String::from("unplugged"), true)));
+        assert_eq!(urgent(&battery, Outcome::Failure(Some(String::from("error")))), Some((String::from("error"), false)));
+        let failing = format("urgent_on_failure = true\nfallback = \"n/a\"");
+        assert_eq!(urgent(&failing, Outcome::Failure(None)), Some((String::from("n/a"), true)));
+        assert!(Format::from_config("section", &table("urgent_prefix = \"\"")).is_err());
+    }
+
+    #[test]
+    fn short_messages_are_formatted_and_cut_down() {
+        let short = |format: &Format, output: &str| format.render(0, &Outcome::Success(output.to_owned())).and_then(|update| update.short_message);
+        assert_eq!(short(&format(""), "Artist - Title"), None);
+        assert_eq!(short(&format("short_width = 6"), "Artist - Title"), Some(String::from("Artis…")));
+        assert_eq!(short(&format("short_width = 20"), "Artist - Title"), Some(String::from("Artist - Title")));
+        assert_eq!(short(&format("short_format = \"[{output}]\"\nshort_width = 3"), "Artist"), Some(String::from("[Ar…]")));
+        assert!(Format::from_config("section", &table("short_width = 0")).is_err());
+    }
+
+    #[test]
+    fn icons_are_picked_by_the_output() {
+        let icon = |format: &Format, output: &str| format.render(0, &Outcome::Success(output.to_owned())).and_then(|update| update.icon);
+        assert_eq!(icon(&format("icon = \"♪\""), "playing"), Some(String::from("♪")));
+        let battery = format("icon = [\"empty\", \"half\", \"full\"]");
+        assert_eq!(icon(&battery, "0%"), Some(String::from("empty")));
+        assert_eq!(icon(&battery, "50%"), Some(String::from("half")));
+        assert_eq!(icon(&battery, "100%"), Some(String::from("full")));
+        assert_eq!(icon(&battery, "charging"), None);
+        assert!(Format::from_config("section", &table("icon = []")).is_err());
+    }
+
+    #[test]
+    fn sanitize_strips_or_shows_control_characters() {
+        let output = "\x1b[1;31mred\x1b[0m\ttab\x1b]0;title\x07\x1b]2;other\x1b\\\x08end";
+        assert_eq!(Sanitize::Strip.apply(output), "red tabend");
+        assert_eq!(Sanitize::Escape.apply("a\x1b[0mb\tc\x07"), "a\\x1b[0mb c\\x07");
+        assert_eq!(Sanitize::None.apply(output), output);
+        assert_eq!(Sanitize::Strip.apply("\u{9b}32mgreen"), "green");
+    }
+
+    #[test]
+    fn once_shows_each_section_a_single_time() {
+        let config = table("[clock]\npath = \"echo 12:00\"\nshell = \"sh\"\nreload = 5\n[stream]\npath = \"echo first; echo second; sleep 10\"\nshell = \"sh\"\n[broken]\npath = 1\nshell = \"sh\"\n");
+        let config = Arc::new(RwLock::new(config));
+        let target = |section_name: &str, position| Target { section_name: section_name.to_owned(), position };
+        let messages = |targets: &[Target]| updates_once(&config, targets).into_iter().map(|update| (update.position, update.message)).collect::<Vec<_>>();
+        assert_eq!(messages(&[target("clock", 2)]), [(2, String::from("12:00"))]);
+        // Streaming scripts are shown by their first line
+        let start = Instant::now();
+        assert_eq!(messages(&[target("stream", 0)]), [(0, String::from("first"))]);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(messages(&[target("broken", 0)]).is_empty());
+    }
+
+    #[test]
+    fn running_a_section_exits_with_how_it_went() {
+        let mut config = table("[hello]\npath = \"echo hello\"\nshell = \"sh\"\nreload = 5\n[fails]\npath = \"exit 3\"\nshell = \"sh\"\nreload = 5\n[both]\ntype = \"rotate\"\nitems = [\"hello\", \"fails\"]\n[greet]\nparameter = \"name\"\npath = \"echo {name}\"\nshell = \"sh\"\nreload = 5\n");
+        assert_eq!(run_section(&mut config, "hello"), 0);
+        assert_eq!(run_section(&mut config, "fails"), 1);
+        assert_eq!(run_section(&mut config, "both"), 1);
+        assert_eq!(run_section(&mut config, "missing"), 1);
+        assert_eq!(run_section(&mut config, "greet:world"), 0);
+    }
+
+    fn script(command: &str, timeout: Option<u64>) -> Script {
+        Script { command: String::from(command), shell: String::from("sh"), duration: None, jitter: 0f64, timeout, is_static: false }
+    }
+
+    #[test]
+    fn scripts_are_not_held_up_by_what_they_leave_running() {
+        let start = Instant::now();
+        assert_eq!(script("sleep 100 & echo done", None).run(), (Outcome::Success(String::from("done\n")), Some(0)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn timeouts_kill_the_whole_script() {
+        let start = Instant::now();
+        assert_eq!(script("sleep 100; echo late", Some(200)).run(), (Outcome::Failure(None), None));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(script("echo early; exit 3", Some(5000)).run(), (Outcome::Failure(Some(String::from("early\n"))), Some(3)));
     }
 }
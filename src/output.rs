@@ -0,0 +1,856 @@
+//! Ways of rendering the bar line for different consumers.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+
+use rustc_serialize::json::Json;
+use toml::{self, Value};
+
+use {Escape, State};
+
+/// The names accepted by `Mode::from_name`.
+pub const MODES: &[&str] = &["plain", "i3bar", "dzen2", "waybar", "xroot", "json", "tmux", "ansi"];
+
+/// How the bar is printed to standard output, chosen by `output` in `[admiral]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Every item's output, one after the other, on a line of its own.
+    Plain,
+    /// i3bar's JSON protocol, also spoken by swaybar, with a block for each item.
+    I3bar,
+    /// Like `Plain`, but with dzen2's `^fg()` and `^bg()` commands for each item's style.
+    Dzen2,
+    /// A JSON object for each line, as read by a Waybar `custom` module.
+    Waybar,
+    /// The line without styles, set as the X root window's name for dwm rather than printed.
+    Xroot,
+    /// A JSON array for each line, holding every item's name and output, for other programs to read.
+    Json,
+    /// The line in tmux's `#[...]` styles, set as its `status-right` rather than printed.
+    Tmux,
+    /// The line in a terminal's ANSI colours, as `--preview` shows it.
+    Ansi,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "plain" => Some(Mode::Plain),
+            "i3bar" => Some(Mode::I3bar),
+            "dzen2" => Some(Mode::Dzen2),
+            "waybar" => Some(Mode::Waybar),
+            "xroot" => Some(Mode::Xroot),
+            "json" => Some(Mode::Json),
+            "tmux" => Some(Mode::Tmux),
+            "ansi" => Some(Mode::Ansi),
+            _ => None,
+        }
+    }
+
+    /// What is printed once, before the first line.
+    pub fn header(&self) -> Option<&'static str> {
+        match *self {
+            Mode::Plain | Mode::Dzen2 | Mode::Waybar | Mode::Xroot | Mode::Json | Mode::Tmux | Mode::Ansi => None,
+            // The status lines that follow make up an array that never ends
+            Mode::I3bar => Some("{\"version\":1}\n["),
+        }
+    }
+
+    /// Renders a line from the message of each item.
+    /// Escapes text that isn't a section's output, which is escaped as its
+    /// section says, so that it's shown as it is in this mode's line.
+    pub fn escape(&self, text: &str) -> String {
+        match *self {
+            Mode::Plain => Escape::Lemonbar.apply(text),
+            Mode::Dzen2 => Escape::Dzen2.apply(text),
+            // Waybar's markup is escaped with the rest of the item, and tmux's # is escaped as it's drawn
+            _ => text.to_owned(),
+        }
+    }
+
+    pub fn render(&self, items: &[Item], messages: &[String]) -> String {
+        match *self {
+            Mode::Plain => {
+                let mut line = String::new();
+                compose(items, messages, &mut line);
+                line
+            },
+            Mode::Xroot => ordered(items, messages).map(|(item, message)| item.text(message)).collect(),
+            Mode::Json => Json::Array(items.iter().zip(messages).map(|(item, message)| item.state(message)).collect()).to_string(),
+            Mode::I3bar => {
+                let blocks = ordered(items, messages)
+                    // An empty block would still be drawn, with separators on either side
+                    .filter(|&(_, message)| !message.is_empty())
+                    .map(|(item, message)| item.block(message))
+                    .collect();
+                format!("{},", Json::Array(blocks))
+            },
+            Mode::Dzen2 => {
+                let mut line = String::new();
+                for (item, message) in ordered(items, messages) {
+                    item.style().dzen2(&item.text(message), &mut line);
+                }
+                line
+            },
+            Mode::Tmux => {
+                let mut line = String::new();
+                for (item, message) in ordered(items, messages) {
+                    // Scripts written for lemonbar shouldn't leave their tags in tmux, where # starts a format
+                    item.style().tmux(&strip_lemonbar(&item.text(message)).replace('#', "##"), &mut line);
+                }
+                line
+            },
+            Mode::Ansi => {
+                let mut line = String::new();
+                for (item, message) in ordered(items, messages) {
+                    item.style().ansi(&strip_lemonbar(&item.text(message)), &mut line);
+                }
+                line
+            },
+            Mode::Waybar => {
+                let shown = ordered(items, messages).filter(|&(_, message)| !message.is_empty()).collect::<Vec<_>>();
+                let mut text = String::new();
+                for &(item, message) in &shown {
+                    item.style().pango(&item.pango_text(&item.text(message)), &mut text);
+                }
+                let tooltip = shown.iter().map(|&(item, message)| item.pango_text(message)).collect::<Vec<_>>().join("\n");
+                // Each item with something to show is a class, for Waybar's stylesheet to match
+                let mut class = shown.iter().map(|&(item, _)| Json::String(item.name.clone())).collect::<Vec<_>>();
+                if shown.iter().any(|&(item, _)| item.urgent) {
+                    class.push(Json::String(String::from("urgent")));
+                }
+
+                let mut object = BTreeMap::new();
+                object.insert(String::from("text"), Json::String(text));
+                object.insert(String::from("tooltip"), Json::String(tooltip));
+                object.insert(String::from("class"), Json::Array(class));
+                Json::Object(object).to_string()
+            },
+        }
+    }
+}
+
+/// Writes the plain bar line into `line`, each item wrapped in lemonbar tags for its style.
+/// When items are given monitors, the line has a part for each monitor, picked
+/// with lemonbar's `%{S}`, showing the items that are on it.
+pub fn compose(items: &[Item], messages: &[String], line: &mut String) {
+    line.clear();
+    let mut monitors = items.iter().flat_map(|item| item.monitors.iter().flatten().cloned()).collect::<Vec<_>>();
+    if monitors.is_empty() {
+        compose_regions(items, messages, |_| true, line);
+        return;
+    }
+
+    monitors.sort_unstable();
+    monitors.dedup();
+    for monitor in monitors {
+        line.push_str(&format!("%{{S{}}}", monitor));
+        // Items without a monitor of their own are shown on them all
+        compose_regions(items, messages, |item| item.monitors.as_ref().is_none_or(|monitors| monitors.contains(&monitor)), line);
+    }
+}
+
+/// Writes the items that are `shown` into `line`. When items are given
+/// regions, those in each region follow lemonbar's `%{l}`, `%{c}`, or `%{r}`.
+fn compose_regions(items: &[Item], messages: &[String], shown: impl Fn(&Item) -> bool, line: &mut String) {
+    let shown = items.iter().zip(messages).filter(|&(item, _)| shown(item));
+    if items.iter().all(|item| item.region.is_none()) {
+        for (item, message) in shown {
+            item.style().lemonbar(&item.lemonbar_text(message), line);
+        }
+        return;
+    }
+
+    for (region, tag) in [(Region::Left, "%{l}"), (Region::Center, "%{c}"), (Region::Right, "%{r}")] {
+        let mut in_region = shown.clone().filter(|&(item, _)| item.region.unwrap_or(Region::Left) == region).peekable();
+        if in_region.peek().is_some() {
+            line.push_str(tag);
+        }
+        for (item, message) in in_region {
+            item.style().lemonbar(&item.lemonbar_text(message), line);
+        }
+    }
+}
+
+/// The items in the order they're shown, left to right: those on the left of
+/// the bar, then the centre, then the right. Output modes that can't split the
+/// bar into regions show them in this order instead.
+fn ordered<'a>(items: &'a [Item], messages: &'a [String]) -> std::vec::IntoIter<(&'a Item, &'a String)> {
+    let mut ordered = items.iter().zip(messages).collect::<Vec<_>>();
+    ordered.sort_by_key(|&(item, _)| item.region.unwrap_or(Region::Left));
+    ordered.into_iter()
+}
+
+/// Which part of the bar an item is shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Region {
+    Left,
+    Center,
+    Right,
+}
+
+/// How an item is coloured and drawn, from its section's `fg`, `bg`,
+/// `underline`, and `font`.
+#[derive(Debug, Clone, Default)]
+pub struct Style {
+    fg: Option<String>,
+    bg: Option<String>,
+    /// Whether the item is underlined, and in which colour if not the bar's own.
+    underline: Option<Option<String>>,
+    /// The bar's font to use, counting from 1 in the order the bar was given them.
+    font: Option<u32>,
+}
+
+/// Reads the colour at `key`, given as `#rrggbb` or as the name of one of `[theme]`'s.
+fn color(section_name: &str, configuration: &toml::Table, key: &str, theme: &toml::Table) -> Result<Option<String>, String> {
+    let color = match configuration.get(key) {
+        Some(Value::String(color)) => color,
+        Some(_) => return Err(format!("Invalid {} found for {}: expected a colour such as \"#ff0000\", or a name from [theme]", key, section_name)),
+        None => return Ok(None),
+    };
+    if color.starts_with('#') {
+        return Ok(Some(color.clone()));
+    }
+    match theme.get(color).map(Value::as_str) {
+        Some(Some(themed)) if themed.starts_with('#') => Ok(Some(themed.to_owned())),
+        Some(_) => Err(format!("Invalid {} found in [theme]: expected a colour such as \"#ff0000\"", color)),
+        None => Err(format!("Failed to find colour {} for {} in [theme]", color, section_name)),
+    }
+}
+
+impl Style {
+    pub fn from_config(section_name: &str, configuration: &toml::Table, theme: &toml::Table) -> Result<Style, String> {
+        let underline = match configuration.get("underline") {
+            Some(&Value::Boolean(false)) | None => None,
+            Some(&Value::Boolean(true)) => Some(None),
+            Some(Value::String(_)) => Some(color(section_name, configuration, "underline", theme)?),
+            Some(_) => return Err(format!("Invalid underline found for {}: expected true or a colour such as \"#ff0000\"", section_name)),
+        };
+        let font = match configuration.get("font") {
+            Some(&Value::Integer(font)) if font >= 1 => Some(font as u32),
+            Some(_) => return Err(format!("Invalid font found for {}: expected the number of one of the bar's fonts, from 1", section_name)),
+            None => None,
+        };
+
+        Ok(Style { fg: color(section_name, configuration, "fg", theme)?, bg: color(section_name, configuration, "bg", theme)?, underline, font })
+    }
+
+    /// Appends `message` to `line` between lemonbar tags that set this style
+    /// and then reset it, so that it doesn't spill over onto the next item.
+    fn lemonbar(&self, message: &str, line: &mut String) {
+        if message.is_empty() {
+            return;
+        }
+        let mut closing = Vec::new();
+        if let Some(ref fg) = self.fg {
+            line.push_str(&format!("%{{F{}}}", fg));
+            closing.push("%{F-}");
+        }
+        if let Some(ref bg) = self.bg {
+            line.push_str(&format!("%{{B{}}}", bg));
+            closing.push("%{B-}");
+        }
+        if let Some(ref underline) = self.underline {
+            if let Some(ref color) = *underline {
+                line.push_str(&format!("%{{U{}}}", color));
+                closing.push("%{U-}");
+            }
+            line.push_str("%{+u}");
+            closing.push("%{-u}");
+        }
+        if let Some(font) = self.font {
+            line.push_str(&format!("%{{T{}}}", font));
+            closing.push("%{T-}");
+        }
+        line.push_str(message);
+        for tag in closing.iter().rev() {
+            line.push_str(tag);
+        }
+    }
+
+    /// Appends `message` to `line` between dzen2 commands that set this style
+    /// and then reset it. dzen2 has no underline, and names its fonts rather
+    /// than numbering them, so those are left out.
+    fn dzen2(&self, message: &str, line: &mut String) {
+        if message.is_empty() {
+            return;
+        }
+        if let Some(ref fg) = self.fg {
+            line.push_str(&format!("^fg({})", fg));
+        }
+        if let Some(ref bg) = self.bg {
+            line.push_str(&format!("^bg({})", bg));
+        }
+        line.push_str(message);
+        if self.bg.is_some() {
+            line.push_str("^bg()");
+        }
+        if self.fg.is_some() {
+            line.push_str("^fg()");
+        }
+    }
+
+    /// Appends `message` to `line` between tmux styles that set this style and
+    /// then reset it. tmux has only the terminal's font, so that's left out.
+    fn tmux(&self, message: &str, line: &mut String) {
+        if message.is_empty() {
+            return;
+        }
+        let mut styles = Vec::new();
+        let mut resets = Vec::new();
+        if let Some(ref fg) = self.fg {
+            styles.push(format!("fg={}", fg));
+            resets.push("fg=default");
+        }
+        if let Some(ref bg) = self.bg {
+            styles.push(format!("bg={}", bg));
+            resets.push("bg=default");
+        }
+        if let Some(ref underline) = self.underline {
+            styles.push(String::from("underscore"));
+            resets.push("nounderscore");
+            if let Some(ref color) = *underline {
+                styles.push(format!("us={}", color));
+                resets.push("us=default");
+            }
+        }
+        if styles.is_empty() {
+            line.push_str(message);
+        } else {
+            line.push_str(&format!("#[{}]{}#[{}]", styles.join(","), message, resets.join(",")));
+        }
+    }
+
+    /// Appends `message` to `line` between ANSI escape sequences that set this
+    /// style and then reset it. Terminals have only the one font.
+    fn ansi(&self, message: &str, line: &mut String) {
+        if message.is_empty() {
+            return;
+        }
+        let mut codes = Vec::new();
+        if let Some((red, green, blue)) = self.fg.as_ref().and_then(|fg| rgb(fg)) {
+            codes.push(format!("38;2;{};{};{}", red, green, blue));
+        }
+        if let Some((red, green, blue)) = self.bg.as_ref().and_then(|bg| rgb(bg)) {
+            codes.push(format!("48;2;{};{};{}", red, green, blue));
+        }
+        if let Some(ref underline) = self.underline {
+            codes.push(String::from("4"));
+            if let Some((red, green, blue)) = underline.as_ref().and_then(|color| rgb(color)) {
+                codes.push(format!("58;2;{};{};{}", red, green, blue));
+            }
+        }
+        if codes.is_empty() {
+            line.push_str(message);
+        } else {
+            line.push_str(&format!("\x1b[{}m{}\x1b[0m", codes.join(";"), message));
+        }
+    }
+
+    /// Appends `message` to `line` in a Pango `<span>` that sets this style.
+    /// Fonts are numbered by the bar, so Pango has nothing to pick by.
+    fn pango(&self, message: &str, line: &mut String) {
+        let mut attributes = String::new();
+        if let Some(ref fg) = self.fg {
+            attributes.push_str(&format!(" foreground=\"{}\"", fg));
+        }
+        if let Some(ref bg) = self.bg {
+            attributes.push_str(&format!(" background=\"{}\"", bg));
+        }
+        if let Some(ref underline) = self.underline {
+            attributes.push_str(" underline=\"single\"");
+            if let Some(ref color) = *underline {
+                attributes.push_str(&format!(" underline_color=\"{}\"", color));
+            }
+        }
+        if attributes.is_empty() {
+            line.push_str(message);
+        } else {
+            line.push_str(&format!("<span{}>{}</span>", attributes, message));
+        }
+    }
+}
+
+/// An item of the bar, with what the output modes need to know to draw it.
+#[derive(Debug, Clone)]
+pub struct Item {
+    name: String,
+    /// The parameter of an item made from a template, such as `/home` for `disk:/home`.
+    instance: Option<String>,
+    style: Style,
+    /// Whether the item's output is Pango markup.
+    markup: bool,
+    /// The monitors the item is shown on, counting from 0, if not all of them.
+    monitors: Option<Vec<u32>>,
+    /// How the item is drawn instead while it's urgent, from `urgent_fg` and `urgent_bg`.
+    urgent_style: Style,
+    urgent: bool,
+    /// How the item is drawn instead while its widget reports each state, from
+    /// `success_color`, `failure_color`, and `pending_color`.
+    state_styles: Vec<(State, Style)>,
+    state: Option<State>,
+    /// What i3bar shows instead when it's short of room.
+    short_message: Option<String>,
+    /// The icon shown before the item's output.
+    icon: Option<String>,
+    /// The bar's font that lemonbar draws the icon in, counting from 1.
+    icon_font: Option<u32>,
+    region: Option<Region>,
+}
+
+impl Item {
+    pub fn from_config(section_name: &str, configuration: &toml::Table, theme: &toml::Table) -> Result<Item, String> {
+        let (name, instance) = match section_name.split_once(':') {
+            Some((name, instance)) => (name.to_owned(), Some(instance.to_owned())),
+            None => (section_name.to_owned(), None),
+        };
+
+        // Output escaped for Pango is meant to be read as Pango markup
+        let escaped = configuration.get("escape").and_then(Value::as_str) == Some("pango");
+        let markup = match configuration.get("markup").map(|v| v.as_str()) {
+            Some(Some("pango")) => true,
+            Some(Some("none")) => false,
+            None => escaped,
+            Some(_) => return Err(format!("Invalid markup found for {}: expected \"pango\" or \"none\"", section_name)),
+        };
+
+        let invalid = || format!("Invalid monitor found for {}: expected the number of a monitor, from 0, or an array of them", section_name);
+        let monitors = match configuration.get("monitor") {
+            Some(&Value::Integer(monitor)) if monitor >= 0 => Some(vec![monitor as u32]),
+            Some(Value::Array(monitors)) if !monitors.is_empty() => Some(monitors.iter()
+                .map(|monitor| monitor.as_integer().filter(|&monitor| monitor >= 0).map(|monitor| monitor as u32))
+                .collect::<Option<Vec<_>>>()
+                .ok_or_else(invalid)?),
+            Some(_) => return Err(invalid()),
+            None => None,
+        };
+
+        let style = Style::from_config(section_name, configuration, theme)?;
+        let urgent_color = |key: &str, default: &str| color(section_name, configuration, key, theme).map(|color| color.or_else(|| Some(default.to_owned())));
+        let icon_font = match configuration.get("icon_font") {
+            Some(&Value::Integer(font)) if font >= 1 => Some(font as u32),
+            Some(_) => return Err(format!("Invalid icon_font found for {}: expected the number of one of the bar's fonts, from 1", section_name)),
+            None => None,
+        };
+        let region = match configuration.get("region").map(|v| v.as_str()) {
+            Some(Some("left")) => Some(Region::Left),
+            Some(Some("center")) => Some(Region::Center),
+            Some(Some("right")) => Some(Region::Right),
+            None => None,
+            Some(_) => return Err(format!("Invalid region found for {}: expected \"left\", \"center\" or \"right\"", section_name)),
+        };
+        let urgent_style = Style { fg: urgent_color("urgent_fg", "#ffffff")?, bg: urgent_color("urgent_bg", "#ff0000")?, ..style.clone() };
+        let mut state_styles = Vec::new();
+        for &(state, key) in &[(State::Success, "success_color"), (State::Failure, "failure_color"), (State::Pending, "pending_color")] {
+            if let Some(fg) = color(section_name, configuration, key, theme)? {
+                state_styles.push((state, Style { fg: Some(fg), ..style.clone() }));
+            }
+        }
+
+        Ok(Item {
+            name,
+            instance,
+            style,
+            markup,
+            monitors,
+            urgent_style,
+            urgent: false,
+            state_styles,
+            state: None,
+            short_message: None,
+            icon: None,
+            icon_font,
+            region,
+        })
+    }
+
+    /// Marks the item as urgent, as a battery running low is, or as back to normal.
+    pub fn set_urgent(&mut self, urgent: bool) {
+        self.urgent = urgent;
+    }
+
+    /// Colours the item for how its widget says things stand, if its section gives a colour for it.
+    pub fn set_state(&mut self, state: Option<State>) {
+        self.state = state;
+    }
+
+    pub fn set_short_message(&mut self, short_message: Option<String>) {
+        self.short_message = short_message;
+    }
+
+    pub fn set_icon(&mut self, icon: Option<String>) {
+        self.icon = icon;
+    }
+
+    /// The item's output, after its icon if it has one.
+    fn text(&self, message: &str) -> String {
+        match self.icon {
+            Some(ref icon) if !message.is_empty() => format!("{} {}", icon, message),
+            _ => message.to_owned(),
+        }
+    }
+
+    /// The item's output after its icon, which is drawn in `icon_font`.
+    fn lemonbar_text(&self, message: &str) -> String {
+        match (&self.icon, self.icon_font) {
+            (Some(icon), Some(icon_font)) if !message.is_empty() => {
+                // Back to the item's own font, if it has one, after the icon
+                let font = self.style().font.map_or(String::from("-"), |font| font.to_string());
+                format!("%{{T{}}}{}%{{T{}}} {}", icon_font, icon, font, message)
+            },
+            _ => self.text(message),
+        }
+    }
+
+    /// `text` as Pango markup, escaped unless the item's output is markup already.
+    fn pango_text(&self, text: &str) -> String {
+        match self.markup {
+            true => text.to_owned(),
+            false => Escape::Pango.apply(text),
+        }
+    }
+
+    fn style(&self) -> &Style {
+        match self.urgent {
+            true => &self.urgent_style,
+            false => self.state_style(),
+        }
+    }
+
+    /// The item's style for its widget's state, setting urgency aside.
+    fn state_style(&self) -> &Style {
+        self.state
+            .and_then(|state| self.state_styles.iter().find(|&&(styled, _)| styled == state))
+            .map_or(&self.style, |(_, style)| style)
+    }
+
+    /// The item's name and output, unstyled.
+    fn state(&self, message: &str) -> Json {
+        let mut state = BTreeMap::new();
+        state.insert(String::from("name"), Json::String(self.name.clone()));
+        if let Some(ref instance) = self.instance {
+            state.insert(String::from("instance"), Json::String(instance.clone()));
+        }
+        state.insert(String::from("text"), Json::String(message.to_owned()));
+        if let Some(ref icon) = self.icon {
+            state.insert(String::from("icon"), Json::String(icon.clone()));
+        }
+        state.insert(String::from("urgent"), Json::Boolean(self.urgent));
+        Json::Object(state)
+    }
+
+    /// The i3bar block showing `message`.
+    fn block(&self, message: &str) -> Json {
+        let mut block = BTreeMap::new();
+        block.insert(String::from("full_text"), Json::String(self.text(message)));
+        if let Some(ref short_message) = self.short_message {
+            block.insert(String::from("short_text"), Json::String(short_message.clone()));
+        }
+        block.insert(String::from("name"), Json::String(self.name.clone()));
+        if let Some(ref instance) = self.instance {
+            block.insert(String::from("instance"), Json::String(instance.clone()));
+        }
+        if let Some(ref fg) = self.state_style().fg {
+            block.insert(String::from("color"), Json::String(fg.clone()));
+        }
+        if let Some(ref bg) = self.style.bg {
+            block.insert(String::from("background"), Json::String(bg.clone()));
+        }
+        block.insert(String::from("markup"), Json::String(String::from(if self.markup { "pango" } else { "none" })));
+        // i3bar draws urgent blocks in its own urgent colours
+        if self.urgent {
+            block.insert(String::from("urgent"), Json::Boolean(true));
+        }
+        Json::Object(block)
+    }
+}
+
+/// The names accepted by `OutputFormat::from_name`.
+pub const NAMES: &[&str] = &["plain", "tmux", "framed"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// The line exactly as the scripts produced it.
+    Plain,
+    /// The line as `output = "tmux"` renders it, for tmux's status line.
+    Tmux,
+    /// The line prefixed with a sequence number, so that consumers can tell when they
+    /// have missed an update and ask for the full state again.
+    Framed,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "plain" => Some(OutputFormat::Plain),
+            "tmux" => Some(OutputFormat::Tmux),
+            "framed" => Some(OutputFormat::Framed),
+            _ => None,
+        }
+    }
+}
+
+/// Reads a colour given as `#rgb`, `#rrggbb`, or `#aarrggbb`, leaving out its alpha.
+fn rgb(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.strip_prefix('#')?;
+    let hex = match hex.len() {
+        3 => hex.chars().flat_map(|digit| [digit, digit]).collect(),
+        6 => hex.to_owned(),
+        8 => hex[2..].to_owned(),
+        _ => return None,
+    };
+    let channel = |start: usize| u8::from_str_radix(hex.get(start..start + 2)?, 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Removes lemonbar `%{...}` blocks, leaving only the text they surround.
+fn strip_lemonbar(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(start) = rest.find("%{") {
+        stripped.push_str(&rest[..start]);
+        rest = match rest[start..].find('}') {
+            Some(end) => &rest[start + end + 1..],
+            None => "",
+        };
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+/// Formats a line for a client. Framed lines are `<sequence> update <line>`, or
+/// `<sequence> full <line>` when the client is being sent the whole state.
+fn frame(format: OutputFormat, sequence: u64, full: bool, line: &str) -> String {
+    match format {
+        OutputFormat::Framed => format!("{} {} {}", sequence, if full { "full" } else { "update" }, line),
+        _ => line.to_owned(),
+    }
+}
+
+/// Clients attached to the control socket, along with the last line sent to
+/// them, both plain and as tmux clients are sent it.
+#[derive(Debug, Default)]
+pub struct Attached {
+    line: String,
+    tmux: String,
+    /// How many lines have been broadcast.
+    sequence: u64,
+    clients: Vec<(OutputFormat, UnixStream)>,
+}
+
+impl Attached {
+    /// The latest line in a client's format.
+    fn current(&self, format: OutputFormat) -> &str {
+        match format {
+            OutputFormat::Tmux => &self.tmux,
+            OutputFormat::Plain | OutputFormat::Framed => &self.line,
+        }
+    }
+
+    /// Adds a client, sending it the current line straight away.
+    pub fn attach(&mut self, format: OutputFormat, mut stream: UnixStream) {
+        if writeln!(stream, "{}", frame(format, self.sequence, true, self.current(format))).is_ok() {
+            self.clients.push((format, stream));
+        }
+    }
+
+    /// Sends the line made of `items` showing `messages` to every client,
+    /// dropping those that have gone away. `line` is the line composed plain.
+    pub fn broadcast(&mut self, line: &str, items: &[Item], messages: &[String]) {
+        line.clone_into(&mut self.line);
+        self.tmux = Mode::Tmux.render(items, messages);
+        self.sequence += 1;
+        let (sequence, plain, tmux) = (self.sequence, &self.line, &self.tmux);
+        self.clients.retain_mut(|&mut (format, ref mut stream)| {
+            let line = if format == OutputFormat::Tmux { tmux } else { plain };
+            writeln!(stream, "{}", frame(format, sequence, false, line)).is_ok()
+        });
+    }
+
+    /// Sends the full state to a framed client that asked to resynchronise.
+    pub fn resync(&self, stream: &mut UnixStream) -> io::Result<()> {
+        writeln!(stream, "{}", frame(OutputFormat::Framed, self.sequence, true, &self.line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+
+    use toml;
+
+    use super::{compose, rgb, Attached, Item, Mode, OutputFormat};
+
+    fn item(source: &str) -> Item {
+        Item::from_config("clock", &toml::Parser::new(source).parse().unwrap(), &toml::Table::new()).unwrap()
+    }
+
+    #[test]
+    fn framed_clients_can_tell_what_they_missed() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let (resynced, mut resync_server) = UnixStream::pair().unwrap();
+        let mut attached = Attached::default();
+        attached.broadcast("first", &[], &[]);
+        attached.attach(OutputFormat::Framed, server);
+        attached.broadcast("second", &[], &[]);
+        attached.broadcast("third", &[], &[]);
+        attached.resync(&mut resync_server).unwrap();
+
+        let lines = BufReader::new(client).lines().take(3).map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(lines, ["1 full first", "2 update second", "3 update third"]);
+        assert_eq!(BufReader::new(resynced).lines().next().unwrap().unwrap(), "3 full third");
+    }
+
+    #[test]
+    fn i3bar_prints_a_block_for_each_item_with_output() {
+        let items = [item("fg = \"#ff0000\"\nbg = \"#000000\""), Item::from_config("disk:/home", &toml::Table::new(), &toml::Table::new()).unwrap()];
+        assert_eq!(Mode::I3bar.header(), Some("{\"version\":1}\n["));
+        let line = Mode::I3bar.render(&items, &[String::from("12:00"), String::new()]);
+        assert_eq!(line, r##"[{"background":"#000000","color":"#ff0000","full_text":"12:00","markup":"none","name":"clock"}],"##);
+        let line = Mode::I3bar.render(&items, &[String::new(), String::from("40%")]);
+        assert_eq!(line, r#"[{"full_text":"40%","instance":"/home","markup":"none","name":"disk"}],"#);
+    }
+
+    #[test]
+    fn lemonbar_tags_set_each_style_and_reset_it() {
+        let items = [item("fg = \"#ff0000\"\nbg = \"#000000\"\nunderline = \"#00ff00\"\nfont = 2"), item("underline = true"), item("")];
+        let messages = [String::from("12:00"), String::from("40%"), String::from("plain")];
+        assert_eq!(Mode::Plain.render(&items, &messages), "%{F#ff0000}%{B#000000}%{U#00ff00}%{+u}%{T2}12:00%{T-}%{-u}%{U-}%{B-}%{F-}%{+u}40%%{-u}plain");
+        assert_eq!(Mode::Plain.render(&items, &[String::new(), String::new(), String::new()]), "");
+
+        let parse = |source: &str| toml::Parser::new(source).parse().unwrap();
+        assert!(Item::from_config("clock", &parse("fg = 1"), &toml::Table::new()).is_err());
+        assert!(Item::from_config("clock", &parse("font = 0"), &toml::Table::new()).is_err());
+    }
+
+    #[test]
+    fn dzen2_commands_set_colours_and_reset_them() {
+        let items = [item("fg = \"#ff0000\"\nbg = \"#000000\"\nunderline = true\nfont = 2"), item("")];
+        let messages = [String::from("12:00"), String::from("plain")];
+        assert_eq!(Mode::Dzen2.render(&items, &messages), "^fg(#ff0000)^bg(#000000)12:00^bg()^fg()plain");
+    }
+
+    #[test]
+    fn waybar_gets_markup_a_tooltip_and_classes() {
+        let battery = Item::from_config("battery", &toml::Parser::new("fg = \"#ff0000\"").parse().unwrap(), &toml::Table::new()).unwrap();
+        let items = [item("markup = \"pango\""), battery, item("")];
+        let messages = [String::from("<b>12:00</b>"), String::from("5% & falling"), String::new()];
+        let line = Mode::Waybar.render(&items, &messages);
+        assert_eq!(line, concat!(
+            r##"{"class":["clock","battery"],"##,
+            r##""text":"<b>12:00</b><span foreground=\"#ff0000\">5% &amp; falling</span>","##,
+            r##""tooltip":"<b>12:00</b>\n5% &amp; falling"}"##,
+        ));
+    }
+
+    #[test]
+    fn tmux_styles_replace_lemonbar_tags() {
+        let items = [item("fg = \"#ff0000\"\nunderline = \"#00ff00\""), item("")];
+        let messages = [String::from("%{F#fff}#1%{F-}"), String::from("plain")];
+        assert_eq!(Mode::Tmux.render(&items, &messages), "#[fg=#ff0000,underscore,us=#00ff00]##1#[fg=default,nounderscore,us=default]plain");
+    }
+
+    #[test]
+    fn text_is_escaped_for_each_mode() {
+        assert_eq!(Mode::Plain.escape("100% ^fg()"), "100%% ^fg()");
+        assert_eq!(Mode::Dzen2.escape("100% ^fg()"), "100% ^^fg()");
+        assert_eq!(Mode::Waybar.escape("<b>"), "<b>");
+    }
+
+    #[test]
+    fn ansi_colours_come_from_hex_colours() {
+        assert_eq!(rgb("#ff8000"), Some((255, 128, 0)));
+        assert_eq!(rgb("#f80"), Some((255, 136, 0)));
+        assert_eq!(rgb("#80ff8000"), Some((255, 128, 0)));
+        assert_eq!(rgb("#ff80"), None);
+        assert_eq!(rgb("red"), None);
+
+        let items = [item("fg = \"#ff0000\"\nbg = \"#000\"\nunderline = true"), item("")];
+        let messages = [String::from("%{F#fff}12:00"), String::from("plain")];
+        assert_eq!(Mode::Ansi.render(&items, &messages), "\x1b[38;2;255;0;0;48;2;0;0;0;4m12:00\x1b[0mplain");
+    }
+
+    #[test]
+    fn items_are_shown_on_their_monitors() {
+        let items = [item("monitor = 1"), item("monitor = [0, 2]"), item("")];
+        let messages = [String::from("one"), String::from("zero and two"), String::from("all")];
+        let mut line = String::new();
+        compose(&items, &messages, &mut line);
+        assert_eq!(line, "%{S0}zero and twoall%{S1}oneall%{S2}zero and twoall");
+
+        let parse = |source: &str| toml::Parser::new(source).parse().unwrap();
+        assert!(Item::from_config("clock", &parse("monitor = -1"), &toml::Table::new()).is_err());
+        assert!(Item::from_config("clock", &parse("monitor = []"), &toml::Table::new()).is_err());
+    }
+
+    #[test]
+    fn urgent_items_stand_out() {
+        let mut battery = item("fg = \"#00ff00\"\nurgent_bg = \"#ffff00\"");
+        battery.set_urgent(true);
+        let items = [battery];
+        let messages = [String::from("5%")];
+        assert_eq!(Mode::Plain.render(&items, &messages), "%{F#ffffff}%{B#ffff00}5%%{B-}%{F-}");
+        assert_eq!(Mode::I3bar.render(&items, &messages), r##"[{"color":"#00ff00","full_text":"5%","markup":"none","name":"clock","urgent":true}],"##);
+        assert!(Mode::Waybar.render(&items, &messages).starts_with(r#"{"class":["clock","urgent"],"#));
+    }
+
+    #[test]
+    fn i3bar_blocks_carry_short_text() {
+        let mut clock = item("");
+        clock.set_short_message(Some(String::from("12")));
+        let line = Mode::I3bar.render(&[clock], &[String::from("12:00")]);
+        assert_eq!(line, r#"[{"full_text":"12:00","markup":"none","name":"clock","short_text":"12"}],"#);
+    }
+
+    #[test]
+    fn icons_come_before_the_output() {
+        let mut clock = item("font = 2\nicon_font = 3");
+        clock.set_icon(Some(String::from("◷")));
+        let items = [clock];
+        assert_eq!(Mode::Plain.render(&items, &[String::from("12:00")]), "%{T2}%{T3}◷%{T2} 12:00%{T-}");
+        assert_eq!(Mode::Dzen2.render(&items, &[String::from("12:00")]), "◷ 12:00");
+        assert_eq!(Mode::Plain.render(&items, &[String::new()]), "");
+    }
+
+    #[test]
+    fn colours_can_be_named_in_the_theme() {
+        let parse = |source: &str| toml::Parser::new(source).parse().unwrap();
+        let theme = parse("red = \"#ff0000\"\nwrong = \"red\"");
+        let themed = Item::from_config("clock", &parse("fg = \"red\"\nbg = \"#000000\""), &theme).unwrap();
+        assert_eq!(Mode::Dzen2.render(&[themed], &[String::from("12:00")]), "^fg(#ff0000)^bg(#000000)12:00^bg()^fg()");
+        assert!(Item::from_config("clock", &parse("fg = \"blue\""), &theme).is_err());
+        assert!(Item::from_config("clock", &parse("fg = \"wrong\""), &theme).is_err());
+    }
+
+    #[test]
+    fn regions_split_the_line_and_order_the_rest() {
+        let items = [item("region = \"right\""), item(""), item("region = \"center\"")];
+        let messages = [String::from("right"), String::from("left"), String::from("center")];
+        let mut line = String::new();
+        compose(&items, &messages, &mut line);
+        assert_eq!(line, "%{l}left%{c}center%{r}right");
+        assert_eq!(Mode::Xroot.render(&items, &messages), "leftcenterright");
+        assert!(Item::from_config("clock", &toml::Parser::new("region = \"top\"").parse().unwrap(), &toml::Table::new()).is_err());
+    }
+
+    #[test]
+    fn attached_tmux_clients_see_the_tmux_output() {
+        let items = [item("fg = \"#ff0000\"")];
+        let messages = [String::from("%{F#fff}12:00 #1")];
+        let (client, server) = UnixStream::pair().unwrap();
+        let (framed, framed_server) = UnixStream::pair().unwrap();
+        let mut attached = Attached::default();
+        attached.attach(OutputFormat::Tmux, server);
+        attached.attach(OutputFormat::Framed, framed_server);
+        attached.broadcast(&messages[0], &items, &messages);
+
+        let mut lines = BufReader::new(client).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "");
+        assert_eq!(lines.next().unwrap().unwrap(), Mode::Tmux.render(&items, &messages));
+        let mut lines = BufReader::new(framed).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "0 full ");
+        assert_eq!(lines.next().unwrap().unwrap(), "1 update %{F#fff}12:00 #1");
+    }
+}
@@ -0,0 +1,158 @@
+//! Rotating sections, which show their member sections one at a time in a
+//! single slot of the bar.
+//!
+//! Each member runs on its own thread as usual, but reports to the rotation's
+//! thread rather than to the bar. Members with no output are skipped over, so
+//! a member that hides itself doesn't leave the slot empty on its turn.
+
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+use toml::{self, Value};
+
+use {Event, Update};
+
+/// The shortest time, in seconds, that each member may be shown for, which
+/// keeps a tiny interval from spinning the rotation's thread.
+const MIN_INTERVAL: f64 = 0.1;
+
+#[derive(Debug)]
+pub struct Rotation {
+    pub members: Vec<String>,
+    /// How long each member is shown for, or `None` to only rotate on request.
+    interval: Option<Duration>,
+}
+
+impl Rotation {
+    pub fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Rotation, String> {
+        let members = match configuration.get("items").and_then(Value::as_slice) {
+            Some(members) => members.iter()
+                .map(|member| member.as_str().map(str::to_owned))
+                .collect::<Option<Vec<_>>>(),
+            None => None,
+        };
+        let members = match members {
+            Some(ref members) if !members.is_empty() => members.clone(),
+            _ => return Err(format!("Invalid items found for {}", section_name)),
+        };
+
+        let interval = match configuration.get("interval") {
+            Some(&Value::Integer(0)) => None,
+            Some(&Value::Integer(seconds)) if seconds > 0 => Some(Duration::from_secs(seconds as u64)),
+            Some(&Value::Float(seconds)) if seconds >= MIN_INTERVAL => Some(Duration::from_millis((seconds * 1000f64) as u64)),
+            Some(_) => return Err(format!("Invalid interval found for {}", section_name)),
+            None => Some(Duration::from_secs(10)),
+        };
+
+        Ok(Rotation { members, interval })
+    }
+
+    /// Shows each member in turn at `position`. Members send their output as
+    /// `Event::Member`, and `Event::Rotate` moves on to the next one early.
+    /// `Event::Refresh` is passed on to each of the members' `threads`, and
+    /// members stop taking turns between `Event::Pause` and `Event::Resume`.
+    pub fn run(self, position: usize, sender: Sender<Update>, events: Receiver<Event>, threads: Vec<Sender<Event>>) {
+        let mut messages = vec![Update::default(); self.members.len()];
+        let mut current = 0;
+        let mut shown_since = Instant::now();
+        // While updates are paused, how long the current member had been shown for
+        let mut paused: Option<Duration> = None;
+
+        loop {
+            let event = match (self.interval, paused) {
+                (Some(interval), None) => events.recv_timeout(interval.saturating_sub(shown_since.elapsed())),
+                _ => events.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            let advance = match event {
+                Ok(Event::Member(update)) => {
+                    let member = update.position;
+                    messages[member] = update;
+                    false
+                },
+                Ok(Event::Rotate) | Err(RecvTimeoutError::Timeout) => true,
+                Ok(Event::Pause(_)) => {
+                    paused = paused.or(Some(shown_since.elapsed()));
+                    continue;
+                },
+                // The current member gets the rest of its turn
+                Ok(Event::Resume) => {
+                    if let Some(shown_for) = paused.take() {
+                        shown_since = Instant::now() - shown_for;
+                    }
+                    continue;
+                },
+                Ok(Event::Refresh) => {
+                    for thread in &threads {
+                        let _ = thread.send(Event::Refresh);
+                    }
+                    continue;
+                },
+                Ok(_) => false,
+                Err(RecvTimeoutError::Disconnected) => return,
+            };
+
+            // Moving on starts from the next member, while a member that has just
+            // hidden itself hands over to the next one with something to show
+            let start = if advance { current + 1 } else { current };
+            let next = (start..start + messages.len())
+                .map(|member| member % messages.len())
+                .find(|&member| !messages[member].message.is_empty());
+            if let Some(next) = next {
+                if next != current {
+                    shown_since = Instant::now();
+                }
+                current = next;
+            }
+            if advance {
+                shown_since = Instant::now();
+            }
+
+            let _ = sender.send(Update { position, ..messages[current].clone() });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::thread;
+
+    use toml;
+
+    use {Event, Update};
+    use super::Rotation;
+
+    fn update(position: usize, message: &str) -> Update {
+        Update { position, message: String::from(message), ..Update::default() }
+    }
+
+    #[test]
+    fn members_take_turns_skipping_empty_ones() {
+        let config = toml::Parser::new("items = [\"a\", \"b\", \"c\"]\ninterval = 0").parse().unwrap();
+        let rotation = Rotation::from_config("rotate", &config).unwrap();
+        let (sender, receiver) = channel();
+        let (events, member_events) = channel();
+        thread::spawn(move || rotation.run(7, sender, member_events, Vec::new()));
+
+        events.send(Event::Member(update(0, "first"))).unwrap();
+        assert_eq!(receiver.recv().unwrap(), update(7, "first"));
+        events.send(Event::Member(update(2, "third"))).unwrap();
+        assert_eq!(receiver.recv().unwrap(), update(7, "first"));
+        events.send(Event::Rotate).unwrap();
+        assert_eq!(receiver.recv().unwrap(), update(7, "third"));
+        events.send(Event::Rotate).unwrap();
+        assert_eq!(receiver.recv().unwrap(), update(7, "first"));
+        // A member hiding itself hands over at once
+        events.send(Event::Member(update(0, ""))).unwrap();
+        assert_eq!(receiver.recv().unwrap(), update(7, "third"));
+    }
+
+    #[test]
+    fn rotations_need_members() {
+        assert!(Rotation::from_config("rotate", &toml::Parser::new("items = []").parse().unwrap()).is_err());
+        assert!(Rotation::from_config("rotate", &toml::Parser::new("items = [\"a\"]\ninterval = -1").parse().unwrap()).is_err());
+        assert!(Rotation::from_config("rotate", &toml::Parser::new("items = [\"a\"]\ninterval = 0.01").parse().unwrap()).is_err());
+        assert!(Rotation::from_config("rotate", &toml::Parser::new("items = [\"a\"]\ninterval = 0.1").parse().unwrap()).is_ok());
+    }
+}
@@ -0,0 +1,157 @@
+//! Daily windows during which a section is allowed to run.
+//!
+//! A section's `separators` are hidden along with it, as they follow its
+//! `active_hours` unless they have their own.
+
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc;
+use toml::{self, Value};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ActiveHours {
+    /// Seconds after local midnight.
+    start: u64,
+    end: u64,
+}
+
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.trim().splitn(2, ':');
+    let hours: u64 = parts.next()?.parse().ok()?;
+    let minutes: u64 = parts.next()?.parse().ok()?;
+    if hours > 24 || minutes > 59 || (hours == 24 && minutes > 0) {
+        return None;
+    }
+    Some((hours * 60 + minutes) * 60)
+}
+
+fn seconds_of_day() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as libc::time_t;
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    unsafe { libc::localtime_r(&now, &mut tm) };
+    (tm.tm_hour as u64 * 60 + tm.tm_min as u64) * 60 + tm.tm_sec as u64
+}
+
+impl ActiveHours {
+    pub fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Option<ActiveHours>, String> {
+        let invalid = || format!("Invalid active_hours found for {}", section_name);
+        let hours = match configuration.get("active_hours") {
+            Some(Value::String(hours)) => hours,
+            Some(_) => return Err(invalid()),
+            None => return Ok(None),
+        };
+
+        let mut bounds = hours.splitn(2, '-');
+        let start = bounds.next().and_then(parse_time).ok_or_else(invalid)?;
+        let end = bounds.next().and_then(parse_time).ok_or_else(invalid)?;
+        if start == end {
+            return Err(invalid());
+        }
+        Ok(Some(ActiveHours { start: start % DAY, end: end % DAY }))
+    }
+
+    fn contains(&self, time: u64) -> bool {
+        if self.start < self.end {
+            self.start <= time && time < self.end
+        } else {
+            // The window wraps past midnight, as in 22:00-06:00
+            time >= self.start || time < self.end
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.contains(seconds_of_day())
+    }
+
+    /// How long until the window next opens or closes.
+    pub fn until_change(&self) -> Duration {
+        let now = seconds_of_day();
+        let until = |bound: u64| match (bound + DAY - now) % DAY {
+            0 => DAY,
+            seconds => seconds,
+        };
+        Duration::from_secs(until(self.start).min(until(self.end)))
+    }
+}
+
+/// Reads the sections named in `separators`.
+pub fn separators(section_name: &str, configuration: &toml::Table) -> Result<Vec<String>, String> {
+    let invalid = || format!("Invalid separators found for {}: expected the names of sections", section_name);
+    match configuration.get("separators") {
+        Some(Value::Array(separators)) => separators.iter()
+            .map(|separator| separator.as_str().map(str::to_owned))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid),
+        Some(_) => Err(invalid()),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// The window a section runs in: its own `active_hours`, or else those of
+/// the section that names it in `separators`.
+pub fn for_section(config_toml: &toml::Table, section_name: &str, configuration: &toml::Table) -> Result<Option<ActiveHours>, String> {
+    if let Some(hours) = ActiveHours::from_config(section_name, configuration)? {
+        return Ok(Some(hours));
+    }
+    for (owner_name, owner) in config_toml.iter().filter_map(|(name, value)| Some((name, value.as_table()?))) {
+        if separators(owner_name, owner)?.iter().any(|separator| separator == section_name) {
+            return ActiveHours::from_config(owner_name, owner);
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+
+    use super::{for_section, parse_time, ActiveHours};
+
+    fn table(source: &str) -> toml::Table {
+        toml::Parser::new(source).parse().unwrap()
+    }
+
+    #[test]
+    fn times_are_hours_and_minutes() {
+        assert_eq!(parse_time("00:00"), Some(0));
+        assert_eq!(parse_time(" 9:30 "), Some((9 * 60 + 30) * 60));
+        assert_eq!(parse_time("24:00"), Some(24 * 60 * 60));
+        assert_eq!(parse_time("24:01"), None);
+        assert_eq!(parse_time("12:60"), None);
+        assert_eq!(parse_time("12"), None);
+        assert_eq!(parse_time("noon"), None);
+    }
+
+    #[test]
+    fn active_hours_are_read_from_the_section() {
+        let hours = |source: &str| ActiveHours::from_config("section", &table(source));
+        assert_eq!(hours("active_hours = \"09:00-17:30\""), Ok(Some(ActiveHours { start: 9 * 3600, end: 17 * 3600 + 1800 })));
+        assert_eq!(hours("active_hours = \"22:00-24:00\""), Ok(Some(ActiveHours { start: 22 * 3600, end: 0 })));
+        assert_eq!(hours(""), Ok(None));
+        assert!(hours("active_hours = \"09:00-09:00\"").is_err());
+        assert!(hours("active_hours = \"09:00\"").is_err());
+        assert!(hours("active_hours = 9").is_err());
+    }
+
+    #[test]
+    fn windows_can_wrap_past_midnight() {
+        let day = ActiveHours { start: 9 * 3600, end: 17 * 3600 };
+        assert!(day.contains(9 * 3600));
+        assert!(!day.contains(17 * 3600));
+        let night = ActiveHours { start: 22 * 3600, end: 6 * 3600 };
+        assert!(night.contains(23 * 3600));
+        assert!(night.contains(3600));
+        assert!(!night.contains(12 * 3600));
+    }
+
+    #[test]
+    fn separators_follow_their_section() {
+        let config = table("[clock]\nactive_hours = \"08:00-18:00\"\nseparators = [\"bar\"]\n[bar]\n[other]\n");
+        let section = |name: &str| for_section(&config, name, config[name].as_table().unwrap());
+        assert_eq!(section("bar"), Ok(Some(ActiveHours { start: 8 * 3600, end: 18 * 3600 })));
+        assert_eq!(section("other"), Ok(None));
+    }
+}
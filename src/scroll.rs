@@ -0,0 +1,193 @@
+//! Scrolling sections, whose output is shown through a window of a fixed
+//! width that moves along it whenever it's too long to fit.
+//!
+//! The sections run as usual, but report to a scroller's thread rather than
+//! to the bar, which sends the bar each frame in turn. Output that fits is
+//! passed on unchanged. The output is scrolled through as the script printed
+//! it, and each frame escaped as the section's `escape` says, so that a frame
+//! never ends partway through an escape sequence. While updates are paused,
+//! the frames stay where they are.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use toml::{self, Value};
+
+use {paused, Escape, Update};
+
+#[derive(Debug, Clone)]
+pub struct Scroll {
+    /// How many characters are shown at once.
+    width: usize,
+    /// How long each frame is shown for.
+    interval: Duration,
+    /// What comes between the end of the output and its start coming round again.
+    separator: String,
+    escape: Escape,
+}
+
+impl Scroll {
+    /// Reads a section's `scroll` table, if it has one.
+    pub fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Option<Scroll>, String> {
+        let scroll = match configuration.get("scroll") {
+            Some(Value::Table(scroll)) => scroll,
+            Some(_) => return Err(format!("Invalid scroll found for {}: expected a table, such as {{ width = 30 }}", section_name)),
+            None => return Ok(None),
+        };
+
+        let width = match scroll.get("width") {
+            Some(&Value::Integer(width)) if width > 0 => width as usize,
+            Some(_) => return Err(format!("Invalid scroll width found for {}: expected a number of characters above 0", section_name)),
+            None => return Err(format!("Failed to find scroll width for {}", section_name)),
+        };
+        let interval = match scroll.get("interval") {
+            Some(&Value::Integer(seconds)) if seconds > 0 => Duration::from_secs(seconds as u64),
+            Some(&Value::Float(seconds)) if seconds > 0f64 => Duration::from_millis((seconds * 1000f64) as u64).max(Duration::from_millis(1)),
+            Some(_) => return Err(format!("Invalid scroll interval found for {}: expected a number of seconds above 0", section_name)),
+            None => Duration::from_millis(250),
+        };
+        let separator = match scroll.get("separator").map(Value::as_str) {
+            Some(Some(separator)) => separator.to_owned(),
+            Some(None) => return Err(format!("Invalid scroll separator found for {}", section_name)),
+            None => String::from("   "),
+        };
+
+        let escape = Escape::from_config(section_name, configuration)?;
+
+        Ok(Some(Scroll { width, interval, separator, escape }))
+    }
+
+    /// The frame `offset` characters along `message`, escaped.
+    fn frame(&self, message: &[char], offset: usize) -> String {
+        let separator = self.separator.chars().collect::<Vec<_>>();
+        self.escape.apply(&message.iter().chain(&separator).cycle().skip(offset).take(self.width).collect::<String>())
+    }
+}
+
+/// A section's output as it's scrolled through.
+struct Scrolling {
+    scroll: Scroll,
+    /// The update being scrolled through, whose message is sent a frame at a time.
+    update: Update,
+    message: Vec<char>,
+    offset: usize,
+    next_frame: Instant,
+}
+
+impl Scrolling {
+    fn fits(&self) -> bool {
+        self.message.len() <= self.scroll.width
+    }
+
+    /// How many frames it takes to come back round to the start.
+    fn length(&self) -> usize {
+        self.message.len() + self.scroll.separator.chars().count()
+    }
+}
+
+/// Returns where the sections at these positions should send their output:
+/// `sender` itself if none of them scroll, or else a scroller that passes
+/// the output on to it a frame at a time.
+pub fn scrolled(sender: &Sender<Update>, scrolls: Vec<(usize, Scroll)>) -> Sender<Update> {
+    if scrolls.is_empty() {
+        return sender.clone();
+    }
+
+    let (scroller, updates) = channel::<Update>();
+    let sender = sender.clone();
+    let mut sections = scrolls.into_iter().map(|(position, scroll)| {
+        (position, Scrolling { scroll, update: Update::default(), message: Vec::new(), offset: 0, next_frame: Instant::now() })
+    }).collect::<HashMap<_, _>>();
+
+    let _ = thread::Builder::new().name(String::from("scroll")).spawn(move || {
+        loop {
+            let next_frame = sections.values().filter(|section| !section.fits()).map(|section| section.next_frame).min();
+            let update = match next_frame {
+                Some(next_frame) => updates.recv_timeout(next_frame.saturating_duration_since(Instant::now())),
+                None => updates.recv().map_err(|_| RecvTimeoutError::Disconnected),
+            };
+
+            match update {
+                Ok(update) => match sections.get_mut(&update.position) {
+                    Some(section) => {
+                        if update == section.update {
+                            continue;
+                        }
+                        // New output starts again from the beginning
+                        section.message = update.message.chars().collect();
+                        section.offset = 0;
+                        section.next_frame = Instant::now() + section.scroll.interval;
+                        let message = match section.fits() {
+                            true => section.scroll.escape.apply(&update.message),
+                            false => section.scroll.frame(&section.message, 0),
+                        };
+                        let _ = sender.send(Update { message, ..update.clone() });
+                        section.update = update;
+                    },
+                    None => {
+                        let _ = sender.send(update);
+                    },
+                },
+                Err(RecvTimeoutError::Timeout) => {
+                    let now = Instant::now();
+                    for (&position, section) in sections.iter_mut().filter(|(_, section)| !section.fits() && section.next_frame <= now) {
+                        if paused() {
+                            section.next_frame = now + section.scroll.interval;
+                            continue;
+                        }
+                        section.offset = (section.offset + 1) % section.length();
+                        section.next_frame = now + section.scroll.interval;
+                        let _ = sender.send(Update { position, message: section.scroll.frame(&section.message, section.offset), ..section.update.clone() });
+                    }
+                },
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    });
+    scroller
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use toml;
+
+    use Escape;
+    use super::Scroll;
+
+    fn scroll(escape: Escape) -> Scroll {
+        Scroll { width: 4, interval: Duration::from_millis(250), separator: String::from(" | "), escape }
+    }
+
+    #[test]
+    fn frames_wrap_round_through_the_separator() {
+        let message = "abcdef".chars().collect::<Vec<_>>();
+        let scroll = scroll(Escape::None);
+        assert_eq!(scroll.frame(&message, 0), "abcd");
+        assert_eq!(scroll.frame(&message, 4), "ef |");
+        assert_eq!(scroll.frame(&message, 7), "| ab");
+        // After the message and separator, it comes back round to the start
+        assert_eq!(scroll.frame(&message, 9), "abcd");
+    }
+
+    #[test]
+    fn scroll_tables_need_a_width() {
+        let parse = |source: &str| toml::Parser::new(source).parse().unwrap();
+        let scroll = Scroll::from_config("mpd", &parse("scroll = { width = 20, interval = 0.5 }")).unwrap().unwrap();
+        assert_eq!((scroll.width, scroll.interval, scroll.separator.as_str()), (20, Duration::from_millis(500), "   "));
+        assert!(Scroll::from_config("mpd", &toml::Table::new()).unwrap().is_none());
+        assert!(Scroll::from_config("mpd", &parse("scroll = { interval = 1 }")).is_err());
+        assert!(Scroll::from_config("mpd", &parse("scroll = 20")).is_err());
+    }
+
+    #[test]
+    fn frames_are_escaped_after_scrolling() {
+        let message = "50%{done}".chars().collect::<Vec<_>>();
+        let scroll = scroll(Escape::Lemonbar);
+        assert_eq!(scroll.frame(&message, 0), "50%%{");
+        assert_eq!(scroll.frame(&message, 2), "%%{do");
+    }
+}
@@ -0,0 +1,129 @@
+//! Refreshing sections when Admiral is sent a signal: every section on
+//! SIGUSR1, as by `pkill -USR1 admiral` after resuming from suspend, and a
+//! section with `signal = n` on SIGRTMIN+n, as i3blocks does.
+//!
+//! The handler only writes the signal's number to a pipe, as little else is
+//! safe to do inside one, and a thread of its own reads it back and passes
+//! the refresh on to the sections.
+
+use std::fs::File;
+use std::io::{stderr, Read, Write};
+use std::mem;
+use std::os::unix::io::FromRawFd;
+use std::ptr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use libc;
+use toml::{self, Value};
+
+use Event;
+
+/// The end of the pipe that the handler writes to.
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn handle(signal: libc::c_int) {
+    let byte = signal as u8;
+    unsafe {
+        // The write mustn't change errno under the code the signal interrupted
+        let errno = *libc::__errno_location();
+        libc::write(PIPE.load(Ordering::Relaxed), &byte as *const u8 as *const libc::c_void, 1);
+        *libc::__errno_location() = errno;
+    }
+}
+
+fn install(signal: libc::c_int) -> bool {
+    unsafe {
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = handle as *const () as libc::sighandler_t;
+        // Whatever the signal interrupts carries on afterwards
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, ptr::null_mut()) == 0
+    }
+}
+
+/// Reads the real-time signal that refreshes a section, given by its offset from SIGRTMIN.
+pub fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Option<libc::c_int>, String> {
+    let highest = libc::SIGRTMAX() - libc::SIGRTMIN();
+    match configuration.get("signal") {
+        Some(&Value::Integer(offset)) if offset >= 1 && offset <= highest as i64 => Ok(Some(libc::SIGRTMIN() + offset as libc::c_int)),
+        Some(_) => Err(format!("Invalid signal found for {}: expected a number from 1 to {}", section_name, highest)),
+        None => Ok(None),
+    }
+}
+
+/// Refreshes every section on SIGUSR1, and the sections run by each of
+/// `signalled` on its signal. `threads` has one sender for each thread
+/// running sections, so that sections sharing one aren't run twice.
+pub fn listen(threads: Vec<Sender<Event>>, signalled: Vec<(libc::c_int, Vec<Sender<Event>>)>) {
+    let mut ends = [0; 2];
+    if unsafe { libc::pipe2(ends.as_mut_ptr(), libc::O_CLOEXEC) } < 0 {
+        let _ = stderr().write("Could not listen for SIGUSR1\n".as_bytes());
+        return;
+    }
+    // A full pipe drops the signal rather than blocking the handler, as the
+    // reader already has one to handle
+    unsafe { libc::fcntl(ends[1], libc::F_SETFL, libc::O_NONBLOCK) };
+    PIPE.store(ends[1], Ordering::Relaxed);
+    if !install(libc::SIGUSR1) {
+        let _ = stderr().write("Could not listen for SIGUSR1\n".as_bytes());
+    }
+    for &(signal, _) in &signalled {
+        if !install(signal) {
+            let _ = stderr().write(format!("Could not listen for SIGRTMIN+{}\n", signal - libc::SIGRTMIN()).as_bytes());
+        }
+    }
+    let mut pipe = unsafe { File::from_raw_fd(ends[0]) };
+
+    let _ = thread::Builder::new().name(String::from("signals")).spawn(move || {
+        let mut signal = [0u8];
+        while pipe.read_exact(&mut signal).is_ok() {
+            let refreshed = match signal[0] as libc::c_int {
+                libc::SIGUSR1 => threads.iter().collect::<Vec<_>>(),
+                signal => signalled.iter().filter(|&&(wanted, _)| wanted == signal).flat_map(|(_, runners)| runners).collect(),
+            };
+            for thread in refreshed {
+                let _ = thread.send(Event::Refresh);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use libc;
+    use toml;
+
+    use Event;
+    use super::{from_config, listen};
+
+    #[test]
+    fn signals_refresh_the_threads_that_want_them() {
+        let (first, first_events) = channel();
+        let (second, second_events) = channel();
+        let signal = libc::SIGRTMIN() + 3;
+        listen(vec![first, second.clone()], vec![(signal, vec![second])]);
+        unsafe { libc::raise(signal) };
+        assert!(matches!(second_events.recv_timeout(Duration::from_secs(5)), Ok(Event::Refresh)));
+        assert!(first_events.try_recv().is_err());
+
+        // Every thread, whatever its own signal, is refreshed on SIGUSR1
+        unsafe { libc::raise(libc::SIGUSR1) };
+        assert!(matches!(first_events.recv_timeout(Duration::from_secs(5)), Ok(Event::Refresh)));
+        assert!(matches!(second_events.recv_timeout(Duration::from_secs(5)), Ok(Event::Refresh)));
+    }
+
+    #[test]
+    fn signals_are_counted_from_sigrtmin() {
+        let parse = |source: &str| toml::Parser::new(source).parse().unwrap();
+        assert_eq!(from_config("volume", &parse("signal = 10")), Ok(Some(libc::SIGRTMIN() + 10)));
+        assert_eq!(from_config("volume", &toml::Table::new()), Ok(None));
+        assert!(from_config("volume", &parse("signal = 0")).is_err());
+        assert!(from_config("volume", &parse("signal = 99")).is_err());
+    }
+}
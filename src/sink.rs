@@ -0,0 +1,635 @@
+//! Where the bar's lines go once they're rendered: standard output, the X
+//! root window's name, tmux's status line, a named pipe, the clients of a
+//! socket, a file, or a bar that Admiral runs itself.
+
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, sync_channel, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, sleep};
+use std::time::Duration;
+
+use libc;
+use toml::{self, Value};
+
+use output::{self, Mode};
+use x11::RootWindow;
+
+pub enum Sink {
+    /// Standard output, ending each line with the terminator.
+    Stdout(&'static str),
+    /// Standard output, redrawing the line in place, for `--preview`.
+    Preview,
+    RootWindow(RootWindow),
+    Tmux(Tmux),
+    Fifo(Fifo),
+    Socket(Socket),
+    OutputFile(OutputFile),
+    /// A bar Admiral runs itself, from `[admiral.bar]`.
+    Bar(Bar),
+}
+
+impl Sink {
+    pub fn send(&mut self, line: &str) -> io::Result<()> {
+        match *self {
+            Sink::Stdout(terminator) => {
+                // Flushed every time, since a consumer reading records that end in
+                // NUL, or a pipe it's buffered through, would otherwise lag behind
+                let stdout = io::stdout();
+                let mut stdout = stdout.lock();
+                write!(stdout, "{}{}", line, terminator)?;
+                stdout.flush()
+            },
+            Sink::Preview => {
+                // Back to the start of the line, which is cleared before drawing over it
+                print!("\r\x1b[2K{}", line);
+                io::stdout().flush()
+            },
+            Sink::RootWindow(ref mut window) => window.set_name(line),
+            Sink::Tmux(ref tmux) => {
+                let _ = tmux.lines.send(line.to_owned());
+                Ok(())
+            },
+            Sink::Bar(ref bar) => {
+                let _ = bar.lines.send(line.to_owned());
+                Ok(())
+            },
+            Sink::Fifo(ref fifo) => {
+                let _ = fifo.lines.send(line.to_owned());
+                Ok(())
+            },
+            Sink::Socket(ref socket) => {
+                socket.broadcast(line);
+                Ok(())
+            },
+            Sink::OutputFile(ref file) => file.write(line),
+        }
+    }
+
+    /// Another handle on the same sink, for the panic hook to send a last line to.
+    pub fn try_clone(&self) -> Option<Sink> {
+        Some(match *self {
+            Sink::Stdout(terminator) => Sink::Stdout(terminator),
+            Sink::Preview => Sink::Preview,
+            Sink::RootWindow(ref window) => Sink::RootWindow(window.try_clone()?),
+            Sink::Tmux(ref tmux) => Sink::Tmux(tmux.clone()),
+            Sink::Fifo(ref fifo) => Sink::Fifo(fifo.clone()),
+            Sink::Socket(ref socket) => Sink::Socket(socket.clone()),
+            Sink::OutputFile(ref file) => Sink::OutputFile(file.clone()),
+            Sink::Bar(ref bar) => Sink::Bar(bar.clone()),
+        })
+    }
+
+    /// Whether lines are written by a thread of the sink's own, after `send` returns.
+    pub fn is_threaded(&self) -> bool {
+        matches!(*self, Sink::Fifo(_) | Sink::Tmux(_) | Sink::Bar(_))
+    }
+}
+
+fn path(table: &toml::Table, key: &str, context: &str) -> Result<Option<PathBuf>, String> {
+    match table.get(key).map(Value::as_str) {
+        Some(Some(path)) => Ok(Some(PathBuf::from(path))),
+        Some(None) => Err(format!("Invalid {} found in {}: expected a path", key, context)),
+        None => Ok(None),
+    }
+}
+
+/// What comes after each line: a newline, or NUL for consumers that read
+/// records delimited by it.
+fn terminator(table: &toml::Table, context: &str) -> Result<&'static str, String> {
+    match table.get("terminator").map(Value::as_str) {
+        Some(Some("newline")) | None => Ok("\n"),
+        Some(Some("nul")) => Ok("\0"),
+        Some(_) => Err(format!("Invalid terminator found in {}: expected \"newline\" or \"nul\"", context)),
+    }
+}
+
+/// Opens a sink for lines in `mode`, sent to a pipe, socket, or file if
+/// there's a path for one, or else printed (or set as the root window's name
+/// or as a tmux option), unless this is a daemon that prints nothing.
+fn open(mode: Mode, fifo: Option<PathBuf>, socket: Option<PathBuf>, file: Option<PathBuf>, table: &toml::Table, daemon: bool, context: &str) -> Result<Option<Sink>, String> {
+    let tmux_option = match table.get("tmux_option").map(Value::as_str) {
+        Some(Some(option)) => option.to_owned(),
+        Some(None) => return Err(format!("Invalid tmux_option found in {}: expected the name of an option, such as \"status-left\"", context)),
+        None => String::from("status-right"),
+    };
+    let terminator = terminator(table, context)?;
+    match (mode, fifo, socket, file) {
+        (_, Some(_), Some(_), _) | (_, Some(_), _, Some(_)) | (_, _, Some(_), Some(_)) => {
+            Err(format!("Failed to use more than one of a pipe, a socket, and a file for one output in {}", context))
+        },
+        (Mode::Xroot, None, None, None) if daemon => Ok(None),
+        (Mode::Xroot, None, None, None) => RootWindow::open().map(|window| Some(Sink::RootWindow(window))),
+        (Mode::Xroot, _, _, _) => Err(format!("Failed to use a pipe, a socket, or a file with output = \"xroot\" in {}", context)),
+        (Mode::Tmux, None, None, None) if daemon => Ok(None),
+        (Mode::Tmux, None, None, None) => Tmux::start(tmux_option).map(|tmux| Some(Sink::Tmux(tmux))),
+        (_, Some(path), None, None) => Fifo::start(path, mode.header(), terminator).map(|fifo| Some(Sink::Fifo(fifo))),
+        (_, None, Some(path), None) => Socket::start(path, mode.header(), terminator).map(|socket| Some(Sink::Socket(socket))),
+        (_, None, None, Some(path)) => OutputFile::new(path, terminator).map(|file| Some(Sink::OutputFile(file))),
+        (_, None, None, None) if daemon => Ok(None),
+        (_, None, None, None) => Ok(Some(Sink::Stdout(terminator))),
+    }
+}
+
+fn mode(table: &toml::Table, context: &str) -> Result<Mode, String> {
+    match table.get("output").map(Value::as_str) {
+        None => Ok(Mode::Plain),
+        Some(name) => name.and_then(Mode::from_name)
+            .ok_or_else(|| format!("Invalid output found in {}: expected one of {}", context, output::MODES.join(", "))),
+    }
+}
+
+/// Reads where a bar's lines go, and in which output mode, from its table,
+/// named `name`: `[admiral]`, or one of `[bars]`. Each table of its `outputs`
+/// array is one sink, with its own `output` mode and a `fifo`, `socket`, or
+/// `file` path. Without any, the table's own `output`, `output_fifo`,
+/// `output_socket`, and `output_file` describe the only sink, which isn't printed if a `bar`
+/// table has Admiral run the bar itself.
+pub fn from_config(table: &toml::Table, name: &str, daemon: bool) -> Result<Vec<(Mode, Sink)>, String> {
+    let bar = match table.get("bar") {
+        Some(Value::Table(bar)) => {
+            let context = format!("[{}.bar]", name);
+            let mode = mode(bar, &context)?;
+            Some((mode, Sink::Bar(Bar::start(bar, mode.header(), &context)?)))
+        },
+        Some(_) => return Err(format!("Invalid bar found in [{0}]: expected a table, as in [{0}.bar]", name)),
+        None => None,
+    };
+
+    let mut sinks = Vec::new();
+    match table.get("outputs") {
+        Some(Value::Array(outputs)) => {
+            if ["output", "output_fifo", "output_socket", "output_file"].iter().any(|key| table.contains_key(*key)) {
+                return Err(format!("Failed to read [{0}]: output, output_fifo, output_socket, and output_file belong in [[{0}.outputs]] when it is used", name));
+            }
+            for (index, output) in outputs.iter().enumerate() {
+                let context = format!("output {} of [[{}.outputs]]", index + 1, name);
+                let output = output.as_table().ok_or_else(|| format!("Invalid {}: expected a table", context))?;
+                let mode = mode(output, &context)?;
+                if let Some(sink) = open(mode, path(output, "fifo", &context)?, path(output, "socket", &context)?, path(output, "file", &context)?, output, daemon, &context)? {
+                    sinks.push((mode, sink));
+                }
+            }
+        },
+        Some(_) => return Err(format!("Invalid outputs found in [{0}]: expected an array of tables, as in [[{0}.outputs]]", name)),
+        None => {
+            let context = format!("[{}]", name);
+            let mode = mode(table, &context)?;
+            let sink = open(mode, path(table, "output_fifo", &context)?, path(table, "output_socket", &context)?, path(table, "output_file", &context)?, table, daemon || bar.is_some(), &context)?;
+            sinks.extend(sink.map(|sink| (mode, sink)));
+        },
+    }
+    // Two outputs printing at once would garble each other's lines
+    if sinks.iter().filter(|(_, sink)| matches!(*sink, Sink::Stdout(_))).count() > 1 {
+        return Err(format!("Failed to read [[{}.outputs]]: only one output can be printed, the rest need a fifo, socket, or file", name));
+    }
+    sinks.extend(bar);
+    Ok(sinks)
+}
+
+/// The output mode of whichever of a bar's outputs would be printed, or
+/// plain text if none would be.
+pub fn printed_mode(table: &toml::Table, name: &str) -> Result<Mode, String> {
+    match table.get("outputs").and_then(Value::as_slice) {
+        Some(outputs) => {
+            let printed = outputs.iter().filter_map(Value::as_table)
+                .find(|output| !["fifo", "socket", "file"].iter().any(|key| output.contains_key(*key)));
+            match printed {
+                Some(output) => mode(output, &format!("[[{}.outputs]]", name)),
+                None => Ok(Mode::Plain),
+            }
+        },
+        None => mode(table, &format!("[{}]", name)),
+    }
+}
+
+/// How long to wait before starting the bar again after it exits.
+const RESTART: Duration = Duration::from_secs(1);
+
+/// A bar that Admiral runs itself, such as lemonbar, writing lines to its
+/// input from a thread of its own. The bar is started again whenever it
+/// exits, and sent the header and the latest line first. Each line the bar
+/// prints, as lemonbar does when a `%{A:command:}` area is clicked, is run
+/// as a command.
+#[derive(Clone)]
+pub struct Bar {
+    lines: Sender<String>,
+}
+
+impl Bar {
+    fn start(bar: &toml::Table, header: Option<&'static str>, context: &str) -> Result<Bar, String> {
+        let command = match bar.get("command").map(Value::as_str) {
+            Some(Some(command)) if !command.trim().is_empty() => command.to_owned(),
+            Some(_) => return Err(format!("Invalid command found in {}: expected the command that starts the bar", context)),
+            None => return Err(format!("Failed to find command in {}", context)),
+        };
+        let (lines, receiver) = channel::<String>();
+
+        thread::Builder::new().name(String::from("bar")).spawn(move || {
+            let mut latest: Option<String> = None;
+            loop {
+                let mut child = match Command::new("sh").arg("-c").arg(&command).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn() {
+                    Ok(child) => child,
+                    Err(e) => {
+                        let _ = io::stderr().write(format!("Failed to run the bar {}: {}\n", command, e).as_bytes());
+                        sleep(RESTART);
+                        continue;
+                    },
+                };
+                let mut input = child.stdin.take().unwrap();
+                let clicks = BufReader::new(child.stdout.take().unwrap());
+                thread::spawn(move || {
+                    for click in clicks.lines().map_while(Result::ok) {
+                        if !click.trim().is_empty() {
+                            run_click(click);
+                        }
+                    }
+                });
+
+                while let Ok(line) = receiver.try_recv() {
+                    latest = Some(line);
+                }
+                let mut sent = header.is_none_or(|header| writeln!(input, "{}", header).is_ok());
+                sent = sent && latest.as_ref().is_none_or(|line| writeln!(input, "{}", line).is_ok());
+                while sent {
+                    match receiver.recv_timeout(READER_CHECK) {
+                        Ok(line) => {
+                            sent = writeln!(input, "{}", line).is_ok();
+                            latest = Some(line);
+                        },
+                        Err(RecvTimeoutError::Timeout) => sent = child.try_wait().is_ok_and(|status| status.is_none()),
+                        Err(RecvTimeoutError::Disconnected) => {
+                            let _ = child.kill();
+                            let _ = child.wait();
+                            return;
+                        },
+                    }
+                }
+
+                drop(input);
+                let _ = child.kill();
+                let _ = child.wait();
+                let _ = io::stderr().write(format!("The bar {} exited; starting it again\n", command).as_bytes());
+                sleep(RESTART);
+            }
+        }).map_err(|e| format!("Failed to run the bar: {}", e))?;
+        Ok(Bar { lines })
+    }
+}
+
+/// Runs a command the bar printed, without waiting for it to finish.
+fn run_click(command: String) {
+    thread::spawn(move || {
+        if let Ok(mut child) = Command::new("sh").arg("-c").arg(&command).spawn() {
+            let _ = child.wait();
+        }
+    });
+}
+
+/// A tmux option that lines are set to by a thread of its own, so that a slow
+/// or stuck `tmux` doesn't hold up the bar. Lines sent while `tmux` is still
+/// running are skipped, leaving only the newest to be set next.
+#[derive(Clone)]
+pub struct Tmux {
+    lines: Sender<String>,
+}
+
+impl Tmux {
+    /// Starts setting `option`, such as `status-right`, to each line.
+    fn start(option: String) -> Result<Tmux, String> {
+        let (lines, receiver) = channel::<String>();
+
+        thread::Builder::new().name(String::from("tmux")).spawn(move || {
+            while let Ok(mut line) = receiver.recv() {
+                while let Ok(newer) = receiver.try_recv() {
+                    line = newer;
+                }
+                // tmux may not be running yet, or may have been restarted, so failures are left for the next line
+                let _ = Command::new("tmux").args(["set-option", "-g", &option, &line]).stdout(Stdio::null()).stderr(Stdio::null()).status();
+            }
+        }).map_err(|e| format!("Failed to start setting tmux's status: {}", e))?;
+        Ok(Tmux { lines })
+    }
+}
+
+/// How often to check whether a FIFO's reader has gone away while there's nothing to write.
+const READER_CHECK: Duration = Duration::from_millis(250);
+
+/// Creates a named pipe at `path` if there's nothing there, failing if there's something else.
+fn make_fifo(path: &Path) -> Result<(), String> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => Ok(()),
+        Ok(_) => Err(format!("Failed to use {} as output_fifo: it isn't a named pipe", path.display())),
+        Err(_) => {
+            let name = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+            if unsafe { libc::mkfifo(name.as_ptr(), 0o600) } < 0 {
+                return Err(format!("Failed to create output_fifo {}: {}", path.display(), io::Error::last_os_error()));
+            }
+            Ok(())
+        },
+    }
+}
+
+/// Whether the last reader has closed the pipe, which Linux reports as an
+/// error on the writing end. Until the pipe is written to or this is checked,
+/// a new reader would share the old pipe and miss the header.
+fn reader_left(pipe: &File) -> bool {
+    let mut poll = libc::pollfd { fd: pipe.as_raw_fd(), events: libc::POLLOUT, revents: 0 };
+    unsafe { libc::poll(&mut poll, 1, 0) > 0 && poll.revents & libc::POLLERR != 0 }
+}
+
+/// A named pipe that lines are written to by a thread of its own, so that
+/// the bar doesn't wait for a reader. The pipe can only be written to while
+/// a reader has it open, so once one goes away, the thread opens it again and
+/// waits for the next, which is sent the header and the latest line first.
+#[derive(Clone)]
+pub struct Fifo {
+    lines: Sender<String>,
+}
+
+impl Fifo {
+    pub fn start(path: PathBuf, header: Option<&'static str>, terminator: &'static str) -> Result<Fifo, String> {
+        make_fifo(&path)?;
+        let (lines, receiver) = channel::<String>();
+
+        let _ = thread::Builder::new().name(String::from("output_fifo")).spawn(move || {
+            let mut latest: Option<String> = None;
+            loop {
+                // Opening the pipe for writing waits for a reader
+                let mut pipe = match make_fifo(&path).ok().and_then(|_| OpenOptions::new().write(true).open(&path).ok()) {
+                    Some(pipe) => pipe,
+                    None => {
+                        sleep(Duration::from_secs(1));
+                        continue;
+                    },
+                };
+                // Only the newest of the lines sent while waiting matters
+                while let Ok(line) = receiver.try_recv() {
+                    latest = Some(line);
+                }
+                if header.is_some_and(|header| write!(pipe, "{}{}", header, terminator).is_err()) {
+                    continue;
+                }
+                if latest.as_ref().is_some_and(|line| write!(pipe, "{}{}", line, terminator).is_err()) {
+                    continue;
+                }
+
+                loop {
+                    let line = match receiver.recv_timeout(READER_CHECK) {
+                        Ok(line) => line,
+                        Err(RecvTimeoutError::Timeout) if reader_left(&pipe) => break,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    };
+                    let written = write!(pipe, "{}{}", line, terminator).is_ok();
+                    latest = Some(line);
+                    if !written {
+                        break;
+                    }
+                }
+            }
+        });
+        Ok(Fifo { lines })
+    }
+}
+
+/// How many lines may wait for a socket's client before it's taken to have
+/// stopped reading and is dropped.
+const CLIENT_BACKLOG: usize = 16;
+
+/// The clients connected to a socket, as the queues their writers take lines
+/// from, and the latest line sent to them.
+#[derive(Default)]
+struct Clients {
+    latest: Option<String>,
+    queues: Vec<SyncSender<String>>,
+}
+
+/// A socket that sends every line to each program connected to it, starting
+/// with the header and the latest line. Each client is written to by a thread
+/// of its own, so that one that stops reading holds up neither the bar nor
+/// the others.
+#[derive(Clone)]
+pub struct Socket {
+    clients: Arc<Mutex<Clients>>,
+}
+
+impl Socket {
+    pub fn start(path: PathBuf, header: Option<&'static str>, terminator: &'static str) -> Result<Socket, String> {
+        if UnixStream::connect(&path).is_ok() {
+            return Err(format!("Failed to use {} as output_socket: something is already listening on it", path.display()));
+        }
+        // Anything left at the path is a stale socket from an instance that has exited
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| format!("Failed to create output_socket {}: {}", path.display(), e))?;
+
+        let clients = Arc::new(Mutex::new(Clients::default()));
+        let accepted = clients.clone();
+        let _ = thread::Builder::new().name(String::from("output_socket")).spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(_) => continue,
+                };
+                let (queue, lines) = sync_channel(CLIENT_BACKLOG);
+                {
+                    let mut clients = accepted.lock().unwrap();
+                    if let Some(ref line) = clients.latest {
+                        let _ = queue.try_send(line.clone());
+                    }
+                    clients.queues.push(queue);
+                }
+                let _ = thread::Builder::new().name(String::from("output_socket client")).spawn(move || write_client(stream, lines, header, terminator));
+            }
+        });
+        Ok(Socket { clients })
+    }
+
+    /// Queues a line for every client, dropping those that have gone away or
+    /// have fallen too far behind.
+    fn broadcast(&self, line: &str) {
+        let mut clients = self.clients.lock().unwrap();
+        clients.latest = Some(line.to_owned());
+        clients.queues.retain(|queue| queue.try_send(line.to_owned()).is_ok());
+    }
+}
+
+/// Writes the header and then each line queued for a socket's client, until
+/// it goes away or its queue is dropped.
+fn write_client(mut stream: UnixStream, lines: Receiver<String>, header: Option<&'static str>, terminator: &'static str) {
+    // A client that stops reading altogether is given up on, so that its thread finishes
+    let _ = stream.set_write_timeout(Some(Duration::from_secs(10)));
+    if header.is_some_and(|header| write!(stream, "{}{}", header, terminator).is_err()) {
+        return;
+    }
+    for line in lines {
+        if write!(stream, "{}{}", line, terminator).is_err() {
+            return;
+        }
+    }
+}
+
+/// A file that holds the latest line, for programs that read it whenever they
+/// like rather than following along. Each line is written to a temporary file
+/// beside it, which then replaces it, so a reader never sees half a line.
+#[derive(Clone)]
+pub struct OutputFile {
+    path: PathBuf,
+    temporary: PathBuf,
+    terminator: &'static str,
+}
+
+impl OutputFile {
+    pub fn new(path: PathBuf, terminator: &'static str) -> Result<OutputFile, String> {
+        let name = match path.file_name() {
+            Some(name) => name.to_string_lossy().into_owned(),
+            None => return Err(format!("Invalid output_file {}: expected the path of a file", path.display())),
+        };
+        let temporary = path.with_file_name(format!(".{}.tmp", name));
+        Ok(OutputFile { path, temporary, terminator })
+    }
+
+    fn write(&self, line: &str) -> io::Result<()> {
+        let written = File::create(&self.temporary)
+            .and_then(|mut file| write!(file, "{}{}", line, self.terminator))
+            .and_then(|_| fs::rename(&self.temporary, &self.path));
+        written.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", self.path.display(), e)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::process;
+    use std::thread::sleep;
+    use std::time::{Duration, Instant};
+
+    use toml;
+
+    use output::Mode;
+    use super::{from_config, terminator, Bar, Fifo, OutputFile, Sink, Socket};
+
+    #[test]
+    fn fifo_readers_get_the_header_and_each_line() {
+        let path = env::temp_dir().join(format!("admiral-test-{}.fifo", process::id()));
+        let _ = fs::remove_file(&path);
+        let fifo = Fifo::start(path.clone(), Some("header"), "\n").unwrap();
+        fifo.lines.send(String::from("first")).unwrap();
+        let mut lines = BufReader::new(File::open(&path).unwrap()).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "header");
+        assert_eq!(lines.next().unwrap().unwrap(), "first");
+        fifo.lines.send(String::from("second")).unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "second");
+        let _ = fs::remove_file(&path);
+
+        let file = env::temp_dir().join(format!("admiral-test-{}.not-fifo", process::id()));
+        fs::write(&file, "").unwrap();
+        assert!(Fifo::start(file.clone(), None, "\n").is_err());
+        let _ = fs::remove_file(&file);
+    }
+
+    #[test]
+    fn each_output_is_a_sink_of_its_own() {
+        let path = env::temp_dir().join(format!("admiral-test-{}.outputs", process::id()));
+        let parse = |source: &str| toml::Parser::new(source).parse().unwrap();
+        let source = format!("[[outputs]]\noutput = \"i3bar\"\n[[outputs]]\noutput = \"json\"\nfifo = \"{}\"", path.display());
+        let sinks = from_config(&parse(&source), "admiral", false).unwrap();
+        assert_eq!(sinks.iter().map(|&(mode, _)| mode).collect::<Vec<_>>(), [Mode::I3bar, Mode::Json]);
+        assert!(matches!(sinks[0].1, Sink::Stdout("\n")));
+        assert!(matches!(sinks[1].1, Sink::Fifo(_)));
+        let _ = fs::remove_file(&path);
+
+        assert!(from_config(&parse("[[outputs]]\n[[outputs]]\noutput = \"json\"\n"), "admiral", false).is_err());
+        assert!(from_config(&parse("output = \"json\"\n[[outputs]]\n"), "admiral", false).is_err());
+        assert!(from_config(&parse("[[outputs]]\nfifo = \"a\"\nsocket = \"b\"\n"), "admiral", false).is_err());
+        assert!(from_config(&parse("[[outputs]]\n[[outputs]]\n"), "admiral", true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn bars_get_each_line_and_their_clicks_are_run() {
+        let directory = env::temp_dir().join(format!("admiral-test-bar-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (input, clicked) = (directory.join("input"), directory.join("clicked"));
+        let command = format!("head -n 2 > {}; echo 'touch {}'; exec sleep 60", input.display(), clicked.display());
+        let mut table = toml::Table::new();
+        table.insert(String::from("command"), toml::Value::String(command));
+        let bar = Bar::start(&table, Some("header"), "[admiral.bar]").unwrap();
+        bar.lines.send(String::from("first")).unwrap();
+
+        let start = Instant::now();
+        while !clicked.exists() && start.elapsed() < Duration::from_secs(5) {
+            sleep(Duration::from_millis(10));
+        }
+        assert!(clicked.exists());
+        assert_eq!(fs::read_to_string(&input).unwrap(), "header\nfirst\n");
+        drop(bar);
+        let _ = fs::remove_dir_all(&directory);
+
+        assert!(Bar::start(&toml::Table::new(), None, "[admiral.bar]").is_err());
+    }
+
+    #[test]
+    fn lines_can_end_in_nul() {
+        let parse = |source: &str| toml::Parser::new(source).parse().unwrap();
+        assert_eq!(terminator(&toml::Table::new(), "[admiral]"), Ok("\n"));
+        assert_eq!(terminator(&parse("terminator = \"nul\""), "[admiral]"), Ok("\0"));
+        assert!(terminator(&parse("terminator = \"tab\""), "[admiral]").is_err());
+
+        let sinks = from_config(&parse("terminator = \"nul\""), "admiral", false).unwrap();
+        assert!(matches!(sinks[..], [(Mode::Plain, Sink::Stdout("\0"))]));
+        let path = env::temp_dir().join(format!("admiral-test-{}.nul", process::id()));
+        let sinks = from_config(&parse(&format!("output_file = \"{}\"\nterminator = \"nul\"", path.display())), "admiral", false).unwrap();
+        if let [(_, Sink::OutputFile(ref file))] = sinks[..] {
+            file.write("line").unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line\0");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn output_files_hold_the_latest_line() {
+        let path = env::temp_dir().join(format!("admiral-test-{}.txt", process::id()));
+        let file = OutputFile::new(path.clone(), "\n").unwrap();
+        file.write("first").unwrap();
+        file.write("second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second\n");
+        let _ = fs::remove_file(&path);
+
+        let missing = OutputFile::new(path.join("in-a-file"), "\n").unwrap();
+        assert!(missing.write("line").is_err());
+    }
+
+    #[test]
+    fn socket_clients_that_stop_reading_hold_nothing_up() {
+        let path = env::temp_dir().join(format!("admiral-test-{}.sock", process::id()));
+        let socket = Socket::start(path.clone(), Some("header"), "\n").unwrap();
+        socket.broadcast("first");
+        let _stalled = UnixStream::connect(&path).unwrap();
+        let reader = UnixStream::connect(&path).unwrap();
+        sleep(Duration::from_millis(100));
+
+        let start = Instant::now();
+        let long = "x".repeat(1 << 16);
+        for _ in 0..64 {
+            socket.broadcast(&long);
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(socket.clients.lock().unwrap().queues.len() < 2);
+
+        let mut lines = BufReader::new(reader).lines();
+        assert_eq!(lines.next().unwrap().unwrap(), "header");
+        assert_eq!(lines.next().unwrap().unwrap(), "first");
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,572 @@
+//! A minimal bar drawn by Admiral itself as a wlr-layer-shell surface, for
+//! Wayland compositors that support it.
+//!
+//! Lines are drawn in software with a single font. The same `%{...}` formatting
+//! that lemonbar reads is understood: `l`, `c`, and `r` for alignment, `F` and `B`
+//! for colours, `R` to swap them, and `A` for click regions, whose commands are
+//! run with `sh -c`. Anything else inside `%{...}` is ignored.
+//!
+//! The bar runs on its own thread. Its event loop waits on both the Wayland
+//! connection and a pipe, which `Bar::show` writes to whenever there is a new
+//! line, so that updates are drawn straight away.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{stderr, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::process::{exit, Command};
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender};
+use std::thread;
+
+use fontdue::{Font, FontSettings, Metrics};
+use libc;
+use smithay_client_toolkit::compositor::{CompositorHandler, CompositorState};
+use smithay_client_toolkit::output::{OutputHandler, OutputState};
+use smithay_client_toolkit::reexports::client::globals::registry_queue_init;
+use smithay_client_toolkit::reexports::client::protocol::{wl_output, wl_pointer, wl_seat, wl_shm, wl_surface};
+use smithay_client_toolkit::reexports::client::{Connection, EventQueue, QueueHandle};
+use smithay_client_toolkit::registry::{ProvidesRegistryState, RegistryState};
+use smithay_client_toolkit::seat::pointer::{PointerEvent, PointerEventKind, PointerHandler};
+use smithay_client_toolkit::seat::{Capability, SeatHandler, SeatState};
+use smithay_client_toolkit::shell::wlr_layer::{Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface, LayerSurfaceConfigure};
+use smithay_client_toolkit::shell::WaylandSurface;
+use smithay_client_toolkit::shm::slot::SlotPool;
+use smithay_client_toolkit::shm::{Shm, ShmHandler};
+use smithay_client_toolkit::{delegate_compositor, delegate_layer, delegate_output, delegate_pointer, delegate_registry, delegate_seat, delegate_shm, registry_handlers};
+use toml::{self, Value};
+
+/// Linux input event codes for the buttons lemonbar numbers 1, 2, and 3.
+const BTN_LEFT: u32 = 0x110;
+const BTN_RIGHT: u32 = 0x111;
+const BTN_MIDDLE: u32 = 0x112;
+
+/// The bar's appearance, read from the `[admiral.wayland]` table.
+struct Settings {
+    font: Font,
+    size: f32,
+    height: u32,
+    bottom: bool,
+    foreground: u32,
+    background: u32,
+}
+
+fn color(name: &str, value: &str) -> Result<u32, String> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let parsed = u32::from_str_radix(hex, 16).map_err(|_| format!("Invalid {} found for admiral.wayland", name))?;
+    match hex.len() {
+        6 => Ok(0xff000000 | parsed),
+        8 => Ok(parsed),
+        _ => Err(format!("Invalid {} found for admiral.wayland", name)),
+    }
+}
+
+/// Finds the file of the default monospace font with fontconfig.
+fn default_font() -> Option<String> {
+    let output = Command::new("fc-match").args(["-f", "%{file}", "monospace"]).output().ok()?;
+    let path = String::from_utf8_lossy(&output.stdout).into_owned();
+    if path.is_empty() { None } else { Some(path) }
+}
+
+impl Settings {
+    fn from_config(configuration: &toml::Table) -> Result<Settings, String> {
+        let string = |key: &str| match configuration.get(key) {
+            Some(Value::String(value)) => Ok(Some(value.clone())),
+            Some(_) => Err(format!("Invalid {} found for admiral.wayland", key)),
+            None => Ok(None),
+        };
+        let number = |key: &str, default: f64| match configuration.get(key) {
+            Some(&Value::Integer(value)) if value > 0 => Ok(value as f64),
+            Some(&Value::Float(value)) if value > 0f64 => Ok(value),
+            Some(_) => Err(format!("Invalid {} found for admiral.wayland", key)),
+            None => Ok(default),
+        };
+
+        let path = match string("font")? {
+            Some(path) => path,
+            None => default_font().ok_or_else(|| String::from("Failed to find a font: set font in admiral.wayland"))?,
+        };
+        let mut data = Vec::new();
+        File::open(&path).and_then(|mut file| file.read_to_end(&mut data))
+            .map_err(|e| format!("Failed to read font {}: {}", path, e))?;
+        let font = Font::from_bytes(data, FontSettings::default())
+            .map_err(|e| format!("Failed to load font {}: {}", path, e))?;
+
+        let size = number("size", 14f64)? as f32;
+        let bottom = match string("position")?.as_deref() {
+            Some("top") | None => false,
+            Some("bottom") => true,
+            Some(_) => return Err(String::from("Invalid position found for admiral.wayland: expected \"top\" or \"bottom\"")),
+        };
+
+        Ok(Settings {
+            font,
+            size,
+            height: number("height", (size * 1.5).ceil() as f64)? as u32,
+            bottom,
+            foreground: color("foreground", &string("foreground")?.unwrap_or_else(|| String::from("#dddddd")))?,
+            background: color("background", &string("background")?.unwrap_or_else(|| String::from("#222222")))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// A run of text drawn with the same attributes.
+#[derive(Debug, Clone)]
+struct Span {
+    text: String,
+    align: Align,
+    foreground: u32,
+    background: u32,
+    /// Commands to run when the span is clicked, by button.
+    actions: Vec<Action>,
+}
+
+/// A lemonbar button number and the command it runs.
+type Action = (u32, String);
+
+/// Splits a line into spans, following lemonbar's `%{...}` formatting.
+fn parse(line: &str, foreground: u32, background: u32) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut current = Span { text: String::new(), align: Align::Left, foreground, background, actions: Vec::new() };
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' || chars.peek() != Some(&'{') {
+            current.text.push(c);
+            continue;
+        }
+        chars.next();
+
+        let mut next = current.clone();
+        next.text.clear();
+        while let Some(c) = chars.next() {
+            match c {
+                '}' => break,
+                ' ' => {},
+                'l' => next.align = Align::Left,
+                'c' => next.align = Align::Center,
+                'r' => next.align = Align::Right,
+                'R' => std::mem::swap(&mut next.foreground, &mut next.background),
+                'F' | 'B' | 'U' => {
+                    let mut value = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if c == ' ' || c == '}' {
+                            break;
+                        }
+                        value.push(c);
+                        chars.next();
+                    }
+                    let (target, default) = match c {
+                        'F' => (&mut next.foreground, foreground),
+                        'B' => (&mut next.background, background),
+                        _ => continue,
+                    };
+                    *target = if value == "-" { default } else { color("colour", &value).unwrap_or(*target) };
+                },
+                'A' => {
+                    let mut button = 1;
+                    if let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                        button = digit;
+                        chars.next();
+                    }
+                    if chars.peek() != Some(&':') {
+                        next.actions.pop();
+                        continue;
+                    }
+                    chars.next();
+                    let mut command = String::new();
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '\\' => command.extend(chars.next()),
+                            ':' => break,
+                            _ => command.push(c),
+                        }
+                    }
+                    next.actions.push((button, command));
+                },
+                _ => {},
+            }
+        }
+
+        let finished = std::mem::replace(&mut current, next);
+        if !finished.text.is_empty() {
+            spans.push(finished);
+        }
+    }
+
+    if !current.text.is_empty() {
+        spans.push(current);
+    }
+    spans
+}
+
+/// Mixes `foreground` over `background` by `coverage`, as a premultiplied ARGB pixel.
+fn blend(foreground: u32, background: u32, coverage: u8) -> [u8; 4] {
+    let channel = |shift: u32| {
+        let f = (foreground >> shift) & 0xff;
+        let b = (background >> shift) & 0xff;
+        (f * coverage as u32 + b * (255 - coverage as u32)) / 255
+    };
+    let alpha = channel(24);
+    let premultiply = |value: u32| (value * alpha / 255) as u8;
+    [premultiply(channel(0)), premultiply(channel(8)), premultiply(channel(16)), alpha as u8]
+}
+
+struct State {
+    registry_state: RegistryState,
+    seat_state: SeatState,
+    output_state: OutputState,
+    shm: Shm,
+    pool: SlotPool,
+    layer: LayerSurface,
+    pointer: Option<wl_pointer::WlPointer>,
+    settings: Settings,
+    glyphs: HashMap<char, (Metrics, Vec<u8>)>,
+    width: u32,
+    configured: bool,
+    dirty: bool,
+    line: String,
+    /// The clickable parts of the bar, as start and end x coordinates with their actions.
+    regions: Vec<(i32, i32, Vec<Action>)>,
+}
+
+impl State {
+    fn glyph(&mut self, c: char) -> &(Metrics, Vec<u8>) {
+        let font = &self.settings.font;
+        let size = self.settings.size;
+        self.glyphs.entry(c).or_insert_with(|| font.rasterize(c, size))
+    }
+
+    fn text_width(&mut self, text: &str) -> i32 {
+        text.chars().map(|c| self.glyph(c).0.advance_width.round() as i32).sum()
+    }
+
+    fn draw(&mut self) {
+        let width = self.width as i32;
+        let height = self.settings.height as i32;
+        let stride = width * 4;
+        let spans = parse(&self.line, self.settings.foreground, self.settings.background);
+
+        // Each alignment's spans are laid out together, starting at its edge or centred
+        let mut placed = Vec::with_capacity(spans.len());
+        for align in [Align::Left, Align::Center, Align::Right] {
+            let spans = spans.iter().filter(|span| span.align == align).collect::<Vec<_>>();
+            let widths = spans.iter().map(|span| self.text_width(&span.text)).collect::<Vec<_>>();
+            let total: i32 = widths.iter().sum();
+            let mut x = match align {
+                Align::Left => 0,
+                Align::Center => (width - total) / 2,
+                Align::Right => width - total,
+            };
+            for (span, span_width) in spans.into_iter().zip(widths) {
+                placed.push((x, span_width, span.clone()));
+                x += span_width;
+            }
+        }
+
+        let (ascent, descent) = match self.settings.font.horizontal_line_metrics(self.settings.size) {
+            Some(metrics) => (metrics.ascent, metrics.descent),
+            None => (self.settings.size, 0f32),
+        };
+        let baseline = ((height as f32 - (ascent - descent)) / 2f32 + ascent).round() as i32;
+
+        let mut canvas = vec![0u8; (stride * height) as usize];
+        let background = blend(self.settings.background, self.settings.background, 0);
+        for pixel in canvas.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&background);
+        }
+
+        self.regions.clear();
+        for (x, span_width, span) in placed {
+            let span_background = blend(span.background, span.background, 0);
+            for y in 0..height {
+                for px in x.max(0)..(x + span_width).min(width) {
+                    let offset = ((y * width + px) * 4) as usize;
+                    canvas[offset..offset + 4].copy_from_slice(&span_background);
+                }
+            }
+
+            let mut pen = x;
+            for c in span.text.chars() {
+                let (metrics, bitmap) = self.glyph(c).clone();
+                let left = pen + metrics.xmin;
+                let top = baseline - metrics.height as i32 - metrics.ymin;
+                for row in 0..metrics.height as i32 {
+                    for column in 0..metrics.width as i32 {
+                        let (px, py) = (left + column, top + row);
+                        if px < 0 || px >= width || py < 0 || py >= height {
+                            continue;
+                        }
+                        let coverage = bitmap[(row * metrics.width as i32 + column) as usize];
+                        if coverage == 0 {
+                            continue;
+                        }
+                        let offset = ((py * width + px) * 4) as usize;
+                        canvas[offset..offset + 4].copy_from_slice(&blend(span.foreground, span.background, coverage));
+                    }
+                }
+                pen += metrics.advance_width.round() as i32;
+            }
+
+            if !span.actions.is_empty() {
+                self.regions.push((x, x + span_width, span.actions));
+            }
+        }
+
+        let (buffer, buffer_canvas) = match self.pool.create_buffer(width, height, stride, wl_shm::Format::Argb8888) {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                let _ = stderr().write(format!("Failed to create a buffer for the bar: {}\n", e).as_bytes());
+                return;
+            },
+        };
+        buffer_canvas.copy_from_slice(&canvas);
+        let surface = self.layer.wl_surface();
+        surface.damage_buffer(0, 0, width, height);
+        if buffer.attach_to(surface).is_ok() {
+            self.layer.commit();
+        }
+        self.dirty = false;
+    }
+
+    fn click(&self, x: f64, button: u32) {
+        let x = x as i32;
+        let region = self.regions.iter().find(|&&(start, end, _)| start <= x && x < end);
+        // Nested click regions are tried from the innermost out
+        let command = region.and_then(|(_, _, actions)| {
+            actions.iter().rev().find(|&&(action_button, _)| action_button == button).map(|(_, command)| command.clone())
+        });
+        if let Some(command) = command {
+            thread::spawn(move || {
+                if let Ok(mut child) = Command::new("sh").arg("-c").arg(&command).spawn() {
+                    let _ = child.wait();
+                }
+            });
+        }
+    }
+}
+
+impl CompositorHandler for State {
+    fn scale_factor_changed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &wl_surface::WlSurface, _: i32) {}
+
+    fn transform_changed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &wl_surface::WlSurface, _: wl_output::Transform) {}
+
+    fn frame(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &wl_surface::WlSurface, _: u32) {}
+
+    fn surface_enter(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &wl_surface::WlSurface, _: &wl_output::WlOutput) {}
+
+    fn surface_leave(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &wl_surface::WlSurface, _: &wl_output::WlOutput) {}
+}
+
+impl OutputHandler for State {
+    fn output_state(&mut self) -> &mut OutputState {
+        &mut self.output_state
+    }
+
+    fn new_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn update_output(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+
+    fn output_destroyed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_output::WlOutput) {}
+}
+
+impl LayerShellHandler for State {
+    fn closed(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &LayerSurface) {
+        let _ = stderr().write(b"The compositor closed the bar\n");
+        exit(1);
+    }
+
+    fn configure(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &LayerSurface, configure: LayerSurfaceConfigure, _: u32) {
+        if configure.new_size.0 > 0 {
+            self.width = configure.new_size.0;
+        }
+        self.configured = true;
+        self.dirty = true;
+    }
+}
+
+impl SeatHandler for State {
+    fn seat_state(&mut self) -> &mut SeatState {
+        &mut self.seat_state
+    }
+
+    fn new_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+
+    fn new_capability(&mut self, _: &Connection, qh: &QueueHandle<Self>, seat: wl_seat::WlSeat, capability: Capability) {
+        if capability == Capability::Pointer && self.pointer.is_none() {
+            self.pointer = self.seat_state.get_pointer(qh, &seat).ok();
+        }
+    }
+
+    fn remove_capability(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat, capability: Capability) {
+        if capability == Capability::Pointer {
+            if let Some(pointer) = self.pointer.take() {
+                pointer.release();
+            }
+        }
+    }
+
+    fn remove_seat(&mut self, _: &Connection, _: &QueueHandle<Self>, _: wl_seat::WlSeat) {}
+}
+
+impl PointerHandler for State {
+    fn pointer_frame(&mut self, _: &Connection, _: &QueueHandle<Self>, _: &wl_pointer::WlPointer, events: &[PointerEvent]) {
+        for event in events {
+            if &event.surface != self.layer.wl_surface() {
+                continue;
+            }
+            // Scrolling is reported as buttons 4 and 5, as lemonbar does
+            let button = match event.kind {
+                PointerEventKind::Press { button: BTN_LEFT, .. } => 1,
+                PointerEventKind::Press { button: BTN_MIDDLE, .. } => 2,
+                PointerEventKind::Press { button: BTN_RIGHT, .. } => 3,
+                PointerEventKind::Axis { ref vertical, .. } if vertical.absolute < 0f64 => 4,
+                PointerEventKind::Axis { ref vertical, .. } if vertical.absolute > 0f64 => 5,
+                _ => continue,
+            };
+            self.click(event.position.0, button);
+        }
+    }
+}
+
+impl ShmHandler for State {
+    fn shm_state(&mut self) -> &mut Shm {
+        &mut self.shm
+    }
+}
+
+impl ProvidesRegistryState for State {
+    fn registry(&mut self) -> &mut RegistryState {
+        &mut self.registry_state
+    }
+    registry_handlers![OutputState, SeatState];
+}
+
+delegate_compositor!(State);
+delegate_output!(State);
+delegate_shm!(State);
+delegate_seat!(State);
+delegate_pointer!(State);
+delegate_layer!(State);
+delegate_registry!(State);
+
+/// The handle through which the main thread gives the bar new lines.
+pub struct Bar {
+    lines: Sender<String>,
+    wake: RawFd,
+}
+
+impl Bar {
+    pub fn show(&self, line: &str) {
+        if self.lines.send(line.to_owned()).is_ok() {
+            let _ = unsafe { libc::write(self.wake, b"\n".as_ptr() as *const libc::c_void, 1) };
+        }
+    }
+}
+
+fn connect(settings: Settings) -> Result<(EventQueue<State>, State), String> {
+    let connection = Connection::connect_to_env().map_err(|e| format!("Failed to connect to Wayland: {}", e))?;
+    let (globals, queue) = registry_queue_init(&connection).map_err(|e| format!("Failed to connect to Wayland: {}", e))?;
+    let qh = queue.handle();
+
+    let compositor = CompositorState::bind(&globals, &qh).map_err(|_| String::from("The compositor does not support wl_compositor"))?;
+    let layer_shell = LayerShell::bind(&globals, &qh).map_err(|_| String::from("The compositor does not support wlr-layer-shell"))?;
+    let shm = Shm::bind(&globals, &qh).map_err(|_| String::from("The compositor does not support wl_shm"))?;
+
+    let layer = layer_shell.create_layer_surface(&qh, compositor.create_surface(&qh), Layer::Top, Some("admiral"), None);
+    let edge = if settings.bottom { Anchor::BOTTOM } else { Anchor::TOP };
+    layer.set_anchor(edge | Anchor::LEFT | Anchor::RIGHT);
+    layer.set_size(0, settings.height);
+    layer.set_exclusive_zone(settings.height as i32);
+    layer.set_keyboard_interactivity(KeyboardInteractivity::None);
+    layer.commit();
+
+    let pool = SlotPool::new((1920 * settings.height * 4) as usize, &shm).map_err(|e| format!("Failed to create a buffer for the bar: {}", e))?;
+    let state = State {
+        registry_state: RegistryState::new(&globals),
+        seat_state: SeatState::new(&globals, &qh),
+        output_state: OutputState::new(&globals, &qh),
+        shm,
+        pool,
+        layer,
+        pointer: None,
+        settings,
+        glyphs: HashMap::new(),
+        width: 0,
+        configured: false,
+        dirty: false,
+        line: String::new(),
+        regions: Vec::new(),
+    };
+    Ok((queue, state))
+}
+
+fn run(mut queue: EventQueue<State>, mut state: State, lines: Receiver<String>, wake: RawFd) {
+    loop {
+        let _ = queue.flush();
+        if let Some(guard) = queue.prepare_read() {
+            let mut fds = [
+                libc::pollfd { fd: guard.connection_fd().as_raw_fd(), events: libc::POLLIN, revents: 0 },
+                libc::pollfd { fd: wake, events: libc::POLLIN, revents: 0 },
+            ];
+            unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) };
+            if fds[0].revents != 0 {
+                let _ = guard.read();
+            }
+            if fds[1].revents != 0 {
+                let mut buffer = [0u8; 64];
+                while unsafe { libc::read(wake, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) } > 0 {}
+            }
+        }
+
+        if let Err(e) = queue.dispatch_pending(&mut state) {
+            let _ = stderr().write(format!("Lost connection to Wayland: {}\n", e).as_bytes());
+            exit(1);
+        }
+
+        // Only the latest line matters
+        if let Some(line) = lines.try_iter().last() {
+            state.line = line;
+            state.dirty = true;
+        }
+        if state.dirty && state.configured && state.width > 0 {
+            state.draw();
+        }
+    }
+}
+
+/// Opens the bar, configured by the `[admiral.wayland]` table.
+pub fn open(configuration: &toml::Table) -> Result<Bar, String> {
+    let settings = Settings::from_config(configuration)?;
+
+    let mut fds = [0 as RawFd; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+        return Err(String::from("Failed to create a pipe for the bar"));
+    }
+    let (wake_read, wake_write) = (fds[0], fds[1]);
+
+    // Wayland objects stay on the bar's thread, which reports whether it connected
+    let (lines, receiver) = channel::<String>();
+    let (started, result) = sync_channel::<Result<(), String>>(1);
+    let _ = thread::Builder::new().name(String::from("wayland")).spawn(move || {
+        match connect(settings) {
+            Ok((queue, state)) => {
+                let _ = started.send(Ok(()));
+                run(queue, state, receiver, wake_read);
+            },
+            Err(e) => { let _ = started.send(Err(e)); },
+        }
+    });
+
+    match result.recv() {
+        Ok(Ok(())) => Ok(Bar { lines, wake: wake_write }),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err(String::from("Failed to start the bar")),
+    }
+}
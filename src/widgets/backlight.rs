@@ -0,0 +1,116 @@
+//! A widget showing a screen's brightness, read from `/sys/class/backlight`.
+//!
+//! The brightness file is watched with inotify, so changes made by brightness
+//! keys (through tools such as `brightnessctl` or `light`) show up at once.
+//! Changes the firmware makes on its own don't always reach inotify, so the
+//! file is read every `reload` seconds as well.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use {Event, Outcome};
+use super::inotify::Watch;
+use super::{fill, seconds, string, Widget};
+
+const BACKLIGHT: &str = "/sys/class/backlight";
+
+struct Backlight {
+    path: PathBuf,
+    format: String,
+    interval: Duration,
+    watch: Option<Watch>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let path = match configuration.get("device") {
+        Some(_) => Path::new(BACKLIGHT).join(string(section_name, configuration, "device", "")?),
+        None => find_backlight().ok_or_else(|| format!("Failed to find a backlight for {}", section_name))?,
+    };
+
+    Ok(Box::new(Backlight {
+        path,
+        format: string(section_name, configuration, "format", "{percent}%")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        watch: None,
+    }))
+}
+
+fn read_number(path: &Path, name: &str) -> Option<u64> {
+    fs::read_to_string(path.join(name)).ok()?.trim().parse().ok()
+}
+
+/// Finds the first backlight device.
+fn find_backlight() -> Option<PathBuf> {
+    let mut devices = fs::read_dir(BACKLIGHT).ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    devices.sort();
+    devices.into_iter().next()
+}
+
+impl Widget for Backlight {
+    /// Polled after every write to the brightness file, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let (brightness, max) = match (read_number(&self.path, "brightness"), read_number(&self.path, "max_brightness")) {
+            (Some(brightness), Some(max)) => (brightness, max),
+            _ => return Some(Outcome::Failure(None)),
+        };
+        let percent = (brightness as f64 * 100f64 / max.max(1) as f64).round();
+
+        Some(Outcome::Success(fill(&self.format, &[
+            ("percent", percent.to_string()),
+            ("brightness", brightness.to_string()),
+            ("max", max.to_string()),
+        ])))
+    }
+
+    /// Falls back to polling every second when the brightness file couldn't be watched.
+    fn wait(&self) -> Duration {
+        match self.watch {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.watch = Watch::start(&[self.path.join("brightness")], libc::IN_MODIFY, |_| true, wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use toml;
+
+    use Outcome;
+
+    #[test]
+    fn brightness_changes_wake_the_widget() {
+        let path = env::temp_dir().join(format!("admiral-test-backlight-{}", process::id()));
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("brightness"), "300\n").unwrap();
+        fs::write(path.join("max_brightness"), "1200\n").unwrap();
+
+        let config = toml::Parser::new(&format!("device = {:?}\nformat = \"{{percent}}% of {{max}}\"", path.to_str().unwrap())).parse().unwrap();
+        let mut backlight = super::new("backlight", &config).unwrap();
+        assert_eq!(backlight.poll(), Some(Outcome::Success(String::from("25% of 1200"))));
+
+        let (wake, woken) = channel();
+        backlight.subscribe(wake);
+        fs::write(path.join("brightness"), "1200\n").unwrap();
+        assert!(woken.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(backlight.poll(), Some(Outcome::Success(String::from("100% of 1200"))));
+        let _ = fs::remove_dir_all(&path);
+    }
+}
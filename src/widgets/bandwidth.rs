@@ -0,0 +1,119 @@
+//! A widget showing how fast a network interface is receiving and sending,
+//! from the byte counters in `/sys/class/net/<interface>/statistics`.
+//!
+//! Rates are worked out from the difference between successive samples, over
+//! the time that actually passed between them rather than the nominal
+//! `reload`. Without an `interface`, the one carrying the default route is
+//! used, and the samples start over whenever that changes.
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use toml;
+
+use Outcome;
+use super::{fill, human, seconds, string, Widget};
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    rx: u64,
+    tx: u64,
+    taken: Instant,
+}
+
+#[derive(Debug)]
+struct Bandwidth {
+    interface: Option<String>,
+    format: String,
+    interval: Duration,
+    /// The interface last sampled, along with its sample.
+    last: Option<(String, Sample)>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let interface = match configuration.get("interface") {
+        Some(_) => Some(string(section_name, configuration, "interface", "")?),
+        None => None,
+    };
+
+    Ok(Box::new(Bandwidth {
+        interface,
+        format: string(section_name, configuration, "format", "{rx}/s {tx}/s")?,
+        interval: seconds(section_name, configuration, "reload", 1f64)?.max(Duration::from_millis(100)),
+        last: None,
+    }))
+}
+
+/// The interface the default route goes through, from `/proc/net/route`.
+fn default_interface() -> Option<String> {
+    let routes = fs::read_to_string("/proc/net/route").ok()?;
+    routes.lines()
+        .skip(1)
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .find(|fields| fields.get(1) == Some(&"00000000"))
+        .and_then(|fields| fields.first().map(|&name| name.to_owned()))
+}
+
+fn counter(interface: &str, name: &str) -> Option<u64> {
+    fs::read_to_string(format!("/sys/class/net/{}/statistics/{}", interface, name)).ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+impl Widget for Bandwidth {
+    fn poll(&mut self) -> Option<Outcome> {
+        let interface = match self.interface.clone().or_else(default_interface) {
+            Some(interface) => interface,
+            None => {
+                self.last = None;
+                return Some(Outcome::Failure(None));
+            },
+        };
+        let sample = match (counter(&interface, "rx_bytes"), counter(&interface, "tx_bytes")) {
+            (Some(rx), Some(tx)) => Sample { rx, tx, taken: Instant::now() },
+            _ => {
+                self.last = None;
+                return Some(Outcome::Failure(None));
+            },
+        };
+
+        let last = self.last.replace((interface.clone(), sample));
+        let previous = match last {
+            Some((ref name, previous)) if *name == interface => previous,
+            // Nothing to compare against yet
+            _ => return None,
+        };
+
+        let elapsed = sample.taken.duration_since(previous.taken).as_secs_f64().max(0.001);
+        // Counters go backwards when an interface is reset
+        let rate = |now: u64, then: u64| human((now.saturating_sub(then) as f64 / elapsed).round() as u64);
+        Some(Outcome::Success(fill(&self.format, &[
+            ("rx", rate(sample.rx, previous.rx)),
+            ("tx", rate(sample.tx, previous.tx)),
+            ("interface", interface),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+
+    use Outcome;
+
+    #[test]
+    fn rates_need_two_samples() {
+        let config = toml::Parser::new("interface = \"lo\"\nformat = \"{interface}\"").parse().unwrap();
+        let mut bandwidth = super::new("bandwidth", &config).unwrap();
+        assert_eq!(bandwidth.poll(), None);
+        assert_eq!(bandwidth.poll(), Some(Outcome::Success(String::from("lo"))));
+
+        let config = toml::Parser::new("interface = \"admiral-missing0\"").parse().unwrap();
+        assert_eq!(super::new("bandwidth", &config).unwrap().poll(), Some(Outcome::Failure(None)));
+    }
+}
@@ -0,0 +1,160 @@
+//! A widget showing a battery's charge, read from `/sys/class/power_supply`.
+//!
+//! The time until the battery is empty (or full) is worked out from its
+//! present rate of discharge, which drivers report either as energy and
+//! power (µWh and µW) or as charge and current (µAh and µA).
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml;
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+const POWER_SUPPLY: &str = "/sys/class/power_supply";
+
+#[derive(Debug)]
+struct Battery {
+    path: PathBuf,
+    interval: Duration,
+    charging: String,
+    discharging: String,
+    full: String,
+    unknown: String,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Battery::from_config(section_name, configuration)?))
+}
+
+fn read(path: &Path, name: &str) -> Option<String> {
+    fs::read_to_string(path.join(name)).ok().map(|value| value.trim().to_owned())
+}
+
+fn read_number(path: &Path, name: &str) -> Option<f64> {
+    read(path, name)?.parse().ok()
+}
+
+/// Finds the first power supply that is a battery.
+fn find_battery() -> Option<PathBuf> {
+    let mut supplies = fs::read_dir(POWER_SUPPLY).ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| read(path, "type").as_deref() == Some("Battery"))
+        .collect::<Vec<_>>();
+    supplies.sort();
+    supplies.into_iter().next()
+}
+
+/// Formats hours as `H:MM`.
+fn hours_minutes(hours: f64) -> String {
+    let minutes = (hours * 60f64).round() as u64;
+    format!("{}:{:02}", minutes / 60, minutes % 60)
+}
+
+impl Battery {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Battery, String> {
+        let path = match configuration.get("battery") {
+            Some(_) => Path::new(POWER_SUPPLY).join(string(section_name, configuration, "battery", "")?),
+            None => find_battery().ok_or_else(|| format!("Failed to find a battery for {}", section_name))?,
+        };
+
+        Ok(Battery {
+            path,
+            interval: seconds(section_name, configuration, "reload", 10f64)?,
+            charging: string(section_name, configuration, "charging", "+{percent}%")?,
+            discharging: string(section_name, configuration, "discharging", "{percent}% {time}")?,
+            full: string(section_name, configuration, "full", "{percent}%")?,
+            unknown: string(section_name, configuration, "unknown", "{percent}%")?,
+        })
+    }
+
+    /// Hours until the battery is empty when discharging, or full when charging.
+    fn hours_left(&self, charging: bool) -> Option<f64> {
+        let (now, full, rate) = match read_number(&self.path, "energy_now") {
+            Some(now) => (now, read_number(&self.path, "energy_full")?, read_number(&self.path, "power_now")?),
+            None => (
+                read_number(&self.path, "charge_now")?,
+                read_number(&self.path, "charge_full")?,
+                read_number(&self.path, "current_now")?,
+            ),
+        };
+        // Some drivers report the rate as negative while discharging
+        let rate = rate.abs();
+        if rate == 0f64 {
+            return None;
+        }
+        Some(if charging { (full - now).max(0f64) / rate } else { now / rate })
+    }
+}
+
+impl Widget for Battery {
+    fn poll(&mut self) -> Option<Outcome> {
+        let percent = match read(&self.path, "capacity") {
+            Some(percent) => percent,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let status = read(&self.path, "status").unwrap_or_default();
+        let (format, charging) = match status.as_str() {
+            "Charging" => (&self.charging, true),
+            "Discharging" => (&self.discharging, false),
+            "Full" | "Not charging" => (&self.full, false),
+            _ => (&self.unknown, false),
+        };
+        let time = match status.as_str() {
+            "Charging" | "Discharging" => self.hours_left(charging).map(hours_minutes).unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        Some(Outcome::Success(fill(format, &[
+            ("percent", percent),
+            ("time", time),
+            ("state", status.to_lowercase()),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use toml;
+
+    use Outcome;
+    use widgets::Widget;
+    use super::Battery;
+
+    #[test]
+    fn time_left_follows_the_rate_either_way() {
+        let path = env::temp_dir().join(format!("admiral-test-battery-{}", process::id()));
+        fs::create_dir_all(&path).unwrap();
+        let write = |name: &str, value: &str| fs::write(path.join(name), format!("{}\n", value)).unwrap();
+        write("capacity", "50");
+        write("status", "Discharging");
+        write("charge_now", "2500000");
+        write("charge_full", "5000000");
+        write("current_now", "-1000000");
+
+        let config = toml::Parser::new(&format!("battery = {:?}", path.to_str().unwrap())).parse().unwrap();
+        let mut battery = Battery::from_config("battery", &config).unwrap();
+        assert_eq!(battery.poll(), Some(Outcome::Success(String::from("50% 2:30"))));
+        write("status", "Charging");
+        write("energy_now", "30000000");
+        write("energy_full", "40000000");
+        write("power_now", "20000000");
+        assert_eq!(battery.poll(), Some(Outcome::Success(String::from("+50%"))));
+        battery.charging = String::from("{state} {time}");
+        assert_eq!(battery.poll(), Some(Outcome::Success(String::from("charging 0:30"))));
+        write("power_now", "0");
+        assert_eq!(battery.poll(), Some(Outcome::Success(String::from("charging "))));
+        let _ = fs::remove_dir_all(&path);
+    }
+}
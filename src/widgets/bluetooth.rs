@@ -0,0 +1,112 @@
+//! A widget showing whether Bluetooth is on, and which devices are connected.
+//!
+//! BlueZ's signals are watched for over D-Bus, so powering the adapter and
+//! connecting devices show up at once, and the state is read with
+//! `bluetoothctl`.
+
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use toml;
+
+use {Event, Outcome};
+use super::dbus::{Bus, Signals};
+use super::{fill, run, seconds, string, Widget};
+
+struct Bluetooth {
+    off: String,
+    on: String,
+    connected: String,
+    separator: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    signals: Option<Signals>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Bluetooth {
+        off: string(section_name, configuration, "off", "")?,
+        on: string(section_name, configuration, "on", "bt")?,
+        connected: string(section_name, configuration, "connected", "bt {devices}")?,
+        separator: string(section_name, configuration, "separator", ", ")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        signals: None,
+    }))
+}
+
+/// Whether the default adapter is powered, or `None` if there isn't one.
+fn powered() -> Option<bool> {
+    is_powered(&run("bluetoothctl", &["show"])?)
+}
+
+/// Reads whether the adapter is powered from the output of `bluetoothctl show`.
+fn is_powered(show: &str) -> Option<bool> {
+    show.lines()
+        .find_map(|line| line.trim().strip_prefix("Powered:"))
+        .map(|powered| powered.trim() == "yes")
+}
+
+/// The names of the connected devices, from lines such as `Device 00:11:22:33:44:55 Headphones`.
+fn connected_devices() -> Vec<String> {
+    device_names(&run("bluetoothctl", &["devices", "Connected"]).unwrap_or_default())
+}
+
+fn device_names(devices: &str) -> Vec<String> {
+    devices.lines()
+        .filter_map(|line| line.strip_prefix("Device "))
+        .map(|device| device.split_once(' ').map_or(device, |(_, name)| name).to_owned())
+        .collect()
+}
+
+impl Widget for Bluetooth {
+    /// Polled after BlueZ's adapters or devices change, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let powered = match powered() {
+            Some(powered) => powered,
+            None => return Some(Outcome::Failure(None)),
+        };
+        if !powered {
+            return Some(Outcome::Success(self.off.clone()));
+        }
+
+        let devices = connected_devices();
+        let format = if devices.is_empty() { &self.on } else { &self.connected };
+        Some(Outcome::Success(fill(format, &[
+            ("count", devices.len().to_string()),
+            ("devices", devices.join(&self.separator)),
+        ])))
+    }
+
+    /// Falls back to polling every second when BlueZ's signals can't be had.
+    fn wait(&self) -> Duration {
+        match self.signals {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        let rules = [
+            "type='signal',sender='org.bluez',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'",
+            "type='signal',sender='org.bluez',interface='org.freedesktop.DBus.ObjectManager'",
+        ];
+        self.signals = Signals::start(Bus::System, &rules, &[], wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{device_names, is_powered};
+
+    #[test]
+    fn bluetoothctl_output_is_read() {
+        let show = "Controller 00:1A:7D:DA:71:13 (public)\n\tName: laptop\n\tPowered: yes\n\tDiscoverable: no\n";
+        assert_eq!(is_powered(show), Some(true));
+        assert_eq!(is_powered("Controller 00:1A:7D:DA:71:13 (public)\n\tPowered: no\n"), Some(false));
+        assert_eq!(is_powered("No default controller available\n"), None);
+
+        let devices = "Device 00:11:22:33:44:55 WH-1000XM4\nDevice AA:BB:CC:DD:EE:FF MX Master 3\n";
+        assert_eq!(device_names(devices), ["WH-1000XM4", "MX Master 3"]);
+        assert!(device_names("").is_empty());
+    }
+}
@@ -0,0 +1,301 @@
+//! A widget showing the status of the latest CI run for a list of
+//! repositories, on GitHub Actions or GitLab CI.
+//!
+//! Requests are made with `curl`, since Admiral has no TLS of its own. GitHub
+//! responses are cached by their ETag, so that unchanged statuses don't count
+//! against the API's rate limit, and the last known status of a repository is
+//! kept when a request fails. The statuses and ETags are kept on disk too, so
+//! that restarting Admiral doesn't spend a request on each repository.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+use toml::{self, Value};
+
+use {Outcome, State};
+use super::{encode, fill, run_with_input, seconds, string, Cache, Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Provider {
+    GitHub,
+    GitLab,
+}
+
+#[derive(Debug)]
+struct Repo {
+    name: String,
+    branch: Option<String>,
+    /// The state of the latest run, or `None` if it isn't known.
+    state: Option<State>,
+    etag: Option<String>,
+}
+
+#[derive(Debug)]
+struct Ci {
+    provider: Provider,
+    api: String,
+    token: Option<String>,
+    repos: Vec<Repo>,
+    cache: Cache,
+    interval: Duration,
+    success: String,
+    failure: String,
+    pending: String,
+    unknown: String,
+    separator: String,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Ci::from_config(section_name, configuration)?))
+}
+
+impl Ci {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Ci, String> {
+        let provider = match string(section_name, configuration, "provider", "github")?.as_str() {
+            "github" => Provider::GitHub,
+            "gitlab" => Provider::GitLab,
+            _ => return Err(format!("Invalid provider found for {}", section_name)),
+        };
+        let default_api = match provider {
+            Provider::GitHub => "https://api.github.com",
+            Provider::GitLab => "https://gitlab.com",
+        };
+
+        let token = match (configuration.get("token"), configuration.get("token_file")) {
+            (Some(_), _) => Some(string(section_name, configuration, "token", "")?),
+            (None, Some(_)) => {
+                let path = string(section_name, configuration, "token_file", "")?;
+                let token = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read token_file for {}: {}", section_name, e))?;
+                Some(token.trim().to_owned())
+            },
+            (None, None) => None,
+        };
+
+        // Each repository is given as "owner/name", optionally followed by "@branch"
+        let repos = match configuration.get("repos") {
+            Some(Value::Array(repos)) => repos.iter()
+                .map(|repo| repo.as_str().ok_or_else(|| format!("Invalid repos found for {}", section_name)))
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(format!("Invalid repos found for {}", section_name)),
+        };
+        let repos = repos.into_iter()
+            .map(|repo| {
+                let (name, branch) = match repo.rsplit_once('@') {
+                    Some((name, branch)) => (name, Some(branch.to_owned())),
+                    None => (repo, None),
+                };
+                Repo { name: name.to_owned(), branch, state: None, etag: None }
+            })
+            .collect();
+        let api = string(section_name, configuration, "api", default_api)?.trim_end_matches('/').to_owned();
+
+        let mut ci = Ci {
+            provider,
+            cache: Cache::new(&format!("ci-{}.json", section_name), format!("{:?} {}", provider, api)),
+            api,
+            token,
+            repos,
+            interval: seconds(section_name, configuration, "reload", 120f64)?,
+            success: string(section_name, configuration, "success", "{repo} ok")?,
+            failure: string(section_name, configuration, "failure", "{repo} failed")?,
+            pending: string(section_name, configuration, "pending", "{repo} running")?,
+            unknown: string(section_name, configuration, "unknown", "{repo} ?")?,
+            separator: string(section_name, configuration, "separator", " ")?,
+        };
+        ci.load();
+        Ok(ci)
+    }
+
+    /// Restores the statuses and ETags saved by the last run, for the repositories still configured.
+    fn load(&mut self) {
+        let saved = match self.cache.load() {
+            Some(saved) => saved,
+            None => return,
+        };
+        for index in 0..self.repos.len() {
+            let url = self.url(&self.repos[index]);
+            if let Some(entry) = saved.find_path(&["repos", &url]) {
+                let repo = &mut self.repos[index];
+                repo.state = match entry.find("state").and_then(Json::as_string) {
+                    Some("success") => Some(State::Success),
+                    Some("failure") => Some(State::Failure),
+                    Some("pending") => Some(State::Pending),
+                    _ => None,
+                };
+                repo.etag = entry.find("etag").and_then(Json::as_string).map(String::from);
+            }
+        }
+    }
+
+    fn save(&self) {
+        let repos = self.repos.iter()
+            .map(|repo| {
+                let mut entry = BTreeMap::new();
+                if let Some(state) = repo.state {
+                    let state = match state {
+                        State::Success => "success",
+                        State::Failure => "failure",
+                        State::Pending => "pending",
+                    };
+                    entry.insert(String::from("state"), Json::String(String::from(state)));
+                }
+                if let Some(ref etag) = repo.etag {
+                    entry.insert(String::from("etag"), Json::String(etag.clone()));
+                }
+                (self.url(repo), Json::Object(entry))
+            })
+            .collect();
+        let mut object = BTreeMap::new();
+        object.insert(String::from("repos"), Json::Object(repos));
+        self.cache.save(object);
+    }
+
+    fn url(&self, repo: &Repo) -> String {
+        match self.provider {
+            Provider::GitHub => {
+                let mut url = format!("{}/repos/{}/actions/runs?per_page=1", self.api, repo.name);
+                if let Some(ref branch) = repo.branch {
+                    url.push_str(&format!("&branch={}", encode(branch)));
+                }
+                url
+            },
+            Provider::GitLab => {
+                let mut url = format!("{}/api/v4/projects/{}/pipelines?per_page=1", self.api, encode(&repo.name));
+                if let Some(ref branch) = repo.branch {
+                    url.push_str(&format!("&ref={}", encode(branch)));
+                }
+                url
+            },
+        }
+    }
+
+    /// Fetches the latest run of `repo`, returning its state and ETag, or `None` if it hasn't changed or can't be fetched.
+    fn fetch(&self, repo: &Repo) -> Option<(Option<State>, Option<String>)> {
+        // Headers are read from standard input, so the token isn't in curl's arguments
+        let mut headers = String::new();
+        match self.provider {
+            Provider::GitHub => {
+                headers.push_str("Accept: application/vnd.github+json\n");
+                if let Some(ref token) = self.token {
+                    headers.push_str(&format!("Authorization: Bearer {}\n", token));
+                }
+                if let Some(ref etag) = repo.etag {
+                    headers.push_str(&format!("If-None-Match: {}\n", etag));
+                }
+            },
+            Provider::GitLab => {
+                if let Some(ref token) = self.token {
+                    headers.push_str(&format!("PRIVATE-TOKEN: {}\n", token));
+                }
+            },
+        }
+        let response = run_with_input("curl", &["-s", "-i", "--max-time", "10", "-H", "@-", &self.url(repo)], &headers)?;
+        parse(self.provider, &response)
+    }
+}
+
+/// Reads the state of the latest run and the ETag from a response, headers included,
+/// returning `None` if it isn't a successful one.
+fn parse(provider: Provider, response: &str) -> Option<(Option<State>, Option<String>)> {
+    let (head, body) = response.split_once("\r\n\r\n")?;
+    if head.split_whitespace().nth(1) != Some("200") {
+        return None;
+    }
+    let etag = head.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|&(name, _)| name.eq_ignore_ascii_case("etag"))
+        .map(|(_, value)| value.trim().to_owned());
+
+    let json = Json::from_str(body).ok()?;
+    let run = match provider {
+        Provider::GitHub => json.find("workflow_runs").and_then(Json::as_array).and_then(|runs| runs.first()),
+        Provider::GitLab => json.as_array().and_then(|pipelines| pipelines.first()),
+    };
+    let run = match run {
+        Some(run) => run,
+        None => return Some((None, etag)),
+    };
+    let field = |name: &str| run.find(name).and_then(Json::as_string).unwrap_or("");
+
+    let state = match provider {
+        Provider::GitHub => match (field("status"), field("conclusion")) {
+            ("completed", "success") | ("completed", "skipped") | ("completed", "neutral") => Some(State::Success),
+            ("completed", _) => Some(State::Failure),
+            _ => Some(State::Pending),
+        },
+        Provider::GitLab => match field("status") {
+            "success" | "skipped" => Some(State::Success),
+            "failed" | "canceled" => Some(State::Failure),
+            "created" | "waiting_for_resource" | "preparing" | "pending" | "running" | "scheduled" => Some(State::Pending),
+            _ => None,
+        },
+    };
+    Some((state, etag))
+}
+
+impl Widget for Ci {
+    fn poll(&mut self) -> Option<Outcome> {
+        for index in 0..self.repos.len() {
+            if let Some((state, etag)) = self.fetch(&self.repos[index]) {
+                let repo = &mut self.repos[index];
+                repo.state = state;
+                repo.etag = etag;
+            }
+        }
+        self.save();
+
+        let statuses = self.repos.iter()
+            .map(|repo| {
+                let format = match repo.state {
+                    Some(State::Success) => &self.success,
+                    Some(State::Failure) => &self.failure,
+                    Some(State::Pending) => &self.pending,
+                    None => &self.unknown,
+                };
+                let name = repo.name.rsplit('/').next().unwrap_or(&repo.name);
+                fill(format, &[
+                    ("repo", name.to_owned()),
+                    ("path", repo.name.clone()),
+                    ("branch", repo.branch.clone().unwrap_or_default()),
+                ])
+            })
+            .collect::<Vec<_>>();
+        Some(Outcome::Success(statuses.join(&self.separator)))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+
+    /// The worst state of any repository, so one broken build colours the whole section.
+    fn state(&self) -> Option<State> {
+        let states = self.repos.iter().filter_map(|repo| repo.state).collect::<Vec<_>>();
+        [State::Failure, State::Pending, State::Success].iter().copied().find(|state| states.contains(state))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Provider, State};
+
+    #[test]
+    fn runs_are_read_from_either_provider() {
+        let github = "HTTP/2 200\r\netag: W/\"abc\"\r\n\r\n{\"workflow_runs\": [{\"status\": \"completed\", \"conclusion\": \"failure\"}]}";
+        assert_eq!(parse(Provider::GitHub, github), Some((Some(State::Failure), Some(String::from("W/\"abc\"")))));
+        let running = "HTTP/2 200\r\n\r\n{\"workflow_runs\": [{\"status\": \"in_progress\", \"conclusion\": null}]}";
+        assert_eq!(parse(Provider::GitHub, running), Some((Some(State::Pending), None)));
+        let gitlab = "HTTP/1.1 200 OK\r\n\r\n[{\"status\": \"skipped\"}, {\"status\": \"failed\"}]";
+        assert_eq!(parse(Provider::GitLab, gitlab), Some((Some(State::Success), None)));
+        assert_eq!(parse(Provider::GitLab, "HTTP/1.1 200 OK\r\n\r\n[]"), Some((None, None)));
+    }
+
+    #[test]
+    fn unchanged_or_failed_responses_keep_the_last_state() {
+        assert_eq!(parse(Provider::GitHub, "HTTP/2 304\r\netag: W/\"abc\"\r\n\r\n"), None);
+        assert_eq!(parse(Provider::GitHub, "HTTP/2 200\r\n\r\nnot json"), None);
+        assert_eq!(parse(Provider::GitHub, ""), None);
+    }
+}
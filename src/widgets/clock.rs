@@ -0,0 +1,100 @@
+//! A widget showing the current time, formatted with `strftime`.
+//!
+//! Rather than polling, the clock sleeps until the next second or minute
+//! boundary, depending on whether its format shows seconds.
+
+use std::ffi::CString;
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc;
+use toml;
+
+use Outcome;
+use super::{string, Widget};
+
+/// Conversions that change every second.
+const SECOND_CONVERSIONS: &[&str] = &["%S", "%T", "%s", "%r", "%X", "%c", "%+"];
+
+extern "C" {
+    // Not exposed by the libc crate
+    fn tzset();
+}
+
+struct Clock {
+    format: CString,
+    /// Whether the format shows seconds, so the clock must tick every second rather than every minute.
+    seconds: bool,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let format = string(section_name, configuration, "format", "%H:%M")?;
+    let seconds = SECOND_CONVERSIONS.iter().any(|conversion| format.contains(conversion));
+    let format = CString::new(format).map_err(|_| format!("Invalid format found for {}", section_name))?;
+
+    unsafe { tzset() };
+    Ok(Box::new(Clock { format, seconds }))
+}
+
+fn local_time(time: libc::time_t) -> libc::tm {
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    unsafe { libc::localtime_r(&time, &mut tm) };
+    tm
+}
+
+fn strftime(format: &CString, tm: &libc::tm) -> String {
+    let mut buffer = vec![0u8; 128];
+    // strftime returns 0 both when the buffer is too small and when the result is empty
+    while buffer.len() <= 4096 {
+        let length = unsafe { libc::strftime(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len(), format.as_ptr(), tm) };
+        if length > 0 {
+            buffer.truncate(length);
+            return String::from_utf8_lossy(&buffer).into_owned();
+        }
+        buffer.resize(buffer.len() * 2, 0);
+    }
+    String::new()
+}
+
+impl Widget for Clock {
+    fn poll(&mut self) -> Option<Outcome> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Some(Outcome::Success(strftime(&self.format, &local_time(now.as_secs() as libc::time_t))))
+    }
+
+    fn wait(&self) -> Duration {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let to_next_second = Duration::from_secs(1) - Duration::from_nanos(now.subsec_nanos() as u64);
+        // A few milliseconds late is better than waking up just before the boundary
+        let margin = Duration::from_millis(5);
+        if self.seconds {
+            return to_next_second + margin;
+        }
+
+        let second = local_time(now.as_secs() as libc::time_t).tm_sec as u64;
+        to_next_second + Duration::from_secs(59u64.saturating_sub(second)) + margin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::mem;
+
+    use libc;
+
+    use super::strftime;
+
+    #[test]
+    fn strftime_grows_its_buffer_as_needed() {
+        let mut tm: libc::tm = unsafe { mem::zeroed() };
+        tm.tm_year = 126;
+        tm.tm_mon = 9;
+        tm.tm_mday = 15;
+        tm.tm_hour = 8;
+        tm.tm_min = 5;
+        assert_eq!(strftime(&CString::new("%Y-%m-%d %H:%M").unwrap(), &tm), "2026-10-15 08:05");
+        assert_eq!(strftime(&CString::new("%Y".repeat(100)).unwrap(), &tm), "2026".repeat(100));
+        assert_eq!(strftime(&CString::new("").unwrap(), &tm), "");
+    }
+}
@@ -0,0 +1,234 @@
+//! A widget that checks whether the internet is reachable, the same
+//! way NetworkManager does: by fetching a known page over plain HTTP.
+//!
+//! The page is fetched every `reload` seconds, and again `holdoff` seconds
+//! after a link or an address changes, which is watched for on an rtnetlink
+//! socket. Waiting out the holdoff keeps the check from running while a
+//! connection is still coming up. The host is resolved on a thread of its
+//! own, so that a resolver that can't be reached doesn't hold up the check
+//! for longer than its timeout.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use toml;
+
+use {Event, Outcome};
+use super::rtnetlink::{Links, RTMGRP_IPV4_IFADDR, RTMGRP_IPV6_IFADDR, RTMGRP_LINK};
+use super::{seconds, string, Widget};
+
+/// How long resolving the host, connecting, and each read or write may take.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const DEFAULT_URL: &str = "http://nmcheck.gnome.org/check_network_status.txt";
+const DEFAULT_EXPECT: &str = "NetworkManager is online";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Online,
+    /// The page was fetched, but its content was wrong, as happens behind a captive portal.
+    Portal,
+    Offline,
+}
+
+struct Connectivity {
+    host: String,
+    port: u16,
+    path: String,
+    expect: String,
+    interval: Duration,
+    holdoff: Duration,
+    online: String,
+    portal: String,
+    offline: String,
+    /// When the page is next fetched, or `None` if it's due straight away.
+    next_check: Option<Instant>,
+    links: Option<Links>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Connectivity::from_config(section_name, configuration)?))
+}
+
+impl Connectivity {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Connectivity, String> {
+        let url = string(section_name, configuration, "url", DEFAULT_URL)?;
+        let rest = url.strip_prefix("http://")
+            .ok_or_else(|| format!("Invalid url found for {}: only http:// URLs are supported", section_name))?;
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().map_err(|_| format!("Invalid url found for {}", section_name))?),
+            None => (authority, 80),
+        };
+
+        Ok(Connectivity {
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+            expect: string(section_name, configuration, "expect", DEFAULT_EXPECT)?,
+            interval: seconds(section_name, configuration, "reload", 300f64)?,
+            holdoff: seconds(section_name, configuration, "holdoff", 5f64)?,
+            online: string(section_name, configuration, "online", "online")?,
+            portal: string(section_name, configuration, "portal", "portal")?,
+            offline: string(section_name, configuration, "offline", "offline")?,
+            next_check: None,
+            links: None,
+        })
+    }
+
+    fn check(&self) -> State {
+        self.fetch(&self.resolve())
+    }
+
+    /// Resolves the host, giving up after `TIMEOUT`. The lookup carries on
+    /// in the background if it's still blocked by then, and its result is dropped.
+    fn resolve(&self) -> Vec<SocketAddr> {
+        let (sender, receiver) = channel();
+        let target = (self.host.clone(), self.port);
+        thread::spawn(move || {
+            let addresses = target.to_socket_addrs().map(|addresses| addresses.collect()).unwrap_or_default();
+            let _ = sender.send(addresses);
+        });
+        receiver.recv_timeout(TIMEOUT).unwrap_or_default()
+    }
+
+    /// Fetches the page from the first of `addresses` that can be connected to.
+    fn fetch(&self, addresses: &[SocketAddr]) -> State {
+        // Failing to resolve the host counts as being offline, as does failing to connect
+        let mut stream = match addresses.iter().find_map(|address| TcpStream::connect_timeout(address, TIMEOUT).ok()) {
+            Some(stream) => stream,
+            None => return State::Offline,
+        };
+
+        let _ = stream.set_read_timeout(Some(TIMEOUT));
+        let _ = stream.set_write_timeout(Some(TIMEOUT));
+        // HTTP/1.0, so that the body isn't sent in chunks and the connection closes after it
+        let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", self.path, self.host);
+        if stream.write_all(request.as_bytes()).is_err() {
+            return State::Offline;
+        }
+
+        let mut response = Vec::new();
+        let _ = stream.take(64 * 1024).read_to_end(&mut response);
+        let response = String::from_utf8_lossy(&response);
+
+        let status = response.split_whitespace().nth(1);
+        let body = response.split_once("\r\n\r\n").map(|(_, body)| body.trim()).unwrap_or("");
+        match status {
+            None => State::Offline,
+            Some("204") if self.expect.is_empty() => State::Online,
+            Some("200") if body == self.expect => State::Online,
+            Some(_) => State::Portal,
+        }
+    }
+}
+
+impl Widget for Connectivity {
+    /// Checks connectivity if a check is due. Being polled before then means
+    /// the network changed, so the check is put off until `holdoff` has passed.
+    fn poll(&mut self) -> Option<Outcome> {
+        let now = Instant::now();
+        match self.next_check {
+            Some(next_check) if now < next_check => {
+                self.next_check = Some(now + self.holdoff);
+                return None;
+            },
+            _ => {},
+        }
+
+        self.next_check = Some(now + self.interval);
+        let text = match self.check() {
+            State::Online => &self.online,
+            State::Portal => &self.portal,
+            State::Offline => &self.offline,
+        };
+        Some(Outcome::Success(text.to_owned()))
+    }
+
+    fn wait(&self) -> Duration {
+        self.next_check.map_or(Duration::from_secs(0), |next_check| next_check.saturating_duration_since(Instant::now()))
+    }
+
+    /// Without an rtnetlink socket, changes to the network aren't noticed, and
+    /// the page is only fetched every `reload` seconds.
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.links = Links::start(RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR, wake);
+    }
+
+    fn refresh(&mut self) {
+        self.next_check = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+
+    use toml;
+
+    use super::{Connectivity, State};
+
+    /// Answers one request on a local port with whatever `reply` makes of it,
+    /// returning a connectivity check against it.
+    fn answer<F: FnOnce(&str) -> String + Send + 'static>(reply: F) -> Connectivity {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let length = stream.read(&mut request).unwrap_or(0);
+            let _ = stream.write_all(reply(&String::from_utf8_lossy(&request[..length])).as_bytes());
+        });
+        let config = toml::Parser::new(&format!("url = \"http://127.0.0.1:{}/check\"\nexpect = \"ok\"", port)).parse().unwrap();
+        Connectivity::from_config("online", &config).unwrap()
+    }
+
+    fn serve(response: &'static str) -> Connectivity {
+        answer(move |_| String::from(response))
+    }
+
+    #[test]
+    fn the_page_decides_the_state() {
+        assert_eq!(serve("HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nok\n").check(), State::Online);
+        assert_eq!(serve("HTTP/1.1 302 Found\r\nLocation: http://portal/\r\n\r\n").check(), State::Portal);
+        assert_eq!(serve("HTTP/1.1 200 OK\r\n\r\n<html>sign in</html>").check(), State::Portal);
+        assert_eq!(serve("").check(), State::Offline);
+    }
+
+    #[test]
+    fn servers_that_would_chunk_the_page_send_it_whole() {
+        // Like most servers, this one only sends HTTP/1.1 clients the body in chunks
+        let check = answer(|request| match request.lines().next() {
+            Some(line) if line.ends_with("HTTP/1.1") => String::from("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n"),
+            _ => String::from("HTTP/1.1 200 OK\r\n\r\nok"),
+        });
+        assert_eq!(check.check(), State::Online);
+    }
+
+    #[test]
+    fn each_address_is_tried_in_turn() {
+        let check = serve("HTTP/1.1 200 OK\r\n\r\nok");
+        // Nothing listens on a port once its listener has been dropped
+        let closed = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let open = SocketAddr::from(([127, 0, 0, 1], check.port));
+        assert_eq!(check.fetch(&[closed, open]), State::Online);
+        assert_eq!(check.fetch(&[]), State::Offline);
+    }
+
+    #[test]
+    fn only_plain_http_is_checked() {
+        let config = toml::Parser::new("url = \"https://example.com/\"").parse().unwrap();
+        assert!(Connectivity::from_config("online", &config).is_err());
+        let config = toml::Parser::new("url = \"http://example.com:8080\"").parse().unwrap();
+        let check = Connectivity::from_config("online", &config).unwrap();
+        assert_eq!((check.host.as_str(), check.port, check.path.as_str()), ("example.com", 8080, "/"));
+    }
+}
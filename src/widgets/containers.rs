@@ -0,0 +1,216 @@
+//! A widget showing how many containers are running, and how many of those are
+//! unhealthy, under Docker or Podman.
+//!
+//! Both are asked over their API's UNIX socket, with plain HTTP/1.0 requests
+//! so that responses come whole rather than chunked. A thread follows the
+//! `/events` stream, which wakes the widget whenever a container changes.
+
+use std::env;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+use toml;
+
+use {Event, Outcome};
+use super::{fill, seconds, string, Widget};
+
+/// How long to wait before connecting again after the event stream ends.
+const RECONNECT: Duration = Duration::from_secs(30);
+/// `/events?filters={"type":["container"]}`, encoded.
+const EVENTS: &str = "/events?filters=%7B%22type%22%3A%5B%22container%22%5D%7D";
+
+struct Containers {
+    socket: PathBuf,
+    format: String,
+    unhealthy_format: String,
+    none: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    events: Option<Events>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let socket = match configuration.get("socket") {
+        Some(_) => PathBuf::from(string(section_name, configuration, "socket", "")?),
+        None => find_socket().ok_or_else(|| format!("Failed to find a Docker or Podman socket for {}; set its socket", section_name))?,
+    };
+    let format = string(section_name, configuration, "format", "{running} running")?;
+
+    Ok(Box::new(Containers {
+        socket,
+        unhealthy_format: string(section_name, configuration, "unhealthy_format", &format)?,
+        format,
+        none: string(section_name, configuration, "none", "")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        events: None,
+    }))
+}
+
+/// Docker's socket, from `$DOCKER_HOST` or where it usually is, or else a rootless Podman's.
+fn find_socket() -> Option<PathBuf> {
+    let docker_host = env::var("DOCKER_HOST").ok()
+        .and_then(|host| host.strip_prefix("unix://").map(PathBuf::from));
+    let podman = env::var_os("XDG_RUNTIME_DIR").map(|runtime| PathBuf::from(runtime).join("podman").join("podman.sock"));
+    docker_host.into_iter()
+        .chain(Some(PathBuf::from("/var/run/docker.sock")))
+        .chain(podman)
+        .chain(Some(PathBuf::from("/run/podman/podman.sock")))
+        .find(|path| path.exists())
+}
+
+fn request(stream: &mut UnixStream, path: &str) -> Option<()> {
+    stream.write_all(format!("GET {} HTTP/1.0\r\nHost: localhost\r\n\r\n", path).as_bytes()).ok()
+}
+
+/// Fetches a path and returns the body of the response, if it succeeds.
+fn get(socket: &Path, path: &str) -> Option<String> {
+    let mut stream = UnixStream::connect(socket).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+    request(&mut stream, path)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    let (head, body) = response.split_once("\r\n\r\n")?;
+    if head.split_whitespace().nth(1) != Some("200") {
+        return None;
+    }
+    Some(body.to_owned())
+}
+
+/// Counts the running containers, and those whose health check is failing.
+fn count(socket: &Path) -> Option<(usize, usize)> {
+    let containers = Json::from_str(&get(socket, "/containers/json")?).ok()?;
+    let containers = containers.as_array()?;
+    let unhealthy = containers.iter()
+        .filter(|container| container.find("Status").and_then(Json::as_string).is_some_and(|status| status.contains("(unhealthy)")))
+        .count();
+    Some((containers.len(), unhealthy))
+}
+
+/// A thread following the event stream, which wakes the widget with every
+/// event. Shutting the stream down when this is dropped lets the thread finish.
+struct Events {
+    stopped: Arc<AtomicBool>,
+    stream: Arc<Mutex<Option<UnixStream>>>,
+}
+
+impl Events {
+    fn start(socket: PathBuf, wake: Sender<Event>) -> Events {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stream = Arc::new(Mutex::new(None));
+        let events = Events { stopped: stopped.clone(), stream: stream.clone() };
+
+        thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                if let Ok(mut connection) = UnixStream::connect(&socket) {
+                    *stream.lock().unwrap() = connection.try_clone().ok();
+                    // Check again whether this was dropped while connecting
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    if request(&mut connection, EVENTS).is_some() {
+                        // Containers may have changed while there was no connection
+                        let _ = wake.send(Event::Wake);
+                        let lines = BufReader::new(connection).lines().map_while(Result::ok);
+                        // Each event is a line of JSON after the headers
+                        for _ in lines.skip_while(|line| !line.trim().is_empty()).filter(|line| !line.trim().is_empty()) {
+                            let _ = wake.send(Event::Wake);
+                        }
+                    }
+                }
+                thread::sleep(RECONNECT);
+            }
+        });
+        events
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(ref stream) = *self.stream.lock().unwrap() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+impl Widget for Containers {
+    /// Polled whenever a container changes, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let (running, unhealthy) = match count(&self.socket) {
+            Some(counts) => counts,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let format = match (running, unhealthy) {
+            (0, _) => return Some(Outcome::Success(self.none.clone())),
+            (_, 0) => &self.format,
+            _ => &self.unhealthy_format,
+        };
+        Some(Outcome::Success(fill(format, &[
+            ("running", running.to_string()),
+            ("unhealthy", unhealthy.to_string()),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.events = Some(Events::start(self.socket.clone(), wake));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::process;
+    use std::thread;
+
+    use toml;
+
+    use Outcome;
+
+    /// Answers each request on a socket with the given responses, in turn.
+    fn serve(name: &str, responses: &'static [&'static str]) -> PathBuf {
+        let socket = env::temp_dir().join(format!("admiral-test-containers-{}-{}", name, process::id()));
+        let _ = fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        thread::spawn(move || {
+            for (response, stream) in responses.iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let mut request = [0u8; 1024];
+                let read = stream.read(&mut request).unwrap();
+                assert!(request[..read].starts_with(b"GET /containers/json HTTP/1.0\r\n"));
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        socket
+    }
+
+    #[test]
+    fn running_and_unhealthy_containers_are_counted() {
+        let socket = serve("count", &[
+            "HTTP/1.0 200 OK\r\nContent-Type: application/json\r\n\r\n[{\"Status\": \"Up 2 hours\"}, {\"Status\": \"Up 5 minutes (unhealthy)\"}]",
+            "HTTP/1.0 200 OK\r\n\r\n[]",
+            "HTTP/1.0 500 Internal Server Error\r\n\r\n{}",
+        ]);
+        let src = format!("socket = \"{}\"\nunhealthy_format = \"{{running}} ({{unhealthy}} sick)\"\nnone = \"idle\"", socket.display());
+        let mut containers = super::new("containers", &toml::Parser::new(&src).parse().unwrap()).unwrap();
+        assert_eq!(containers.poll(), Some(Outcome::Success(String::from("2 (1 sick)"))));
+        assert_eq!(containers.poll(), Some(Outcome::Success(String::from("idle"))));
+        assert_eq!(containers.poll(), Some(Outcome::Failure(None)));
+        let _ = fs::remove_file(&socket);
+    }
+}
@@ -0,0 +1,172 @@
+//! A widget counting down to a date, such as "conference in 12d 4h".
+//!
+//! Rather than polling, the countdown sleeps until what it shows next changes:
+//! a day at a time while it's only showing days, and then by the hour and the
+//! minute as the date gets closer.
+
+use std::mem;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc;
+use toml::{self, Value};
+
+use Outcome;
+use super::{fill, string, Widget};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+/// The longest to sleep at once, so a suspend that the sleep doesn't count isn't noticed too late.
+const LONGEST_WAIT: Duration = Duration::from_secs(HOUR);
+
+struct Countdown {
+    target: SystemTime,
+    format: String,
+    done: String,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let target = match configuration.get("date") {
+        // TOML's own dates are in UTC, as in 2024-05-27T09:00:00Z
+        Some(Value::Datetime(date)) => parse_date(date.trim_end_matches('Z'), false),
+        Some(Value::String(date)) => parse_date(date, true),
+        Some(_) => None,
+        None => return Err(format!("Failed to find date for {}", section_name)),
+    };
+    let target = target.ok_or_else(|| format!("Invalid date found for {}: expected a date such as \"2024-05-27\" or \"2024-05-27 09:00\"", section_name))?;
+
+    Ok(Box::new(Countdown {
+        target,
+        format: string(section_name, configuration, "format", "{left}")?,
+        done: string(section_name, configuration, "done", "")?,
+    }))
+}
+
+/// Reads a date as `YYYY-MM-DD`, optionally followed by a time as `HH:MM` or
+/// `HH:MM:SS` after a space or a `T`, in local time or UTC.
+fn parse_date(date: &str, local: bool) -> Option<SystemTime> {
+    let (day, time) = match date.split_once([' ', 'T']) {
+        Some((day, time)) => (day, time),
+        None => (date, "00:00"),
+    };
+    let numbers = |text: &str, separator: char| -> Option<Vec<i32>> {
+        text.trim().split(separator).map(|number| number.parse().ok()).collect()
+    };
+    let day = numbers(day, '-')?;
+    let time = numbers(time, ':')?;
+    if day.len() != 3 || time.len() < 2 || time.len() > 3 {
+        return None;
+    }
+
+    let mut tm: libc::tm = unsafe { mem::zeroed() };
+    tm.tm_year = day[0] - 1900;
+    tm.tm_mon = day[1] - 1;
+    tm.tm_mday = day[2];
+    tm.tm_hour = time[0];
+    tm.tm_min = time[1];
+    tm.tm_sec = time.get(2).cloned().unwrap_or(0);
+    // Let mktime work out whether daylight saving time applies
+    tm.tm_isdst = -1;
+    let seconds = if local { unsafe { libc::mktime(&mut tm) } } else { unsafe { libc::timegm(&mut tm) } };
+    if seconds < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
+
+impl Countdown {
+    /// The smallest unit that's shown with `remaining` left, in seconds.
+    fn unit(&self, remaining: u64) -> u64 {
+        if self.format.contains("{minutes}") {
+            MINUTE
+        } else if self.format.contains("{hours}") {
+            HOUR
+        } else if self.format.contains("{left}") {
+            match remaining {
+                0..DAY => MINUTE,
+                DAY..=604799 => HOUR,
+                _ => DAY,
+            }
+        } else {
+            DAY
+        }
+    }
+}
+
+/// Describes the time left with its two largest units, as `12d 4h`, `4h 12m`, or `12m`.
+fn left(remaining: u64) -> String {
+    let (days, hours, minutes) = (remaining / DAY, remaining % DAY / HOUR, remaining % HOUR / MINUTE);
+    match (days, hours) {
+        (0, 0) => format!("{}m", minutes),
+        (0, _) => format!("{}h {}m", hours, minutes),
+        // A week off, the hours aren't worth showing
+        (7.., _) => format!("{}d", days),
+        _ => format!("{}d {}h", days, hours),
+    }
+}
+
+impl Widget for Countdown {
+    fn poll(&mut self) -> Option<Outcome> {
+        let remaining = match self.target.duration_since(SystemTime::now()) {
+            Ok(remaining) if remaining.as_secs() > 0 => remaining.as_secs(),
+            _ => return Some(Outcome::Success(self.done.clone())),
+        };
+        Some(Outcome::Success(fill(&self.format, &[
+            ("left", left(remaining)),
+            ("days", (remaining / DAY).to_string()),
+            ("hours", (remaining % DAY / HOUR).to_string()),
+            ("minutes", (remaining % HOUR / MINUTE).to_string()),
+            ("total_hours", (remaining / HOUR).to_string()),
+        ])))
+    }
+
+    /// Sleeps until the smallest unit shown next ticks down.
+    fn wait(&self) -> Duration {
+        let remaining = match self.target.duration_since(SystemTime::now()) {
+            Ok(remaining) => remaining,
+            Err(_) => return LONGEST_WAIT,
+        };
+        let unit = Duration::from_secs(self.unit(remaining.as_secs()));
+        let until_tick = Duration::from_nanos((remaining.as_nanos() % unit.as_nanos()) as u64);
+        // A few milliseconds late is better than waking up just before the tick
+        (until_tick + Duration::from_millis(5)).min(LONGEST_WAIT)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use toml;
+
+    use Outcome;
+    use super::{left, parse_date, DAY, HOUR, MINUTE};
+
+    #[test]
+    fn utc_dates_are_read() {
+        assert_eq!(parse_date("2024-05-27", false), Some(UNIX_EPOCH + Duration::from_secs(1716768000)));
+        assert_eq!(parse_date("2024-05-27T09:00:30", false), Some(UNIX_EPOCH + Duration::from_secs(1716768000 + 9 * HOUR + 30)));
+        assert_eq!(parse_date("2024-05-27 09:00", false), parse_date("2024-05-27T09:00:00", false));
+        assert_eq!(parse_date("2024-05", false), None);
+        assert_eq!(parse_date("tomorrow", false), None);
+    }
+
+    #[test]
+    fn the_two_largest_units_are_left() {
+        assert_eq!(left(12 * MINUTE), "12m");
+        assert_eq!(left(4 * HOUR + 12 * MINUTE), "4h 12m");
+        assert_eq!(left(2 * DAY + 4 * HOUR + 12 * MINUTE), "2d 4h");
+        assert_eq!(left(12 * DAY + 4 * HOUR), "12d");
+    }
+
+    #[test]
+    fn past_dates_show_done() {
+        let configuration = toml::Parser::new("date = 2000-01-01T00:00:00Z\ndone = \"over\"").parse().unwrap();
+        let mut countdown = super::new("countdown", &configuration).unwrap();
+        assert_eq!(countdown.poll(), Some(Outcome::Success(String::from("over"))));
+
+        let configuration = toml::Parser::new("date = \"not a date\"").parse().unwrap();
+        assert!(super::new("countdown", &configuration).is_err());
+    }
+}
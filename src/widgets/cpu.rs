@@ -0,0 +1,121 @@
+//! A widget showing CPU utilisation, worked out from the difference
+//! between successive samples of `/proc/stat`.
+//!
+//! A sample is taken when the widget is created, so the first usage is shown
+//! one `reload` later, covering that interval rather than the time since boot.
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use toml;
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+/// Busy and total time spent by a CPU, in clock ticks.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Times {
+    busy: u64,
+    total: u64,
+}
+
+impl Times {
+    fn percent_since(&self, earlier: &Times) -> u64 {
+        let total = self.total.saturating_sub(earlier.total);
+        let busy = self.busy.saturating_sub(earlier.busy);
+        (busy * 100 + total / 2).checked_div(total).unwrap_or(0)
+    }
+}
+
+#[derive(Debug)]
+struct Cpu {
+    format: String,
+    core_format: String,
+    core_separator: String,
+    interval: Duration,
+    /// The whole machine, followed by each core.
+    sample: Vec<Times>,
+    last_check: Instant,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Cpu {
+        format: string(section_name, configuration, "format", "{percent}%")?,
+        core_format: string(section_name, configuration, "core_format", "{percent}%")?,
+        core_separator: string(section_name, configuration, "core_separator", " ")?,
+        // Shorter intervals would measure little more than the cost of reading /proc/stat
+        interval: seconds(section_name, configuration, "reload", 1f64)?.max(Duration::from_millis(100)),
+        sample: sample(),
+        last_check: Instant::now(),
+    }))
+}
+
+/// Reads the time spent by every CPU from `/proc/stat`.
+fn sample() -> Vec<Times> {
+    parse(&fs::read_to_string("/proc/stat").unwrap_or_default())
+}
+
+/// Reads the time spent by every CPU from the contents of `/proc/stat`.
+fn parse(stat: &str) -> Vec<Times> {
+    stat.lines()
+        .filter(|line| line.starts_with("cpu"))
+        .map(|line| {
+            // user nice system idle iowait irq softirq steal, leaving out guest time,
+            // which is already counted in user and nice
+            let fields = line.split_whitespace()
+                .skip(1)
+                .take(8)
+                .map(|field| field.parse::<u64>().unwrap_or(0))
+                .collect::<Vec<_>>();
+            let total = fields.iter().sum::<u64>();
+            let idle = fields.get(3).unwrap_or(&0) + fields.get(4).unwrap_or(&0);
+            Times { busy: total - idle, total }
+        })
+        .collect()
+}
+
+impl Widget for Cpu {
+    fn poll(&mut self) -> Option<Outcome> {
+        if self.last_check.elapsed() < self.interval {
+            return None;
+        }
+        self.last_check = Instant::now();
+
+        let sample = sample();
+        if sample.is_empty() {
+            return Some(Outcome::Failure(None));
+        }
+        let percents = sample.iter()
+            .zip(self.sample.iter().chain(std::iter::repeat(&Times::default())))
+            .map(|(now, earlier)| now.percent_since(earlier))
+            .collect::<Vec<_>>();
+        self.sample = sample;
+        let mut percents = percents.into_iter();
+
+        let percent = percents.next().unwrap_or(0);
+        let cores = percents.enumerate()
+            .map(|(core, percent)| fill(&self.core_format, &[("core", core.to_string()), ("percent", percent.to_string())]))
+            .collect::<Vec<_>>()
+            .join(&self.core_separator);
+        Some(Outcome::Success(fill(&self.format, &[("percent", percent.to_string()), ("cores", cores)])))
+    }
+
+    /// Samples are taken exactly `reload` seconds apart, however short that is.
+    fn wait(&self) -> Duration {
+        self.interval.saturating_sub(self.last_check.elapsed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Times};
+
+    #[test]
+    fn stat_lines_give_busy_and_total_time() {
+        let stat = "cpu  100 10 50 800 40 0 0 0 20 0\ncpu0 60 5 25 400 10 0 0 0 0 0\nintr 12345 0 0\nctxt 999\n";
+        let times = parse(stat);
+        assert_eq!(times, vec![Times { busy: 160, total: 1000 }, Times { busy: 90, total: 500 }]);
+        assert_eq!(Times { busy: 220, total: 1100 }.percent_since(&times[0]), 60);
+        assert_eq!(times[0].percent_since(&times[0]), 0);
+    }
+}
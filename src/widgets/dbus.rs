@@ -0,0 +1,668 @@
+//! Just enough of D-Bus for widgets to read a service's properties and be
+//! woken by its signals.
+//!
+//! A connection authenticates and registers with the bus, after which it can
+//! call methods and wait for their replies, decoding them into `Value`s. A
+//! connection listening for signals adds match rules for the ones wanted, and
+//! makes any calls the service needs before it sends them (such as systemd's
+//! `Subscribe`). Its thread then wakes the widget whenever they arrive, without
+//! decoding them.
+
+use std::env;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::net::Shutdown;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use Event;
+
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const SIGNAL: u8 = 4;
+const NO_REPLY_EXPECTED: u8 = 1;
+
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// How long to let a burst of signals settle before waking the widget once for all of them.
+const SETTLE: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bus {
+    System,
+    Session,
+}
+
+/// A method to call, as its destination, object path, interface, and member.
+pub type Call<'a> = (&'a str, &'a str, &'a str, &'a str);
+
+/// A value decoded from a message. Dictionaries are arrays of two-field structs.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Byte(u8),
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Double(f64),
+    Str(String),
+    Array(Vec<Value>),
+    Struct(Vec<Value>),
+    Variant(Box<Value>),
+}
+
+impl Value {
+    /// The value inside any variants wrapping it.
+    pub fn inner(&self) -> &Value {
+        match *self {
+            Value::Variant(ref value) => value.inner(),
+            ref value => value,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match *self.inner() {
+            Value::Str(ref string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_int(&self) -> Option<i64> {
+        match *self.inner() {
+            Value::Int(int) => Some(int),
+            Value::Uint(uint) => Some(uint as i64),
+            _ => None,
+        }
+    }
+
+    /// Looks up an entry of a dictionary with string keys, such as an `a{sv}`.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match *self.inner() {
+            Value::Array(ref entries) => entries.iter().find_map(|entry| match *entry {
+                Value::Struct(ref fields) if fields.len() == 2 && fields[0].as_str() == Some(key) => Some(fields[1].inner()),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn connect(bus: Bus) -> Option<UnixStream> {
+    let address = match bus {
+        Bus::System => env::var("DBUS_SYSTEM_BUS_ADDRESS").unwrap_or_else(|_| String::from("unix:path=/var/run/dbus/system_bus_socket")),
+        Bus::Session => env::var("DBUS_SESSION_BUS_ADDRESS").ok()?,
+    };
+    // An address may list several ways to connect, separated by semicolons
+    for address in address.split(';') {
+        let parameters = match address.strip_prefix("unix:") {
+            Some(parameters) => parameters,
+            None => continue,
+        };
+        for parameter in parameters.split(',') {
+            let stream = match parameter.split_once('=') {
+                Some(("path", path)) => UnixStream::connect(path).ok(),
+                Some(("abstract", name)) => SocketAddr::from_abstract_name(name.as_bytes()).ok()
+                    .and_then(|address| UnixStream::connect_addr(&address).ok()),
+                _ => None,
+            };
+            if stream.is_some() {
+                return stream;
+            }
+        }
+    }
+    None
+}
+
+fn pad(message: &mut Vec<u8>, alignment: usize) {
+    while !message.len().is_multiple_of(alignment) {
+        message.push(0);
+    }
+}
+
+fn push_string(message: &mut Vec<u8>, string: &str) {
+    pad(message, 4);
+    message.extend_from_slice(&(string.len() as u32).to_le_bytes());
+    message.extend_from_slice(string.as_bytes());
+    message.push(0);
+}
+
+fn push_signature(message: &mut Vec<u8>, signature: &str) {
+    message.push(signature.len() as u8);
+    message.extend_from_slice(signature.as_bytes());
+    message.push(0);
+}
+
+/// The signature of a value to be sent. Only the simple types that are ever
+/// sent, and variants of them, have one.
+fn signature(value: &Value) -> Option<String> {
+    Some(String::from(match *value {
+        Value::Byte(_) => "y",
+        Value::Bool(_) => "b",
+        Value::Int(_) => "i",
+        Value::Uint(_) => "u",
+        Value::Double(_) => "d",
+        Value::Str(_) => "s",
+        Value::Variant(_) => "v",
+        Value::Array(_) | Value::Struct(_) => return None,
+    }))
+}
+
+/// Encodes a value for the body of a message, returning `None` for one that can't be sent.
+fn push_value(message: &mut Vec<u8>, value: &Value) -> Option<()> {
+    match *value {
+        Value::Byte(byte) => message.push(byte),
+        Value::Bool(boolean) => {
+            pad(message, 4);
+            message.extend_from_slice(&(boolean as u32).to_le_bytes());
+        },
+        Value::Int(int) => {
+            pad(message, 4);
+            message.extend_from_slice(&(int as i32).to_le_bytes());
+        },
+        Value::Uint(uint) => {
+            pad(message, 4);
+            message.extend_from_slice(&(uint as u32).to_le_bytes());
+        },
+        Value::Double(double) => {
+            pad(message, 8);
+            message.extend_from_slice(&double.to_bits().to_le_bytes());
+        },
+        Value::Str(ref string) => push_string(message, string),
+        Value::Variant(ref inner) => {
+            push_signature(message, &signature(inner)?);
+            push_value(message, inner)?;
+        },
+        Value::Array(_) | Value::Struct(_) => return None,
+    }
+    Some(())
+}
+
+fn push_field(fields: &mut Vec<u8>, code: u8, signature: u8, value: &str) {
+    pad(fields, 8);
+    fields.extend_from_slice(&[code, 1, signature, 0]);
+    match signature {
+        b'g' => push_signature(fields, value),
+        _ => push_string(fields, value),
+    }
+}
+
+/// Builds a little-endian method call, returning `None` if an argument can't be sent.
+fn method_call(serial: u32, call: Call, arguments: &[Value], flags: u8) -> Option<Vec<u8>> {
+    let (destination, path, interface, member) = call;
+    let mut body = Vec::new();
+    let mut signatures = String::new();
+    for argument in arguments {
+        signatures.push_str(&signature(argument)?);
+        push_value(&mut body, argument)?;
+    }
+
+    // Fields are laid out as if from the start of the message, which is 16 bytes in
+    let mut fields = vec![0u8; 16];
+    push_field(&mut fields, FIELD_PATH, b'o', path);
+    push_field(&mut fields, FIELD_DESTINATION, b's', destination);
+    push_field(&mut fields, FIELD_INTERFACE, b's', interface);
+    push_field(&mut fields, FIELD_MEMBER, b's', member);
+    if !signatures.is_empty() {
+        push_field(&mut fields, FIELD_SIGNATURE, b'g', &signatures);
+    }
+    let fields = fields.split_off(16);
+
+    let mut message = vec![b'l', METHOD_CALL, flags, 1];
+    message.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    message.extend_from_slice(&serial.to_le_bytes());
+    message.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+    message.extend_from_slice(&fields);
+    pad(&mut message, 8);
+    message.extend_from_slice(&body);
+    Some(message)
+}
+
+/// The length of the first complete type in a signature.
+fn type_length(signature: &[u8]) -> Option<usize> {
+    match *signature.first()? {
+        b'a' => Some(1 + type_length(&signature[1..])?),
+        open if open == b'(' || open == b'{' => {
+            let close = if open == b'(' { b')' } else { b'}' };
+            let mut length = 1;
+            while *signature.get(length)? != close {
+                length += type_length(&signature[length..])?;
+            }
+            Some(length + 1)
+        },
+        _ => Some(1),
+    }
+}
+
+fn alignment(kind: u8) -> usize {
+    match kind {
+        b'n' | b'q' => 2,
+        b'b' | b'i' | b'u' | b'h' | b's' | b'o' | b'a' => 4,
+        b'x' | b't' | b'd' | b'(' | b'{' => 8,
+        _ => 1,
+    }
+}
+
+/// Decodes values from part of a message. As the fields and the body both
+/// start on an 8-byte boundary, aligning from their start is the same as
+/// aligning from the start of the message.
+struct Decoder<'a> {
+    data: &'a [u8],
+    position: usize,
+    big_endian: bool,
+}
+
+impl<'a> Decoder<'a> {
+    fn align(&mut self, alignment: usize) {
+        self.position = self.position.div_ceil(alignment) * alignment;
+    }
+
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.position..self.position.checked_add(length)?)?;
+        self.position += length;
+        Some(bytes)
+    }
+
+    fn number(&mut self, size: usize) -> Option<u64> {
+        self.align(size);
+        let bytes = self.take(size)?;
+        let bytes: Box<dyn Iterator<Item = &u8>> = if self.big_endian { Box::new(bytes.iter()) } else { Box::new(bytes.iter().rev()) };
+        Some(bytes.fold(0, |number, &byte| number << 8 | byte as u64))
+    }
+
+    /// Decodes a value of a single complete type.
+    fn value(&mut self, kind: &[u8]) -> Option<Value> {
+        Some(match *kind.first()? {
+            b'y' => Value::Byte(self.take(1)?[0]),
+            b'b' => Value::Bool(self.number(4)? != 0),
+            b'n' => Value::Int(self.number(2)? as u16 as i16 as i64),
+            b'i' => Value::Int(self.number(4)? as u32 as i32 as i64),
+            b'x' => Value::Int(self.number(8)? as i64),
+            b'q' => Value::Uint(self.number(2)?),
+            b'u' | b'h' => Value::Uint(self.number(4)?),
+            b't' => Value::Uint(self.number(8)?),
+            b'd' => Value::Double(f64::from_bits(self.number(8)?)),
+            b's' | b'o' => {
+                let length = self.number(4)? as usize;
+                Value::Str(String::from_utf8_lossy(&self.take(length.checked_add(1)?)?[..length]).into_owned())
+            },
+            b'g' => {
+                let length = self.take(1)?[0] as usize;
+                Value::Str(String::from_utf8_lossy(&self.take(length + 1)?[..length]).into_owned())
+            },
+            b'v' => {
+                let length = self.take(1)?[0] as usize;
+                let signature = &self.take(length + 1)?[..length];
+                if type_length(signature) != Some(signature.len()) {
+                    return None;
+                }
+                Value::Variant(Box::new(self.value(signature)?))
+            },
+            b'a' => {
+                let length = self.number(4)? as usize;
+                let element = &kind[1..];
+                // The elements are aligned even when there are none
+                self.align(alignment(*element.first()?));
+                let end = self.position.checked_add(length)?;
+                let mut elements = Vec::new();
+                while self.position < end {
+                    elements.push(self.value(element)?);
+                }
+                Value::Array(elements)
+            },
+            b'(' | b'{' => {
+                self.align(8);
+                let mut fields = Vec::new();
+                let mut rest = &kind[1..kind.len() - 1];
+                while !rest.is_empty() {
+                    let length = type_length(rest)?;
+                    fields.push(self.value(&rest[..length])?);
+                    rest = &rest[length..];
+                }
+                Value::Struct(fields)
+            },
+            _ => return None,
+        })
+    }
+}
+
+/// A message read from the bus, with what's needed to match replies to calls.
+struct Message {
+    kind: u8,
+    reply_serial: Option<u64>,
+    signature: Vec<u8>,
+    body: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Message {
+    /// Decodes the body's values, or returns `None` if it's malformed.
+    fn values(&self) -> Option<Vec<Value>> {
+        let mut decoder = Decoder { data: &self.body, position: 0, big_endian: self.big_endian };
+        let mut values = Vec::new();
+        let mut rest = &self.signature[..];
+        while !rest.is_empty() {
+            let length = type_length(rest)?;
+            values.push(decoder.value(&rest[..length])?);
+            rest = &rest[length..];
+        }
+        Some(values)
+    }
+}
+
+/// Parses the message at the start of `data`, returning it with its length,
+/// or `None` if not all of it has arrived yet.
+fn parse_message(data: &[u8]) -> std::io::Result<Option<(Message, usize)>> {
+    if data.len() < 16 {
+        return Ok(None);
+    }
+    let big_endian = match data[0] {
+        b'l' => false,
+        b'B' => true,
+        _ => return Err(ErrorKind::InvalidData.into()),
+    };
+    let number = |bytes: &[u8]| {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    };
+    let body = number(&data[4..8]) as usize;
+    let fields = number(&data[12..16]) as usize;
+    let header = 16 + fields.div_ceil(8) * 8;
+    // Messages are at most 128 MiB
+    if header + body > 1 << 27 {
+        return Err(ErrorKind::InvalidData.into());
+    }
+    if data.len() < header + body {
+        return Ok(None);
+    }
+
+    let mut message = Message { kind: data[1], reply_serial: None, signature: Vec::new(), body: data[header..header + body].to_vec(), big_endian };
+    // The fields are decoded from the start of the message, so that they're aligned as they were sent
+    let mut decoder = Decoder { data: &data[..16 + fields], position: 16, big_endian };
+    while decoder.position < 16 + fields {
+        let field = match decoder.value(b"(yv)") {
+            Some(Value::Struct(field)) => field,
+            _ => return Err(ErrorKind::InvalidData.into()),
+        };
+        match (&field[0], field[1].inner()) {
+            (Value::Byte(FIELD_REPLY_SERIAL), Value::Uint(serial)) => message.reply_serial = Some(*serial),
+            (Value::Byte(FIELD_SIGNATURE), Value::Str(signature)) => message.signature = signature.clone().into_bytes(),
+            _ => {},
+        }
+    }
+    Ok(Some((message, header + body)))
+}
+
+/// Reads messages from a connection, keeping whatever part of one has arrived
+/// when a read times out, so that the rest can be read later.
+struct Incoming {
+    stream: UnixStream,
+    buffer: Vec<u8>,
+}
+
+impl Incoming {
+    /// Reads the next whole message. A timeout leaves the stream where it was.
+    fn next(&mut self) -> std::io::Result<Message> {
+        loop {
+            if let Some((message, length)) = parse_message(&self.buffer)? {
+                self.buffer.drain(..length);
+                return Ok(message);
+            }
+            let mut chunk = [0u8; 4096];
+            match self.stream.read(&mut chunk)? {
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                read => self.buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+    }
+}
+
+/// A connection to a bus, registered and ready to make calls.
+pub struct Connection {
+    stream: UnixStream,
+    incoming: Incoming,
+    serial: u32,
+    /// Whether reading or writing has failed, leaving the connection unusable.
+    broken: bool,
+}
+
+impl Connection {
+    pub fn open(bus: Bus) -> Option<Connection> {
+        let mut stream = connect(bus)?;
+        stream.set_read_timeout(Some(Duration::from_secs(5))).ok()?;
+
+        // The EXTERNAL mechanism takes the user id, written in hex of its decimal digits
+        let uid = unsafe { libc::getuid() }.to_string();
+        let uid = uid.bytes().map(|digit| format!("{:02x}", digit)).collect::<String>();
+        stream.write_all(format!("\0AUTH EXTERNAL {}\r\n", uid).as_bytes()).ok()?;
+        let mut reader = BufReader::new(stream.try_clone().ok()?);
+        let mut reply = String::new();
+        reader.read_line(&mut reply).ok()?;
+        if !reply.starts_with("OK ") {
+            return None;
+        }
+        stream.write_all(b"BEGIN\r\n").ok()?;
+
+        // Anything read past the reply is the start of the first message
+        let incoming = Incoming { buffer: reader.buffer().to_vec(), stream: reader.into_inner() };
+        let mut connection = Connection { stream, incoming, serial: 0, broken: false };
+        connection.call(("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "Hello"), &[])?;
+        Some(connection)
+    }
+
+    fn send(&mut self, call: Call, arguments: &[Value], flags: u8) -> Option<u32> {
+        self.serial += 1;
+        let message = method_call(self.serial, call, arguments, flags)?;
+        if self.stream.write_all(&message).is_err() {
+            self.broken = true;
+            return None;
+        }
+        Some(self.serial)
+    }
+
+    /// Whether the connection has failed, rather than just a call on it, and
+    /// needs to be opened again.
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    /// Calls a method, returning the values it replies with, or `None` if it
+    /// replies with an error.
+    fn call_with(&mut self, call: Call, arguments: &[Value]) -> Option<Vec<Value>> {
+        let serial = self.send(call, arguments, 0)?;
+        // Signals and replies to earlier calls may arrive first
+        loop {
+            let message = match self.incoming.next() {
+                Ok(message) => message,
+                Err(_) => {
+                    self.broken = true;
+                    return None;
+                },
+            };
+            if message.reply_serial == Some(serial as u64) {
+                return match message.kind {
+                    METHOD_RETURN => message.values(),
+                    _ => None,
+                };
+            }
+        }
+    }
+
+    /// Calls a method with string arguments, returning the values it replies
+    /// with, or `None` if it replies with an error.
+    pub fn call(&mut self, call: Call, arguments: &[&str]) -> Option<Vec<Value>> {
+        let arguments = arguments.iter().map(|&argument| Value::Str(argument.to_owned())).collect::<Vec<_>>();
+        self.call_with(call, &arguments)
+    }
+
+    /// Reads a property of one of a service's objects.
+    pub fn property(&mut self, destination: &str, path: &str, interface: &str, name: &str) -> Option<Value> {
+        let get = (destination, path, "org.freedesktop.DBus.Properties", "Get");
+        self.call(get, &[interface, name])?.into_iter().next().map(|value| value.inner().clone())
+    }
+
+    /// Sets a property of one of a service's objects, returning `None` if it
+    /// couldn't be.
+    pub fn set_property(&mut self, destination: &str, path: &str, interface: &str, name: &str, value: Value) -> Option<()> {
+        let set = (destination, path, "org.freedesktop.DBus.Properties", "Set");
+        let arguments = [Value::Str(interface.to_owned()), Value::Str(name.to_owned()), Value::Variant(Box::new(value))];
+        self.call_with(set, &arguments).map(|_| ())
+    }
+}
+
+/// Makes calls on a kept connection, opening it first if need be. A
+/// connection that breaks is dropped, so that it's opened afresh next time.
+pub fn with_connection<T>(connection: &mut Option<Connection>, bus: Bus, call: impl FnOnce(&mut Connection) -> Option<T>) -> Option<T> {
+    if connection.is_none() {
+        *connection = Connection::open(bus);
+    }
+    let result = call(connection.as_mut()?);
+    if connection.as_ref().is_some_and(Connection::is_broken) {
+        *connection = None;
+    }
+    result
+}
+
+/// A connection listening for signals, whose thread wakes the widget when
+/// they arrive. Shutting the connection down when this is dropped lets the
+/// thread finish.
+pub struct Signals(UnixStream);
+
+impl Signals {
+    pub fn start(bus: Bus, rules: &[&str], calls: &[Call], wake: Sender<Event>) -> Option<Signals> {
+        let mut connection = Connection::open(bus)?;
+        for rule in rules {
+            let add_match = ("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "AddMatch");
+            connection.send(add_match, &[Value::Str((*rule).to_owned())], NO_REPLY_EXPECTED)?;
+        }
+        for &call in calls {
+            connection.send(call, &[], NO_REPLY_EXPECTED)?;
+        }
+
+        let Connection { stream, mut incoming, .. } = connection;
+        let signals = Signals(stream);
+        thread::spawn(move || {
+            let _ = incoming.stream.set_read_timeout(None);
+            while let Ok(message) = incoming.next() {
+                if message.kind != SIGNAL {
+                    continue;
+                }
+                // Take in the rest of the burst, which often comes with a change
+                let _ = incoming.stream.set_read_timeout(Some(SETTLE));
+                loop {
+                    match incoming.next() {
+                        Ok(_) => continue,
+                        Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+                        Err(_) => return,
+                    }
+                }
+                let _ = incoming.stream.set_read_timeout(None);
+                if wake.send(Event::Wake).is_err() {
+                    return;
+                }
+            }
+        });
+        Some(signals)
+    }
+}
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Write};
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::{method_call, pad, parse_message, push_signature, push_string, type_length, Decoder, Incoming, Value, METHOD_CALL};
+
+    const CALL: (&str, &str, &str, &str) = ("org.example", "/org/example", "org.example.Interface", "Method");
+
+    #[test]
+    fn signatures_are_split_into_complete_types() {
+        assert_eq!(type_length(b"s"), Some(1));
+        assert_eq!(type_length(b"a{sv}i"), Some(5));
+        assert_eq!(type_length(b"(ya(ss))u"), Some(8));
+        assert_eq!(type_length(b"(ss"), None);
+        assert_eq!(type_length(b""), None);
+    }
+
+    #[test]
+    fn calls_read_back_as_sent() {
+        let arguments = [Value::Str(String::from("name")), Value::Variant(Box::new(Value::Str(String::from("balanced"))))];
+        let message = method_call(7, CALL, &arguments, 0).unwrap();
+        let (parsed, length) = parse_message(&message).unwrap().unwrap();
+        assert_eq!(length, message.len());
+        assert_eq!(parsed.kind, METHOD_CALL);
+        assert_eq!(parsed.signature, b"sv");
+        assert_eq!(parsed.values(), Some(arguments.to_vec()));
+    }
+
+    #[test]
+    fn dictionaries_are_looked_up_by_key() {
+        let mut entries = Vec::new();
+        push_string(&mut entries, "Profile");
+        push_signature(&mut entries, "s");
+        push_string(&mut entries, "balanced");
+        let mut body = (entries.len() as u32).to_le_bytes().to_vec();
+        pad(&mut body, 8);
+        body.extend_from_slice(&entries);
+
+        let mut decoder = Decoder { data: &body, position: 0, big_endian: false };
+        let dictionary = decoder.value(b"a{sv}").unwrap();
+        assert_eq!(decoder.position, body.len());
+        assert_eq!(dictionary.get("Profile").and_then(Value::as_str), Some("balanced"));
+        assert_eq!(dictionary.get("Driver"), None);
+    }
+
+    #[test]
+    fn numbers_follow_the_message_endianness() {
+        let data = [0, 0, 0, 5, 0xff, 0xff, 0xff, 0xfe];
+        let mut decoder = Decoder { data: &data, position: 0, big_endian: true };
+        assert_eq!(decoder.value(b"u"), Some(Value::Uint(5)));
+        assert_eq!(decoder.value(b"i"), Some(Value::Int(-2)));
+        let mut decoder = Decoder { data: &data, position: 0, big_endian: false };
+        assert_eq!(decoder.value(b"u"), Some(Value::Uint(5 << 24)));
+        // A string longer than what's left is malformed rather than read past the end
+        let mut decoder = Decoder { data: &[9, 0, 0, 0, b'a', 0], position: 0, big_endian: false };
+        assert_eq!(decoder.value(b"s"), None);
+    }
+
+    #[test]
+    fn partial_messages_wait_for_the_rest() {
+        let message = method_call(1, CALL, &[Value::Str(String::from("argument"))], 0).unwrap();
+        for length in 0..message.len() {
+            assert!(parse_message(&message[..length]).unwrap().is_none());
+        }
+        assert!(parse_message(b"xxxxxxxxxxxxxxxx").is_err());
+    }
+
+    #[test]
+    fn timeouts_keep_what_has_arrived() {
+        let message = method_call(1, CALL, &[Value::Str(String::from("argument"))], 0).unwrap();
+        let (mut sender, receiver) = UnixStream::pair().unwrap();
+        receiver.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let mut incoming = Incoming { stream: receiver, buffer: Vec::new() };
+
+        sender.write_all(&message[..20]).unwrap();
+        let error = incoming.next().err().unwrap();
+        assert!(error.kind() == ErrorKind::WouldBlock || error.kind() == ErrorKind::TimedOut);
+        sender.write_all(&message[20..]).unwrap();
+        sender.write_all(&message).unwrap();
+        for _ in 0..2 {
+            let message = incoming.next().unwrap();
+            assert_eq!(message.values(), Some(vec![Value::Str(String::from("argument"))]));
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! A widget showing how much space is used on a filesystem.
+//!
+//! Sizes are worked out the same way as by `df`: free space is what is
+//! available to unprivileged users, and the percentage used leaves out the
+//! blocks reserved for root.
+
+use std::ffi::CString;
+use std::mem;
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use Outcome;
+use super::{fill, human, seconds, string, Widget};
+
+#[derive(Debug)]
+struct Disk {
+    mount: CString,
+    format: String,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let mount = string(section_name, configuration, "mount", "/")?;
+
+    Ok(Box::new(Disk {
+        mount: CString::new(mount).map_err(|_| format!("Invalid mount found for {}", section_name))?,
+        format: string(section_name, configuration, "format", "{percent}%")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+    }))
+}
+
+/// Returns the used, available, and total bytes of the filesystem mounted at `mount`.
+fn usage(mount: &CString) -> Option<(u64, u64, u64)> {
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(mount.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let block = stat.f_frsize as u64;
+    let total = stat.f_blocks as u64 * block;
+    let used = (stat.f_blocks as u64).saturating_sub(stat.f_bfree as u64) * block;
+    Some((used, stat.f_bavail as u64 * block, total))
+}
+
+impl Widget for Disk {
+    fn poll(&mut self) -> Option<Outcome> {
+        let (used, free, total) = match usage(&self.mount) {
+            Some(usage) => usage,
+            None => return Some(Outcome::Failure(None)),
+        };
+        // Rounded up, like df, so a nearly full disk never shows as less full than it is
+        let usable = used + free;
+        let percent = (used * 100).div_ceil(usable.max(1));
+
+        Some(Outcome::Success(fill(&self.format, &[
+            ("used", human(used)),
+            ("free", human(free)),
+            ("total", human(total)),
+            ("percent", percent.to_string()),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+
+    use super::usage;
+
+    #[test]
+    fn usage_adds_up_for_a_real_filesystem() {
+        let (used, free, total) = usage(&CString::new("/").unwrap()).unwrap();
+        assert!(total > 0);
+        assert!(used + free <= total);
+        assert_eq!(usage(&CString::new("/nonexistent/admiral").unwrap()), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! A widget showing how fast a fan is spinning, read from `/sys/class/hwmon`.
+//!
+//! A fan can be picked by its chip and label, as with temperature sensors.
+//! Without a label, the fastest of the chip's fans (or of every fan) is
+//! shown, which is what matters on machines whose fans take turns.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml;
+
+use Outcome;
+use super::temperature::{read, HWMON};
+use super::{fill, seconds, string, Widget};
+
+#[derive(Debug)]
+struct Fan {
+    chip: Option<String>,
+    label: Option<String>,
+    format: String,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let chip = match configuration.get("chip") {
+        Some(_) => Some(string(section_name, configuration, "chip", "")?),
+        None => None,
+    };
+    let label = match configuration.get("label") {
+        Some(_) => Some(string(section_name, configuration, "label", "")?),
+        None => None,
+    };
+    Ok(Box::new(Fan {
+        chip,
+        label,
+        format: string(section_name, configuration, "format", "{rpm} RPM")?,
+        interval: seconds(section_name, configuration, "reload", 5f64)?,
+    }))
+}
+
+/// Finds the inputs of every fan under `hwmon` whose chip and label match, where given.
+fn fans(hwmon: &Path, chip: Option<&str>, label: Option<&str>) -> Vec<PathBuf> {
+    let chips = match fs::read_dir(hwmon) {
+        Ok(chips) => chips,
+        Err(_) => return Vec::new(),
+    };
+    let mut inputs = Vec::new();
+    for chip_path in chips.filter_map(Result::ok).map(|entry| entry.path()) {
+        if chip.is_some() && read(&chip_path.join("name")).as_deref() != chip {
+            continue;
+        }
+        let entries = match fs::read_dir(&chip_path) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for name in entries.filter_map(Result::ok).map(|entry| entry.file_name().to_string_lossy().into_owned()) {
+            if !(name.starts_with("fan") && name.ends_with("_input")) {
+                continue;
+            }
+            if label.is_none() || read(&chip_path.join(name.replace("_input", "_label"))).as_deref() == label {
+                inputs.push(chip_path.join(name));
+            }
+        }
+    }
+    inputs.sort();
+    inputs
+}
+
+impl Widget for Fan {
+    fn poll(&mut self) -> Option<Outcome> {
+        // Found again each time, since hwmon's numbering can change when drivers are reloaded
+        let rpm = fans(Path::new(HWMON), self.chip.as_deref(), self.label.as_deref()).iter()
+            .filter_map(|input| read(input)?.parse::<u64>().ok())
+            .max();
+        match rpm {
+            Some(rpm) => Some(Outcome::Success(fill(&self.format, &[("rpm", rpm.to_string())]))),
+            None => Some(Outcome::Failure(None)),
+        }
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::fans;
+
+    #[test]
+    fn fans_are_found_by_chip_and_label() {
+        let hwmon = env::temp_dir().join(format!("admiral-test-fans-{}", process::id()));
+        let chip = |number: u32, name: &str, fans: &[(u32, &str)]| {
+            let path = hwmon.join(format!("hwmon{}", number));
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("name"), format!("{}\n", name)).unwrap();
+            for &(fan, label) in fans {
+                fs::write(path.join(format!("fan{}_input", fan)), "2400\n").unwrap();
+                fs::write(path.join(format!("fan{}_label", fan)), format!("{}\n", label)).unwrap();
+            }
+            path
+        };
+        let thinkpad = chip(0, "thinkpad", &[(1, "CPU"), (2, "GPU")]);
+        let nct = chip(1, "nct6775", &[(1, "CPU")]);
+
+        assert_eq!(fans(&hwmon, None, None), [thinkpad.join("fan1_input"), thinkpad.join("fan2_input"), nct.join("fan1_input")]);
+        assert_eq!(fans(&hwmon, Some("thinkpad"), None), [thinkpad.join("fan1_input"), thinkpad.join("fan2_input")]);
+        assert_eq!(fans(&hwmon, None, Some("CPU")), [thinkpad.join("fan1_input"), nct.join("fan1_input")]);
+        assert!(fans(&hwmon, Some("nct6775"), Some("GPU")).is_empty());
+        assert!(fans(&hwmon.join("missing"), None, None).is_empty());
+        let _ = fs::remove_dir_all(&hwmon);
+    }
+}
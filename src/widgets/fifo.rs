@@ -0,0 +1,175 @@
+//! A widget showing each line written to a named pipe, so that other programs
+//! can push text into the bar with `echo recording > /path/to/fifo`.
+//!
+//! The pipe is created if it doesn't exist, and opened for writing as well as
+//! reading, so that it never reaches end-of-file as writers come and go.
+
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use {Event, Outcome};
+use super::{fill, string, Widget};
+
+struct Fifo {
+    path: PathBuf,
+    format: String,
+    initial: String,
+    reader: Option<Reader>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let path = string(section_name, configuration, "path", "")?;
+    if path.is_empty() {
+        return Err(format!("Failed to find path for {}", section_name));
+    }
+
+    Ok(Box::new(Fifo {
+        path: PathBuf::from(path),
+        format: string(section_name, configuration, "format", "{line}")?,
+        initial: string(section_name, configuration, "initial", "")?,
+        reader: None,
+    }))
+}
+
+/// Opens the pipe at `path`, creating it first if there's nothing there.
+fn open(path: &Path) -> Option<File> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => {},
+        Ok(_) => return None,
+        Err(_) => {
+            let name = CString::new(path.as_os_str().as_bytes()).ok()?;
+            if unsafe { libc::mkfifo(name.as_ptr(), 0o600) } < 0 {
+                return None;
+            }
+        },
+    }
+    OpenOptions::new().read(true).write(true).open(path).ok()
+}
+
+/// A thread reading lines from the pipe, which keeps the latest and wakes the
+/// widget with each. When this is dropped, a newline is written to the pipe
+/// so the thread sees it's been stopped, and it's waited for, so that the
+/// newline can't reach whatever opens the pipe next.
+struct Reader {
+    stopped: Arc<AtomicBool>,
+    pipe: File,
+    line: Arc<Mutex<Option<String>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Reader {
+    fn start(path: &Path, wake: Sender<Event>) -> Option<Reader> {
+        let pipe = open(path)?;
+        let stopped = Arc::new(AtomicBool::new(false));
+        let line = Arc::new(Mutex::new(None));
+        let writer = pipe.try_clone().ok()?;
+        let thread_line = line.clone();
+        let thread_stopped = stopped.clone();
+
+        let thread = thread::spawn(move || {
+            for read in BufReader::new(pipe).lines() {
+                if thread_stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                match read {
+                    Ok(read) => *thread_line.lock().unwrap() = Some(read),
+                    Err(_) => break,
+                }
+                if wake.send(Event::Wake).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(Reader { stopped, pipe: writer, line, thread: Some(thread) })
+    }
+}
+
+impl Drop for Reader {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if self.pipe.write_all(b"\n").is_ok() {
+            if let Some(thread) = self.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+impl Widget for Fifo {
+    /// Polled after every line written to the pipe.
+    fn poll(&mut self) -> Option<Outcome> {
+        let reader = match self.reader {
+            Some(ref reader) => reader,
+            None => return Some(Outcome::Failure(None)),
+        };
+        Some(Outcome::Success(match *reader.line.lock().unwrap() {
+            Some(ref line) => fill(&self.format, &[("line", line.clone())]),
+            None => self.initial.clone(),
+        }))
+    }
+
+    /// Lines arrive as events, so there's nothing to check for in between.
+    fn wait(&self) -> Duration {
+        Duration::from_secs(3600)
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.reader = Reader::start(&self.path, wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs::{self, OpenOptions};
+    use std::io::Write;
+    use std::process;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use toml;
+
+    use Outcome;
+
+    #[test]
+    fn lines_written_to_the_pipe_are_shown() {
+        let directory = env::temp_dir().join(format!("admiral-test-fifo-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("pipe");
+
+        let src = format!("path = \"{}\"\nformat = \"<{{line}}>\"\ninitial = \"nothing yet\"", path.display());
+        let mut fifo = super::new("fifo", &toml::Parser::new(&src).parse().unwrap()).unwrap();
+        assert_eq!(fifo.poll(), Some(Outcome::Failure(None)));
+        let (wake, woken) = channel();
+        fifo.subscribe(wake);
+        assert_eq!(fifo.poll(), Some(Outcome::Success(String::from("nothing yet"))));
+
+        let mut writer = OpenOptions::new().write(true).open(&path).unwrap();
+        writer.write_all(b"recording\n").unwrap();
+        woken.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(fifo.poll(), Some(Outcome::Success(String::from("<recording>"))));
+
+        drop(fifo);
+        let _ = fs::remove_dir_all(&directory);
+    }
+
+    #[test]
+    fn files_that_are_not_pipes_are_left_alone() {
+        let path = env::temp_dir().join(format!("admiral-test-fifo-file-{}", process::id()));
+        fs::write(&path, "").unwrap();
+        assert!(super::open(&path).is_none());
+        let _ = fs::remove_file(&path);
+    }
+}
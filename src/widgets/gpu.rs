@@ -0,0 +1,178 @@
+//! A widget showing a GPU's load, video memory, and temperature.
+//!
+//! AMD GPUs are read from the amdgpu driver's files under
+//! `/sys/class/drm/<card>/device`. NVIDIA GPUs are read through NVML, which
+//! is only built in with the `nvml` feature, since it links against a library
+//! that only comes with NVIDIA's driver.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(feature = "nvml")]
+use nvml_wrapper::Nvml;
+#[cfg(feature = "nvml")]
+use nvml_wrapper::enum_wrappers::device::TemperatureSensor;
+use toml;
+
+use Outcome;
+use super::{fill, human, seconds, string, Widget};
+
+const DRM: &str = "/sys/class/drm";
+
+enum Source {
+    /// A card's `device` directory.
+    Amdgpu(PathBuf),
+    /// NVML, and the index of the device to read.
+    #[cfg(feature = "nvml")]
+    Nvml(Box<Nvml>, u32),
+}
+
+/// A GPU's load as a percentage, its video memory in bytes, and its temperature in degrees Celsius.
+struct Reading {
+    load: u32,
+    vram_used: u64,
+    vram_total: u64,
+    temperature: Option<f64>,
+}
+
+struct Gpu {
+    source: Source,
+    format: String,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let source = match string(section_name, configuration, "backend", "amdgpu")?.as_str() {
+        "amdgpu" => {
+            let device = match configuration.get("card") {
+                Some(_) => Path::new(DRM).join(string(section_name, configuration, "card", "")?).join("device"),
+                None => find_amdgpu().ok_or_else(|| format!("Failed to find an amdgpu card for {}", section_name))?,
+            };
+            Source::Amdgpu(device)
+        },
+        "nvml" => nvml(section_name, configuration)?,
+        _ => return Err(format!("Invalid backend found for {}: expected \"amdgpu\" or \"nvml\"", section_name)),
+    };
+
+    Ok(Box::new(Gpu {
+        source,
+        format: string(section_name, configuration, "format", "{load}%")?,
+        interval: seconds(section_name, configuration, "reload", 5f64)?,
+    }))
+}
+
+#[cfg(feature = "nvml")]
+fn nvml(section_name: &str, configuration: &toml::Table) -> Result<Source, String> {
+    let index = match configuration.get("index") {
+        Some(&toml::Value::Integer(index)) if index >= 0 => index as u32,
+        Some(_) => return Err(format!("Invalid index found for {}", section_name)),
+        None => 0,
+    };
+    let nvml = Nvml::init().map_err(|e| format!("Failed to load NVML for {}: {}", section_name, e))?;
+    nvml.device_by_index(index).map_err(|e| format!("Failed to find GPU {} for {}: {}", index, section_name, e))?;
+    Ok(Source::Nvml(Box::new(nvml), index))
+}
+
+#[cfg(not(feature = "nvml"))]
+fn nvml(section_name: &str, _configuration: &toml::Table) -> Result<Source, String> {
+    Err(format!("Invalid backend found for {}: admiral was built without NVML support; rebuild it with --features nvml", section_name))
+}
+
+fn read_number(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Finds the first card driven by amdgpu, which is the one that reports how busy it is.
+fn find_amdgpu() -> Option<PathBuf> {
+    let mut cards = fs::read_dir(DRM).ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path().join("device"))
+        .filter(|device| device.join("gpu_busy_percent").exists())
+        .collect::<Vec<_>>();
+    cards.sort();
+    cards.into_iter().next()
+}
+
+/// Reads the temperature from the card's hwmon directory, which hwmon reports in millidegrees.
+fn amdgpu_temperature(device: &Path) -> Option<f64> {
+    fs::read_dir(device.join("hwmon")).ok()?
+        .filter_map(Result::ok)
+        .find_map(|entry| read_number(&entry.path().join("temp1_input")))
+        .map(|millidegrees| millidegrees as f64 / 1000f64)
+}
+
+impl Source {
+    fn read(&self) -> Option<Reading> {
+        match *self {
+            Source::Amdgpu(ref device) => Some(Reading {
+                load: read_number(&device.join("gpu_busy_percent"))? as u32,
+                vram_used: read_number(&device.join("mem_info_vram_used"))?,
+                vram_total: read_number(&device.join("mem_info_vram_total"))?,
+                temperature: amdgpu_temperature(device),
+            }),
+            #[cfg(feature = "nvml")]
+            Source::Nvml(ref nvml, index) => {
+                let device = nvml.device_by_index(index).ok()?;
+                let memory = device.memory_info().ok()?;
+                Some(Reading {
+                    load: device.utilization_rates().ok()?.gpu,
+                    vram_used: memory.used,
+                    vram_total: memory.total,
+                    temperature: device.temperature(TemperatureSensor::Gpu).ok().map(f64::from),
+                })
+            },
+        }
+    }
+}
+
+impl Widget for Gpu {
+    fn poll(&mut self) -> Option<Outcome> {
+        let reading = match self.source.read() {
+            Some(reading) => reading,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let vram_percent = (reading.vram_used * 100 + reading.vram_total / 2).checked_div(reading.vram_total).unwrap_or(0);
+
+        Some(Outcome::Success(fill(&self.format, &[
+            ("load", reading.load.to_string()),
+            ("vram_used", human(reading.vram_used)),
+            ("vram_total", human(reading.vram_total)),
+            ("vram_percent", vram_percent.to_string()),
+            ("temp", reading.temperature.map_or_else(String::new, |degrees| format!("{:.0}", degrees))),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use toml;
+
+    use Outcome;
+
+    #[test]
+    fn amdgpu_cards_are_read_from_sysfs() {
+        let card = env::temp_dir().join(format!("admiral-test-card-{}", process::id()));
+        let device = card.join("device");
+        fs::create_dir_all(device.join("hwmon/hwmon3")).unwrap();
+        fs::write(device.join("gpu_busy_percent"), "37\n").unwrap();
+        fs::write(device.join("mem_info_vram_used"), "1073741824\n").unwrap();
+        fs::write(device.join("mem_info_vram_total"), "4294967296\n").unwrap();
+        fs::write(device.join("hwmon/hwmon3/temp1_input"), "54500\n").unwrap();
+
+        let source = format!("card = {:?}\nformat = \"{{load}}% {{vram_used}}/{{vram_total}} ({{vram_percent}}%) {{temp}}C\"", card.to_str().unwrap());
+        let mut gpu = super::new("gpu", &toml::Parser::new(&source).parse().unwrap()).unwrap();
+        assert_eq!(gpu.poll(), Some(Outcome::Success(String::from("37% 1.0G/4.0G (25%) 54C"))));
+        fs::remove_file(device.join("gpu_busy_percent")).unwrap();
+        assert_eq!(gpu.poll(), Some(Outcome::Failure(None)));
+        let _ = fs::remove_dir_all(&card);
+    }
+}
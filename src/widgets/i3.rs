@@ -0,0 +1,166 @@
+//! A client for the IPC shared by i3 and sway, for the widgets that follow the
+//! window manager.
+//!
+//! Each message is the magic string `i3-ipc`, the payload's length and the
+//! message's type as native-endian 32-bit integers, and a JSON payload. Events
+//! are sent on connections that have subscribed to them, with the top bit of
+//! their type set.
+
+use std::env;
+use std::io::{Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+
+use Event;
+
+const MAGIC: &[u8] = b"i3-ipc";
+
+pub const GET_WORKSPACES: u32 = 1;
+const SUBSCRIBE: u32 = 2;
+pub const GET_TREE: u32 = 4;
+
+/// Whether i3 or sway seems to be running, from their environment variables.
+pub fn is_running() -> bool {
+    env::var_os("SWAYSOCK").is_some() || env::var_os("I3SOCK").is_some()
+}
+
+/// Finds the window manager's socket, from sway's or i3's environment variable, or by asking i3.
+fn socket_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("SWAYSOCK").or_else(|| env::var_os("I3SOCK")) {
+        return Some(PathBuf::from(path));
+    }
+    let output = Command::new("i3").arg("--get-socketpath").output().ok()?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    if !output.status.success() || path.is_empty() {
+        return None;
+    }
+    Some(PathBuf::from(path))
+}
+
+pub struct Connection(UnixStream);
+
+impl Connection {
+    pub fn open(timeout: Option<Duration>) -> Option<Connection> {
+        let stream = UnixStream::connect(socket_path()?).ok()?;
+        stream.set_read_timeout(timeout).ok()?;
+        Some(Connection(stream))
+    }
+
+    fn send(&mut self, kind: u32, payload: &str) -> Option<()> {
+        let mut message = MAGIC.to_vec();
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        self.0.write_all(&message).ok()
+    }
+
+    /// Reads the next message, returning its type and payload.
+    fn receive(&mut self) -> Option<(u32, Json)> {
+        let mut header = [0u8; 14];
+        self.0.read_exact(&mut header).ok()?;
+        if &header[..6] != MAGIC {
+            return None;
+        }
+        let length = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]);
+        let kind = u32::from_ne_bytes([header[10], header[11], header[12], header[13]]);
+        let mut payload = vec![0u8; length as usize];
+        self.0.read_exact(&mut payload).ok()?;
+        Some((kind, Json::from_str(&String::from_utf8_lossy(&payload)).ok()?))
+    }
+
+    /// Sends a request and returns its reply, skipping any events sent in between.
+    pub fn request(&mut self, kind: u32, payload: &str) -> Option<Json> {
+        self.send(kind, payload)?;
+        loop {
+            let (reply_kind, reply) = self.receive()?;
+            if reply_kind == kind {
+                return Some(reply);
+            }
+        }
+    }
+}
+
+/// A connection subscribed to some of the window manager's events, whose
+/// thread wakes the widget whenever one arrives. Shutting the connection down
+/// when this is dropped lets the thread finish.
+pub struct Events(UnixStream);
+
+impl Events {
+    pub fn start(events: &[&str], wake: Sender<Event>) -> Option<Events> {
+        let mut connection = Connection::open(None)?;
+        let events = events.iter().map(|event| format!("\"{}\"", event)).collect::<Vec<_>>().join(",");
+        let reply = connection.request(SUBSCRIBE, &format!("[{}]", events))?;
+        if reply.find("success").and_then(Json::as_boolean) != Some(true) {
+            return None;
+        }
+
+        let stream = connection.0.try_clone().ok()?;
+        thread::spawn(move || {
+            while connection.receive().is_some() {
+                if wake.send(Event::Wake).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(Events(stream))
+    }
+}
+
+impl Drop for Events {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown(Shutdown::Both);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    use rustc_serialize::json::Json;
+
+    use super::{Connection, GET_WORKSPACES, MAGIC};
+
+    fn message(kind: u32, payload: &str) -> Vec<u8> {
+        let mut message = MAGIC.to_vec();
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(payload.as_bytes());
+        message
+    }
+
+    #[test]
+    fn replies_are_read_past_events() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server.write_all(&message(0x80000000, "{\"change\": \"focus\"}")).unwrap();
+        server.write_all(&message(GET_WORKSPACES, "[{\"name\": \"1\", \"focused\": true}]")).unwrap();
+
+        let mut connection = Connection(client);
+        let reply = connection.request(GET_WORKSPACES, "").unwrap();
+        assert_eq!(reply.as_array().and_then(|workspaces| workspaces[0].find("name")).and_then(Json::as_string), Some("1"));
+
+        let mut request = [0u8; 14];
+        server.read_exact(&mut request).unwrap();
+        assert_eq!(&request[..], &message(GET_WORKSPACES, "")[..]);
+    }
+
+    #[test]
+    fn other_protocols_are_not_read() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server.write_all(b"not-ipc\0\0\0\0\0\0\0{}").unwrap();
+        assert!(Connection(client).receive().is_none());
+
+        let (client, mut server) = UnixStream::pair().unwrap();
+        // Cut off before the payload ends
+        server.write_all(&message(GET_WORKSPACES, "[{\"name\": \"1\"}]")[..20]).unwrap();
+        drop(server);
+        assert!(Connection(client).receive().is_none());
+    }
+}
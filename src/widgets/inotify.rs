@@ -0,0 +1,91 @@
+//! Watching files and directories with inotify, for widgets that show
+//! something read from them.
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+use std::sync::mpsc::Sender;
+use std::thread;
+
+use libc;
+
+use Event;
+
+/// inotify watches on some paths, whose thread wakes the widget whenever one
+/// of the events in the mask happens to them. Events about a directory's
+/// entries are only counted if `filter` accepts the entry's name. Removing the
+/// watches when this is dropped makes the kernel send `IN_IGNORED` for each,
+/// which lets the thread finish and close the inotify instance.
+pub struct Watch {
+    inotify: libc::c_int,
+    watches: Vec<libc::c_int>,
+}
+
+impl Watch {
+    /// Watches every path given, or none of them if any can't be watched.
+    pub fn start<P: AsRef<Path>>(paths: &[P], mask: u32, filter: fn(&str) -> bool, wake: Sender<Event>) -> Option<Watch> {
+        let inotify = unsafe { libc::inotify_init1(libc::IN_CLOEXEC) };
+        if inotify < 0 {
+            return None;
+        }
+        let mut watches = Vec::with_capacity(paths.len());
+        for path in paths {
+            let watch = CString::new(path.as_ref().as_os_str().as_bytes()).ok()
+                .map(|path| unsafe { libc::inotify_add_watch(inotify, path.as_ptr(), mask) });
+            match watch {
+                Some(watch) if watch >= 0 => watches.push(watch),
+                _ => {
+                    unsafe { libc::close(inotify) };
+                    return None;
+                },
+            }
+        }
+
+        let mut watching = watches.len();
+        thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            while watching > 0 {
+                let length = unsafe { libc::read(inotify, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len()) };
+                if length < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                if length <= 0 {
+                    break;
+                }
+                let mut offset = 0;
+                let mut wanted = false;
+                while offset + mem::size_of::<libc::inotify_event>() <= length as usize {
+                    // A byte buffer needn't be aligned for the events read into it
+                    let event = unsafe { ptr::read_unaligned(buffer.as_ptr().add(offset) as *const libc::inotify_event) };
+                    let start = offset + mem::size_of::<libc::inotify_event>();
+                    offset = start + event.len as usize;
+                    if event.mask & libc::IN_IGNORED != 0 {
+                        watching -= 1;
+                        continue;
+                    }
+                    // The name is padded with NULs, and is empty for events about the watched path itself
+                    let name = buffer[start..offset.min(length as usize)].split(|&byte| byte == 0).next().unwrap_or_default();
+                    wanted |= name.is_empty() || filter(&String::from_utf8_lossy(name));
+                }
+                // Keep reading even once the runner is gone, so the instance is
+                // only closed after the watches have been removed
+                if wanted {
+                    let _ = wake.send(Event::Wake);
+                }
+            }
+            unsafe { libc::close(inotify) };
+        });
+        Some(Watch { inotify, watches })
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        for &watch in &self.watches {
+            unsafe { libc::inotify_rm_watch(self.inotify, watch) };
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! A widget showing whether a network interface is up, and its addresses.
+//!
+//! The state is read from `/sys/class/net` and the addresses with
+//! `getifaddrs`, again whenever an rtnetlink socket is told of a link or an
+//! address changing, so the section follows `ip link` and `ip addr` without
+//! polling them.
+
+use std::ffi::CStr;
+use std::fs;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::ptr;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use {Event, Outcome};
+use super::rtnetlink::{Links, RTMGRP_IPV4_IFADDR, RTMGRP_IPV6_IFADDR, RTMGRP_LINK};
+use super::{fill, seconds, string, Widget};
+
+const NET: &str = "/sys/class/net";
+const IFF_UP: u32 = 1;
+
+struct Interface {
+    interface: String,
+    up: String,
+    down: String,
+    /// How often to read the interface anyway, in case a netlink message is missed.
+    interval: Duration,
+    links: Option<Links>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let interface = string(section_name, configuration, "interface", "")?;
+    if interface.is_empty() {
+        return Err(format!("No interface found for {}", section_name));
+    }
+
+    Ok(Box::new(Interface {
+        interface,
+        up: string(section_name, configuration, "up", "{interface} {ip}")?,
+        down: string(section_name, configuration, "down", "{interface} down")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        links: None,
+    }))
+}
+
+/// Whether the interface is up and has a carrier. Interfaces that don't report
+/// their operational state, such as loopback and most tun devices, only need to be up.
+fn is_up(interface: &str) -> bool {
+    let path = Path::new(NET).join(interface);
+    match fs::read_to_string(path.join("operstate")).as_ref().map(|state| state.trim()) {
+        Ok("up") => true,
+        Ok("unknown") => fs::read_to_string(path.join("flags")).ok()
+            .and_then(|flags| u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+            .is_some_and(|flags| flags & IFF_UP != 0),
+        _ => false,
+    }
+}
+
+/// The interface's first IPv4 address, and its first IPv6 address that isn't
+/// link-local, as every interface with IPv6 has one of those.
+fn addresses(interface: &str) -> (Option<Ipv4Addr>, Option<Ipv6Addr>) {
+    let (mut ipv4, mut ipv6) = (None, None);
+    let mut addresses = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addresses) } < 0 {
+        return (ipv4, ipv6);
+    }
+    let mut next = addresses;
+    while let Some(entry) = unsafe { next.as_ref() } {
+        next = entry.ifa_next;
+        if entry.ifa_addr.is_null() || unsafe { CStr::from_ptr(entry.ifa_name) }.to_bytes() != interface.as_bytes() {
+            continue;
+        }
+        match unsafe { (*entry.ifa_addr).sa_family } as libc::c_int {
+            libc::AF_INET if ipv4.is_none() => {
+                let address = unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in) };
+                ipv4 = Some(Ipv4Addr::from(u32::from_be(address.sin_addr.s_addr)));
+            },
+            libc::AF_INET6 if ipv6.is_none() => {
+                let address = Ipv6Addr::from(unsafe { &*(entry.ifa_addr as *const libc::sockaddr_in6) }.sin6_addr.s6_addr);
+                if address.segments()[0] & 0xffc0 != 0xfe80 {
+                    ipv6 = Some(address);
+                }
+            },
+            _ => {},
+        }
+    }
+    unsafe { libc::freeifaddrs(addresses) };
+    (ipv4, ipv6)
+}
+
+impl Widget for Interface {
+    /// Polled after links or addresses change, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        if !is_up(&self.interface) {
+            return Some(Outcome::Success(fill(&self.down, &[("interface", self.interface.clone())])));
+        }
+        let (ipv4, ipv6) = addresses(&self.interface);
+        let ipv4 = ipv4.map(|address| address.to_string()).unwrap_or_default();
+        let ipv6 = ipv6.map(|address| address.to_string()).unwrap_or_default();
+        let ip = if ipv4.is_empty() { ipv6.clone() } else { ipv4.clone() };
+        Some(Outcome::Success(fill(&self.up, &[
+            ("interface", self.interface.clone()),
+            ("ip", ip),
+            ("ipv4", ipv4),
+            ("ipv6", ipv6),
+        ])))
+    }
+
+    /// Falls back to polling every second when changes can't be watched for.
+    fn wait(&self) -> Duration {
+        match self.links {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.links = Links::start(RTMGRP_LINK | RTMGRP_IPV4_IFADDR | RTMGRP_IPV6_IFADDR, wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::sync::mpsc::channel;
+
+    use toml;
+
+    use Outcome;
+    use super::{addresses, is_up, Links, RTMGRP_IPV4_IFADDR, RTMGRP_LINK};
+
+    #[test]
+    fn loopback_is_up_with_its_addresses() {
+        assert!(is_up("lo"));
+        assert!(!is_up("admiral-missing0"));
+        let (ipv4, ipv6) = addresses("lo");
+        assert_eq!(ipv4, Some(Ipv4Addr::LOCALHOST));
+        assert!(ipv6.is_none() || ipv6 == Some(Ipv6Addr::LOCALHOST));
+        assert_eq!(addresses("admiral-missing0"), (None, None));
+    }
+
+    #[test]
+    fn missing_interfaces_show_as_down() {
+        let config = toml::Parser::new("interface = \"admiral-missing0\"").parse().unwrap();
+        let mut interface = super::new("net", &config).unwrap();
+        assert_eq!(interface.poll(), Some(Outcome::Success(String::from("admiral-missing0 down"))));
+        assert!(super::new("net", &toml::Table::new()).is_err());
+    }
+
+    #[test]
+    fn changes_are_watched_once_subscribed() {
+        let (wake, _woken) = channel();
+        assert!(Links::start(RTMGRP_LINK | RTMGRP_IPV4_IFADDR, wake).is_some());
+    }
+}
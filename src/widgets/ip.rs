@@ -0,0 +1,124 @@
+//! A widget showing the machine's public IP address, as reported by an HTTP
+//! endpoint such as `https://api.ipify.org`.
+//!
+//! The address is cached, and only fetched again every `reload` seconds or
+//! when the default route changes (after joining another network, or a VPN
+//! coming up), so the endpoint isn't asked every second.
+
+use std::fs;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use toml;
+
+use Outcome;
+use super::{fill, run, seconds, string, Widget};
+
+/// How long to wait before trying again after a fetch fails.
+const RETRY: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+struct Ip {
+    url: String,
+    format: String,
+    interval: Duration,
+    /// The default routes when the address was last fetched.
+    routes: Option<String>,
+    address: Option<String>,
+    /// When to fetch the address again, even if the routes stay the same.
+    next_fetch: Option<Instant>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Ip {
+        url: string(section_name, configuration, "url", "https://api.ipify.org")?,
+        format: string(section_name, configuration, "format", "{ip}")?,
+        interval: seconds(section_name, configuration, "reload", 3600f64)?,
+        routes: None,
+        address: None,
+        next_fetch: None,
+    }))
+}
+
+/// The IPv4 and IPv6 default routes, as lines of `/proc/net/route` and `/proc/net/ipv6_route`.
+fn default_routes() -> String {
+    let ipv4 = fs::read_to_string("/proc/net/route").unwrap_or_default();
+    let ipv6 = fs::read_to_string("/proc/net/ipv6_route").unwrap_or_default();
+    let ipv4 = ipv4.lines().filter(|line| line.split_whitespace().nth(1) == Some("00000000"));
+    let ipv6 = ipv6.lines().filter(|line| line.starts_with("00000000000000000000000000000000 00 "));
+    ipv4.chain(ipv6).collect::<Vec<_>>().join("\n")
+}
+
+impl Widget for Ip {
+    fn poll(&mut self) -> Option<Outcome> {
+        let routes = default_routes();
+        let moved = self.routes.as_ref() != Some(&routes);
+        if !moved && self.next_fetch.is_some_and(|next_fetch| Instant::now() < next_fetch) {
+            return None;
+        }
+        self.routes = Some(routes);
+
+        let address = run("curl", &["-sf", "--max-time", "10", &self.url])
+            .map(|body| body.trim().to_owned())
+            .filter(|body| body.parse::<IpAddr>().is_ok());
+        match address {
+            Some(address) => {
+                self.next_fetch = Some(Instant::now() + self.interval);
+                self.address = Some(address);
+            },
+            None => {
+                self.next_fetch = Some(Instant::now() + RETRY.min(self.interval));
+                // The old address can't be trusted on a new network
+                if moved {
+                    self.address = None;
+                }
+            },
+        }
+
+        match self.address {
+            Some(ref address) => Some(Outcome::Success(fill(&self.format, &[("ip", address.clone())]))),
+            None => Some(Outcome::Failure(None)),
+        }
+    }
+
+    /// Checks the routes every few seconds.
+    fn wait(&self) -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn refresh(&mut self) {
+        self.next_fetch = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use toml;
+
+    use Outcome;
+
+    #[test]
+    fn the_address_is_kept_until_a_fetch_gives_a_new_one() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for (body, stream) in ["203.0.113.7\n", "<html>captive portal</html>"].iter().zip(listener.incoming()) {
+                let mut stream = stream.unwrap();
+                let _ = stream.read(&mut [0u8; 1024]);
+                let _ = write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+            }
+        });
+
+        let config = toml::Parser::new(&format!("url = {:?}\nformat = \"ip {{ip}}\"", url)).parse().unwrap();
+        let mut ip = super::new("ip", &config).unwrap();
+        assert_eq!(ip.poll(), Some(Outcome::Success(String::from("ip 203.0.113.7"))));
+        // Not fetched again until reload passes, or the routes change
+        assert_eq!(ip.poll(), None);
+        ip.refresh();
+        assert_eq!(ip.poll(), Some(Outcome::Success(String::from("ip 203.0.113.7"))));
+    }
+}
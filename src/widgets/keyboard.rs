@@ -0,0 +1,129 @@
+//! A widget showing the active keyboard layout.
+//!
+//! Under sway, layout changes are watched for over its IPC with
+//! `swaymsg -t subscribe`, and the layout is read from `swaymsg -t get_inputs`.
+//! Under X, `xkb-switch` does both, since XKB's state can't be had from the
+//! output of `setxkbmap` alone.
+
+use std::env;
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+use toml;
+
+use {Event, Outcome};
+use super::{fill, run, seconds, string, Subscription, Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Sway,
+    X11,
+}
+
+struct Keyboard {
+    backend: Backend,
+    /// The sway input identifier of the keyboard to show, rather than the first.
+    input: Option<String>,
+    format: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    subscription: Option<Subscription>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let default_backend = if env::var_os("SWAYSOCK").is_some() { "sway" } else { "x11" };
+    let backend = match string(section_name, configuration, "backend", default_backend)?.as_str() {
+        "sway" => Backend::Sway,
+        "x11" => Backend::X11,
+        _ => return Err(format!("Invalid backend found for {}: expected \"sway\" or \"x11\"", section_name)),
+    };
+    let input = match configuration.get("input") {
+        Some(_) => Some(string(section_name, configuration, "input", "")?),
+        None => None,
+    };
+
+    Ok(Box::new(Keyboard {
+        backend,
+        input,
+        format: string(section_name, configuration, "format", "{layout}")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        subscription: None,
+    }))
+}
+
+/// Whether a line from `swaymsg -t subscribe` is about a keyboard's layout changing.
+fn is_layout_event(line: &str) -> bool {
+    line.contains("\"xkb_layout\"") || line.contains("\"xkb_keymap\"")
+}
+
+/// The active layout of the given keyboard, or of the first keyboard that has one.
+fn sway_layout(input: Option<&str>) -> Option<String> {
+    active_layout(&Json::from_str(&run("swaymsg", &["-r", "-t", "get_inputs"])?).ok()?, input)
+}
+
+/// Finds the active layout in sway's list of inputs.
+fn active_layout(inputs: &Json, input: Option<&str>) -> Option<String> {
+    inputs.as_array()?
+        .iter()
+        .filter(|device| input.is_none_or(|input| device.find("identifier").and_then(Json::as_string) == Some(input)))
+        .filter_map(|device| device.find("xkb_active_layout_name").and_then(Json::as_string))
+        .next()
+        .map(str::to_owned)
+}
+
+impl Widget for Keyboard {
+    /// Polled after every layout change, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let layout = match self.backend {
+            Backend::Sway => sway_layout(self.input.as_deref()),
+            Backend::X11 => run("xkb-switch", &["-p"]).map(|layout| layout.trim().to_owned()),
+        };
+        match layout {
+            Some(layout) => Some(Outcome::Success(fill(&self.format, &[("layout", layout)]))),
+            None => Some(Outcome::Failure(None)),
+        }
+    }
+
+    /// Falls back to polling every second when layout changes can't be watched for.
+    fn wait(&self) -> Duration {
+        match self.subscription {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.subscription = match self.backend {
+            Backend::Sway => Subscription::start(Command::new("swaymsg").args(["-r", "-m", "-t", "subscribe", "[\"input\"]"]), wake, is_layout_event),
+            Backend::X11 => Subscription::start(Command::new("xkb-switch").arg("-W"), wake, |_| true),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use super::{active_layout, is_layout_event};
+
+    #[test]
+    fn the_layout_is_found_among_the_inputs() {
+        let inputs = Json::from_str(r#"[
+            {"identifier": "1:1:Power_Button", "type": "keyboard"},
+            {"identifier": "1:1:AT_Translated_Set_2_keyboard", "type": "keyboard", "xkb_active_layout_name": "English (US)"},
+            {"identifier": "1133:49971:Logitech_Keyboard", "type": "keyboard", "xkb_active_layout_name": "German"}
+        ]"#).unwrap();
+        assert_eq!(active_layout(&inputs, None), Some(String::from("English (US)")));
+        assert_eq!(active_layout(&inputs, Some("1133:49971:Logitech_Keyboard")), Some(String::from("German")));
+        assert_eq!(active_layout(&inputs, Some("1:1:Power_Button")), None);
+    }
+
+    #[test]
+    fn only_layout_changes_wake_the_widget() {
+        assert!(is_layout_event(r#"{"change": "xkb_layout", "input": {"identifier": "1:1:keyboard"}}"#));
+        assert!(is_layout_event(r#"{"change": "xkb_keymap", "input": {}}"#));
+        assert!(!is_layout_event(r#"{"change": "added", "input": {}}"#));
+    }
+}
@@ -0,0 +1,53 @@
+//! A widget showing the system's load averages, read from `/proc/loadavg`.
+
+use std::fs;
+use std::time::Duration;
+
+use toml;
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+#[derive(Debug)]
+struct Load {
+    format: String,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Load {
+        format: string(section_name, configuration, "format", "{1} {5} {15}")?,
+        interval: seconds(section_name, configuration, "reload", 5f64)?,
+    }))
+}
+
+impl Widget for Load {
+    fn poll(&mut self) -> Option<Outcome> {
+        let loadavg = fs::read_to_string("/proc/loadavg").unwrap_or_default();
+        match loads(&loadavg) {
+            Some(loads) => Some(Outcome::Success(fill(&self.format, &loads))),
+            None => Some(Outcome::Failure(None)),
+        }
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Reads the one, five, and fifteen minute load averages from the contents of `/proc/loadavg`.
+fn loads(loadavg: &str) -> Option<[(&'static str, String); 3]> {
+    let mut loads = loadavg.split_whitespace().map(str::to_owned);
+    Some([("1", loads.next()?), ("5", loads.next()?), ("15", loads.next()?)])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::loads;
+
+    #[test]
+    fn loadavg_gives_three_periods() {
+        assert_eq!(loads("0.52 0.58 0.59 2/1183 41234\n").unwrap(), [("1", String::from("0.52")), ("5", String::from("0.58")), ("15", String::from("0.59"))]);
+        assert_eq!(loads("0.52 0.58"), None);
+    }
+}
@@ -0,0 +1,373 @@
+//! A widget showing how many unread messages there are in a maildir or an
+//! IMAP mailbox.
+//!
+//! A maildir's `new` and `cur` directories are watched with inotify, so mail
+//! shows up as soon as it's delivered or read. An IMAP mailbox is kept open in
+//! `IDLE` on a thread, which counts the unseen messages again whenever the
+//! server says the mailbox changed. IMAP is only built in with the `imap`
+//! feature, since it links against the system's TLS library.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use {Event, Outcome};
+use super::inotify::Watch;
+use super::{fill, seconds, string, Widget};
+
+#[cfg(feature = "imap")]
+use self::imap::Idle;
+
+enum Source {
+    Maildir(PathBuf, Option<Watch>),
+    #[cfg(feature = "imap")]
+    Imap(imap::Account, Option<Idle>),
+}
+
+struct Mail {
+    source: Source,
+    format: String,
+    none: String,
+    /// How often to check a maildir anyway, in case a change is missed.
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let source = match string(section_name, configuration, "backend", "maildir")?.as_str() {
+        "maildir" => {
+            let path = string(section_name, configuration, "path", "")?;
+            if path.is_empty() {
+                return Err(format!("Failed to find path for {}", section_name));
+            }
+            Source::Maildir(PathBuf::from(path), None)
+        },
+        "imap" => imap_source(section_name, configuration)?,
+        _ => return Err(format!("Invalid backend found for {}: expected \"maildir\" or \"imap\"", section_name)),
+    };
+
+    Ok(Box::new(Mail {
+        source,
+        format: string(section_name, configuration, "format", "{count} new")?,
+        none: string(section_name, configuration, "none", "")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+    }))
+}
+
+#[cfg(feature = "imap")]
+fn imap_source(section_name: &str, configuration: &toml::Table) -> Result<Source, String> {
+    Ok(Source::Imap(imap::Account::from_config(section_name, configuration)?, None))
+}
+
+#[cfg(not(feature = "imap"))]
+fn imap_source(section_name: &str, _configuration: &toml::Table) -> Result<Source, String> {
+    Err(format!("Invalid backend found for {}: admiral was built without IMAP support; rebuild it with --features imap", section_name))
+}
+
+/// Counts the messages in a maildir that haven't been seen: everything in
+/// `new`, and whatever in `cur` lacks the `S` flag.
+fn count_maildir(path: &Path) -> Option<usize> {
+    let names = |directory: &str| -> Option<Vec<String>> {
+        Some(fs::read_dir(path.join(directory)).ok()?
+            .filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| !name.starts_with('.'))
+            .collect())
+    };
+    let new = names("new")?.len();
+    let unseen = names("cur")?.iter()
+        .filter(|name| name.rsplit_once(":2,").is_none_or(|(_, flags)| !flags.contains('S')))
+        .count();
+    Some(new + unseen)
+}
+
+impl Widget for Mail {
+    /// Polled whenever the maildir or mailbox changes, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let count = match self.source {
+            Source::Maildir(ref path, _) => count_maildir(path),
+            #[cfg(feature = "imap")]
+            Source::Imap(_, ref idle) => idle.as_ref().and_then(Idle::count),
+        };
+        match count {
+            Some(0) => Some(Outcome::Success(self.none.clone())),
+            Some(count) => Some(Outcome::Success(fill(&self.format, &[("count", count.to_string())]))),
+            None => Some(Outcome::Failure(None)),
+        }
+    }
+
+    /// Falls back to polling a maildir every second when it couldn't be watched.
+    fn wait(&self) -> Duration {
+        match self.source {
+            Source::Maildir(_, None) => Duration::from_secs(1),
+            _ => self.interval,
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        match self.source {
+            Source::Maildir(ref path, ref mut watch) => {
+                let mask = libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO;
+                *watch = Watch::start(&[path.join("new"), path.join("cur")], mask, |_| true, wake);
+            },
+            #[cfg(feature = "imap")]
+            Source::Imap(ref account, ref mut idle) => *idle = Some(Idle::start(account.clone(), wake)),
+        }
+    }
+}
+
+#[cfg(feature = "imap")]
+mod imap {
+    use std::fs;
+    use std::io::{BufRead, BufReader, ErrorKind, Write};
+    use std::net::{Shutdown, TcpStream, ToSocketAddrs};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::Sender;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use native_tls::{TlsConnector, TlsStream};
+    use toml::{self, Value};
+
+    use Event;
+    use super::super::string;
+
+    /// How long to wait before connecting again after the connection fails.
+    const RECONNECT: Duration = Duration::from_secs(30);
+    /// Servers may drop a connection left in `IDLE` for 30 minutes, so it's renewed before then.
+    const RENEW: Duration = Duration::from_secs(29 * 60);
+
+    #[derive(Clone)]
+    pub struct Account {
+        host: String,
+        port: u16,
+        user: String,
+        password: String,
+        mailbox: String,
+    }
+
+    impl Account {
+        pub fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Account, String> {
+            let host = string(section_name, configuration, "host", "")?;
+            if host.is_empty() {
+                return Err(format!("Failed to find host for {}", section_name));
+            }
+            let port = match configuration.get("port") {
+                Some(&Value::Integer(port)) if port > 0 && port <= 65535 => port as u16,
+                Some(_) => return Err(format!("Invalid port found for {}: expected a port number", section_name)),
+                None => 993,
+            };
+            let password = match (configuration.get("password"), configuration.get("password_file")) {
+                (Some(_), _) => string(section_name, configuration, "password", "")?,
+                (None, Some(_)) => {
+                    let path = string(section_name, configuration, "password_file", "")?;
+                    let password = fs::read_to_string(&path)
+                        .map_err(|e| format!("Failed to read password_file for {}: {}", section_name, e))?;
+                    password.trim_end_matches('\n').to_owned()
+                },
+                (None, None) => return Err(format!("Failed to find password or password_file for {}", section_name)),
+            };
+
+            Ok(Account {
+                host,
+                port,
+                user: string(section_name, configuration, "user", "")?,
+                password,
+                mailbox: string(section_name, configuration, "mailbox", "INBOX")?,
+            })
+        }
+    }
+
+    /// Quotes a string for IMAP.
+    fn quote(string: &str) -> String {
+        format!("\"{}\"", string.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+
+    struct Connection {
+        reader: BufReader<TlsStream<TcpStream>>,
+        tag: u32,
+    }
+
+    impl Connection {
+        fn open(account: &Account, tcp: TcpStream) -> Option<Connection> {
+            let stream = TlsConnector::new().ok()?.connect(&account.host, tcp).ok()?;
+            let mut connection = Connection { reader: BufReader::new(stream), tag: 0 };
+            if !connection.line()?.starts_with("* OK") {
+                return None;
+            }
+            connection.command(&format!("LOGIN {} {}", quote(&account.user), quote(&account.password)))?;
+            connection.command(&format!("EXAMINE {}", quote(&account.mailbox)))?;
+            Some(connection)
+        }
+
+        fn line(&mut self) -> Option<String> {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            Some(line.trim_end().to_owned())
+        }
+
+        fn write(&mut self, line: &str) -> Option<()> {
+            self.reader.get_mut().write_all(format!("{}\r\n", line).as_bytes()).ok()
+        }
+
+        /// Sends a command and returns its untagged responses, if it succeeds.
+        fn command(&mut self, command: &str) -> Option<Vec<String>> {
+            self.tag += 1;
+            let tag = format!("a{}", self.tag);
+            self.write(&format!("{} {}", tag, command))?;
+            self.finish(&tag)
+        }
+
+        /// Reads the responses to a command until it completes.
+        fn finish(&mut self, tag: &str) -> Option<Vec<String>> {
+            let mut responses = Vec::new();
+            loop {
+                let line = self.line()?;
+                match line.strip_prefix(tag).and_then(|status| status.strip_prefix(' ')) {
+                    Some(status) if status.starts_with("OK") => return Some(responses),
+                    Some(_) => return None,
+                    None => responses.push(line),
+                }
+            }
+        }
+
+        fn unseen(&mut self) -> Option<usize> {
+            let responses = self.command("SEARCH UNSEEN")?;
+            Some(responses.iter()
+                .filter_map(|response| response.strip_prefix("* SEARCH"))
+                .map(|numbers| numbers.split_whitespace().count())
+                .sum())
+        }
+
+        /// Waits in `IDLE` until the mailbox changes, or it's time to renew.
+        fn idle(&mut self) -> Option<()> {
+            self.tag += 1;
+            let tag = format!("a{}", self.tag);
+            self.write(&format!("{} IDLE", tag))?;
+            if !self.line()?.starts_with('+') {
+                return None;
+            }
+            self.reader.get_ref().get_ref().set_read_timeout(Some(RENEW)).ok()?;
+            loop {
+                let mut line = String::new();
+                match self.reader.read_line(&mut line) {
+                    Ok(0) => return None,
+                    Ok(_) if line.contains("EXISTS") || line.contains("EXPUNGE") || line.contains("FETCH") => break,
+                    Ok(_) => continue,
+                    Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => break,
+                    Err(_) => return None,
+                }
+            }
+            self.reader.get_ref().get_ref().set_read_timeout(None).ok()?;
+            self.write("DONE")?;
+            self.finish(&tag).map(|_| ())
+        }
+    }
+
+    /// A thread keeping the mailbox in `IDLE`, which counts its unseen
+    /// messages and wakes the widget whenever it changes. Shutting the
+    /// connection down when this is dropped lets the thread finish.
+    pub struct Idle {
+        stopped: Arc<AtomicBool>,
+        stream: Arc<Mutex<Option<TcpStream>>>,
+        count: Arc<Mutex<Option<usize>>>,
+    }
+
+    impl Idle {
+        pub fn start(account: Account, wake: Sender<Event>) -> Idle {
+            let stopped = Arc::new(AtomicBool::new(false));
+            let stream = Arc::new(Mutex::new(None));
+            let count = Arc::new(Mutex::new(None));
+            let idle = Idle { stopped: stopped.clone(), stream: stream.clone(), count: count.clone() };
+
+            thread::spawn(move || {
+                while !stopped.load(Ordering::SeqCst) {
+                    let tcp = (account.host.as_str(), account.port).to_socket_addrs().ok()
+                        .and_then(|mut addresses| addresses.next())
+                        .and_then(|address| TcpStream::connect_timeout(&address, Duration::from_secs(10)).ok());
+                    if let Some(tcp) = tcp {
+                        *stream.lock().unwrap() = tcp.try_clone().ok();
+                        // Check again whether this was dropped while connecting
+                        if stopped.load(Ordering::SeqCst) {
+                            break;
+                        }
+                        if let Some(mut connection) = Connection::open(&account, tcp) {
+                            while let Some(unseen) = connection.unseen() {
+                                *count.lock().unwrap() = Some(unseen);
+                                let _ = wake.send(Event::Wake);
+                                if connection.idle().is_none() {
+                                    break;
+                                }
+                            }
+                        }
+                    }
+                    *count.lock().unwrap() = None;
+                    let _ = wake.send(Event::Wake);
+                    thread::sleep(RECONNECT);
+                }
+            });
+            idle
+        }
+
+        /// The number of unseen messages, or `None` while not connected.
+        pub fn count(&self) -> Option<usize> {
+            *self.count.lock().unwrap()
+        }
+    }
+
+    impl Drop for Idle {
+        fn drop(&mut self) {
+            self.stopped.store(true, Ordering::SeqCst);
+            if let Some(ref stream) = *self.stream.lock().unwrap() {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use toml;
+
+    use Outcome;
+    use super::count_maildir;
+
+    #[test]
+    fn unseen_mail_is_counted_and_watched() {
+        let maildir = env::temp_dir().join(format!("admiral-test-maildir-{}", process::id()));
+        for directory in &["new", "cur", "tmp"] {
+            fs::create_dir_all(maildir.join(directory)).unwrap();
+        }
+        let deliver = |name: &str| fs::write(maildir.join(name), "Subject: hi\n\n").unwrap();
+        deliver("new/1700000000.M1P1.host");
+        deliver("cur/1700000001.M2P1.host:2,S");
+        deliver("cur/1700000002.M3P1.host:2,FR");
+        deliver("cur/1700000003.M4P1.host");
+        deliver("cur/.hidden");
+        deliver("tmp/1700000004.M5P1.host");
+        assert_eq!(count_maildir(&maildir), Some(3));
+        assert_eq!(count_maildir(&maildir.join("missing")), None);
+
+        let config = toml::Parser::new(&format!("path = {:?}", maildir.to_str().unwrap())).parse().unwrap();
+        let mut mail = super::new("mail", &config).unwrap();
+        let (wake, woken) = channel();
+        mail.subscribe(wake);
+        assert_eq!(mail.poll(), Some(Outcome::Success(String::from("3 new"))));
+        deliver("new/1700000005.M6P1.host");
+        assert!(woken.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(mail.poll(), Some(Outcome::Success(String::from("4 new"))));
+        let _ = fs::remove_dir_all(&maildir);
+    }
+}
@@ -0,0 +1,211 @@
+//! A widget showing what an MPRIS media player (Spotify, a browser, mpv...) is
+//! playing.
+//!
+//! Players are found on the session bus by their `org.mpris.MediaPlayer2.*`
+//! names, and their status and metadata are read over a connection kept open
+//! between polls. Their `PropertiesChanged` signals, and players coming and
+//! going, wake the widget, which reads the player afresh each time.
+
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use toml;
+
+use {Event, Outcome};
+use super::dbus::{self, with_connection, Bus, Connection, Signals};
+use super::{fill, seconds, string, Widget};
+
+const PREFIX: &str = "org.mpris.MediaPlayer2.";
+const PATH: &str = "/org/mpris/MediaPlayer2";
+const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+
+/// Matches any player in `players`, as with `playerctl --player`.
+const ANY: &str = "%any";
+
+struct Media {
+    /// The players to follow, in order of preference.
+    players: Vec<String>,
+    playing: String,
+    paused: String,
+    stopped: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    /// The bus name of the player shown last, kept to while no other is playing.
+    shown: Option<String>,
+    connection: Option<Connection>,
+    signals: Option<Signals>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let players = match configuration.get("players") {
+        Some(players) => players.as_slice()
+            .and_then(|players| players.iter().map(|player| player.as_str().map(String::from)).collect::<Option<Vec<_>>>())
+            .ok_or_else(|| format!("Invalid players found for {}", section_name))?,
+        None => vec![String::from(ANY)],
+    };
+
+    Ok(Box::new(Media {
+        players,
+        playing: string(section_name, configuration, "playing", "{artist} - {title}")?,
+        paused: string(section_name, configuration, "paused", "{artist} - {title} (paused)")?,
+        stopped: string(section_name, configuration, "stopped", "")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        shown: None,
+        connection: None,
+        signals: None,
+    }))
+}
+
+/// A player's name from its bus name, without the instance some add to tell
+/// theirs apart, as in `org.mpris.MediaPlayer2.firefox.instance_1_84`.
+fn player_name(bus_name: &str) -> &str {
+    let name = bus_name.strip_prefix(PREFIX).unwrap_or(bus_name);
+    match name.find(".instance") {
+        Some(end) => &name[..end],
+        None => name,
+    }
+}
+
+/// Picks the player to show from those running, as bus names with their
+/// status. The most preferred player wins, and among equally preferred ones,
+/// one that's playing, then the one shown last, then one that's paused.
+fn pick<'a>(running: &'a [(String, String)], preferences: &[String], shown: Option<&str>) -> Option<&'a (String, String)> {
+    let rank = |(bus_name, status): &(String, String)| {
+        let preference = preferences.iter().position(|preference| preference == ANY || preference == player_name(bus_name))?;
+        let standing = match status.as_str() {
+            "Playing" => 0,
+            _ if shown == Some(bus_name.as_str()) => 1,
+            "Paused" => 2,
+            _ => 3,
+        };
+        Some((preference, standing))
+    };
+    running.iter()
+        .filter_map(|player| rank(player).map(|rank| (rank, player)))
+        .min_by_key(|&(rank, _)| rank)
+        .map(|(_, player)| player)
+}
+
+/// The players on the bus, with their status.
+fn running(connection: &mut Connection) -> Option<Vec<(String, String)>> {
+    let names = connection.call(("org.freedesktop.DBus", "/org/freedesktop/DBus", "org.freedesktop.DBus", "ListNames"), &[])?;
+    let names = match names.first() {
+        Some(dbus::Value::Array(names)) => names.iter().filter_map(dbus::Value::as_str).filter(|name| name.starts_with(PREFIX)).map(String::from).collect::<Vec<_>>(),
+        _ => return None,
+    };
+    Some(names.into_iter()
+        .map(|name| {
+            let status = connection.property(&name, PATH, PLAYER, "PlaybackStatus").and_then(|status| status.as_str().map(String::from));
+            (name, status.unwrap_or_default())
+        })
+        .collect())
+}
+
+/// A tag from a player's metadata. Artists are a list, and are joined with commas.
+fn tag(metadata: &dbus::Value, key: &str) -> String {
+    match metadata.get(key) {
+        Some(dbus::Value::Array(values)) => values.iter().filter_map(dbus::Value::as_str).collect::<Vec<_>>().join(", "),
+        Some(value) => value.as_str().unwrap_or_default().to_owned(),
+        None => String::new(),
+    }
+}
+
+impl Widget for Media {
+    /// Polled whenever a player changes track or status, or comes or goes, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let (players, shown) = (&self.players, self.shown.as_deref());
+        let player = with_connection(&mut self.connection, Bus::Session, |connection| {
+            let running = running(connection)?;
+            // No players just means nothing is playing
+            let (name, status) = match pick(&running, players, shown) {
+                Some(player) => player.clone(),
+                None => return Some(None),
+            };
+            let metadata = connection.property(&name, PATH, PLAYER, "Metadata").unwrap_or(dbus::Value::Array(Vec::new()));
+            Some(Some((name, status, metadata)))
+        });
+        let (name, status, metadata) = match player {
+            Some(Some(player)) => player,
+            Some(None) => (String::new(), String::new(), dbus::Value::Array(Vec::new())),
+            None => return Some(Outcome::Failure(None)),
+        };
+
+        let format = match status.as_str() {
+            "Playing" => &self.playing,
+            "Paused" => &self.paused,
+            _ => &self.stopped,
+        };
+        let output = fill(format, &[
+            ("artist", tag(&metadata, "xesam:artist")),
+            ("title", tag(&metadata, "xesam:title")),
+            ("album", tag(&metadata, "xesam:album")),
+            ("player", player_name(&name).to_owned()),
+            ("status", status.clone()),
+        ]);
+        self.shown = Some(name).filter(|name| !name.is_empty());
+        Some(Outcome::Success(output))
+    }
+
+    /// Falls back to polling every second when players' signals can't be listened for.
+    fn wait(&self) -> Duration {
+        match self.signals {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        let rules = [
+            "type='signal',path='/org/mpris/MediaPlayer2',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',arg0='org.mpris.MediaPlayer2.Player'",
+            "type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg0namespace='org.mpris.MediaPlayer2'",
+        ];
+        self.signals = Signals::start(Bus::Session, &rules, &[], wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pick, player_name, tag, ANY};
+    use widgets::dbus::Value;
+
+    fn players(players: &[(&str, &str)]) -> Vec<(String, String)> {
+        players.iter().map(|&(name, status)| (format!("org.mpris.MediaPlayer2.{}", name), String::from(status))).collect()
+    }
+
+    #[test]
+    fn instances_share_their_player_name() {
+        assert_eq!(player_name("org.mpris.MediaPlayer2.spotify"), "spotify");
+        assert_eq!(player_name("org.mpris.MediaPlayer2.firefox.instance_1_84"), "firefox");
+    }
+
+    #[test]
+    fn preferred_players_come_first() {
+        let running = players(&[("mpv", "Playing"), ("spotify", "Paused")]);
+        let preferences = [String::from("spotify"), String::from(ANY)];
+        assert_eq!(pick(&running, &preferences, None).map(|player| player_name(&player.0)), Some("spotify"));
+        let preferences = [String::from("vlc")];
+        assert_eq!(pick(&running, &preferences, None), None);
+    }
+
+    #[test]
+    fn a_playing_player_wins_then_the_one_shown() {
+        let any = [String::from(ANY)];
+        let running = players(&[("mpv", "Paused"), ("spotify", "Playing")]);
+        assert_eq!(pick(&running, &any, None).map(|player| player_name(&player.0)), Some("spotify"));
+        let running = players(&[("mpv", "Paused"), ("spotify", "Stopped")]);
+        assert_eq!(pick(&running, &any, Some("org.mpris.MediaPlayer2.spotify")).map(|player| player_name(&player.0)), Some("spotify"));
+        assert_eq!(pick(&running, &any, None).map(|player| player_name(&player.0)), Some("mpv"));
+    }
+
+    #[test]
+    fn artists_are_joined() {
+        let entry = |key: &str, value: Value| Value::Struct(vec![Value::Str(String::from(key)), Value::Variant(Box::new(value))]);
+        let metadata = Value::Array(vec![
+            entry("xesam:artist", Value::Array(vec![Value::Str(String::from("One")), Value::Str(String::from("Two"))])),
+            entry("xesam:title", Value::Str(String::from("Song"))),
+        ]);
+        assert_eq!(tag(&metadata, "xesam:artist"), "One, Two");
+        assert_eq!(tag(&metadata, "xesam:title"), "Song");
+        assert_eq!(tag(&metadata, "xesam:album"), "");
+    }
+}
@@ -0,0 +1,114 @@
+//! A widget showing memory usage, read from `/proc/meminfo`.
+
+use std::fs;
+use std::time::Duration;
+
+use toml;
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+/// How amounts of memory are shown.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    MiB,
+    GiB,
+    /// As a percentage of the total.
+    Percent,
+}
+
+impl Unit {
+    pub fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Unit, String> {
+        match string(section_name, configuration, "unit", "GiB")?.as_str() {
+            "MiB" => Ok(Unit::MiB),
+            "GiB" => Ok(Unit::GiB),
+            "%" => Ok(Unit::Percent),
+            _ => Err(format!("Invalid unit found for {}: expected \"MiB\", \"GiB\" or \"%\"", section_name)),
+        }
+    }
+
+    /// Formats `kib` kibibytes out of `total_kib`.
+    pub fn format(&self, kib: u64, total_kib: u64) -> String {
+        match *self {
+            Unit::MiB => format!("{}", kib / 1024),
+            Unit::GiB => format!("{:.1}", kib as f64 / (1024 * 1024) as f64),
+            Unit::Percent => format!("{}", (kib * 100 + total_kib / 2).checked_div(total_kib).unwrap_or(0)),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Memory {
+    format: String,
+    unit: Unit,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Memory {
+        format: string(section_name, configuration, "format", "{used}/{total}")?,
+        unit: Unit::from_config(section_name, configuration)?,
+        interval: seconds(section_name, configuration, "reload", 5f64)?,
+    }))
+}
+
+/// Reads a field of `/proc/meminfo`, in kibibytes.
+pub fn field(meminfo: &str, name: &str) -> Option<u64> {
+    meminfo.lines()
+        .find(|line| line.split(':').next() == Some(name))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kib| kib.parse().ok())
+}
+
+impl Widget for Memory {
+    fn poll(&mut self) -> Option<Outcome> {
+        let meminfo = fs::read_to_string("/proc/meminfo").unwrap_or_default();
+        let (total, available) = match (field(&meminfo, "MemTotal"), field(&meminfo, "MemAvailable")) {
+            (Some(total), Some(available)) => (total, available),
+            _ => return Some(Outcome::Failure(None)),
+        };
+        let free = field(&meminfo, "MemFree").unwrap_or(available);
+        let used = total.saturating_sub(available);
+        let swap_total = field(&meminfo, "SwapTotal").unwrap_or(0);
+        let swap_used = swap_total.saturating_sub(field(&meminfo, "SwapFree").unwrap_or(0));
+
+        let unit = self.unit;
+        Some(Outcome::Success(fill(&self.format, &[
+            ("used", unit.format(used, total)),
+            ("available", unit.format(available, total)),
+            ("free", unit.format(free, total)),
+            ("total", unit.format(total, total)),
+            ("percent", Unit::Percent.format(used, total)),
+            ("swap_used", unit.format(swap_used, swap_total)),
+            ("swap_total", unit.format(swap_total, swap_total)),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field, Unit};
+
+    #[test]
+    fn meminfo_fields_are_found_by_name() {
+        let meminfo = "MemTotal:       16303832 kB\nMemFree:         1203412 kB\nMemAvailable:    8151916 kB\nSwapTotal:             0 kB\n";
+        assert_eq!(field(meminfo, "MemTotal"), Some(16303832));
+        assert_eq!(field(meminfo, "MemAvailable"), Some(8151916));
+        assert_eq!(field(meminfo, "SwapTotal"), Some(0));
+        assert_eq!(field(meminfo, "Mem"), None);
+        assert_eq!(field(meminfo, "Shmem"), None);
+    }
+
+    #[test]
+    fn units_format_amounts() {
+        assert_eq!(Unit::MiB.format(2048 * 1024 + 1000, 0), "2048");
+        assert_eq!(Unit::GiB.format(3 * 1024 * 1024 / 2, 0), "1.5");
+        assert_eq!(Unit::Percent.format(1, 3), "33");
+        assert_eq!(Unit::Percent.format(2, 3), "67");
+        assert_eq!(Unit::Percent.format(0, 0), "0");
+    }
+}
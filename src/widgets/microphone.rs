@@ -0,0 +1,89 @@
+//! A widget showing whether the default microphone is muted, or being recorded
+//! from, which is handy as a privacy indicator during calls.
+//!
+//! Like the volume widget, it watches `pactl subscribe` for changes to sources
+//! and the streams recording from them instead of polling.
+
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use toml;
+
+use {Event, Outcome};
+use super::{fill, first_percent, run, seconds, string, Subscription, Widget};
+
+struct Microphone {
+    idle: String,
+    in_use: String,
+    muted: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    subscription: Option<Subscription>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Microphone {
+        idle: string(section_name, configuration, "idle", "")?,
+        in_use: string(section_name, configuration, "in_use", "mic {volume}%")?,
+        muted: string(section_name, configuration, "muted", "mic muted")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        subscription: None,
+    }))
+}
+
+/// Whether a line from `pactl subscribe` is about a source, a recording stream, or the default source changing.
+fn is_pulse_source_event(line: &str) -> bool {
+    line.contains(" on source ") || line.contains(" on source-output ") || line.contains(" on server")
+}
+
+impl Widget for Microphone {
+    /// Polled after every change reported by `pactl subscribe`, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let volume = run("pactl", &["get-source-volume", "@DEFAULT_SOURCE@"]).as_deref().and_then(first_percent);
+        let muted = run("pactl", &["get-source-mute", "@DEFAULT_SOURCE@"]).map(|mute| mute.contains("yes"));
+        let (volume, muted) = match (volume, muted) {
+            (Some(volume), Some(muted)) => (volume, muted),
+            _ => return Some(Outcome::Failure(None)),
+        };
+        // Each stream recording from any source, including monitors of outputs
+        let streams = run("pactl", &["list", "short", "source-outputs"])
+            .map_or(0, |outputs| outputs.lines().filter(|line| !line.trim().is_empty()).count());
+
+        let format = match (muted, streams) {
+            (true, _) => &self.muted,
+            (false, 0) => &self.idle,
+            (false, _) => &self.in_use,
+        };
+        Some(Outcome::Success(fill(format, &[
+            ("volume", volume.to_string()),
+            ("streams", streams.to_string()),
+        ])))
+    }
+
+    /// Falls back to polling every second when `pactl subscribe` couldn't be started.
+    fn wait(&self) -> Duration {
+        match self.subscription {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.subscription = Subscription::start(Command::new("pactl").arg("subscribe"), wake, is_pulse_source_event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_pulse_source_event;
+
+    #[test]
+    fn only_source_events_wake_the_widget() {
+        assert!(is_pulse_source_event("Event 'change' on source #51"));
+        assert!(is_pulse_source_event("Event 'new' on source-output #1203"));
+        assert!(is_pulse_source_event("Event 'change' on server #-1"));
+        assert!(!is_pulse_source_event("Event 'change' on sink #48"));
+        assert!(!is_pulse_source_event("Event 'new' on sink-input #1204"));
+    }
+}
@@ -0,0 +1,487 @@
+//! Widgets are sections that Admiral handles natively, without running a command.
+//!
+//! A section selects a widget with its `type` entry. Each widget is built from
+//! its section by the constructor registered for it in `REGISTRY`, and is then
+//! polled by its section's thread.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::mem;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rustc_serialize::json::Json;
+use toml::{self, Value};
+
+use {Event, Outcome, State};
+
+pub mod backlight;
+pub mod bandwidth;
+pub mod battery;
+pub mod bluetooth;
+pub mod ci;
+pub mod clock;
+pub mod connectivity;
+pub mod containers;
+pub mod countdown;
+pub mod cpu;
+pub mod dbus;
+pub mod disk;
+pub mod fan;
+pub mod fifo;
+pub mod gpu;
+pub mod i3;
+pub mod inotify;
+pub mod interface;
+pub mod ip;
+pub mod keyboard;
+pub mod load;
+pub mod mail;
+pub mod media;
+pub mod memory;
+pub mod microphone;
+pub mod mpd;
+pub mod notifications;
+pub mod ping;
+pub mod power;
+pub mod price;
+pub mod remote;
+pub mod rtnetlink;
+pub mod storage;
+pub mod swap;
+pub mod systemd;
+pub mod tail;
+pub mod tasks;
+pub mod temperature;
+pub mod text;
+pub mod timer;
+pub mod updates;
+pub mod uptime;
+pub mod volume;
+pub mod vpn;
+pub mod weather;
+pub mod wifi;
+pub mod window;
+pub mod workspaces;
+
+pub trait Widget: Send {
+    /// Returns new output, or `None` if nothing has changed since the last call.
+    fn poll(&mut self) -> Option<Outcome>;
+
+    /// How long to wait before polling again.
+    fn wait(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    /// Called before the first poll with a sender that widgets watching for events
+    /// can send `Event::Wake` down, to be polled again without waiting.
+    fn subscribe(&mut self, _wake: Sender<Event>) {}
+
+    /// Called when the section is asked to refresh, before it is polled again.
+    /// Most widgets read afresh on every poll anyway, and need do nothing.
+    fn refresh(&mut self) {}
+
+    /// Called with an action sent to the section with `admiral ctl action`,
+    /// before it is polled again. Widgets ignore actions they don't know.
+    fn action(&mut self, _action: &str) {}
+
+    /// How things stand, for widgets that say, such as whether a build passed,
+    /// which the section's item is coloured by. Asked after every poll.
+    fn state(&self) -> Option<State> {
+        None
+    }
+}
+
+/// A running command that reports events, such as `pactl subscribe`. The widget
+/// that started it is woken whenever it prints a line that `filter` accepts.
+/// The command is killed when the subscription is dropped.
+pub struct Subscription {
+    child: Child,
+}
+
+impl Subscription {
+    pub fn start(command: &mut Command, wake: Sender<Event>, filter: fn(&str) -> bool) -> Option<Subscription> {
+        let mut child = command.stdout(Stdio::piped()).stderr(Stdio::null()).spawn().ok()?;
+        let reader = BufReader::new(child.stdout.take()?);
+        thread::spawn(move || {
+            for line in reader.lines().map_while(Result::ok) {
+                if filter(&line) && wake.send(Event::Wake).is_err() {
+                    break;
+                }
+            }
+        });
+        Some(Subscription { child })
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+type Constructor = fn(&str, &toml::Table) -> Result<Box<dyn Widget>, String>;
+
+/// Every widget, by the name used for it in `type`.
+pub const REGISTRY: &[(&str, Constructor)] = &[
+    ("backlight", backlight::new),
+    ("bandwidth", bandwidth::new),
+    ("battery", battery::new),
+    ("bluetooth", bluetooth::new),
+    ("ci", ci::new),
+    ("clock", clock::new),
+    ("connectivity", connectivity::new),
+    ("containers", containers::new),
+    ("countdown", countdown::new),
+    ("cpu", cpu::new),
+    ("disk", disk::new),
+    ("fan", fan::new),
+    ("fifo", fifo::new),
+    ("gpu", gpu::new),
+    ("interface", interface::new),
+    ("ip", ip::new),
+    ("keyboard", keyboard::new),
+    ("load", load::new),
+    ("mail", mail::new),
+    ("media", media::new),
+    ("memory", memory::new),
+    ("microphone", microphone::new),
+    ("mpd", mpd::new),
+    ("notifications", notifications::new),
+    ("ping", ping::new),
+    ("power", power::new),
+    ("price", price::new),
+    ("remote", remote::new),
+    ("storage", storage::new),
+    ("swap", swap::new),
+    ("systemd", systemd::new),
+    ("tail", tail::new),
+    ("tasks", tasks::new),
+    ("temperature", temperature::new),
+    ("text", text::new),
+    ("timer", timer::new),
+    ("updates", updates::new),
+    ("uptime", uptime::new),
+    ("volume", volume::new),
+    ("vpn", vpn::new),
+    ("weather", weather::new),
+    ("wifi", wifi::new),
+    ("window", window::new),
+    ("workspaces", workspaces::new),
+];
+
+/// Builds the widget for a section, or returns `None` if `type_name` isn't a widget.
+pub fn from_config(type_name: &str, section_name: &str, configuration: &toml::Table) -> Option<Result<Box<dyn Widget>, String>> {
+    REGISTRY.iter()
+        .find(|&&(name, _)| name == type_name)
+        .map(|&(_, constructor)| constructor(section_name, configuration))
+}
+
+/// Reads a duration given in seconds as an integer or a float.
+pub fn seconds(section_name: &str, configuration: &toml::Table, key: &str, default: f64) -> Result<Duration, String> {
+    match configuration.get(key) {
+        Some(&Value::Float(float)) if float >= 0f64 => Ok(Duration::from_millis((float * 1000f64) as u64)),
+        Some(&Value::Integer(int)) if int >= 0 => Ok(Duration::from_secs(int as u64)),
+        Some(_) => Err(format!("Invalid {} found for {}", key, section_name)),
+        None => Ok(Duration::from_millis((default * 1000f64) as u64)),
+    }
+}
+
+pub fn string(section_name: &str, configuration: &toml::Table, key: &str, default: &str) -> Result<String, String> {
+    match configuration.get(key).map(Value::as_str) {
+        Some(Some(string)) => Ok(string.to_owned()),
+        Some(None) => Err(format!("Invalid {} found for {}", key, section_name)),
+        None => Ok(default.to_owned()),
+    }
+}
+
+/// Formats a size in bytes with a binary prefix, as `df -h` does.
+pub fn human(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    for prefix in ["", "K", "M", "G", "T"] {
+        if size < 1024f64 {
+            return match prefix {
+                "" => format!("{}B", bytes),
+                _ => format!("{:.1}{}", size, prefix),
+            };
+        }
+        size /= 1024f64;
+    }
+    format!("{:.1}P", size)
+}
+
+/// Percent-encodes everything but unreserved characters, for a URL.
+pub fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Seconds since the epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |now| now.as_secs())
+}
+
+/// A file in `$XDG_CACHE_HOME/admiral` where a widget keeps what it fetched
+/// between runs, along with its `source`: what it was fetched with, such as a
+/// URL, so that anything fetched with other settings isn't used. The source is
+/// written to disk, so it should leave out API keys and other secrets.
+#[derive(Debug)]
+pub struct Cache {
+    path: Option<PathBuf>,
+    source: String,
+}
+
+impl Cache {
+    pub fn new(name: &str, source: String) -> Cache {
+        let path = env::var_os("XDG_CACHE_HOME").map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .map(|cache_home| cache_home.join("admiral").join(name));
+        Cache { path, source }
+    }
+
+    /// The object saved last, if it was fetched from the same source.
+    pub fn load(&self) -> Option<Json> {
+        let saved = Json::from_str(&fs::read_to_string(self.path.as_ref()?).ok()?).ok()?;
+        match saved.find("source").and_then(Json::as_string) == Some(&self.source) {
+            true => Some(saved),
+            false => None,
+        }
+    }
+
+    pub fn save(&self, mut object: BTreeMap<String, Json>) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+        object.insert(String::from("source"), Json::String(self.source.clone()));
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        let _ = fs::write(path, Json::Object(object).to_string());
+    }
+}
+
+/// What's fetched by a widget using `Fetched`, and how it's kept in its cache.
+pub trait Saved: Sized {
+    fn save(&self, object: &mut BTreeMap<String, Json>);
+
+    fn load(saved: &Json) -> Option<Self>;
+}
+
+/// How long a widget waits before fetching again after a fetch fails.
+const RETRY: Duration = Duration::from_secs(60);
+
+/// Something a widget fetches every `interval`, such as the weather, which is
+/// kept in a `Cache` so that restarting Admiral shows it straight away without
+/// fetching it again while it's recent. When a fetch fails, the last value
+/// fetched is kept but marked stale, and fetching is tried again after a minute.
+#[derive(Debug)]
+pub struct Fetched<T> {
+    cache: Cache,
+    value: Option<T>,
+    /// Whether the last fetch failed, so `value` is out of date.
+    stale: bool,
+    /// Whether `value` has been shown since it last changed.
+    shown: bool,
+    interval: Duration,
+    next_fetch: Option<Instant>,
+}
+
+impl<T: Saved> Fetched<T> {
+    /// Picks up the value saved by an earlier run, waiting out the rest of its `interval` if it's recent.
+    pub fn new(cache: Cache, interval: Duration) -> Fetched<T> {
+        let mut fetched = Fetched { cache, value: None, stale: false, shown: false, interval, next_fetch: None };
+        if let Some(saved) = fetched.cache.load() {
+            fetched.value = T::load(&saved);
+            let age = Duration::from_secs(now().saturating_sub(saved.find("fetched").and_then(Json::as_u64).unwrap_or(0)));
+            if let (Some(_), Some(left)) = (&fetched.value, interval.checked_sub(age)) {
+                fetched.next_fetch = Some(Instant::now() + left);
+            }
+        }
+        fetched
+    }
+
+    /// Whether it's time to fetch again.
+    pub fn due(&self) -> bool {
+        self.next_fetch.is_none_or(|next_fetch| Instant::now() >= next_fetch)
+    }
+
+    /// Keeps and saves what was just fetched, or if the fetch failed, keeps the
+    /// last value but marks it stale.
+    pub fn update(&mut self, value: Option<T>) {
+        match value {
+            Some(value) => {
+                let mut object = BTreeMap::new();
+                value.save(&mut object);
+                object.insert(String::from("fetched"), Json::U64(now()));
+                self.cache.save(object);
+                self.value = Some(value);
+                self.stale = false;
+                self.next_fetch = Some(Instant::now() + self.interval);
+            },
+            None => {
+                self.stale = true;
+                self.next_fetch = Some(Instant::now() + RETRY.min(self.interval));
+            },
+        }
+        self.shown = false;
+    }
+
+    /// Whether the value has changed since the last call, which counts as showing it.
+    pub fn changed(&mut self) -> bool {
+        !mem::replace(&mut self.shown, true)
+    }
+
+    pub fn value(&self) -> Option<&T> {
+        self.value.as_ref()
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    /// Has the next poll fetch again, however recent the value is.
+    pub fn refresh(&mut self) {
+        self.next_fetch = None;
+    }
+}
+
+/// Runs a command and returns its output, if it succeeds.
+pub fn run(program: &str, arguments: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(arguments).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Runs a command with `input` written to its standard input, and returns its
+/// output if it succeeds. Secrets such as API tokens are passed this way,
+/// since any user can read a process's arguments.
+pub fn run_with_input(program: &str, arguments: &[&str], input: &str) -> Option<String> {
+    let mut child = Command::new(program).args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn().ok()?;
+    let written = child.stdin.take()?.write_all(input.as_bytes());
+    let output = child.wait_with_output().ok()?;
+    if written.is_err() || !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Fetches `url` with `curl`, returning the body if the request succeeds. The
+/// URL is read from standard input as a `curl` configuration, so an API key in
+/// it isn't in curl's arguments.
+pub fn fetch_url(url: &str) -> Option<String> {
+    // met.no turns away requests that don't say who's asking
+    let user_agent = concat!("admiral/", env!("CARGO_PKG_VERSION"), " github.com/kbrgl/admiral");
+    // Quoted values in the configuration take backslash escapes, so a quote in the URL can't end it early
+    let mut quoted = String::new();
+    for c in url.chars() {
+        match c {
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    run_with_input("curl", &["-sf", "--max-time", "10", "-A", user_agent, "--config", "-"], &format!("url = \"{}\"\n", quoted))
+}
+
+/// The first percentage in a command's output, such as `[65%]` or `/  65% /`.
+pub fn first_percent(output: &str) -> Option<u32> {
+    output.split('%').next()?
+        .rsplit(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Replaces each `{name}` in `format` with its value, leaving any other braces
+/// as they are. The format is read once, so a value containing `{name}`, such
+/// as a song title, is shown as it is rather than filled in itself.
+pub fn fill(format: &str, values: &[(&str, String)]) -> String {
+    let mut filled = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest[1..].find('}')
+            .and_then(|end| values.iter().find(|&&(name, _)| name == &rest[1..end + 1]).map(|(_, value)| (end + 2, value)));
+        match value {
+            Some((length, value)) => {
+                filled.push_str(value);
+                rest = &rest[length..];
+            },
+            None => {
+                filled.push('{');
+                rest = &rest[1..];
+            },
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+    use std::sync::mpsc::channel;
+
+    use Event;
+    use super::{fill, human, Subscription};
+
+    #[test]
+    fn fill_replaces_each_placeholder() {
+        let values = [("name", String::from("admiral")), ("count", String::from("3"))];
+        assert_eq!(fill("{name}: {count} of {count}", &values), "admiral: 3 of 3");
+        assert_eq!(fill("no placeholders", &values), "no placeholders");
+    }
+
+    #[test]
+    fn fill_leaves_other_braces() {
+        let values = [("name", String::from("admiral"))];
+        assert_eq!(fill("{unknown} {name} {", &values), "{unknown} admiral {");
+        assert_eq!(fill("{{name}}", &values), "{admiral}");
+        assert_eq!(fill("}{name", &values), "}{name");
+    }
+
+    #[test]
+    fn human_sizes_use_binary_prefixes() {
+        assert_eq!(human(0), "0B");
+        assert_eq!(human(1023), "1023B");
+        assert_eq!(human(1536), "1.5K");
+        assert_eq!(human(5 * 1024 * 1024 * 1024), "5.0G");
+        assert_eq!(human(3 << 50), "3.0P");
+    }
+
+    #[test]
+    fn subscriptions_wake_for_the_lines_they_want() {
+        let (wake, woken) = channel();
+        let _subscription = Subscription::start(Command::new("sh").args(["-c", "echo skip; echo wake; echo skip; echo wake"]), wake, |line| line == "wake").unwrap();
+        let events = woken.iter().collect::<Vec<_>>();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| matches!(*event, Event::Wake)));
+    }
+
+    #[test]
+    fn fill_does_not_fill_in_values() {
+        let values = [("title", String::from("{artist}")), ("artist", String::from("someone"))];
+        assert_eq!(fill("{title} by {artist}", &values), "{artist} by someone");
+    }
+}
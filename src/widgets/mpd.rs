@@ -0,0 +1,265 @@
+//! A widget showing what MPD is playing.
+//!
+//! A second connection to MPD sits in `idle player`, which MPD answers as soon
+//! as playback changes, so a new track shows up straight away. The state is
+//! read over a fresh connection each time, with `status` and `currentsong`.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use toml::{self, Value};
+
+use {Event, Outcome};
+use super::{fill, seconds, string, Widget};
+
+/// How long to wait before connecting again after losing MPD.
+const RECONNECT: Duration = Duration::from_secs(5);
+
+struct Mpd {
+    address: String,
+    password: Option<String>,
+    playing: String,
+    paused: String,
+    stopped: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    /// Whether the last poll found MPD playing, to keep `{elapsed}` ticking.
+    was_playing: bool,
+    idle: Option<Idle>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let port = match configuration.get("port") {
+        Some(&Value::Integer(port)) if port > 0 && port <= 65535 => port,
+        Some(_) => return Err(format!("Invalid port found for {}", section_name)),
+        None => 6600,
+    };
+    let password = match configuration.get("password") {
+        Some(_) => Some(string(section_name, configuration, "password", "")?),
+        None => None,
+    };
+
+    Ok(Box::new(Mpd {
+        address: format!("{}:{}", string(section_name, configuration, "host", "localhost")?, port),
+        password,
+        playing: string(section_name, configuration, "playing", "{artist} - {title}")?,
+        paused: string(section_name, configuration, "paused", "{artist} - {title} (paused)")?,
+        stopped: string(section_name, configuration, "stopped", "")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        was_playing: false,
+        idle: None,
+    }))
+}
+
+/// A connection to MPD, past its greeting and any password.
+struct Connection {
+    reader: BufReader<TcpStream>,
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn open(address: &str, password: Option<&str>, timeout: Option<Duration>) -> Option<Connection> {
+        let stream = TcpStream::connect(address).ok()?;
+        stream.set_read_timeout(timeout).ok()?;
+        let mut connection = Connection { reader: BufReader::new(stream.try_clone().ok()?), stream };
+
+        let mut greeting = String::new();
+        connection.reader.read_line(&mut greeting).ok()?;
+        if !greeting.starts_with("OK MPD") {
+            return None;
+        }
+        if let Some(password) = password {
+            connection.command(&format!("password \"{}\"", password.replace('\\', "\\\\").replace('"', "\\\"")))?;
+        }
+        Some(connection)
+    }
+
+    /// Sends a command and returns the fields of its response.
+    fn command(&mut self, command: &str) -> Option<Vec<(String, String)>> {
+        self.stream.write_all(format!("{}\n", command).as_bytes()).ok()?;
+        let mut fields = Vec::new();
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end_matches('\n');
+            if line == "OK" {
+                return Some(fields);
+            }
+            if line.starts_with("ACK") {
+                return None;
+            }
+            if let Some((key, value)) = line.split_once(": ") {
+                fields.push((key.to_owned(), value.to_owned()));
+            }
+        }
+    }
+}
+
+/// A thread keeping a connection in `idle player`, which wakes the widget
+/// whenever MPD answers. Shutting the connection down when this is dropped
+/// lets the thread finish.
+struct Idle {
+    stopped: Arc<AtomicBool>,
+    stream: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl Idle {
+    fn start(address: String, password: Option<String>, wake: Sender<Event>) -> Idle {
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stream = Arc::new(Mutex::new(None));
+        let idle = Idle { stopped: stopped.clone(), stream: stream.clone() };
+
+        thread::spawn(move || {
+            while !stopped.load(Ordering::SeqCst) {
+                if let Some(mut connection) = Connection::open(&address, password.as_deref(), None) {
+                    *stream.lock().unwrap() = connection.stream.try_clone().ok();
+                    // Check again whether this was dropped while connecting
+                    if stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    // MPD may have changed while there was no connection
+                    let _ = wake.send(Event::Wake);
+                    while connection.command("idle player").is_some() {
+                        let _ = wake.send(Event::Wake);
+                    }
+                }
+                thread::sleep(RECONNECT);
+            }
+        });
+        idle
+    }
+}
+
+impl Drop for Idle {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(ref stream) = *self.stream.lock().unwrap() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+/// Formats seconds as `M:SS`.
+fn minutes_seconds(seconds: &str) -> String {
+    let seconds = seconds.parse::<f64>().unwrap_or(0f64).round() as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+impl Widget for Mpd {
+    /// Polled whenever playback changes, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let mut connection = match Connection::open(&self.address, self.password.as_deref(), Some(Duration::from_secs(2))) {
+            Some(connection) => connection,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let (status, song) = match (connection.command("status"), connection.command("currentsong")) {
+            (Some(status), Some(song)) => (status, song),
+            _ => return Some(Outcome::Failure(None)),
+        };
+        let field = |fields: &[(String, String)], name: &str| fields.iter()
+            .find(|&(key, _)| key == name)
+            .map_or_else(String::new, |(_, value)| value.clone());
+
+        let state = field(&status, "state");
+        self.was_playing = state == "play";
+        let format = match state.as_str() {
+            "play" => &self.playing,
+            "pause" => &self.paused,
+            _ => &self.stopped,
+        };
+        Some(Outcome::Success(fill(format, &[
+            ("artist", field(&song, "Artist")),
+            ("title", field(&song, "Title")),
+            ("album", field(&song, "Album")),
+            ("file", field(&song, "file")),
+            ("elapsed", minutes_seconds(&field(&status, "elapsed"))),
+            ("duration", minutes_seconds(&field(&status, "duration"))),
+        ])))
+    }
+
+    /// Polls every second while playing, if the time played is shown.
+    fn wait(&self) -> Duration {
+        if self.was_playing && self.playing.contains("{elapsed}") {
+            Duration::from_secs(1)
+        } else {
+            self.interval
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.idle = Some(Idle::start(self.address.clone(), self.password.clone(), wake));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    use toml;
+
+    use Outcome;
+    use super::minutes_seconds;
+
+    /// Answers one connection as MPD would, with `answer` giving the response
+    /// to each command, and returns the address to reach it at.
+    fn serve(answer: fn(&str) -> &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"OK MPD 0.23.5\n").unwrap();
+            for command in BufReader::new(stream.try_clone().unwrap()).lines().map_while(Result::ok) {
+                if stream.write_all(answer(&command).as_bytes()).is_err() {
+                    break;
+                }
+            }
+        });
+        address
+    }
+
+    fn mpd(address: &str, extra: &str) -> Box<dyn super::Widget> {
+        let (host, port) = address.split_once(':').unwrap();
+        let source = format!("host = \"{}\"\nport = {}\nplaying = \"{{artist}} - {{title}} {{elapsed}}/{{duration}}\"\n{}", host, port, extra);
+        super::new("mpd", &toml::Parser::new(&source).parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn status_and_song_are_read_from_responses() {
+        let address = serve(|command| match command {
+            "password \"se\\\"cret\"" => "OK\n",
+            "status" => "volume: 80\nstate: play\nelapsed: 65.432\nduration: 200.000\nOK\n",
+            "currentsong" => "file: a/b.flac\nArtist: Someone\nTitle: Song: the remix\nOK\n",
+            _ => "ACK [5@0] {} unknown command\n",
+        });
+        let mut mpd = mpd(&address, "password = 'se\"cret'");
+        assert_eq!(mpd.poll(), Some(Outcome::Success(String::from("Someone - Song: the remix 1:05/3:20"))));
+        // Playing with the time shown ticks every second
+        assert_eq!(mpd.wait().as_secs(), 1);
+    }
+
+    #[test]
+    fn errors_show_the_fallback() {
+        let address = serve(|command| match command {
+            "status" => "ACK [4@0] {status} you don't have permission for \"status\"\n",
+            _ => "OK\n",
+        });
+        assert_eq!(mpd(&address, "").poll(), Some(Outcome::Failure(None)));
+    }
+
+    #[test]
+    fn times_are_minutes_and_seconds() {
+        assert_eq!(minutes_seconds("0"), "0:00");
+        assert_eq!(minutes_seconds("59.6"), "1:00");
+        assert_eq!(minutes_seconds("3725.1"), "62:05");
+        assert_eq!(minutes_seconds(""), "0:00");
+    }
+}
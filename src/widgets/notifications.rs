@@ -0,0 +1,95 @@
+//! A widget showing whether dunst's notifications are paused, as they are
+//! while do-not-disturb is on, and how many are waiting to be shown.
+//!
+//! dunst signals over D-Bus when it's paused or resumed, so the widget is
+//! woken at once, and reads its state with `dunstctl`. Notifications can be
+//! paused from the bar with `admiral ctl action <section> toggle`.
+
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use toml;
+
+use {Event, Outcome};
+use super::dbus::{Bus, Signals};
+use super::{fill, run, seconds, string, Widget};
+
+struct Notifications {
+    paused: String,
+    active: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    signals: Option<Signals>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Notifications {
+        paused: string(section_name, configuration, "paused", "dnd")?,
+        active: string(section_name, configuration, "active", "")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        signals: None,
+    }))
+}
+
+fn is_paused() -> Option<bool> {
+    paused(&run("dunstctl", &["is-paused"])?)
+}
+
+/// Reads `dunstctl is-paused`, which prints `true` or `false`.
+fn paused(output: &str) -> Option<bool> {
+    match output.trim() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+impl Widget for Notifications {
+    /// Polled after dunst is paused or resumed, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let format = match is_paused() {
+            Some(true) => &self.paused,
+            Some(false) => &self.active,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let waiting = run("dunstctl", &["count", "waiting"]).map(|count| count.trim().to_owned()).unwrap_or_default();
+        Some(Outcome::Success(fill(format, &[("waiting", waiting)])))
+    }
+
+    /// Falls back to polling every second when dunst's signals can't be had.
+    fn wait(&self) -> Duration {
+        match self.signals {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        let rules = [
+            "type='signal',path='/org/freedesktop/Notifications',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',arg0='org.dunstproject.cmd0'",
+        ];
+        self.signals = Signals::start(Bus::Session, &rules, &[], wake);
+    }
+
+    fn action(&mut self, action: &str) {
+        let paused = match action {
+            "pause" => "true",
+            "resume" => "false",
+            "toggle" => "toggle",
+            _ => return,
+        };
+        let _ = run("dunstctl", &["set-paused", paused]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::paused;
+
+    #[test]
+    fn dunstctl_says_whether_it_is_paused() {
+        assert_eq!(paused("true\n"), Some(true));
+        assert_eq!(paused("false\n"), Some(false));
+        assert_eq!(paused("Failed to connect\n"), None);
+    }
+}
@@ -0,0 +1,195 @@
+//! A widget showing the round-trip time to a host, or that it's down.
+//!
+//! Pings are sent over an unprivileged ICMP socket (`SOCK_DGRAM` with
+//! `IPPROTO_ICMP`), so neither root nor a setuid `ping` is needed. Linux only
+//! allows these for groups in `net.ipv4.ping_group_range`, which most
+//! distributions set to cover every group. The kernel fills in the echo
+//! request's identifier and checksum itself.
+
+use std::mem;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use libc;
+use toml;
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+#[derive(Debug)]
+struct Ping {
+    host: String,
+    format: String,
+    down: String,
+    timeout: Duration,
+    interval: Duration,
+    sequence: u16,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let host = string(section_name, configuration, "host", "")?;
+    if host.is_empty() {
+        return Err(format!("Failed to find host for {}", section_name));
+    }
+
+    Ok(Box::new(Ping {
+        host,
+        format: string(section_name, configuration, "format", "{rtt} ms")?,
+        down: string(section_name, configuration, "down", "down")?,
+        timeout: seconds(section_name, configuration, "timeout", 2f64)?.max(Duration::from_millis(1)),
+        interval: seconds(section_name, configuration, "reload", 10f64)?,
+        sequence: 0,
+    }))
+}
+
+/// A ping socket, closed when dropped.
+struct Socket(libc::c_int);
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+/// Why pings can't be sent at all, rather than going unanswered.
+const NOT_PERMITTED: &str = "unprivileged ICMP sockets not permitted (net.ipv4.ping_group_range)";
+
+/// Sends an echo request to `address` and waits up to `timeout` for its reply,
+/// returning `Err` if no ping socket could be opened for its family.
+fn ping(address: SocketAddr, sequence: u16, timeout: Duration) -> Result<Option<Duration>, &'static str> {
+    let (family, protocol, request, reply) = match address {
+        SocketAddr::V4(_) => (libc::AF_INET, libc::IPPROTO_ICMP, ICMP_ECHO_REQUEST, ICMP_ECHO_REPLY),
+        SocketAddr::V6(_) => (libc::AF_INET6, libc::IPPROTO_ICMPV6, ICMPV6_ECHO_REQUEST, ICMPV6_ECHO_REPLY),
+    };
+    let socket = unsafe { libc::socket(family, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, protocol) };
+    if socket < 0 {
+        return Err(NOT_PERMITTED);
+    }
+    let socket = Socket(socket);
+
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let length = match address {
+        SocketAddr::V4(address) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+            sockaddr.sin_addr.s_addr = u32::from_ne_bytes(address.ip().octets());
+            mem::size_of::<libc::sockaddr_in>()
+        },
+        SocketAddr::V6(address) => {
+            let sockaddr = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sockaddr.sin6_addr.s6_addr = address.ip().octets();
+            sockaddr.sin6_scope_id = address.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        },
+    };
+
+    // Type, code, checksum, identifier, and sequence number, then a little padding
+    let mut message = [0u8; 16];
+    message[0] = request;
+    message[6..8].copy_from_slice(&sequence.to_be_bytes());
+
+    let sent = Instant::now();
+    let result = unsafe {
+        libc::sendto(socket.0, message.as_ptr() as *const libc::c_void, message.len(), 0, &storage as *const _ as *const libc::sockaddr, length as libc::socklen_t)
+    };
+    if result < 0 {
+        return Ok(None);
+    }
+
+    // Replies to earlier pings that timed out may still arrive, so wait for this one's
+    let mut buffer = [0u8; 1500];
+    loop {
+        let left = match timeout.checked_sub(sent.elapsed()) {
+            Some(left) => left,
+            None => return Ok(None),
+        };
+        let wait = libc::timeval { tv_sec: left.as_secs() as libc::time_t, tv_usec: left.subsec_micros().max(1) as libc::suseconds_t };
+        unsafe {
+            libc::setsockopt(socket.0, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &wait as *const _ as *const libc::c_void, mem::size_of::<libc::timeval>() as libc::socklen_t)
+        };
+        let received = unsafe { libc::recv(socket.0, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0) };
+        if received < 0 {
+            return Ok(None);
+        }
+        if received >= 8 && buffer[0] == reply && buffer[6..8] == sequence.to_be_bytes() {
+            return Ok(Some(sent.elapsed()));
+        }
+    }
+}
+
+/// Pings each of a host's addresses in turn until one replies, as an address
+/// in a family without a route never will. Also returns why pings couldn't be
+/// sent, if they couldn't be sent to any of the addresses.
+fn first_reply<F: FnMut(SocketAddr) -> Result<Option<Duration>, &'static str>>(addresses: &[SocketAddr], mut ping: F) -> (Option<Duration>, Option<&'static str>) {
+    let mut error = None;
+    let mut sent = false;
+    for &address in addresses {
+        match ping(address) {
+            Ok(Some(rtt)) => return (Some(rtt), None),
+            Ok(None) => sent = true,
+            Err(e) => error = Some(e),
+        }
+    }
+    (None, error.filter(|_| !sent))
+}
+
+impl Widget for Ping {
+    fn poll(&mut self) -> Option<Outcome> {
+        self.sequence = self.sequence.wrapping_add(1);
+
+        // Resolved each time, so that a host that's gone missing shows as down
+        let addresses: Vec<_> = (self.host.as_str(), 0).to_socket_addrs().map(Iterator::collect).unwrap_or_default();
+        let (rtt, error) = first_reply(&addresses, |address| ping(address, self.sequence, self.timeout));
+        if let (None, Some(error)) = (rtt, error) {
+            return Some(Outcome::Failure(Some(String::from(error))));
+        }
+        Some(Outcome::Success(match rtt {
+            Some(rtt) => fill(&self.format, &[
+                ("rtt", format!("{:.0}", rtt.as_secs_f64() * 1000f64)),
+                ("host", self.host.clone()),
+            ]),
+            None => fill(&self.down, &[("host", self.host.clone())]),
+        }))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use toml;
+
+    use Outcome;
+    use super::{first_reply, NOT_PERMITTED};
+
+    #[test]
+    fn unknown_hosts_are_down() {
+        let config = toml::Parser::new("host = \"admiral.invalid\"\ndown = \"{host} down\"").parse().unwrap();
+        let mut ping = super::new("ping", &config).unwrap();
+        assert_eq!(ping.poll(), Some(Outcome::Success(String::from("admiral.invalid down"))));
+        assert!(super::new("ping", &toml::Table::new()).is_err());
+    }
+
+    #[test]
+    fn each_address_is_tried_until_one_replies() {
+        let ipv6 = SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 0));
+        let ipv4 = SocketAddr::from(([127, 0, 0, 1], 0));
+        let rtt = Duration::from_millis(12);
+        assert_eq!(first_reply(&[ipv6, ipv4], |address| Ok(Some(rtt).filter(|_| address.is_ipv4()))), (Some(rtt), None));
+        // The sysctl is only to blame when no ping could be sent at all
+        assert_eq!(first_reply(&[ipv6, ipv4], |address| if address.is_ipv6() { Err(NOT_PERMITTED) } else { Ok(None) }), (None, None));
+        assert_eq!(first_reply(&[ipv6, ipv4], |_| Err(NOT_PERMITTED)), (None, Some(NOT_PERMITTED)));
+        assert_eq!(first_reply(&[], |_| Err(NOT_PERMITTED)), (None, None));
+    }
+}
@@ -0,0 +1,173 @@
+//! A widget showing the platform power profile set with power-profiles-daemon,
+//! or that gamemode is active, as it overrides the profile while it is.
+//!
+//! Both daemons' properties are read over D-Bus, on connections kept open
+//! between polls, and their signals are watched for, so the widget changes as
+//! soon as the profile does. The `next` action switches to the profile after
+//! the current one, which is also given as `{next}`.
+
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use toml;
+
+use {Event, Outcome};
+use super::dbus::{self, with_connection, Bus, Connection, Signals};
+use super::{fill, seconds, string, Widget};
+
+/// The profiles in the order they're cycled through.
+const PROFILES: &[&str] = &["power-saver", "balanced", "performance"];
+
+/// A D-Bus service's name, object, and interface.
+type Daemon = (&'static str, &'static str, &'static str);
+
+/// power-profiles-daemon's name, object, and interface, as it's now known and as it was before.
+const DAEMONS: &[Daemon] = &[
+    ("org.freedesktop.UPower.PowerProfiles", "/org/freedesktop/UPower/PowerProfiles", "org.freedesktop.UPower.PowerProfiles"),
+    ("net.hadess.PowerProfiles", "/net/hadess/PowerProfiles", "net.hadess.PowerProfiles"),
+];
+
+const GAMEMODE: &str = "com.feralinteractive.GameMode";
+const GAMEMODE_PATH: &str = "/com/feralinteractive/GameMode";
+
+/// How often to read the profile while the daemons can't be listened to.
+const UNWATCHED: Duration = Duration::from_secs(5);
+
+struct Power {
+    format: String,
+    /// The icons for power-saver, balanced, and performance, in that order.
+    icons: [String; 3],
+    gamemode: String,
+    /// How often to read the profile even though no signal came, in case one was missed.
+    interval: Duration,
+    profiles: Option<Signals>,
+    games: Option<Signals>,
+    /// The connections properties are read on, opened again only once they fail.
+    system: Option<Connection>,
+    session: Option<Connection>,
+}
+
+/// The active profile and the profiles this machine has, read from whichever
+/// daemon answers, along with its name, object, and interface.
+fn read_profiles(connection: &mut Connection) -> Option<(String, Vec<String>, Daemon)> {
+    DAEMONS.iter().find_map(|&daemon| {
+        let (name, path, interface) = daemon;
+        let active = connection.property(name, path, interface, "ActiveProfile")?.as_str()?.to_owned();
+        let available = match connection.property(name, path, interface, "Profiles") {
+            Some(dbus::Value::Array(profiles)) => profiles.iter()
+                .filter_map(|profile| profile.get("Profile").and_then(dbus::Value::as_str).map(String::from))
+                .collect(),
+            _ => Vec::new(),
+        };
+        Some((active, available, daemon))
+    })
+}
+
+/// Whether any game has asked gamemode for its optimisations.
+fn gamemode_active(connection: &mut Connection) -> Option<bool> {
+    connection.property(GAMEMODE, GAMEMODE_PATH, GAMEMODE, "ClientCount")
+        .and_then(|count| count.as_int())
+        .map(|count| count > 0)
+}
+
+/// The profile after `active`, skipping any this machine doesn't have, as
+/// performance is missing where the hardware can't support it.
+fn next_profile(active: &str, available: &[String]) -> String {
+    let start = PROFILES.iter().position(|&name| name == active).unwrap_or(0);
+    (1..=PROFILES.len())
+        .map(|step| PROFILES[(start + step) % PROFILES.len()])
+        .find(|name| available.is_empty() || available.iter().any(|available| available == name))
+        .unwrap_or("balanced")
+        .to_owned()
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Power::from_config(section_name, configuration)?))
+}
+
+impl Power {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Power, String> {
+        Ok(Power {
+            format: string(section_name, configuration, "format", "{icon}")?,
+            icons: [
+                string(section_name, configuration, "power_saver", "▼")?,
+                string(section_name, configuration, "balanced", "◆")?,
+                string(section_name, configuration, "performance", "▲")?,
+            ],
+            gamemode: string(section_name, configuration, "gamemode", "G")?,
+            interval: seconds(section_name, configuration, "reload", 60f64)?,
+            profiles: None,
+            games: None,
+            system: None,
+            session: None,
+        })
+    }
+}
+
+impl Widget for Power {
+    fn poll(&mut self) -> Option<Outcome> {
+        // gamemode not running at all is the same as no games using it
+        let gamemode = with_connection(&mut self.session, Bus::Session, gamemode_active).unwrap_or(false);
+        let (active, available) = match with_connection(&mut self.system, Bus::System, read_profiles) {
+            Some((active, available, _)) => (active, available),
+            None if gamemode => (String::new(), Vec::new()),
+            None => return Some(Outcome::Failure(None)),
+        };
+        let icon = match PROFILES.iter().position(|&name| name == active) {
+            _ if gamemode => self.gamemode.clone(),
+            Some(index) => self.icons[index].clone(),
+            None => String::from("?"),
+        };
+        let profile = if gamemode { String::from("gamemode") } else { active.clone() };
+        Some(Outcome::Success(fill(&self.format, &[
+            ("icon", icon),
+            ("profile", profile),
+            ("next", next_profile(&active, &available)),
+        ])))
+    }
+
+    /// Reads the profile again every `reload` seconds, or more often when
+    /// power-profiles-daemon's signals can't be had.
+    fn wait(&self) -> Duration {
+        match self.profiles {
+            Some(_) => self.interval,
+            None => UNWATCHED.min(self.interval),
+        }
+    }
+
+    /// Starts listening to both daemons' signals, which wake the widget down `wake`.
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        let rules: Vec<String> = DAEMONS.iter()
+            .map(|&(_, path, _)| format!("type='signal',path='{}',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'", path))
+            .collect();
+        self.profiles = Signals::start(Bus::System, &rules.iter().map(String::as_str).collect::<Vec<_>>(), &[], wake.clone());
+        let rules = [format!("type='signal',path='{}',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'", GAMEMODE_PATH)];
+        self.games = Signals::start(Bus::Session, &[&rules[0]], &[], wake);
+    }
+
+    /// `next` switches to the profile after the current one.
+    fn action(&mut self, action: &str) {
+        if action != "next" {
+            return;
+        }
+        let _ = with_connection(&mut self.system, Bus::System, |connection| {
+            let (active, available, (name, path, interface)) = read_profiles(connection)?;
+            let next = dbus::Value::Str(next_profile(&active, &available));
+            connection.set_property(name, path, interface, "ActiveProfile", next)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_profile;
+
+    #[test]
+    fn profiles_cycle_through_those_available() {
+        assert_eq!(next_profile("power-saver", &[]), "balanced");
+        assert_eq!(next_profile("performance", &[]), "power-saver");
+        let available = [String::from("power-saver"), String::from("balanced")];
+        assert_eq!(next_profile("balanced", &available), "power-saver");
+        assert_eq!(next_profile("unknown", &available), "balanced");
+    }
+}
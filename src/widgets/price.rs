@@ -0,0 +1,158 @@
+//! A widget showing a price, such as a cryptocurrency's or a stock's, read
+//! from any JSON API.
+//!
+//! The price is found in the response by a path such as `data.amount`. As
+//! with the weather, each fetch is saved to `$XDG_CACHE_HOME/admiral`, and
+//! when a fetch fails the last price fetched is still shown, with
+//! `stale_format`, instead of the section failing.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+use toml::{self, Value};
+
+use Outcome;
+use super::{encode, fetch_url, fill, seconds, string, Cache, Fetched, Saved, Widget};
+
+#[derive(Debug, Clone, Copy)]
+struct Quote {
+    price: f64,
+}
+
+impl Saved for Quote {
+    fn save(&self, object: &mut BTreeMap<String, Json>) {
+        object.insert(String::from("price"), Json::F64(self.price));
+    }
+
+    fn load(saved: &Json) -> Option<Quote> {
+        Some(Quote { price: saved.find("price").and_then(Json::as_f64)? })
+    }
+}
+
+#[derive(Debug)]
+struct Price {
+    url: String,
+    path: String,
+    decimals: usize,
+    format: String,
+    stale_format: String,
+    quote: Fetched<Quote>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let url = string(section_name, configuration, "url", "")?;
+    if url.is_empty() {
+        return Err(format!("Failed to find url for {}", section_name));
+    }
+    let path = string(section_name, configuration, "path", "")?;
+    if path.is_empty() {
+        return Err(format!("Failed to find path for {}", section_name));
+    }
+    let decimals = match configuration.get("decimals") {
+        Some(&Value::Integer(decimals)) if (0..=16).contains(&decimals) => decimals as usize,
+        Some(_) => return Err(format!("Invalid decimals found for {}: expected a number from 0 to 16", section_name)),
+        None => 2,
+    };
+    let key = match (configuration.get("api_key"), configuration.get("api_key_file")) {
+        (Some(_), _) => string(section_name, configuration, "api_key", "")?,
+        (None, Some(_)) => {
+            let path = string(section_name, configuration, "api_key_file", "")?;
+            let key = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read api_key_file for {}: {}", section_name, e))?;
+            key.trim().to_owned()
+        },
+        (None, None) => String::new(),
+    };
+    let format = string(section_name, configuration, "format", "{price}")?;
+
+    // The URL is saved as it's given, before the API key is put in, so the key isn't written to disk
+    let cache = Cache::new(&format!("price-{}.json", section_name), format!("{} {}", url, path));
+    Ok(Box::new(Price {
+        url: fill(&url, &[("api_key", encode(&key))]),
+        path,
+        decimals,
+        stale_format: string(section_name, configuration, "stale_format", &format)?,
+        format,
+        quote: Fetched::new(cache, seconds(section_name, configuration, "reload", 300f64)?),
+    }))
+}
+
+/// Follows a path such as `data.amount` or `quotes.0.price` into a response,
+/// where a number picks an item of an array. Prices given as strings, as many
+/// APIs do to keep their precision, are read as numbers too.
+fn find_price(json: &Json, path: &str) -> Option<f64> {
+    let mut found = json;
+    for key in path.split('.') {
+        found = match *found {
+            Json::Array(ref items) => items.get(key.parse::<usize>().ok()?)?,
+            _ => found.find(key)?,
+        };
+    }
+    found.as_f64().or_else(|| found.as_string()?.trim().parse().ok())
+}
+
+impl Price {
+    fn fetch(&self) -> Option<Quote> {
+        let body = fetch_url(&self.url)?;
+        let json = Json::from_str(&body).ok()?;
+        Some(Quote { price: find_price(&json, &self.path)? })
+    }
+}
+
+impl Widget for Price {
+    fn poll(&mut self) -> Option<Outcome> {
+        if self.quote.due() {
+            let quote = self.fetch();
+            self.quote.update(quote);
+        }
+        if !self.quote.changed() {
+            return None;
+        }
+
+        let quote = match self.quote.value() {
+            Some(quote) => quote,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let format = if self.quote.is_stale() { &self.stale_format } else { &self.format };
+        Some(Outcome::Success(fill(format, &[
+            ("price", format!("{:.*}", self.decimals, quote.price)),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn refresh(&mut self) {
+        self.quote.refresh();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use toml;
+
+    use super::find_price;
+
+    #[test]
+    fn prices_are_found_by_their_path() {
+        let json = Json::from_str(r#"{"data": {"amount": "64123.50"}, "quotes": [{"price": 1.25}, {"price": 2}]}"#).unwrap();
+        assert_eq!(find_price(&json, "data.amount"), Some(64123.5));
+        assert_eq!(find_price(&json, "quotes.0.price"), Some(1.25));
+        assert_eq!(find_price(&json, "quotes.1.price"), Some(2.0));
+        assert_eq!(find_price(&json, "quotes.2.price"), None);
+        assert_eq!(find_price(&json, "quotes.first.price"), None);
+        assert_eq!(find_price(&json, "data"), None);
+    }
+
+    #[test]
+    fn configuration_is_checked() {
+        let parse = |src: &str| toml::Parser::new(src).parse().unwrap();
+        assert!(super::new("price", &parse("url = \"http://localhost\"")).is_err());
+        assert!(super::new("price", &parse("path = \"data.amount\"")).is_err());
+        assert!(super::new("price", &parse("url = \"http://localhost\"\npath = \"data.amount\"\ndecimals = 17")).is_err());
+    }
+}
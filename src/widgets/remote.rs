@@ -0,0 +1,78 @@
+//! A widget showing text pushed to it through the control socket, with
+//! `admiral ctl set <section> <text>`, so that other programs can use a slot
+//! of the bar without Admiral running anything for it.
+
+use std::time::Duration;
+
+use toml;
+
+use Outcome;
+use super::{fill, string, Widget};
+
+struct Remote {
+    format: String,
+    initial: String,
+    text: Option<String>,
+    changed: bool,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Remote {
+        format: string(section_name, configuration, "format", "{text}")?,
+        initial: string(section_name, configuration, "initial", "")?,
+        text: None,
+        changed: true,
+    }))
+}
+
+impl Widget for Remote {
+    /// Shows the text whenever it's set.
+    fn poll(&mut self) -> Option<Outcome> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        Some(Outcome::Success(match self.text {
+            Some(ref text) if !text.is_empty() => fill(&self.format, &[("text", text.clone())]),
+            Some(_) => String::new(),
+            None => self.initial.clone(),
+        }))
+    }
+
+    /// The text only changes with an action, which wakes the section anyway.
+    fn wait(&self) -> Duration {
+        Duration::from_secs(3600)
+    }
+
+    /// `set <text>` shows the text, and `clear` hides the section.
+    fn action(&mut self, action: &str) {
+        let text = match action.split_once(' ') {
+            Some(("set", text)) => text,
+            None if action == "set" || action == "clear" => "",
+            _ => return,
+        };
+        self.text = Some(text.to_owned());
+        self.changed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+
+    use Outcome;
+
+    #[test]
+    fn set_text_is_shown_until_cleared() {
+        let configuration = toml::Parser::new("format = \"[{text}]\"\ninitial = \"waiting\"").parse().unwrap();
+        let mut remote = super::new("remote", &configuration).unwrap();
+        assert_eq!(remote.poll(), Some(Outcome::Success(String::from("waiting"))));
+        assert_eq!(remote.poll(), None);
+        remote.action("set recording 2 min");
+        assert_eq!(remote.poll(), Some(Outcome::Success(String::from("[recording 2 min]"))));
+        remote.action("unknown");
+        assert_eq!(remote.poll(), None);
+        remote.action("clear");
+        assert_eq!(remote.poll(), Some(Outcome::Success(String::new())));
+    }
+}
@@ -0,0 +1,65 @@
+//! Listening for changes to the network interfaces on an rtnetlink socket,
+//! for widgets that show something about them.
+
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+
+use libc;
+
+use Event;
+
+/// Multicast groups to listen to: links, and IPv4 and IPv6 addresses, changing.
+pub const RTMGRP_LINK: u32 = 1;
+pub const RTMGRP_IPV4_IFADDR: u32 = 0x10;
+pub const RTMGRP_IPV6_IFADDR: u32 = 0x100;
+
+/// An rtnetlink socket listening to some groups, whose thread wakes the
+/// widget when anything is sent to them. As a netlink socket can't be shut
+/// down, the thread checks every few seconds whether this has been dropped.
+pub struct Links {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Links {
+    pub fn start(groups: u32, wake: Sender<Event>) -> Option<Links> {
+        let socket = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_ROUTE) };
+        if socket < 0 {
+            return None;
+        }
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = groups;
+        let timeout = libc::timeval { tv_sec: 5, tv_usec: 0 };
+        let bound = unsafe {
+            libc::setsockopt(socket, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout as *const _ as *const libc::c_void, mem::size_of::<libc::timeval>() as libc::socklen_t);
+            libc::bind(socket, &address as *const _ as *const libc::sockaddr, mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+        };
+        if bound < 0 {
+            unsafe { libc::close(socket) };
+            return None;
+        }
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let links = Links { stopped: stopped.clone() };
+        thread::spawn(move || {
+            let mut buffer = [0u8; 8192];
+            while !stopped.load(Ordering::SeqCst) {
+                let received = unsafe { libc::recv(socket, buffer.as_mut_ptr() as *mut libc::c_void, buffer.len(), 0) };
+                if received > 0 && wake.send(Event::Wake).is_err() {
+                    break;
+                }
+            }
+            unsafe { libc::close(socket) };
+        });
+        Some(links)
+    }
+}
+
+impl Drop for Links {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,146 @@
+//! A widget reporting inode usage of a filesystem and, optionally,
+//! the SMART health of the disk behind it.
+//!
+//! SMART health is read with `smartctl -H`, which usually needs root, so it
+//! is checked far less often than inode usage.
+
+use std::ffi::CString;
+use std::mem;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use libc;
+use toml;
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Health {
+    Healthy,
+    Failing,
+    Unknown,
+}
+
+#[derive(Debug)]
+struct Storage {
+    mount: CString,
+    device: Option<String>,
+    format: String,
+    interval: Duration,
+    smart_interval: Duration,
+    healthy: String,
+    failing: String,
+    unknown: String,
+    health: Health,
+    last_smart_check: Option<Instant>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Storage::from_config(section_name, configuration)?))
+}
+
+impl Storage {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Storage, String> {
+        let mount = string(section_name, configuration, "mount", "/")?;
+        let device = match configuration.get("device") {
+            Some(_) => Some(string(section_name, configuration, "device", "")?),
+            None => None,
+        };
+
+        Ok(Storage {
+            mount: CString::new(mount).map_err(|_| format!("Invalid mount found for {}", section_name))?,
+            device,
+            format: string(section_name, configuration, "format", "{inodes_percent}%{smart}")?,
+            interval: seconds(section_name, configuration, "reload", 60f64)?,
+            smart_interval: seconds(section_name, configuration, "smart_reload", 3600f64)?,
+            healthy: string(section_name, configuration, "healthy", "")?,
+            failing: string(section_name, configuration, "failing", " SMART FAILING")?,
+            unknown: string(section_name, configuration, "unknown", "")?,
+            health: Health::Unknown,
+            last_smart_check: None,
+        })
+    }
+}
+
+impl Widget for Storage {
+    fn poll(&mut self) -> Option<Outcome> {
+        let now = Instant::now();
+        if let Some(ref device) = self.device {
+            if self.last_smart_check.is_none_or(|last_check| now - last_check >= self.smart_interval) {
+                self.last_smart_check = Some(now);
+                self.health = smart_health(device);
+            }
+        }
+
+        let (used, total) = match inodes(&self.mount) {
+            Some(inodes) => inodes,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let percent = (used * 100).checked_div(total).unwrap_or(0);
+        let smart = match self.health {
+            Health::Healthy => &self.healthy,
+            Health::Failing => &self.failing,
+            Health::Unknown => &self.unknown,
+        };
+
+        Some(Outcome::Success(fill(&self.format, &[
+            ("inodes_used", used.to_string()),
+            ("inodes_total", total.to_string()),
+            ("inodes_free", (total - used).to_string()),
+            ("inodes_percent", percent.to_string()),
+            ("smart", smart.to_owned()),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Returns the used and total inode counts of the filesystem mounted at `mount`.
+fn inodes(mount: &CString) -> Option<(u64, u64)> {
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(mount.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let total = stat.f_files as u64;
+    Some((total.saturating_sub(stat.f_ffree as u64), total))
+}
+
+fn smart_health(device: &str) -> Health {
+    let output = match Command::new("smartctl").args(["-H", device]).output() {
+        Ok(output) => output,
+        Err(_) => return Health::Unknown,
+    };
+
+    health(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Reads the overall health from the output of `smartctl -H`.
+fn health(output: &str) -> Health {
+    // ATA and NVMe disks report PASSED or FAILED, while SCSI disks report OK
+    let result = output.lines()
+        .find(|line| line.contains("overall-health") || line.contains("Health Status"))
+        .and_then(|line| line.rsplit(':').next())
+        .map(str::trim);
+    match result {
+        Some("PASSED") | Some("OK") => Health::Healthy,
+        Some(_) => Health::Failing,
+        None => Health::Unknown,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{health, Health};
+
+    #[test]
+    fn smartctl_output_gives_the_health() {
+        assert_eq!(health("=== START OF READ SMART DATA SECTION ===\nSMART overall-health self-assessment test result: PASSED\n"), Health::Healthy);
+        assert_eq!(health("SMART overall-health self-assessment test result: FAILED!\n"), Health::Failing);
+        assert_eq!(health("SMART Health Status: OK\n"), Health::Healthy);
+        assert_eq!(health("Smartctl open device: /dev/sda failed: Permission denied\n"), Health::Unknown);
+    }
+}
@@ -0,0 +1,86 @@
+//! A widget showing swap usage, read from `/proc/meminfo`.
+
+use std::fs;
+use std::time::Duration;
+
+use toml::{self, Value};
+
+use Outcome;
+use super::memory::{field, Unit};
+use super::{fill, seconds, string, Widget};
+
+#[derive(Debug)]
+struct Swap {
+    format: String,
+    unit: Unit,
+    /// Whether to show nothing while no swap is in use.
+    hide_unused: bool,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let hide_unused = match configuration.get("hide_unused") {
+        Some(&Value::Boolean(hide_unused)) => hide_unused,
+        Some(_) => return Err(format!("Invalid hide_unused found for {}", section_name)),
+        None => false,
+    };
+
+    Ok(Box::new(Swap {
+        format: string(section_name, configuration, "format", "{used}/{total}")?,
+        unit: Unit::from_config(section_name, configuration)?,
+        hide_unused,
+        interval: seconds(section_name, configuration, "reload", 5f64)?,
+    }))
+}
+
+impl Swap {
+    /// Shows the swap usage read from the contents of `/proc/meminfo`.
+    fn render(&self, meminfo: &str) -> Outcome {
+        let (total, free) = match (field(meminfo, "SwapTotal"), field(meminfo, "SwapFree")) {
+            (Some(total), Some(free)) => (total, free),
+            _ => return Outcome::Failure(None),
+        };
+        let used = total.saturating_sub(free);
+        if used == 0 && self.hide_unused {
+            return Outcome::Success(String::new());
+        }
+
+        let unit = self.unit;
+        Outcome::Success(fill(&self.format, &[
+            ("used", unit.format(used, total)),
+            ("free", unit.format(free, total)),
+            ("total", unit.format(total, total)),
+            ("percent", Unit::Percent.format(used, total)),
+        ]))
+    }
+}
+
+impl Widget for Swap {
+    fn poll(&mut self) -> Option<Outcome> {
+        Some(self.render(&fs::read_to_string("/proc/meminfo").unwrap_or_default()))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+
+    use Outcome;
+    use super::{Swap, Unit};
+
+    #[test]
+    fn unused_swap_can_be_hidden() {
+        let mut swap = Swap { format: String::from("{used}/{total} {percent}%"), unit: Unit::MiB, hide_unused: true, interval: Default::default() };
+        let meminfo = "MemTotal:       16303832 kB\nSwapTotal:       8388604 kB\nSwapFree:        6291452 kB\n";
+        assert_eq!(swap.render(meminfo), Outcome::Success(String::from("2048/8191 25%")));
+        assert_eq!(swap.render("SwapTotal:       8388604 kB\nSwapFree:        8388604 kB\n"), Outcome::Success(String::new()));
+        swap.hide_unused = false;
+        assert_eq!(swap.render("SwapTotal:       0 kB\nSwapFree:        0 kB\n"), Outcome::Success(String::from("0/0 0%")));
+        assert_eq!(swap.render("MemTotal:       16303832 kB\n"), Outcome::Failure(None));
+        assert!(super::new("swap", &toml::Parser::new("hide_unused = \"yes\"").parse().unwrap()).is_err());
+    }
+}
@@ -0,0 +1,135 @@
+//! A widget showing the state of a systemd unit, or how many units have
+//! failed.
+//!
+//! systemd is asked over D-Bus to send out its signals, which wake the widget
+//! when the unit (or any unit, for the failed count) changes, and the state is
+//! read with `systemctl`. With `user = true`, the user's own manager is watched
+//! instead of the system's.
+
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use toml::{self, Value};
+
+use {Event, Outcome};
+use super::dbus::{Bus, Call, Signals};
+use super::{fill, run, seconds, string, Widget};
+
+const MANAGER: Call<'static> = ("org.freedesktop.systemd1", "/org/freedesktop/systemd1", "org.freedesktop.systemd1.Manager", "Subscribe");
+
+struct Systemd {
+    /// The unit to show, or `None` to count the failed units.
+    unit: Option<String>,
+    user: bool,
+    format: String,
+    failed_format: String,
+    none: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    signals: Option<Signals>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let unit = match configuration.get("unit") {
+        Some(_) => Some(string(section_name, configuration, "unit", "")?),
+        None => None,
+    };
+    let user = match configuration.get("user") {
+        Some(&Value::Boolean(user)) => user,
+        Some(_) => return Err(format!("Invalid user found for {}", section_name)),
+        None => false,
+    };
+    let format = match unit {
+        Some(_) => string(section_name, configuration, "format", "{unit}: {state}")?,
+        None => string(section_name, configuration, "format", "{count} failed")?,
+    };
+
+    Ok(Box::new(Systemd {
+        unit,
+        user,
+        failed_format: string(section_name, configuration, "failed_format", &format)?,
+        format,
+        none: string(section_name, configuration, "none", "")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        signals: None,
+    }))
+}
+
+/// The object path systemd gives a unit, in which anything but letters and
+/// digits is escaped as `_` and two hex digits.
+fn unit_path(unit: &str) -> String {
+    let mut path = String::from("/org/freedesktop/systemd1/unit/");
+    for byte in unit.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => path.push(byte as char),
+            _ => path.push_str(&format!("_{:02x}", byte)),
+        }
+    }
+    path
+}
+
+impl Systemd {
+    fn systemctl(&self, arguments: &[&str]) -> Option<String> {
+        let mut all = Vec::with_capacity(arguments.len() + 1);
+        if self.user {
+            all.push("--user");
+        }
+        all.extend_from_slice(arguments);
+        run("systemctl", &all)
+    }
+}
+
+impl Widget for Systemd {
+    /// Polled after the unit's properties change, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        match self.unit {
+            Some(ref unit) => {
+                let state = match self.systemctl(&["show", "--property=ActiveState", "--value", "--", unit]) {
+                    Some(state) => state.trim().to_owned(),
+                    None => return Some(Outcome::Failure(None)),
+                };
+                let format = if state == "failed" { &self.failed_format } else { &self.format };
+                Some(Outcome::Success(fill(format, &[("unit", unit.clone()), ("state", state)])))
+            },
+            None => {
+                let failed = match self.systemctl(&["list-units", "--state=failed", "--plain", "--no-legend", "--no-pager"]) {
+                    Some(failed) => failed.lines().filter(|line| !line.trim().is_empty()).count(),
+                    None => return Some(Outcome::Failure(None)),
+                };
+                Some(Outcome::Success(match failed {
+                    0 => self.none.clone(),
+                    _ => fill(&self.format, &[("count", failed.to_string())]),
+                }))
+            },
+        }
+    }
+
+    /// Falls back to polling every second when systemd's signals can't be had.
+    fn wait(&self) -> Duration {
+        match self.signals {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        let bus = if self.user { Bus::Session } else { Bus::System };
+        let rule = match self.unit {
+            Some(ref unit) => format!("type='signal',sender='org.freedesktop.systemd1',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged',path='{}'", unit_path(unit)),
+            None => String::from("type='signal',sender='org.freedesktop.systemd1',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'"),
+        };
+        self.signals = Signals::start(bus, &[&rule], &[MANAGER], wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unit_path;
+
+    #[test]
+    fn unit_names_are_escaped_into_object_paths() {
+        assert_eq!(unit_path("sshd.service"), "/org/freedesktop/systemd1/unit/sshd_2eservice");
+        assert_eq!(unit_path("getty@tty1.service"), "/org/freedesktop/systemd1/unit/getty_40tty1_2eservice");
+        assert_eq!(unit_path("systemd-journald.service"), "/org/freedesktop/systemd1/unit/systemd_2djournald_2eservice");
+    }
+}
@@ -0,0 +1,139 @@
+//! A widget showing the last line of a file, for tools that write their
+//! status to one.
+//!
+//! The file's directory is watched with inotify rather than the file itself,
+//! so that a file which is replaced (as editors and many tools do, writing a
+//! new file and renaming it over the old) or which doesn't exist yet is still
+//! followed.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use {Event, Outcome};
+use super::inotify::Watch;
+use super::{fill, seconds, string, Widget};
+
+/// How much of the end of the file to read at a time while looking for its last line.
+const CHUNK: u64 = 4096;
+/// The longest a last line may be, so a file without newlines isn't read whole.
+const LONGEST_LINE: u64 = 64 * 1024;
+
+struct Tail {
+    path: PathBuf,
+    format: String,
+    missing: String,
+    /// How often to read the file anyway, in case a change is missed.
+    interval: Duration,
+    watch: Option<Watch>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let path = string(section_name, configuration, "path", "")?;
+    if path.is_empty() {
+        return Err(format!("Failed to find path for {}", section_name));
+    }
+
+    Ok(Box::new(Tail {
+        path: PathBuf::from(path),
+        format: string(section_name, configuration, "format", "{line}")?,
+        missing: string(section_name, configuration, "missing", "")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        watch: None,
+    }))
+}
+
+/// Reads the last line of a file that isn't empty, from its end.
+fn last_line(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let length = file.seek(SeekFrom::End(0)).ok()?;
+    let mut tail = Vec::new();
+    let mut start = length;
+    while start > 0 && length - start < LONGEST_LINE {
+        let read = CHUNK.min(start);
+        start -= read;
+        let mut chunk = vec![0u8; read as usize];
+        file.seek(SeekFrom::Start(start)).ok()?;
+        file.read_exact(&mut chunk).ok()?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+
+        // A newline before the last line's text means it's all been read
+        let text = tail.iter().rposition(|&byte| !byte.is_ascii_whitespace());
+        if text.is_some_and(|text| tail[..text].contains(&b'\n')) {
+            break;
+        }
+    }
+
+    let tail = String::from_utf8_lossy(&tail);
+    Some(tail.lines().rev().find(|line| !line.trim().is_empty()).unwrap_or_default().to_owned())
+}
+
+impl Widget for Tail {
+    /// Polled whenever something in the file's directory changes, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        Some(Outcome::Success(match last_line(&self.path) {
+            Some(line) => fill(&self.format, &[("line", line)]),
+            None => self.missing.clone(),
+        }))
+    }
+
+    /// Falls back to polling every second when the file's directory couldn't be watched.
+    fn wait(&self) -> Duration {
+        match self.watch {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        let directory = match self.path.parent() {
+            Some(directory) if directory != Path::new("") => directory,
+            _ => Path::new("."),
+        };
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_MODIFY | libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO;
+        self.watch = Watch::start(&[directory], mask, |_| true, wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use toml;
+
+    use Outcome;
+    use super::last_line;
+
+    #[test]
+    fn the_last_line_with_text_is_shown() {
+        let directory = env::temp_dir().join(format!("admiral-test-tail-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("status");
+
+        fs::write(&path, "first\nsecond\n\n  \n").unwrap();
+        assert_eq!(last_line(&path), Some(String::from("second")));
+        // Longer than one chunk, so it's read in pieces from the end
+        let long = "x".repeat(10000);
+        fs::write(&path, format!("first\n{}\n", long)).unwrap();
+        assert_eq!(last_line(&path), Some(long));
+        fs::write(&path, "").unwrap();
+        assert_eq!(last_line(&path), Some(String::new()));
+
+        let src = format!("path = \"{}\"\nformat = \"[{{line}}]\"\nmissing = \"gone\"", path.display());
+        let mut tail = super::new("tail", &toml::Parser::new(&src).parse().unwrap()).unwrap();
+        fs::write(&path, "one\ntwo").unwrap();
+        assert_eq!(tail.poll(), Some(Outcome::Success(String::from("[two]"))));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tail.poll(), Some(Outcome::Success(String::from("gone"))));
+
+        let _ = fs::remove_dir_all(&directory);
+    }
+}
@@ -0,0 +1,175 @@
+//! A widget showing how many tasks are pending, in Taskwarrior or a todo.txt
+//! file.
+//!
+//! Rather than counting on a timer, the widget watches where the tasks are
+//! kept with inotify, and counts again when they change. Taskwarrior's tasks
+//! are counted from `task export`, with garbage collection, recurrence, and
+//! hooks turned off so that counting doesn't write to the data it watches.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use libc;
+use rustc_serialize::json::Json;
+use toml;
+
+use {Event, Outcome};
+use super::inotify::Watch;
+use super::{fill, seconds, string, Widget};
+
+enum Backend {
+    /// Taskwarrior, with its data directory and a filter for the tasks to count.
+    Taskwarrior(PathBuf, String),
+    TodoTxt(PathBuf),
+}
+
+struct Tasks {
+    backend: Backend,
+    format: String,
+    none: String,
+    /// How often to count anyway, in case a change is missed.
+    interval: Duration,
+    watch: Option<Watch>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let backend = match string(section_name, configuration, "backend", "taskwarrior")?.as_str() {
+        "taskwarrior" => {
+            let data = match configuration.get("data") {
+                Some(_) => Some(PathBuf::from(string(section_name, configuration, "data", "")?)),
+                None => env::var_os("TASKDATA").map(PathBuf::from)
+                    .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".task"))),
+            };
+            let data = data.ok_or_else(|| format!("Failed to find Taskwarrior's data for {}; set its data", section_name))?;
+            Backend::Taskwarrior(data, string(section_name, configuration, "filter", "")?)
+        },
+        "todotxt" => {
+            let path = string(section_name, configuration, "path", "")?;
+            if path.is_empty() {
+                return Err(format!("Failed to find path for {}", section_name));
+            }
+            Backend::TodoTxt(PathBuf::from(path))
+        },
+        _ => return Err(format!("Invalid backend found for {}: expected \"taskwarrior\" or \"todotxt\"", section_name)),
+    };
+
+    Ok(Box::new(Tasks {
+        backend,
+        format: string(section_name, configuration, "format", "{count} tasks")?,
+        none: string(section_name, configuration, "none", "")?,
+        interval: seconds(section_name, configuration, "reload", 300f64)?,
+        watch: None,
+    }))
+}
+
+fn count_taskwarrior(data: &Path, filter: &str) -> Option<usize> {
+    let output = Command::new("task")
+        .arg(format!("rc.data.location={}", data.display()))
+        .args(["rc.hooks=off", "rc.gc=off", "rc.recurrence=off", "rc.verbose=nothing", "rc.json.array=on", "status:pending"])
+        .args(filter.split_whitespace())
+        .arg("export")
+        .output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let tasks = Json::from_str(&String::from_utf8_lossy(&output.stdout)).ok()?;
+    Some(tasks.as_array()?.len())
+}
+
+/// Counts the lines of a todo.txt file, leaving out blank lines and completed
+/// tasks, which start with `x `.
+fn count_todo_txt(path: &Path) -> Option<usize> {
+    let todo = fs::read_to_string(path).ok()?;
+    Some(todo.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with("x "))
+        .count())
+}
+
+/// Whether a file in Taskwarrior's data directory holds tasks: `pending.data`
+/// and `completed.data` before Taskwarrior 3, and its SQLite database after.
+fn is_task_data(name: &str) -> bool {
+    name.ends_with(".data") || name.starts_with("taskchampion.sqlite3")
+}
+
+impl Widget for Tasks {
+    /// Polled whenever the tasks change, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let count = match self.backend {
+            Backend::Taskwarrior(ref data, ref filter) => count_taskwarrior(data, filter),
+            Backend::TodoTxt(ref path) => count_todo_txt(path),
+        };
+        match count {
+            Some(0) => Some(Outcome::Success(self.none.clone())),
+            Some(count) => Some(Outcome::Success(fill(&self.format, &[("count", count.to_string())]))),
+            None => Some(Outcome::Failure(None)),
+        }
+    }
+
+    /// Falls back to polling every second when the tasks couldn't be watched.
+    fn wait(&self) -> Duration {
+        match self.watch {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        // Files are watched through their directory, as they're often replaced rather than written to
+        let mask = libc::IN_MODIFY | libc::IN_CLOSE_WRITE | libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_TO;
+        self.watch = match self.backend {
+            // Taskwarrior 3 opens its database for writing even to read it, so closing it can't count
+            Backend::Taskwarrior(ref data, _) => Watch::start(&[data], mask & !libc::IN_CLOSE_WRITE, is_task_data, wake),
+            Backend::TodoTxt(ref path) => {
+                let directory = match path.parent() {
+                    Some(directory) if directory != Path::new("") => directory,
+                    _ => Path::new("."),
+                };
+                Watch::start(&[directory], mask, |name| name.ends_with(".txt"), wake)
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use toml;
+
+    use Outcome;
+    use super::{count_todo_txt, is_task_data};
+
+    #[test]
+    fn pending_todo_txt_tasks_are_counted() {
+        let path = env::temp_dir().join(format!("admiral-test-tasks-{}.txt", process::id()));
+        fs::write(&path, "(A) call mum\nx 2024-05-01 pay rent\n\n  \nwater plants +home\n").unwrap();
+        assert_eq!(count_todo_txt(&path), Some(2));
+
+        let src = format!("backend = \"todotxt\"\npath = \"{}\"\nnone = \"done\"", path.display());
+        let mut tasks = super::new("tasks", &toml::Parser::new(&src).parse().unwrap()).unwrap();
+        assert_eq!(tasks.poll(), Some(Outcome::Success(String::from("2 tasks"))));
+        fs::write(&path, "x 2024-05-01 pay rent\n").unwrap();
+        assert_eq!(tasks.poll(), Some(Outcome::Success(String::from("done"))));
+        fs::remove_file(&path).unwrap();
+        assert_eq!(tasks.poll(), Some(Outcome::Failure(None)));
+    }
+
+    #[test]
+    fn taskwarrior_data_files_are_recognised() {
+        assert!(is_task_data("pending.data"));
+        assert!(is_task_data("taskchampion.sqlite3-wal"));
+        assert!(!is_task_data("taskrc"));
+    }
+
+    #[test]
+    fn unknown_backends_are_refused() {
+        let configuration = toml::Parser::new("backend = \"things\"").parse().unwrap();
+        assert!(super::new("tasks", &configuration).is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! A widget showing a temperature sensor's reading, read from `/sys/class/hwmon`.
+//!
+//! Sensors are picked by the name of their chip (such as `coretemp` or
+//! `k10temp`) and their label (such as `Package id 0` or `Tctl`), which stay
+//! the same across boots, unlike the numbering of the hwmon directories. That
+//! numbering can change when drivers are reloaded too, so the sensor is found
+//! again whenever it can't be read.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use toml::{self, Value};
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+pub const HWMON: &str = "/sys/class/hwmon";
+
+#[derive(Debug)]
+struct Temperature {
+    chip: Option<String>,
+    label: Option<String>,
+    /// The sensor's `temp*_input` file, once it's been found.
+    input: Option<PathBuf>,
+    fahrenheit: bool,
+    warning: Option<f64>,
+    critical: Option<f64>,
+    format: String,
+    warning_format: String,
+    critical_format: String,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Temperature::from_config(section_name, configuration)?))
+}
+
+pub fn read(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|value| value.trim().to_owned())
+}
+
+fn threshold(section_name: &str, configuration: &toml::Table, key: &str) -> Result<Option<f64>, String> {
+    match configuration.get(key) {
+        Some(&Value::Integer(degrees)) => Ok(Some(degrees as f64)),
+        Some(&Value::Float(degrees)) => Ok(Some(degrees)),
+        Some(_) => Err(format!("Invalid {} found for {}", key, section_name)),
+        None => Ok(None),
+    }
+}
+
+/// Finds the input of the first sensor in `hwmon` whose chip and label match, where given.
+fn find_sensor(hwmon: &Path, chip: Option<&str>, label: Option<&str>) -> Option<PathBuf> {
+    let mut chips = fs::read_dir(hwmon).ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| chip.is_none() || read(&path.join("name")).as_deref() == chip)
+        .collect::<Vec<_>>();
+    chips.sort();
+
+    for chip in chips {
+        let entries = match fs::read_dir(&chip) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut inputs = entries.filter_map(Result::ok)
+            .map(|entry| entry.file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("temp") && name.ends_with("_input"))
+            .collect::<Vec<_>>();
+        // temp2 before temp10
+        inputs.sort_by_key(|name| name.trim_start_matches("temp").trim_end_matches("_input").parse::<u32>().unwrap_or(0));
+
+        for input in inputs {
+            let label_file = chip.join(input.replace("_input", "_label"));
+            if label.is_none() || read(&label_file).as_deref() == label {
+                return Some(chip.join(input));
+            }
+        }
+    }
+    None
+}
+
+impl Temperature {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Temperature, String> {
+        let chip = match configuration.get("chip") {
+            Some(_) => Some(string(section_name, configuration, "chip", "")?),
+            None => None,
+        };
+        let label = match configuration.get("label") {
+            Some(_) => Some(string(section_name, configuration, "label", "")?),
+            None => None,
+        };
+
+        let fahrenheit = match string(section_name, configuration, "unit", "C")?.as_str() {
+            "C" => false,
+            "F" => true,
+            _ => return Err(format!("Invalid unit found for {}: expected \"C\" or \"F\"", section_name)),
+        };
+        let format = string(section_name, configuration, "format", if fahrenheit { "{temp}°F" } else { "{temp}°C" })?;
+
+        Ok(Temperature {
+            input: find_sensor(Path::new(HWMON), chip.as_deref(), label.as_deref()),
+            chip,
+            label,
+            fahrenheit,
+            warning: threshold(section_name, configuration, "warning")?,
+            critical: threshold(section_name, configuration, "critical")?,
+            warning_format: string(section_name, configuration, "warning_format", &format)?,
+            critical_format: string(section_name, configuration, "critical_format", &format)?,
+            format,
+            interval: seconds(section_name, configuration, "reload", 5f64)?,
+        })
+    }
+}
+
+impl Widget for Temperature {
+    fn poll(&mut self) -> Option<Outcome> {
+        let reading = |input: &Option<PathBuf>| read(input.as_ref()?)?.parse::<f64>().ok();
+        // hwmon reports millidegrees Celsius
+        let millidegrees = match reading(&self.input) {
+            Some(millidegrees) => millidegrees,
+            None => {
+                self.input = find_sensor(Path::new(HWMON), self.chip.as_deref(), self.label.as_deref());
+                match reading(&self.input) {
+                    Some(millidegrees) => millidegrees,
+                    None => return Some(Outcome::Failure(None)),
+                }
+            },
+        };
+        let mut degrees = millidegrees / 1000f64;
+        if self.fahrenheit {
+            degrees = degrees * 9f64 / 5f64 + 32f64;
+        }
+
+        // Thresholds are in the same unit as the reading
+        let format = match (self.warning, self.critical) {
+            (_, Some(critical)) if degrees >= critical => &self.critical_format,
+            (Some(warning), _) if degrees >= warning => &self.warning_format,
+            _ => &self.format,
+        };
+        Some(Outcome::Success(fill(format, &[("temp", format!("{:.0}", degrees))])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::find_sensor;
+
+    #[test]
+    fn sensors_are_found_by_chip_and_label() {
+        let hwmon = env::temp_dir().join(format!("admiral-test-hwmon-{}", process::id()));
+        let chip = |number: u32, name: &str, sensors: &[(u32, &str)]| {
+            let path = hwmon.join(format!("hwmon{}", number));
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("name"), format!("{}\n", name)).unwrap();
+            for &(sensor, label) in sensors {
+                fs::write(path.join(format!("temp{}_input", sensor)), "45000\n").unwrap();
+                fs::write(path.join(format!("temp{}_label", sensor)), format!("{}\n", label)).unwrap();
+            }
+            path
+        };
+        let acpi = chip(0, "acpitz", &[(1, "")]);
+        let core = chip(1, "coretemp", &[(10, "Core 1"), (2, "Core 0"), (1, "Package id 0")]);
+
+        assert_eq!(find_sensor(&hwmon, None, None), Some(acpi.join("temp1_input")));
+        assert_eq!(find_sensor(&hwmon, Some("coretemp"), None), Some(core.join("temp1_input")));
+        assert_eq!(find_sensor(&hwmon, None, Some("Core 1")), Some(core.join("temp10_input")));
+        assert_eq!(find_sensor(&hwmon, Some("acpitz"), Some("Core 0")), None);
+        assert_eq!(find_sensor(&hwmon, Some("k10temp"), None), None);
+        let _ = fs::remove_dir_all(&hwmon);
+    }
+}
@@ -0,0 +1,51 @@
+//! A widget showing fixed text, for separators, labels, and icons, without
+//! running a command to print it.
+
+use std::time::Duration;
+
+use toml;
+
+use Outcome;
+use super::{string, Widget};
+
+struct Text {
+    text: String,
+    shown: bool,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Text {
+        text: string(section_name, configuration, "text", "")?,
+        shown: false,
+    }))
+}
+
+impl Widget for Text {
+    /// Shows the text once; it only changes when the section is reconfigured.
+    fn poll(&mut self) -> Option<Outcome> {
+        if self.shown {
+            return None;
+        }
+        self.shown = true;
+        Some(Outcome::Success(self.text.clone()))
+    }
+
+    fn wait(&self) -> Duration {
+        Duration::from_secs(3600)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toml;
+
+    use Outcome;
+
+    #[test]
+    fn text_is_shown_once() {
+        let configuration = toml::Parser::new("text = \"|\"").parse().unwrap();
+        let mut text = super::new("separator", &configuration).unwrap();
+        assert_eq!(text.poll(), Some(Outcome::Success(String::from("|"))));
+        assert_eq!(text.poll(), None);
+    }
+}
@@ -0,0 +1,336 @@
+//! A pomodoro timer, alternating work sessions with breaks, or with no breaks,
+//! a plain countdown.
+//!
+//! The timer is controlled with `admiral ctl action <section> <action>`, which
+//! a bar's click areas can run. Its state is saved to
+//! `$XDG_STATE_HOME/admiral`, so restarting Admiral carries on from where the
+//! timer was, counting the time it was away.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use toml::{self, Value};
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Phase {
+    Work,
+    Break,
+    LongBreak,
+}
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Work => "work",
+            Phase::Break => "break",
+            Phase::LongBreak => "long break",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Idle,
+    /// Running until the given time.
+    Running(SystemTime),
+    /// Paused with the given time left.
+    Paused(Duration),
+}
+
+#[derive(Debug)]
+struct Timer {
+    work: Duration,
+    short_break: Duration,
+    long_break: Duration,
+    /// How many work sessions come before a long break, or 0 for none.
+    long_break_after: u32,
+    idle: String,
+    running: String,
+    paused: String,
+    on_end: Option<String>,
+    state_file: Option<PathBuf>,
+    phase: Phase,
+    state: State,
+    /// Work sessions finished since the timer was last reset.
+    sessions: u32,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let long_break_after = match configuration.get("long_break_after") {
+        Some(&Value::Integer(sessions)) if sessions >= 0 => sessions as u32,
+        Some(_) => return Err(format!("Invalid long_break_after found for {}: expected a number of sessions", section_name)),
+        None => 4,
+    };
+    let on_end = match configuration.get("on_end") {
+        Some(_) => Some(string(section_name, configuration, "on_end", "")?),
+        None => None,
+    };
+
+    let mut timer = Timer {
+        work: seconds(section_name, configuration, "work", 1500f64)?,
+        short_break: seconds(section_name, configuration, "break", 300f64)?,
+        long_break: seconds(section_name, configuration, "long_break", 900f64)?,
+        long_break_after,
+        idle: string(section_name, configuration, "idle", "{phase}")?,
+        running: string(section_name, configuration, "running", "{phase} {remaining}")?,
+        paused: string(section_name, configuration, "paused", "{phase} {remaining} (paused)")?,
+        on_end,
+        state_file: state_file(section_name),
+        phase: Phase::Work,
+        state: State::Idle,
+        sessions: 0,
+    };
+    timer.load();
+    Ok(Box::new(timer))
+}
+
+fn state_file(section_name: &str) -> Option<PathBuf> {
+    let state_home = env::var_os("XDG_STATE_HOME").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("state")))?;
+    Some(state_home.join("admiral").join(format!("timer-{}", section_name)))
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Formats a duration as `M:SS`, or `H:MM:SS` from an hour up.
+fn clock(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds / 3600 {
+        0 => format!("{}:{:02}", seconds / 60, seconds % 60),
+        hours => format!("{}:{:02}:{:02}", hours, seconds / 60 % 60, seconds % 60),
+    }
+}
+
+impl Timer {
+    fn length(&self, phase: Phase) -> Duration {
+        match phase {
+            Phase::Work => self.work,
+            Phase::Break => self.short_break,
+            Phase::LongBreak => self.long_break,
+        }
+    }
+
+    /// Time left in the current phase.
+    fn remaining(&self) -> Duration {
+        match self.state {
+            State::Idle => self.length(self.phase),
+            State::Running(end) => end.duration_since(SystemTime::now()).unwrap_or_default(),
+            State::Paused(left) => left,
+        }
+    }
+
+    /// Restores the state saved by an earlier run. It's saved as a line such
+    /// as `work 2 running 1700000000`, `break 3 paused 120`, or `work 0 idle`.
+    fn load(&mut self) {
+        let saved = match self.state_file.as_ref().and_then(|path| fs::read_to_string(path).ok()) {
+            Some(saved) => saved,
+            None => return,
+        };
+        let mut words = saved.split_whitespace();
+        let phase = match words.next() {
+            Some("work") => Phase::Work,
+            Some("break") => Phase::Break,
+            Some("long_break") => Phase::LongBreak,
+            _ => return,
+        };
+        let sessions = match words.next().and_then(|sessions| sessions.parse().ok()) {
+            Some(sessions) => sessions,
+            None => return,
+        };
+        let state = match (words.next(), words.next().and_then(|number| number.parse().ok())) {
+            (Some("idle"), _) => State::Idle,
+            (Some("running"), Some(end)) => State::Running(UNIX_EPOCH + Duration::from_secs(end)),
+            (Some("paused"), Some(left)) => State::Paused(Duration::from_secs(left)),
+            _ => return,
+        };
+        self.phase = phase;
+        self.sessions = sessions;
+        self.state = state;
+    }
+
+    fn save(&self) {
+        let path = match self.state_file {
+            Some(ref path) => path,
+            None => return,
+        };
+        let phase = match self.phase {
+            Phase::Work => "work",
+            Phase::Break => "break",
+            Phase::LongBreak => "long_break",
+        };
+        let state = match self.state {
+            State::Idle => String::from("idle"),
+            State::Running(end) => format!("running {}", unix_seconds(end)),
+            State::Paused(left) => format!("paused {}", left.as_secs()),
+        };
+        if let Some(directory) = path.parent() {
+            let _ = fs::create_dir_all(directory);
+        }
+        let _ = fs::write(path, format!("{} {} {}\n", phase, self.sessions, state));
+    }
+
+    /// Moves on to the phase after the current one, which starts running
+    /// straight away. Without breaks, the timer stops instead.
+    fn advance(&mut self) {
+        if let Some(ref on_end) = self.on_end {
+            let command = fill(on_end, &[("phase", self.phase.name().to_owned())]);
+            thread::spawn(move || {
+                if let Ok(mut child) = Command::new("sh").arg("-c").arg(&command).spawn() {
+                    let _ = child.wait();
+                }
+            });
+        }
+
+        if self.phase == Phase::Work {
+            self.sessions += 1;
+        }
+        self.phase = match self.phase {
+            Phase::Work if self.long_break_after > 0 && self.sessions.is_multiple_of(self.long_break_after) => Phase::LongBreak,
+            Phase::Work => Phase::Break,
+            _ => Phase::Work,
+        };
+        self.state = if self.length(self.phase) == Duration::from_secs(0) {
+            self.phase = Phase::Work;
+            State::Idle
+        } else {
+            State::Running(SystemTime::now() + self.length(self.phase))
+        };
+        self.save();
+    }
+}
+
+impl Widget for Timer {
+    fn poll(&mut self) -> Option<Outcome> {
+        if let State::Running(end) = self.state {
+            if SystemTime::now() >= end {
+                self.advance();
+            }
+        }
+
+        let format = match self.state {
+            State::Idle => &self.idle,
+            State::Running(_) => &self.running,
+            State::Paused(_) => &self.paused,
+        };
+        // Round up, so that the timer reads 0:00 only once it's finished
+        let remaining = self.remaining();
+        let remaining = Duration::from_secs(remaining.as_secs() + u64::from(remaining.subsec_nanos() > 0));
+        Some(Outcome::Success(fill(format, &[
+            ("phase", self.phase.name().to_owned()),
+            ("remaining", clock(remaining)),
+            ("sessions", self.sessions.to_string()),
+        ])))
+    }
+
+    /// Polls as each second ticks over while running, and otherwise only when an action arrives.
+    fn wait(&self) -> Duration {
+        match self.state {
+            // Just past the next whole second left, so it's shown as soon as it's reached
+            State::Running(_) => Duration::new(0, self.remaining().subsec_nanos()) + Duration::from_millis(1),
+            _ => Duration::from_secs(3600),
+        }
+    }
+
+    fn action(&mut self, action: &str) {
+        self.state = match (action, self.state) {
+            ("start", State::Idle) | ("toggle", State::Idle) => State::Running(SystemTime::now() + self.length(self.phase)),
+            ("start", State::Paused(left)) | ("toggle", State::Paused(left)) => State::Running(SystemTime::now() + left),
+            ("pause", State::Running(_)) | ("toggle", State::Running(_)) => State::Paused(self.remaining()),
+            ("skip", _) => {
+                self.advance();
+                return;
+            },
+            ("reset", _) => {
+                self.phase = Phase::Work;
+                self.sessions = 0;
+                State::Idle
+            },
+            (_, state) => state,
+        };
+        self.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::time::Duration;
+
+    use Outcome;
+    use widgets::Widget;
+    use super::{clock, Phase, State, Timer};
+
+    fn timer(state_file: Option<&str>) -> Timer {
+        let mut timer = Timer {
+            work: Duration::from_secs(60),
+            short_break: Duration::from_secs(10),
+            long_break: Duration::from_secs(30),
+            long_break_after: 2,
+            idle: String::from("{phase}"),
+            running: String::from("{phase} {remaining}"),
+            paused: String::from("{phase} {remaining} (paused)"),
+            on_end: None,
+            state_file: state_file.map(|name| env::temp_dir().join(format!("admiral-test-timer-{}-{}", name, process::id()))),
+            phase: Phase::Work,
+            state: State::Idle,
+            sessions: 0,
+        };
+        timer.load();
+        timer
+    }
+
+    #[test]
+    fn clocks_show_hours_only_when_needed() {
+        assert_eq!(clock(Duration::from_secs(0)), "0:00");
+        assert_eq!(clock(Duration::from_secs(1500)), "25:00");
+        assert_eq!(clock(Duration::from_secs(3723)), "1:02:03");
+    }
+
+    #[test]
+    fn every_few_sessions_earn_a_long_break() {
+        let mut timer = timer(None);
+        let mut phases = Vec::new();
+        for _ in 0..4 {
+            timer.advance();
+            phases.push(timer.phase);
+        }
+        assert_eq!(phases, [Phase::Break, Phase::Work, Phase::LongBreak, Phase::Work]);
+        assert_eq!(timer.sessions, 2);
+    }
+
+    #[test]
+    fn toggling_pauses_and_resumes() {
+        let mut timer = timer(None);
+        assert_eq!(timer.poll(), Some(Outcome::Success(String::from("work"))));
+        timer.action("toggle");
+        assert!(matches!(timer.state, State::Running(_)));
+        timer.action("toggle");
+        assert!(matches!(timer.state, State::Paused(_)));
+        assert_eq!(timer.poll(), Some(Outcome::Success(String::from("work 1:00 (paused)"))));
+        timer.action("reset");
+        assert_eq!(timer.state, State::Idle);
+    }
+
+    #[test]
+    fn state_survives_a_restart() {
+        let mut first = timer(Some("restart"));
+        first.action("skip");
+        first.action("pause");
+        let second = timer(Some("restart"));
+        let _ = fs::remove_file(second.state_file.as_ref().unwrap());
+        assert_eq!(second.phase, Phase::Break);
+        assert_eq!(second.sessions, 1);
+        assert!(matches!(second.state, State::Paused(_)));
+    }
+}
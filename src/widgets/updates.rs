@@ -0,0 +1,152 @@
+//! A widget showing how many package updates are pending, with pacman, apt,
+//! or dnf.
+//!
+//! Checking can take a while and may go to the network, so it's only done
+//! every `reload` seconds, and whenever the package manager is done changing
+//! what's installed, which is watched for with inotify: pacman removing its
+//! lock, dpkg replacing its status file, or rpm writing its database.
+
+use std::env;
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use {Event, Outcome};
+use super::inotify::Watch;
+use super::{fill, seconds, string, Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Pacman,
+    Apt,
+    Dnf,
+}
+
+struct Updates {
+    backend: Backend,
+    format: String,
+    none: String,
+    interval: Duration,
+    watch: Option<Watch>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let backend = match configuration.get("backend") {
+        Some(_) => match string(section_name, configuration, "backend", "")?.as_str() {
+            "pacman" => Backend::Pacman,
+            "apt" => Backend::Apt,
+            "dnf" => Backend::Dnf,
+            _ => return Err(format!("Invalid backend found for {}: expected \"pacman\", \"apt\", or \"dnf\"", section_name)),
+        },
+        None => detect().ok_or_else(|| format!("Failed to find a package manager for {}; set its backend", section_name))?,
+    };
+
+    Ok(Box::new(Updates {
+        backend,
+        format: string(section_name, configuration, "format", "{count} updates")?,
+        none: string(section_name, configuration, "none", "")?,
+        interval: seconds(section_name, configuration, "reload", 3600f64)?,
+        watch: None,
+    }))
+}
+
+fn on_path(program: &str) -> bool {
+    env::var_os("PATH").is_some_and(|path| env::split_paths(&path).any(|directory| directory.join(program).is_file()))
+}
+
+/// Picks the backend for whichever package manager is installed.
+fn detect() -> Option<Backend> {
+    [("checkupdates", Backend::Pacman), ("apt-get", Backend::Apt), ("dnf", Backend::Dnf)].iter()
+        .find(|&&(program, _)| on_path(program))
+        .map(|&(_, backend)| backend)
+}
+
+/// The directory each package manager changes once it's done, the kind of
+/// change, and which of the directory's entries it happens to.
+fn finished(backend: Backend) -> (&'static str, u32, fn(&str) -> bool) {
+    match backend {
+        Backend::Pacman => ("/var/lib/pacman", libc::IN_DELETE, |name| name == "db.lck"),
+        // dpkg writes status-new and moves it over the old one
+        Backend::Apt => ("/var/lib/dpkg", libc::IN_MOVED_TO, |name| name == "status"),
+        Backend::Dnf => ("/var/lib/rpm", libc::IN_CLOSE_WRITE, |name| name == "rpmdb.sqlite"),
+    }
+}
+
+/// Counts the pending updates, or returns `None` if the check failed.
+fn count(backend: Backend) -> Option<usize> {
+    let output = match backend {
+        // checkupdates syncs a copy of the databases, so it doesn't need root
+        Backend::Pacman => Command::new("checkupdates").output().ok()?,
+        // Simulating needs no lock, but relies on the lists being kept up to date, as apt's timers do
+        Backend::Apt => Command::new("apt-get").args(["-s", "-o", "Debug::NoLocking=true", "upgrade"]).output().ok()?,
+        Backend::Dnf => Command::new("dnf").args(["check-update", "-q"]).output().ok()?,
+    };
+    parse(backend, output.status.code(), &String::from_utf8_lossy(&output.stdout))
+}
+
+/// Counts the pending updates from what the package manager printed and the code it exited with.
+fn parse(backend: Backend, code: Option<i32>, stdout: &str) -> Option<usize> {
+    match backend {
+        // checkupdates exits with 2 when there are no updates
+        Backend::Pacman => match code {
+            Some(0) => Some(stdout.lines().filter(|line| !line.trim().is_empty()).count()),
+            Some(2) => Some(0),
+            _ => None,
+        },
+        Backend::Apt if code == Some(0) => Some(stdout.lines().filter(|line| line.starts_with("Inst ")).count()),
+        Backend::Apt => None,
+        // dnf exits with 100 when there are updates, listing one package a line,
+        // and then any packages they obsolete, which aren't counted
+        Backend::Dnf => match code {
+            Some(0) => Some(0),
+            Some(100) => Some(stdout.lines()
+                .take_while(|line| !line.starts_with("Obsoleting"))
+                .filter(|line| line.split_whitespace().count() == 3)
+                .count()),
+            _ => None,
+        },
+    }
+}
+
+impl Widget for Updates {
+    fn poll(&mut self) -> Option<Outcome> {
+        match count(self.backend) {
+            Some(0) => Some(Outcome::Success(self.none.clone())),
+            Some(count) => Some(Outcome::Success(fill(&self.format, &[("count", count.to_string())]))),
+            None => Some(Outcome::Failure(None)),
+        }
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        let (directory, mask, filter) = finished(self.backend);
+        self.watch = Watch::start(&[directory], mask, filter, wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse, Backend};
+
+    #[test]
+    fn each_package_manager_is_counted() {
+        assert_eq!(parse(Backend::Pacman, Some(0), "linux 6.9.1.arch1-1 -> 6.9.2.arch1-1\nmesa 1:24.1.0-1 -> 1:24.1.1-1\n"), Some(2));
+        assert_eq!(parse(Backend::Pacman, Some(2), ""), Some(0));
+        assert_eq!(parse(Backend::Pacman, Some(1), ""), None);
+
+        let apt = "Reading package lists...\nInst libc6 [2.36-9] (2.36-9+deb12u7 Debian:12.6/stable [amd64])\nInst tzdata [2024a-0] (2024a-0+deb12u1 Debian:12.6/stable [all])\nConf libc6 (2.36-9+deb12u7 Debian:12.6/stable [amd64])\n";
+        assert_eq!(parse(Backend::Apt, Some(0), apt), Some(2));
+        assert_eq!(parse(Backend::Apt, Some(100), apt), None);
+
+        let dnf = "\nkernel.x86_64    6.9.4-200.fc40    updates\nvim-minimal.x86_64    2:9.1.393-1.fc40    updates\nObsoleting Packages\ngrub2-tools.x86_64    1:2.06-121.fc40    updates\n";
+        assert_eq!(parse(Backend::Dnf, Some(100), dnf), Some(2));
+        assert_eq!(parse(Backend::Dnf, Some(0), ""), Some(0));
+        assert_eq!(parse(Backend::Dnf, Some(1), "Error: Failed to download metadata\n"), None);
+    }
+}
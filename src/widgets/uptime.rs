@@ -0,0 +1,62 @@
+//! A widget showing how long the system has been up, read from `/proc/uptime`.
+
+use std::fs;
+use std::time::Duration;
+
+use toml;
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+#[derive(Debug)]
+struct Uptime {
+    format: String,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Uptime {
+        format: string(section_name, configuration, "format", "{days}d {hours}h {minutes}m")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+    }))
+}
+
+impl Widget for Uptime {
+    fn poll(&mut self) -> Option<Outcome> {
+        let uptime = fs::read_to_string("/proc/uptime").unwrap_or_default();
+        match parts(&uptime) {
+            Some(parts) => Some(Outcome::Success(fill(&self.format, &parts))),
+            None => Some(Outcome::Failure(None)),
+        }
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+/// Splits the uptime read from the contents of `/proc/uptime` into days, hours, and minutes.
+fn parts(uptime: &str) -> Option<[(&'static str, String); 4]> {
+    // The first field is the uptime in seconds, the second the time spent idle
+    let seconds = uptime.split_whitespace().next()?.parse::<f64>().ok()? as u64;
+    let minutes = seconds / 60;
+    Some([
+        ("days", (minutes / (24 * 60)).to_string()),
+        ("hours", (minutes / 60 % 24).to_string()),
+        ("minutes", (minutes % 60).to_string()),
+        ("total_hours", (minutes / 60).to_string()),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use widgets::fill;
+    use super::parts;
+
+    #[test]
+    fn uptime_is_split_into_days_hours_and_minutes() {
+        let parts = parts("273845.67 1043981.20\n").unwrap();
+        assert_eq!(fill("{days}d {hours}h {minutes}m, {total_hours}h in all", &parts), "3d 4h 4m, 76h in all");
+        assert!(super::parts("").is_none());
+    }
+}
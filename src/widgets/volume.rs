@@ -0,0 +1,130 @@
+//! A widget showing the volume of the default output, and whether it is muted.
+//!
+//! Rather than polling, the widget watches for changes with `pactl subscribe`
+//! (which works with PipeWire as well as PulseAudio), or `alsactl monitor`
+//! for plain ALSA, and reads the volume again whenever one is reported.
+
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use toml;
+
+use {Event, Outcome};
+use super::{fill, first_percent, run, seconds, string, Subscription, Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Pulse,
+    Alsa,
+}
+
+struct Volume {
+    backend: Backend,
+    /// The ALSA mixer control to show.
+    control: String,
+    format: String,
+    muted_format: String,
+    /// How often to read the volume anyway, in case a change is missed.
+    interval: Duration,
+    subscription: Option<Subscription>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let backend = match string(section_name, configuration, "backend", "pulse")?.as_str() {
+        "pulse" => Backend::Pulse,
+        "alsa" => Backend::Alsa,
+        _ => return Err(format!("Invalid backend found for {}: expected \"pulse\" or \"alsa\"", section_name)),
+    };
+    let format = string(section_name, configuration, "format", "{volume}%")?;
+
+    Ok(Box::new(Volume {
+        backend,
+        control: string(section_name, configuration, "control", "Master")?,
+        muted_format: string(section_name, configuration, "muted_format", &format)?,
+        format,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        subscription: None,
+    }))
+}
+
+/// Reads the volume and mute state of the default sink with `pactl`.
+fn pulse() -> Option<(u32, bool)> {
+    let volume = first_percent(&run("pactl", &["get-sink-volume", "@DEFAULT_SINK@"])?)?;
+    let muted = run("pactl", &["get-sink-mute", "@DEFAULT_SINK@"])?.contains("yes");
+    Some((volume, muted))
+}
+
+/// Reads the volume and mute state of an ALSA mixer control with `amixer`.
+fn alsa(control: &str) -> Option<(u32, bool)> {
+    amixer(&run("amixer", &["get", control])?)
+}
+
+/// Reads the volume and mute state from the output of `amixer get`, from the first channel that has them.
+fn amixer(output: &str) -> Option<(u32, bool)> {
+    let line = output.lines().find(|line| line.contains('%'))?;
+    Some((first_percent(line)?, line.contains("[off]")))
+}
+
+/// Whether a line from `pactl subscribe` is about an output's volume, or the default output changing.
+fn is_pulse_sink_event(line: &str) -> bool {
+    line.contains(" on sink ") || line.contains(" on server")
+}
+
+impl Widget for Volume {
+    /// Polled after every change reported by the event monitor, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let state = match self.backend {
+            Backend::Pulse => pulse(),
+            Backend::Alsa => alsa(&self.control),
+        };
+        let (volume, muted) = match state {
+            Some(state) => state,
+            None => return Some(Outcome::Failure(None)),
+        };
+
+        let format = if muted { &self.muted_format } else { &self.format };
+        Some(Outcome::Success(fill(format, &[
+            ("volume", volume.to_string()),
+            ("muted", if muted { String::from("muted") } else { String::new() }),
+        ])))
+    }
+
+    /// Falls back to polling every second when the event monitor couldn't be started.
+    fn wait(&self) -> Duration {
+        match self.subscription {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.subscription = match self.backend {
+            Backend::Pulse => Subscription::start(Command::new("pactl").arg("subscribe"), wake, is_pulse_sink_event),
+            Backend::Alsa => Subscription::start(Command::new("alsactl").arg("monitor"), wake, |_| true),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{amixer, first_percent, is_pulse_sink_event};
+
+    #[test]
+    fn volumes_are_read_from_either_backend() {
+        assert_eq!(first_percent("Volume: front-left: 42597 /  65% / -11.23 dB,   front-right: 42597 /  65% / -11.23 dB"), Some(65));
+        assert_eq!(first_percent("no volume here"), None);
+        let output = "Simple mixer control 'Master',0\n  Capabilities: pvolume pswitch\n  Limits: Playback 0 - 87\n  Mono: Playback 70 [80%] [-12.75dB] [off]\n";
+        assert_eq!(amixer(output), Some((80, true)));
+        assert_eq!(amixer("  Front Left: Playback 65536 [100%] [on]\n"), Some((100, false)));
+        assert_eq!(amixer("Simple mixer control 'Capture',0\n"), None);
+    }
+
+    #[test]
+    fn only_sink_events_wake_the_widget() {
+        assert!(is_pulse_sink_event("Event 'change' on sink #48"));
+        assert!(is_pulse_sink_event("Event 'change' on server #-1"));
+        assert!(!is_pulse_sink_event("Event 'change' on sink-input #1203"));
+        assert!(!is_pulse_sink_event("Event 'new' on client #1207"));
+    }
+}
@@ -0,0 +1,163 @@
+//! A widget showing which VPNs are connected.
+//!
+//! By default, VPNs are found among the network interfaces: WireGuard
+//! interfaces, and tun or tap devices (as OpenVPN and most others use) that
+//! are up. Links coming and going are watched for on an rtnetlink socket. With
+//! `backend = "networkmanager"`, NetworkManager's active VPN and WireGuard
+//! connections are shown by name instead, followed over D-Bus.
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use toml;
+
+use {Event, Outcome};
+use super::dbus::{Bus, Signals};
+use super::rtnetlink::{Links, RTMGRP_LINK};
+use super::{fill, run, seconds, string, Widget};
+
+const NET: &str = "/sys/class/net";
+const IFF_UP: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Interfaces,
+    NetworkManager,
+}
+
+struct Vpn {
+    backend: Backend,
+    connected: String,
+    disconnected: String,
+    separator: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    links: Option<Links>,
+    signals: Option<Signals>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let backend = match string(section_name, configuration, "backend", "interfaces")?.as_str() {
+        "interfaces" => Backend::Interfaces,
+        "networkmanager" => Backend::NetworkManager,
+        _ => return Err(format!("Invalid backend found for {}: expected \"interfaces\" or \"networkmanager\"", section_name)),
+    };
+
+    Ok(Box::new(Vpn {
+        backend,
+        connected: string(section_name, configuration, "connected", "vpn {name}")?,
+        disconnected: string(section_name, configuration, "disconnected", "")?,
+        separator: string(section_name, configuration, "separator", ", ")?,
+        interval: seconds(section_name, configuration, "reload", 60f64)?,
+        links: None,
+        signals: None,
+    }))
+}
+
+/// The WireGuard, tun, and tap interfaces under `net` that are up.
+fn vpn_interfaces(net: &Path) -> Vec<String> {
+    let mut interfaces = fs::read_dir(net).map(|entries| entries.filter_map(Result::ok)
+        .filter(|entry| {
+            let path = entry.path();
+            let wireguard = fs::read_to_string(path.join("uevent")).is_ok_and(|uevent| uevent.lines().any(|line| line == "DEVTYPE=wireguard"));
+            let tun = path.join("tun_flags").exists();
+            let up = fs::read_to_string(path.join("flags")).ok()
+                .and_then(|flags| u32::from_str_radix(flags.trim().trim_start_matches("0x"), 16).ok())
+                .is_some_and(|flags| flags & IFF_UP != 0);
+            (wireguard || tun) && up
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()).unwrap_or_default();
+    interfaces.sort();
+    interfaces
+}
+
+/// The names of NetworkManager's active VPN and WireGuard connections, or
+/// `None` if it couldn't be asked.
+fn networkmanager_connections() -> Option<Vec<String>> {
+    Some(vpn_connections(&run("nmcli", &["-t", "-f", "TYPE,NAME", "connection", "show", "--active"])?))
+}
+
+/// Picks the VPN and WireGuard connections out of what `nmcli -t` lists.
+fn vpn_connections(active: &str) -> Vec<String> {
+    // Colons in names are escaped, but types have none, so the first colon ends the type
+    active.lines()
+        .filter_map(|line| line.split_once(':'))
+        .filter(|&(kind, _)| kind == "vpn" || kind == "wireguard")
+        .map(|(_, name)| name.replace("\\:", ":"))
+        .collect()
+}
+
+impl Widget for Vpn {
+    /// Polled after links or connections change, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let names = match self.backend {
+            Backend::Interfaces => vpn_interfaces(Path::new(NET)),
+            Backend::NetworkManager => match networkmanager_connections() {
+                Some(names) => names,
+                None => return Some(Outcome::Failure(None)),
+            },
+        };
+        Some(Outcome::Success(match names.len() {
+            0 => self.disconnected.clone(),
+            count => fill(&self.connected, &[("name", names.join(&self.separator)), ("count", count.to_string())]),
+        }))
+    }
+
+    /// Falls back to polling every second when changes can't be watched for.
+    fn wait(&self) -> Duration {
+        match (&self.links, &self.signals) {
+            (None, None) => Duration::from_secs(1),
+            _ => self.interval,
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        match self.backend {
+            Backend::Interfaces => self.links = Links::start(RTMGRP_LINK, wake),
+            Backend::NetworkManager => {
+                let rule = "type='signal',sender='org.freedesktop.NetworkManager',path='/org/freedesktop/NetworkManager',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'";
+                self.signals = Signals::start(Bus::System, &[rule], &[], wake);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::{vpn_connections, vpn_interfaces};
+
+    #[test]
+    fn vpn_interfaces_are_those_up() {
+        let net = env::temp_dir().join(format!("admiral-test-net-{}", process::id()));
+        let interface = |name: &str, uevent: &str, tun: bool, flags: &str| {
+            let path = net.join(name);
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("uevent"), uevent).unwrap();
+            fs::write(path.join("flags"), flags).unwrap();
+            if tun {
+                fs::write(path.join("tun_flags"), "0x1001\n").unwrap();
+            }
+        };
+        interface("wg0", "DEVTYPE=wireguard\nINTERFACE=wg0\n", false, "0x1091\n");
+        interface("tun0", "INTERFACE=tun0\n", true, "0x1091\n");
+        interface("tun1", "INTERFACE=tun1\n", true, "0x1090\n");
+        interface("eth0", "INTERFACE=eth0\n", false, "0x1003\n");
+
+        assert_eq!(vpn_interfaces(&net), ["tun0", "wg0"]);
+        assert!(vpn_interfaces(&net.join("missing")).is_empty());
+        let _ = fs::remove_dir_all(&net);
+    }
+
+    #[test]
+    fn networkmanager_vpns_are_picked_out() {
+        let active = "802-11-wireless:Home\nvpn:Work\\: Office\nwireguard:wg-home\nloopback:lo\n";
+        assert_eq!(vpn_connections(active), ["Work: Office", "wg-home"]);
+    }
+}
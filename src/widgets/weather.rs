@@ -0,0 +1,295 @@
+//! A widget showing the current weather, from wttr.in, met.no, or
+//! OpenWeatherMap.
+//!
+//! Each fetch is saved to `$XDG_CACHE_HOME/admiral`, so restarting Admiral
+//! shows the weather straight away without asking the service again. When a
+//! fetch fails, such as while offline, the last weather fetched is still shown,
+//! with `stale_format`, instead of the section failing.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+use toml::{self, Value};
+
+use Outcome;
+use super::{encode, fetch_url, fill, seconds, string, Cache, Fetched, Saved, Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    Wttr,
+    MetNo,
+    OpenWeatherMap,
+}
+
+/// The weather, in the section's units: degrees, percent humidity, and km/h or mph of wind.
+#[derive(Debug, Clone)]
+struct Report {
+    temp: f64,
+    condition: String,
+    humidity: f64,
+    wind: f64,
+}
+
+impl Saved for Report {
+    fn save(&self, object: &mut BTreeMap<String, Json>) {
+        object.insert(String::from("temp"), Json::F64(self.temp));
+        object.insert(String::from("condition"), Json::String(self.condition.clone()));
+        object.insert(String::from("humidity"), Json::F64(self.humidity));
+        object.insert(String::from("wind"), Json::F64(self.wind));
+    }
+
+    fn load(saved: &Json) -> Option<Report> {
+        Some(Report {
+            temp: number(saved.find("temp"))?,
+            condition: saved.find("condition").and_then(Json::as_string).unwrap_or("").to_owned(),
+            humidity: number(saved.find("humidity")).unwrap_or(0f64),
+            wind: number(saved.find("wind")).unwrap_or(0f64),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Weather {
+    backend: Backend,
+    url: String,
+    imperial: bool,
+    format: String,
+    stale_format: String,
+    report: Fetched<Report>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Weather::from_config(section_name, configuration)?))
+}
+
+fn coordinate(section_name: &str, configuration: &toml::Table, key: &str) -> Result<Option<f64>, String> {
+    match configuration.get(key) {
+        Some(&Value::Float(degrees)) => Ok(Some(degrees)),
+        Some(&Value::Integer(degrees)) => Ok(Some(degrees as f64)),
+        Some(_) => Err(format!("Invalid {} found for {}", key, section_name)),
+        None => Ok(None),
+    }
+}
+
+/// Reads a number that may be given as a JSON number or a string, as wttr.in does.
+fn number(json: Option<&Json>) -> Option<f64> {
+    let json = json?;
+    json.as_f64().or_else(|| json.as_string()?.parse().ok())
+}
+
+impl Weather {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Weather, String> {
+        let backend = match string(section_name, configuration, "backend", "wttr")?.as_str() {
+            "wttr" => Backend::Wttr,
+            "metno" => Backend::MetNo,
+            "openweathermap" => Backend::OpenWeatherMap,
+            _ => return Err(format!("Invalid backend found for {}: expected \"wttr\", \"metno\", or \"openweathermap\"", section_name)),
+        };
+        let imperial = match string(section_name, configuration, "units", "metric")?.as_str() {
+            "metric" => false,
+            "imperial" => true,
+            _ => return Err(format!("Invalid units found for {}: expected \"metric\" or \"imperial\"", section_name)),
+        };
+        let location = string(section_name, configuration, "location", "")?;
+        let position = match (coordinate(section_name, configuration, "latitude")?, coordinate(section_name, configuration, "longitude")?) {
+            (Some(latitude), Some(longitude)) => Some((latitude, longitude)),
+            (None, None) => None,
+            _ => return Err(format!("Failed to find both latitude and longitude for {}", section_name)),
+        };
+        let key = match (configuration.get("api_key"), configuration.get("api_key_file")) {
+            (Some(_), _) => string(section_name, configuration, "api_key", "")?,
+            (None, Some(_)) => {
+                let path = string(section_name, configuration, "api_key_file", "")?;
+                let key = fs::read_to_string(&path)
+                    .map_err(|e| format!("Failed to read api_key_file for {}: {}", section_name, e))?;
+                key.trim().to_owned()
+            },
+            (None, None) => String::new(),
+        };
+
+        let url = match backend {
+            // Without a location, wttr.in guesses one from the address asking
+            Backend::Wttr => format!("https://wttr.in/{}?format=j1", encode(&location)),
+            Backend::MetNo => {
+                let (latitude, longitude) = position
+                    .ok_or_else(|| format!("Failed to find latitude and longitude for {}, which met.no needs", section_name))?;
+                // met.no asks for no more than four decimal places, which is plenty anyway
+                format!("https://api.met.no/weatherapi/locationforecast/2.0/compact?lat={:.4}&lon={:.4}", latitude, longitude)
+            },
+            Backend::OpenWeatherMap => {
+                if key.is_empty() {
+                    return Err(format!("Failed to find api_key for {}, which OpenWeatherMap needs", section_name));
+                }
+                let place = match position {
+                    Some((latitude, longitude)) => format!("lat={}&lon={}", latitude, longitude),
+                    None if !location.is_empty() => format!("q={}", encode(&location)),
+                    None => return Err(format!("Failed to find a location for {}", section_name)),
+                };
+                let units = if imperial { "imperial" } else { "metric" };
+                format!("https://api.openweathermap.org/data/2.5/weather?{}&units={}&appid={}", place, units, encode(&key))
+            },
+        };
+
+        let format = string(section_name, configuration, "format", "{temp}° {condition}")?;
+        // A report fetched with other settings could be for another place or in
+        // other units. The API key is left out, so it isn't written to disk
+        let source = format!("{} {}", url.split("&appid=").next().unwrap_or(""), if imperial { "imperial" } else { "metric" });
+        let cache = Cache::new(&format!("weather-{}.json", section_name), source);
+
+        Ok(Weather {
+            backend,
+            url,
+            imperial,
+            stale_format: string(section_name, configuration, "stale_format", &format)?,
+            format,
+            report: Fetched::new(cache, seconds(section_name, configuration, "reload", 900f64)?),
+        })
+    }
+
+    fn fetch(&self) -> Option<Report> {
+        self.read(&fetch_url(&self.url)?)
+    }
+
+    /// Reads the weather from the body of the backend's response.
+    fn read(&self, body: &str) -> Option<Report> {
+        let json = Json::from_str(body).ok()?;
+
+        let (celsius, condition, humidity, kmh) = match self.backend {
+            Backend::Wttr => {
+                let current = json.find("current_condition")?.as_array()?.first()?;
+                let condition = current.find("weatherDesc")
+                    .and_then(Json::as_array)
+                    .and_then(|descriptions| descriptions.first())
+                    .and_then(|description| description.find("value"))
+                    .and_then(Json::as_string)
+                    .unwrap_or("");
+                (number(current.find("temp_C"))?, condition.to_owned(), number(current.find("humidity")), number(current.find("windspeedKmph")))
+            },
+            Backend::MetNo => {
+                let now = json.find_path(&["properties", "timeseries"])?.as_array()?.first()?.find("data")?;
+                let details = now.find_path(&["instant", "details"])?;
+                // Symbols look like "partlycloudy_day"
+                let symbol = now.find_path(&["next_1_hours", "summary", "symbol_code"]).and_then(Json::as_string).unwrap_or("");
+                let condition = symbol.split('_').next().unwrap_or("").to_owned();
+                let wind = number(details.find("wind_speed")).map(|metres| metres * 3.6);
+                (number(details.find("air_temperature"))?, condition, number(details.find("relative_humidity")), wind)
+            },
+            Backend::OpenWeatherMap => {
+                let condition = json.find("weather")
+                    .and_then(Json::as_array)
+                    .and_then(|conditions| conditions.first())
+                    .and_then(|condition| condition.find("description"))
+                    .and_then(Json::as_string)
+                    .unwrap_or("");
+                let temp = number(json.find_path(&["main", "temp"]))?;
+                let humidity = number(json.find_path(&["main", "humidity"]));
+                let wind = number(json.find_path(&["wind", "speed"]));
+                // OpenWeatherMap converts for itself, giving m/s or mph
+                let report = Report {
+                    temp,
+                    condition: condition.to_owned(),
+                    humidity: humidity.unwrap_or(0f64),
+                    wind: wind.map_or(0f64, |speed| if self.imperial { speed } else { speed * 3.6 }),
+                };
+                return Some(report);
+            },
+        };
+
+        Some(Report {
+            temp: if self.imperial { celsius * 9f64 / 5f64 + 32f64 } else { celsius },
+            condition,
+            humidity: humidity.unwrap_or(0f64),
+            wind: kmh.map_or(0f64, |kmh| if self.imperial { kmh / 1.609344 } else { kmh }),
+        })
+    }
+}
+
+impl Widget for Weather {
+    fn poll(&mut self) -> Option<Outcome> {
+        if self.report.due() {
+            let report = self.fetch();
+            self.report.update(report);
+        }
+        if !self.report.changed() {
+            return None;
+        }
+
+        let report = match self.report.value() {
+            Some(report) => report,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let format = if self.report.is_stale() { &self.stale_format } else { &self.format };
+        Some(Outcome::Success(fill(format, &[
+            ("temp", format!("{:.0}", report.temp)),
+            ("condition", report.condition.clone()),
+            ("humidity", format!("{:.0}", report.humidity)),
+            ("wind", format!("{:.0}", report.wind)),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn refresh(&mut self) {
+        self.report.refresh();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use rustc_serialize::json::Json;
+    use toml;
+
+    use super::{Report, Saved, Weather};
+
+    fn weather(source: &str) -> Result<Weather, String> {
+        Weather::from_config("weather-test", &toml::Parser::new(source).parse().unwrap())
+    }
+
+    #[test]
+    fn each_backend_is_read_in_the_units_asked_for() {
+        let wttr = r#"{"current_condition": [{"temp_C": "20", "humidity": "56", "windspeedKmph": "16", "weatherDesc": [{"value": "Partly cloudy"}]}]}"#;
+        let report = weather("").unwrap().read(wttr).unwrap();
+        assert_eq!((report.temp, report.condition.as_str(), report.humidity, report.wind), (20f64, "Partly cloudy", 56f64, 16f64));
+        let report = weather("units = \"imperial\"").unwrap().read(wttr).unwrap();
+        assert_eq!(report.temp, 68f64);
+        assert!((report.wind - 9.94).abs() < 0.01);
+
+        let metno = r#"{"properties": {"timeseries": [{"data": {
+            "instant": {"details": {"air_temperature": 4.5, "relative_humidity": 80, "wind_speed": 5}},
+            "next_1_hours": {"summary": {"symbol_code": "lightrain_night"}}
+        }}]}}"#;
+        let report = weather("backend = \"metno\"\nlatitude = 59.9\nlongitude = 10.75").unwrap().read(metno).unwrap();
+        assert_eq!((report.temp, report.condition.as_str(), report.humidity, report.wind), (4.5, "lightrain", 80f64, 18f64));
+
+        let openweathermap = r#"{"weather": [{"description": "clear sky"}], "main": {"temp": 71.2, "humidity": 40}, "wind": {"speed": 3}}"#;
+        let report = weather("backend = \"openweathermap\"\nlocation = \"Austin\"\napi_key = \"key\"\nunits = \"imperial\"").unwrap().read(openweathermap).unwrap();
+        assert_eq!((report.temp, report.condition.as_str(), report.wind), (71.2, "clear sky", 3f64));
+
+        assert!(weather("").unwrap().read("Unknown location; please try ~40.7,-74.0").is_none());
+    }
+
+    #[test]
+    fn backends_need_what_they_ask_with() {
+        assert!(weather("backend = \"metno\"").is_err());
+        assert!(weather("backend = \"metno\"\nlatitude = 59.9").is_err());
+        assert!(weather("backend = \"openweathermap\"\nlocation = \"Austin\"").is_err());
+        assert!(weather("backend = \"openweathermap\"\napi_key = \"key\"").is_err());
+        let url = weather("location = \"São Paulo\"").unwrap().url;
+        assert_eq!(url, "https://wttr.in/S%C3%A3o%20Paulo?format=j1");
+    }
+
+    #[test]
+    fn reports_are_saved_and_loaded_again() {
+        let report = Report { temp: -3.5, condition: String::from("snow"), humidity: 90f64, wind: 12f64 };
+        let mut object = BTreeMap::new();
+        report.save(&mut object);
+        let loaded = Report::load(&Json::Object(object)).unwrap();
+        assert_eq!((loaded.temp, loaded.condition, loaded.humidity, loaded.wind), (-3.5, String::from("snow"), 90f64, 12f64));
+    }
+}
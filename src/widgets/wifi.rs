@@ -0,0 +1,248 @@
+//! A widget showing the network a wireless interface is connected to and the
+//! strength of its signal.
+//!
+//! The SSID is asked of the kernel over nl80211, with the two generic netlink
+//! requests that needs written out by hand: one to look up nl80211's family
+//! id, and one to get the interface. The signal comes from `/proc/net/wireless`.
+
+use std::ffi::CString;
+use std::fs;
+use std::mem;
+use std::time::Duration;
+
+use libc;
+use toml;
+
+use Outcome;
+use super::{fill, seconds, string, Widget};
+
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 1;
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_SSID: u16 = 52;
+
+/// Sizes of the netlink and generic netlink headers.
+const NLMSG_HEADER: usize = 16;
+const GENL_HEADER: usize = 4;
+
+#[derive(Debug)]
+struct Wifi {
+    interface: Option<String>,
+    connected: String,
+    disconnected: String,
+    interval: Duration,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let interface = match configuration.get("interface") {
+        Some(_) => Some(string(section_name, configuration, "interface", "")?),
+        None => None,
+    };
+
+    Ok(Box::new(Wifi {
+        interface,
+        connected: string(section_name, configuration, "connected", "{ssid} {quality}%")?,
+        disconnected: string(section_name, configuration, "disconnected", "disconnected")?,
+        interval: seconds(section_name, configuration, "reload", 5f64)?,
+    }))
+}
+
+/// A generic netlink socket, closed when dropped.
+struct Netlink(libc::c_int);
+
+impl Drop for Netlink {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0) };
+    }
+}
+
+fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+fn push_attribute(message: &mut Vec<u8>, kind: u16, payload: &[u8]) {
+    message.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+    message.extend_from_slice(&kind.to_ne_bytes());
+    message.extend_from_slice(payload);
+    message.resize(align(message.len()), 0);
+}
+
+/// Finds an attribute's payload among those following a generic netlink header.
+fn find_attribute(payload: &[u8], kind: u16) -> Option<&[u8]> {
+    let mut offset = 0;
+    while offset + 4 <= payload.len() {
+        let length = u16::from_ne_bytes([payload[offset], payload[offset + 1]]) as usize;
+        // The top bits of the type are flags
+        let attribute = u16::from_ne_bytes([payload[offset + 2], payload[offset + 3]]) & 0x3fff;
+        if length < 4 || offset + length > payload.len() {
+            return None;
+        }
+        if attribute == kind {
+            return Some(&payload[offset + 4..offset + length]);
+        }
+        offset += align(length);
+    }
+    None
+}
+
+impl Netlink {
+    fn open() -> Option<Netlink> {
+        let socket = unsafe { libc::socket(libc::AF_NETLINK, libc::SOCK_RAW | libc::SOCK_CLOEXEC, libc::NETLINK_GENERIC) };
+        if socket < 0 {
+            return None;
+        }
+        let timeout = libc::timeval { tv_sec: 1, tv_usec: 0 };
+        unsafe {
+            libc::setsockopt(socket, libc::SOL_SOCKET, libc::SO_RCVTIMEO, &timeout as *const _ as *const libc::c_void, mem::size_of::<libc::timeval>() as libc::socklen_t)
+        };
+        Some(Netlink(socket))
+    }
+
+    /// Sends a request and returns the attributes of its reply.
+    fn request(&self, family: u16, command: u8, attributes: &[(u16, &[u8])]) -> Option<Vec<u8>> {
+        let mut message = vec![0u8; NLMSG_HEADER];
+        message.extend_from_slice(&[command, 1, 0, 0]);
+        for &(kind, payload) in attributes {
+            push_attribute(&mut message, kind, payload);
+        }
+        let length = message.len() as u32;
+        message[0..4].copy_from_slice(&length.to_ne_bytes());
+        message[4..6].copy_from_slice(&family.to_ne_bytes());
+        message[6..8].copy_from_slice(&NLM_F_REQUEST.to_ne_bytes());
+
+        if unsafe { libc::send(self.0, message.as_ptr() as *const libc::c_void, message.len(), 0) } < 0 {
+            return None;
+        }
+
+        let mut reply = vec![0u8; 16 * 1024];
+        let received = unsafe { libc::recv(self.0, reply.as_mut_ptr() as *mut libc::c_void, reply.len(), 0) };
+        if received < (NLMSG_HEADER + GENL_HEADER) as isize {
+            return None;
+        }
+        let length = (u32::from_ne_bytes([reply[0], reply[1], reply[2], reply[3]]) as usize).min(received as usize);
+        let kind = u16::from_ne_bytes([reply[4], reply[5]]);
+        if kind == NLMSG_ERROR || length < NLMSG_HEADER + GENL_HEADER {
+            return None;
+        }
+        Some(reply[NLMSG_HEADER + GENL_HEADER..length].to_vec())
+    }
+}
+
+/// Asks nl80211 for the SSID the interface is connected to.
+fn ssid(interface: &str) -> Option<String> {
+    let name = CString::new(interface).ok()?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return None;
+    }
+
+    let netlink = Netlink::open()?;
+    let family = netlink.request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &[(CTRL_ATTR_FAMILY_NAME, b"nl80211\0")])?;
+    let family = find_attribute(&family, CTRL_ATTR_FAMILY_ID)?;
+    let family = u16::from_ne_bytes([*family.first()?, *family.get(1)?]);
+
+    let attributes = netlink.request(family, NL80211_CMD_GET_INTERFACE, &[(NL80211_ATTR_IFINDEX, &index.to_ne_bytes())])?;
+    let ssid = find_attribute(&attributes, NL80211_ATTR_SSID)?;
+    Some(String::from_utf8_lossy(ssid).into_owned())
+}
+
+/// Reads an interface's link quality (out of 70) and signal level (in dBm) from
+/// `/proc/net/wireless`, or the first wireless interface's if none is given.
+fn signal(interface: Option<&str>) -> Option<(String, f64, f64)> {
+    parse_wireless(&fs::read_to_string("/proc/net/wireless").ok()?, interface)
+}
+
+/// Reads the link quality and signal level from the contents of `/proc/net/wireless`.
+fn parse_wireless(wireless: &str, interface: Option<&str>) -> Option<(String, f64, f64)> {
+    wireless.lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (name, rest) = line.split_once(':')?;
+            let fields = rest.split_whitespace().collect::<Vec<_>>();
+            let quality = fields.get(1)?.trim_end_matches('.').parse().ok()?;
+            let level = fields.get(2)?.trim_end_matches('.').parse().ok()?;
+            Some((name.trim().to_owned(), quality, level))
+        })
+        .find(|(name, _, _)| interface.is_none_or(|interface| interface == name))
+}
+
+impl Widget for Wifi {
+    fn poll(&mut self) -> Option<Outcome> {
+        let (interface, quality, level) = match signal(self.interface.as_deref()) {
+            Some(signal) => signal,
+            None => return Some(Outcome::Success(fill(&self.disconnected, &[("interface", self.interface.clone().unwrap_or_default())]))),
+        };
+        let ssid = match ssid(&interface) {
+            Some(ssid) => ssid,
+            None => return Some(Outcome::Success(fill(&self.disconnected, &[("interface", interface)]))),
+        };
+
+        Some(Outcome::Success(fill(&self.connected, &[
+            ("ssid", ssid),
+            ("quality", format!("{:.0}", (quality * 100f64 / 70f64).min(100f64))),
+            ("signal", format!("{:.0}", level)),
+            ("interface", interface),
+        ])))
+    }
+
+    fn wait(&self) -> Duration {
+        self.interval
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_attribute, parse_wireless, push_attribute, Netlink, CTRL_ATTR_FAMILY_ID, CTRL_ATTR_FAMILY_NAME, CTRL_CMD_GETFAMILY, GENL_ID_CTRL};
+
+    #[test]
+    fn attributes_are_padded_and_found_again() {
+        let mut attributes = Vec::new();
+        push_attribute(&mut attributes, 3, &7u32.to_ne_bytes());
+        push_attribute(&mut attributes, 52, b"home");
+        push_attribute(&mut attributes, 2, b"nl80211\0");
+        assert_eq!(attributes.len(), 8 + 8 + 12);
+        assert_eq!(find_attribute(&attributes, 52), Some(&b"home"[..]));
+        assert_eq!(find_attribute(&attributes, 2), Some(&b"nl80211\0"[..]));
+        assert_eq!(find_attribute(&attributes, 1), None);
+
+        // Nested and byte-order flags in the type are ignored
+        let mut flagged = Vec::new();
+        push_attribute(&mut flagged, 52 | 0x8000, b"ssid");
+        assert_eq!(find_attribute(&flagged, 52), Some(&b"ssid"[..]));
+    }
+
+    #[test]
+    fn truncated_attributes_are_not_read_past() {
+        let mut attributes = Vec::new();
+        push_attribute(&mut attributes, 52, b"a longer ssid");
+        assert_eq!(find_attribute(&attributes[..10], 52), None);
+        assert_eq!(find_attribute(&[0, 0, 52, 0], 52), None);
+    }
+
+    #[test]
+    fn generic_netlink_families_are_looked_up() {
+        let netlink = match Netlink::open() {
+            Some(netlink) => netlink,
+            None => return,
+        };
+        let family = netlink.request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &[(CTRL_ATTR_FAMILY_NAME, b"nlctrl\0")]).unwrap();
+        assert_eq!(find_attribute(&family, CTRL_ATTR_FAMILY_ID), Some(&GENL_ID_CTRL.to_ne_bytes()[..]));
+        assert_eq!(netlink.request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, &[(CTRL_ATTR_FAMILY_NAME, b"admiral\0")]), None);
+    }
+
+    #[test]
+    fn signal_is_read_for_the_interface() {
+        let wireless = "Inter-| sta-|   Quality        |   Discarded packets               | Missed | WE\n \
+                        face | tus | link level noise |  nwid  crypt   frag  retry   misc | beacon | 22\n\
+                        wlan0: 0000   56.  -54.  -256        0      0      0      0     12        0\n\
+                        wlan1: 0000   35.  -75.  -256        0      0      0      0      0        0\n";
+        assert_eq!(parse_wireless(wireless, None), Some((String::from("wlan0"), 56f64, -54f64)));
+        assert_eq!(parse_wireless(wireless, Some("wlan1")), Some((String::from("wlan1"), 35f64, -75f64)));
+        assert_eq!(parse_wireless(wireless, Some("wlan2")), None);
+    }
+}
@@ -0,0 +1,179 @@
+//! A widget showing the title of the focused window.
+//!
+//! Under i3 and sway, the title is found in the layout tree and followed with
+//! window events over their IPC socket. Under other window managers, it's read
+//! from the EWMH properties on the root window with `xprop`, which also
+//! reports changes to the active window with `-spy`.
+
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+use toml::{self, Value};
+
+use {Event, Outcome};
+use super::i3::{self, Connection, Events};
+use super::{fill, run, seconds, string, Subscription, Widget};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Backend {
+    I3,
+    Ewmh,
+}
+
+struct Window {
+    backend: Backend,
+    format: String,
+    max_length: Option<usize>,
+    ellipsis: String,
+    /// How often to check anyway, in case a change is missed.
+    interval: Duration,
+    /// What keeps the title up to date between polls, depending on the backend.
+    events: Option<Events>,
+    subscription: Option<Subscription>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    let default_backend = if i3::is_running() { "i3" } else { "ewmh" };
+    let backend = match string(section_name, configuration, "backend", default_backend)?.as_str() {
+        "i3" | "sway" => Backend::I3,
+        "ewmh" => Backend::Ewmh,
+        _ => return Err(format!("Invalid backend found for {}: expected \"i3\", \"sway\", or \"ewmh\"", section_name)),
+    };
+    let max_length = match configuration.get("max_length") {
+        Some(&Value::Integer(length)) if length > 0 => Some(length as usize),
+        Some(_) => return Err(format!("Invalid max_length found for {}", section_name)),
+        None => None,
+    };
+
+    Ok(Box::new(Window {
+        backend,
+        format: string(section_name, configuration, "format", "{title}")?,
+        max_length,
+        ellipsis: string(section_name, configuration, "ellipsis", "…")?,
+        interval: seconds(section_name, configuration, "reload", 5f64)?,
+        events: None,
+        subscription: None,
+    }))
+}
+
+/// Finds the focused node's name in i3's layout tree.
+fn focused_name(node: &Json) -> Option<String> {
+    if node.find("focused").and_then(Json::as_boolean) == Some(true) {
+        // Workspaces and outputs are focused when they have no windows
+        return match node.find("type").and_then(Json::as_string) {
+            Some("con") | Some("floating_con") => node.find("name").and_then(Json::as_string).map(str::to_owned),
+            _ => None,
+        };
+    }
+    ["nodes", "floating_nodes"].iter()
+        .filter_map(|key| node.find(key).and_then(Json::as_array))
+        .flatten()
+        .find_map(focused_name)
+}
+
+fn i3_title() -> Option<String> {
+    let tree = Connection::open(Some(Duration::from_secs(2)))?.request(i3::GET_TREE, "")?;
+    Some(focused_name(&tree).unwrap_or_default())
+}
+
+/// Reads a string property that `xprop` prints as `NAME(TYPE) = "value"`, undoing its escapes.
+fn xprop_string(output: &str) -> Option<String> {
+    let quoted = &output[output.find('"')? + 1..output.trim_end().rfind('"')?];
+    let mut value = String::new();
+    let mut characters = quoted.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' => value.extend(characters.next()),
+            _ => value.push(character),
+        }
+    }
+    Some(value)
+}
+
+fn ewmh_title() -> Option<String> {
+    let active = run("xprop", &["-root", "_NET_ACTIVE_WINDOW"])?;
+    let id = match active.split_whitespace().last() {
+        // Nothing is focused
+        Some("0x0") | None => return Some(String::new()),
+        Some(id) => id.trim_end_matches(',').to_owned(),
+    };
+    let name = run("xprop", &["-id", &id, "_NET_WM_NAME"]).and_then(|name| xprop_string(&name));
+    match name {
+        Some(name) => Some(name),
+        // Windows that don't set the EWMH name may still have the older one
+        None => Some(run("xprop", &["-id", &id, "WM_NAME"]).and_then(|name| xprop_string(&name)).unwrap_or_default()),
+    }
+}
+
+impl Widget for Window {
+    /// Polled after every focus and title change, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let title = match self.backend {
+            Backend::I3 => i3_title(),
+            Backend::Ewmh => ewmh_title(),
+        };
+        let mut title = match title {
+            Some(title) => title,
+            None => return Some(Outcome::Failure(None)),
+        };
+        if title.is_empty() {
+            return Some(Outcome::Success(String::new()));
+        }
+
+        if let Some(max_length) = self.max_length {
+            if title.chars().count() > max_length {
+                title = title.chars().take(max_length).collect::<String>() + &self.ellipsis;
+            }
+        }
+        Some(Outcome::Success(fill(&self.format, &[("title", title)])))
+    }
+
+    /// Falls back to polling every second when changes can't be watched for.
+    fn wait(&self) -> Duration {
+        if self.events.is_some() || self.subscription.is_some() {
+            self.interval
+        } else {
+            Duration::from_secs(1)
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        match self.backend {
+            Backend::I3 => self.events = Events::start(&["window", "workspace"], wake),
+            Backend::Ewmh => self.subscription = Subscription::start(Command::new("xprop").args(["-spy", "-root", "_NET_ACTIVE_WINDOW"]), wake, |_| true),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+
+    use super::{focused_name, xprop_string};
+
+    #[test]
+    fn the_focused_window_is_found_in_the_tree() {
+        let tree = Json::from_str(r#"{"type": "root", "focused": false, "nodes": [
+            {"type": "output", "name": "eDP-1", "focused": false, "nodes": [
+                {"type": "workspace", "name": "1", "focused": false, "nodes": [
+                    {"type": "con", "name": "Terminal", "focused": false, "nodes": []}
+                ], "floating_nodes": [
+                    {"type": "floating_con", "name": "Picture-in-Picture", "focused": true, "nodes": []}
+                ]}
+            ]}
+        ]}"#).unwrap();
+        assert_eq!(focused_name(&tree), Some(String::from("Picture-in-Picture")));
+        // An empty workspace is focused, rather than a window
+        let empty = Json::from_str(r#"{"type": "root", "focused": false, "nodes": [{"type": "workspace", "name": "2", "focused": true}]}"#).unwrap();
+        assert_eq!(focused_name(&empty), None);
+    }
+
+    #[test]
+    fn xprop_strings_are_unescaped() {
+        assert_eq!(xprop_string("_NET_WM_NAME(UTF8_STRING) = \"say \\\"hi\\\" \\\\ bye\"\n"), Some(String::from("say \"hi\" \\ bye")));
+        assert_eq!(xprop_string("WM_NAME(STRING) = \"\"\n"), Some(String::new()));
+        assert_eq!(xprop_string("_NET_WM_NAME:  not found.\n"), None);
+    }
+}
@@ -0,0 +1,131 @@
+//! A widget listing i3's or sway's workspaces, which follows workspace events
+//! over the window manager's IPC socket.
+
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use rustc_serialize::json::Json;
+use toml;
+
+use {Event, Outcome};
+use super::i3::{self, Connection, Events};
+use super::{fill, seconds, string, Widget};
+
+struct Workspaces {
+    /// The output whose workspaces to list, rather than every output's.
+    output: Option<String>,
+    focused: String,
+    visible: String,
+    urgent: String,
+    unfocused: String,
+    separator: String,
+    /// How often to check anyway, in case an event is missed.
+    interval: Duration,
+    events: Option<Events>,
+}
+
+pub fn new(section_name: &str, configuration: &toml::Table) -> Result<Box<dyn Widget>, String> {
+    Ok(Box::new(Workspaces::from_config(section_name, configuration)?))
+}
+
+impl Workspaces {
+    fn from_config(section_name: &str, configuration: &toml::Table) -> Result<Workspaces, String> {
+        let output = match configuration.get("output") {
+            Some(_) => Some(string(section_name, configuration, "output", "")?),
+            None => None,
+        };
+        let unfocused = string(section_name, configuration, "unfocused", " {name} ")?;
+
+        Ok(Workspaces {
+            output,
+            focused: string(section_name, configuration, "focused", "[{name}]")?,
+            visible: string(section_name, configuration, "visible", &unfocused)?,
+            urgent: string(section_name, configuration, "urgent", "!{name}!")?,
+            unfocused,
+            separator: string(section_name, configuration, "separator", "")?,
+            interval: seconds(section_name, configuration, "reload", 60f64)?,
+            events: None,
+        })
+    }
+
+    /// Lists the workspaces of the output, each in the format for how it stands.
+    fn render(&self, workspaces: &[Json]) -> String {
+        let flag = |workspace: &Json, name: &str| workspace.find(name).and_then(Json::as_boolean).unwrap_or(false);
+        let text = |workspace: &Json, name: &str| workspace.find(name).and_then(Json::as_string).unwrap_or("").to_owned();
+        let shown = workspaces.iter()
+            .filter(|workspace| self.output.as_ref().is_none_or(|output| text(workspace, "output") == *output))
+            .map(|workspace| {
+                // Urgency is what most needs noticing, even on the focused workspace
+                let format = if flag(workspace, "urgent") {
+                    &self.urgent
+                } else if flag(workspace, "focused") {
+                    &self.focused
+                } else if flag(workspace, "visible") {
+                    &self.visible
+                } else {
+                    &self.unfocused
+                };
+                let num = workspace.find("num").and_then(Json::as_i64).map_or_else(String::new, |num| num.to_string());
+                fill(format, &[
+                    ("name", text(workspace, "name")),
+                    ("num", num),
+                    ("output", text(workspace, "output")),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        shown.join(&self.separator)
+    }
+}
+
+impl Widget for Workspaces {
+    /// Polled after every workspace event, as well as every `reload`.
+    fn poll(&mut self) -> Option<Outcome> {
+        let workspaces = match Connection::open(Some(Duration::from_secs(2))).and_then(|mut connection| connection.request(i3::GET_WORKSPACES, "")) {
+            Some(workspaces) => workspaces,
+            None => return Some(Outcome::Failure(None)),
+        };
+        let workspaces = match workspaces.as_array() {
+            Some(workspaces) => workspaces,
+            None => return Some(Outcome::Failure(None)),
+        };
+
+        Some(Outcome::Success(self.render(workspaces)))
+    }
+
+    /// Falls back to polling every second when events can't be subscribed to.
+    fn wait(&self) -> Duration {
+        match self.events {
+            Some(_) => self.interval,
+            None => Duration::from_secs(1),
+        }
+    }
+
+    fn subscribe(&mut self, wake: Sender<Event>) {
+        self.events = Events::start(&["workspace", "output"], wake);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_serialize::json::Json;
+    use toml;
+
+    use super::Workspaces;
+
+    #[test]
+    fn each_workspace_is_shown_as_it_stands() {
+        let workspaces = Json::from_str(r#"[
+            {"num": 1, "name": "1: web", "output": "eDP-1", "focused": true, "visible": true, "urgent": false},
+            {"num": 2, "name": "2", "output": "eDP-1", "focused": false, "visible": false, "urgent": true},
+            {"num": 3, "name": "3", "output": "HDMI-A-1", "focused": false, "visible": true, "urgent": false},
+            {"num": -1, "name": "music", "output": "eDP-1", "focused": false, "visible": false, "urgent": false}
+        ]"#).unwrap();
+        let workspaces = workspaces.as_array().unwrap();
+
+        let all = Workspaces::from_config("workspaces", &toml::Parser::new("visible = \"({num})\"").parse().unwrap()).unwrap();
+        assert_eq!(all.render(workspaces), "[1: web]!2!(3) music ");
+        let laptop = Workspaces::from_config("workspaces", &toml::Parser::new("output = \"eDP-1\"\nseparator = \"|\"").parse().unwrap()).unwrap();
+        assert_eq!(laptop.render(workspaces), "[1: web]|!2!| music ");
+    }
+}
@@ -0,0 +1,333 @@
+//! Just enough of the X11 protocol to set the root window's name, which dwm
+//! and similar window managers show as their status text.
+//!
+//! Only local displays are supported, reached through the server's UNIX
+//! socket. The client authenticates with the display's MIT-MAGIC-COOKIE-1
+//! from the Xauthority file, if it has one. Each name set is followed by a
+//! request with a reply, so that any error the server sends for it is read
+//! before going on.
+
+use std::env;
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixStream};
+use std::path::PathBuf;
+
+const INTERN_ATOM: u8 = 16;
+const CHANGE_PROPERTY: u8 = 18;
+const GET_INPUT_FOCUS: u8 = 43;
+const PROP_MODE_REPLACE: u8 = 0;
+/// The atom for `WM_NAME`, which is predefined.
+const WM_NAME: u32 = 39;
+
+/// Xauthority's family for local connections, and for entries matching any address.
+const FAMILY_LOCAL: u16 = 256;
+const FAMILY_WILD: u16 = 65535;
+
+pub struct RootWindow {
+    stream: UnixStream,
+    root: u32,
+    /// The atom for `UTF8_STRING`, so names needn't be Latin-1.
+    utf8_string: u32,
+    /// The longest request the server takes, in bytes.
+    maximum_request: usize,
+}
+
+fn pad(message: &mut Vec<u8>) {
+    while !message.len().is_multiple_of(4) {
+        message.push(0);
+    }
+}
+
+/// The display number from `$DISPLAY`, such as 0 from `:0` or `:0.0`.
+fn display_number() -> Result<String, String> {
+    let display = env::var("DISPLAY").map_err(|_| String::from("Failed to find an X display: $DISPLAY isn't set"))?;
+    parse_display(&display)
+}
+
+fn parse_display(display: &str) -> Result<String, String> {
+    // `unix:0` is the same display as `:0`, reached the same way
+    let number = match display.strip_prefix("unix").unwrap_or(display).strip_prefix(':') {
+        Some(number) => number.split('.').next().unwrap_or(""),
+        None => return Err(format!("Failed to use X display {}: only local displays are supported", display)),
+    };
+    if number.is_empty() || !number.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(format!("Invalid X display found: {}", display));
+    }
+    Ok(number.to_owned())
+}
+
+fn connect(number: &str) -> Option<UnixStream> {
+    let path = format!("/tmp/.X11-unix/X{}", number);
+    // Servers listen on an abstract socket of the same name too, which works inside sandboxes
+    SocketAddr::from_abstract_name(path.as_bytes()).ok()
+        .and_then(|address| UnixStream::connect_addr(&address).ok())
+        .or_else(|| UnixStream::connect(&path).ok())
+}
+
+/// The display's MIT-MAGIC-COOKIE-1, if the Xauthority file has one for it.
+fn cookie(number: &str) -> Option<Vec<u8>> {
+    let path = env::var_os("XAUTHORITY").map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".Xauthority")))?;
+    let file = fs::read(path).ok()?;
+    let hostname = fs::read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+    find_cookie(&file, hostname.trim(), number)
+}
+
+/// Finds the cookie for a local display in the contents of an Xauthority file.
+fn find_cookie(file: &[u8], hostname: &str, number: &str) -> Option<Vec<u8>> {
+    // Each entry is a family, then an address, display number, name, and data, each prefixed by its length
+    let mut rest = file;
+    while rest.len() >= 2 {
+        let family = u16::from_be_bytes([rest[0], rest[1]]);
+        rest = &rest[2..];
+        let mut fields = Vec::new();
+        for _ in 0..4 {
+            if rest.len() < 2 {
+                return None;
+            }
+            let length = u16::from_be_bytes([rest[0], rest[1]]) as usize;
+            let field = rest.get(2..2 + length)?;
+            fields.push(field);
+            rest = &rest[2 + length..];
+        }
+        let (address, display, name, data) = (fields[0], fields[1], fields[2], fields[3]);
+        let local = family == FAMILY_WILD || (family == FAMILY_LOCAL && address == hostname.as_bytes());
+        if local && display == number.as_bytes() && name == b"MIT-MAGIC-COOKIE-1" {
+            return Some(data.to_vec());
+        }
+    }
+    None
+}
+
+impl RootWindow {
+    pub fn open() -> Result<RootWindow, String> {
+        let number = display_number()?;
+        let mut stream = connect(&number).ok_or_else(|| format!("Failed to connect to X display :{}", number))?;
+
+        let (name, data): (&[u8], Vec<u8>) = match cookie(&number) {
+            Some(data) => (b"MIT-MAGIC-COOKIE-1", data),
+            None => (b"", Vec::new()),
+        };
+        let mut setup = vec![b'l', 0];
+        setup.extend_from_slice(&11u16.to_le_bytes());
+        setup.extend_from_slice(&0u16.to_le_bytes());
+        setup.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        setup.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        setup.extend_from_slice(&[0, 0]);
+        setup.extend_from_slice(name);
+        pad(&mut setup);
+        setup.extend_from_slice(&data);
+        pad(&mut setup);
+        stream.write_all(&setup).map_err(|e| format!("Failed to connect to X display :{}: {}", number, e))?;
+
+        let failed = |e: io::Error| format!("Failed to connect to X display :{}: {}", number, e);
+        let mut header = [0u8; 8];
+        stream.read_exact(&mut header).map_err(failed)?;
+        let mut reply = vec![0u8; u16::from_le_bytes([header[6], header[7]]) as usize * 4];
+        stream.read_exact(&mut reply).map_err(failed)?;
+        let (root, maximum_request) = parse_setup(&header, &reply).map_err(|e| format!("X display :{} {}", number, e))?;
+
+        let mut window = RootWindow { stream, root, utf8_string: 0, maximum_request };
+        window.utf8_string = window.intern_atom("UTF8_STRING").map_err(failed)?;
+        Ok(window)
+    }
+
+    fn intern_atom(&mut self, name: &str) -> io::Result<u32> {
+        let mut request = vec![INTERN_ATOM, 0];
+        request.extend_from_slice(&((8 + name.len()).div_ceil(4) as u16).to_le_bytes());
+        request.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        request.extend_from_slice(&[0, 0]);
+        request.extend_from_slice(name.as_bytes());
+        pad(&mut request);
+        self.stream.write_all(&request)?;
+
+        let mut reply = [0u8; 32];
+        self.stream.read_exact(&mut reply)?;
+        if reply[0] != 1 {
+            return Err(ErrorKind::InvalidData.into());
+        }
+        Ok(u32::from_le_bytes([reply[8], reply[9], reply[10], reply[11]]))
+    }
+
+    /// Another handle on the same connection.
+    pub fn try_clone(&self) -> Option<RootWindow> {
+        Some(RootWindow { stream: self.stream.try_clone().ok()?, root: self.root, utf8_string: self.utf8_string, maximum_request: self.maximum_request })
+    }
+
+    /// Sets the root window's name, which dwm shows as its status text. A
+    /// name the server refuses, or one too long to send, is an error of kind
+    /// `InvalidInput`, and leaves the connection as it was.
+    pub fn set_name(&mut self, name: &str) -> io::Result<()> {
+        let mut request = change_property(self.root, self.utf8_string, name);
+        if request.len() > self.maximum_request {
+            let message = format!("the name is {} bytes long, but the display takes at most {}", name.len(), self.maximum_request - 24);
+            return Err(io::Error::new(ErrorKind::InvalidInput, message));
+        }
+        request.extend_from_slice(&[GET_INPUT_FOCUS, 0, 1, 0]);
+        self.stream.write_all(&request)?;
+
+        // Errors for the name come before the reply, as the server handles requests in order
+        let mut error = None;
+        loop {
+            let mut packet = [0u8; 32];
+            self.stream.read_exact(&mut packet)?;
+            match packet[0] {
+                0 => error = Some(packet[1]),
+                1 => break,
+                // Events aren't asked for, but any that come anyway are skipped
+                _ => continue,
+            }
+        }
+        match error {
+            Some(code) => Err(io::Error::new(ErrorKind::InvalidInput, format!("the display refused the name with {}", error_name(code)))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Reads the server's reply to the connection setup, returning the first
+/// screen's root window and the longest request the server takes, in bytes.
+fn parse_setup(header: &[u8; 8], reply: &[u8]) -> Result<(u32, usize), String> {
+    if header[0] != 1 {
+        // A refusal gives its reason straight after the header
+        let reason = String::from_utf8_lossy(&reply[..(header[1] as usize).min(reply.len())]).into_owned();
+        return Err(format!("refused the connection: {}", reason.trim()));
+    }
+    if reply.len() < 32 {
+        return Err(String::from("sent too short a reply to connecting"));
+    }
+
+    // The first screen's root window comes after the vendor's name and the pixmap formats
+    let vendor = u16::from_le_bytes([reply[16], reply[17]]) as usize;
+    let maximum_request = u16::from_le_bytes([reply[18], reply[19]]) as usize * 4;
+    let formats = reply[21] as usize;
+    let screen = 32 + vendor.div_ceil(4) * 4 + formats * 8;
+    match reply.get(screen..screen + 4) {
+        Some(root) => Ok((u32::from_le_bytes([root[0], root[1], root[2], root[3]]), maximum_request)),
+        None => Err(String::from("has no screens")),
+    }
+}
+
+/// A ChangeProperty request replacing `WM_NAME` on `window`.
+fn change_property(window: u32, utf8_string: u32, name: &str) -> Vec<u8> {
+    let mut request = vec![CHANGE_PROPERTY, PROP_MODE_REPLACE];
+    // The length is in units of four bytes, and wraps round for a long enough name, which `set_name` refuses to send
+    request.extend_from_slice(&((24 + name.len()).div_ceil(4) as u16).to_le_bytes());
+    request.extend_from_slice(&window.to_le_bytes());
+    request.extend_from_slice(&WM_NAME.to_le_bytes());
+    request.extend_from_slice(&utf8_string.to_le_bytes());
+    // Eight bits to each unit of the name
+    request.extend_from_slice(&[8, 0, 0, 0]);
+    request.extend_from_slice(&(name.len() as u32).to_le_bytes());
+    request.extend_from_slice(name.as_bytes());
+    pad(&mut request);
+    request
+}
+
+/// The name of a core protocol error, for the errors a ChangeProperty can cause.
+fn error_name(code: u8) -> String {
+    match code {
+        2 => String::from("BadValue"),
+        3 => String::from("BadWindow"),
+        5 => String::from("BadAtom"),
+        11 => String::from("BadAlloc"),
+        16 => String::from("BadLength"),
+        code => format!("error {}", code),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{ErrorKind, Read, Write};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    use super::{change_property, find_cookie, parse_display, parse_setup, RootWindow, CHANGE_PROPERTY, GET_INPUT_FOCUS};
+
+    /// A setup reply with a vendor name, one pixmap format, and a screen whose root window is `root`.
+    fn setup_reply(root: u32) -> Vec<u8> {
+        let mut reply = vec![0u8; 32];
+        reply[16..18].copy_from_slice(&5u16.to_le_bytes());
+        reply[18..20].copy_from_slice(&0xffffu16.to_le_bytes());
+        reply[21] = 1;
+        reply.extend_from_slice(b"X.Org\0\0\0");
+        reply.extend_from_slice(&[0u8; 8]);
+        reply.extend_from_slice(&root.to_le_bytes());
+        reply.extend_from_slice(&[0u8; 36]);
+        reply
+    }
+
+    #[test]
+    fn displays_are_local_numbers() {
+        assert_eq!(parse_display(":0"), Ok(String::from("0")));
+        assert_eq!(parse_display("unix:1.0"), Ok(String::from("1")));
+        assert!(parse_display("host:0").is_err());
+        assert!(parse_display(":").is_err());
+        assert!(parse_display(":a").is_err());
+    }
+
+    #[test]
+    fn cookies_are_found_for_the_display() {
+        let entry = |family: u16, address: &str, display: &str, data: &[u8]| {
+            let mut entry = family.to_be_bytes().to_vec();
+            for field in [address.as_bytes(), display.as_bytes(), b"MIT-MAGIC-COOKIE-1", data] {
+                entry.extend_from_slice(&(field.len() as u16).to_be_bytes());
+                entry.extend_from_slice(field);
+            }
+            entry
+        };
+        let mut file = entry(256, "other", "0", b"wrong host");
+        file.extend(entry(256, "host", "1", b"wrong display"));
+        file.extend(entry(256, "host", "0", b"right"));
+        assert_eq!(find_cookie(&file, "host", "0"), Some(b"right".to_vec()));
+        assert_eq!(find_cookie(&entry(65535, "", "2", b"any"), "host", "2"), Some(b"any".to_vec()));
+        assert_eq!(find_cookie(&file[..file.len() - 3], "host", "0"), None);
+    }
+
+    #[test]
+    fn setup_replies_give_the_root_window() {
+        let header = [1, 0, 11, 0, 0, 0, 0, 0];
+        assert_eq!(parse_setup(&header, &setup_reply(0x1e2)), Ok((0x1e2, 0xffff * 4)));
+        assert!(parse_setup(&header, &setup_reply(0x1e2)[..40]).is_err());
+        let refused = [0, 7, 11, 0, 0, 0, 0, 0];
+        assert_eq!(parse_setup(&refused, b"No auth\0"), Err(String::from("refused the connection: No auth")));
+    }
+
+    #[test]
+    fn names_are_padded_to_whole_units() {
+        let request = change_property(0x1e2, 300, "hello");
+        assert_eq!(request.len(), 32);
+        assert_eq!(request[0], CHANGE_PROPERTY);
+        assert_eq!(u16::from_le_bytes([request[2], request[3]]), 8);
+        assert_eq!(&request[24..29], b"hello");
+    }
+
+    #[test]
+    fn refused_names_are_reported() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let mut window = RootWindow { stream: client, root: 0x1e2, utf8_string: 300, maximum_request: 64 };
+        let server = thread::spawn(move || {
+            for error in [None, Some(16)] {
+                let mut request = [0u8; 32];
+                server.read_exact(&mut request).unwrap();
+                assert_eq!(request[28], GET_INPUT_FOCUS);
+                let mut reply = [0u8; 32];
+                if let Some(code) = error {
+                    reply[1] = code;
+                    server.write_all(&reply).unwrap();
+                }
+                reply[0] = 1;
+                server.write_all(&reply).unwrap();
+            }
+        });
+        assert!(window.set_name("fine").is_ok());
+        let error = window.set_name("long").err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("BadLength"));
+        server.join().unwrap();
+        // Too long a name isn't sent at all
+        assert_eq!(window.set_name(&"x".repeat(64)).err().map(|e| e.kind()), Some(ErrorKind::InvalidInput));
+    }
+}